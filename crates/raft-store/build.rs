@@ -0,0 +1,7 @@
+fn main() -> std::io::Result<()> { 
+    let protoc = protoc_bin_vendored::protoc_bin_path()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e.to_string()))?;
+    prost_build::Config::new()
+        .protoc_executable(protoc)
+        .compile_protos(&["proto/command.proto"], &["proto/"])
+}
@@ -1,47 +1,158 @@
-use bytes::{Buf, BufMut, BytesMut};
+use std::io::{Error, ErrorKind};
 
-#[derive(Debug)]
-pub enum Command { 
+use bytes::Buf;
+
+/**
+ * Wire types generated from `proto/command.proto`.
+ * * Entries are encoded with `pb::Command::from(cmd).encode_to_vec()`, see the
+ *   `TryFrom<&[u8]>` impl of `Command` for the way back.
+ */
+pub mod pb { 
+    include!(concat!(env!("OUT_DIR"), "/command.rs"));
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SingleOp { 
     Put { key: Vec<u8>, val: Vec<u8>},
     Delete { key: Vec<u8>}
 }
 
-impl Command { 
-    pub fn encode(&self) -> Vec<u8> { 
-        let mut buf = BytesMut::new();
-        match self { 
-            Command::Put { key, val } => { 
-                buf.put_u8(1);
-                buf.put_u32(key.len() as u32);
-                buf.extend_from_slice(&key);
-                buf.put_u32(val.len() as u32);
-                buf.extend_from_slice(&val);
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command { 
+    Put { key: Vec<u8>, val: Vec<u8>},
+    Delete { key: Vec<u8>},
+    /**
+     * Batch of ops applied as one unit, no other command is applied in between.
+     */
+    Merge { ops: Vec<SingleOp> },
+    /**
+     * No-op marking a point in the log, reads served after it is applied see every
+     * command committed before it. `nonce` keeps barriers apart.
+     */
+    ReadBarrier { nonce: u64 },
+    /**
+     * Writes `new_val` to `key` only if its current value is `expected`, a missing key
+     * never matches. The waiter of the proposal learns whether the write happened.
+     */
+    Cas { key: Vec<u8>, expected: Vec<u8>, new_val: Vec<u8> }
+}
+
+impl From<Command> for pb::Command { 
+    fn from(cmd: Command) -> Self { 
+        let body = match cmd { 
+            Command::Put { key, val } => pb::command::Body::Put(pb::Put { key, val }),
+            Command::Delete { key } => pb::command::Body::Delete(pb::Delete { key }),
+            Command::Merge { ops } => pb::command::Body::Merge(pb::Merge { 
+                ops: ops.into_iter().map(pb::SingleOp::from).collect()
+            }),
+            Command::ReadBarrier { nonce } => pb::command::Body::ReadBarrier(pb::ReadBarrier { nonce }),
+            Command::Cas { key, expected, new_val } => pb::command::Body::Cas(pb::Cas { key, expected, new_val })
+        };
+        pb::Command { body: Some(body) }
+    }
+}
+
+impl From<SingleOp> for pb::SingleOp { 
+    fn from(op: SingleOp) -> Self { 
+        let op = match op { 
+            SingleOp::Put { key, val } => pb::single_op::Op::Put(pb::Put { key, val }),
+            SingleOp::Delete { key } => pb::single_op::Op::Delete(pb::Delete { key })
+        };
+        pb::SingleOp { op: Some(op) }
+    }
+}
+
+impl TryFrom<pb::Command> for Command { 
+    type Error = Error;
+
+    fn try_from(cmd: pb::Command) -> Result<Self, Self::Error> { 
+        match cmd.body { 
+            Some(pb::command::Body::Put(pb::Put { key, val })) => Ok(Command::Put { key, val }),
+            Some(pb::command::Body::Delete(pb::Delete { key })) => Ok(Command::Delete { key }),
+            Some(pb::command::Body::Merge(merge)) => { 
+                let ops = merge.ops.into_iter().map(|op| match op.op { 
+                    Some(pb::single_op::Op::Put(pb::Put { key, val })) => Ok(SingleOp::Put { key, val }),
+                    Some(pb::single_op::Op::Delete(pb::Delete { key })) => Ok(SingleOp::Delete { key }),
+                    None => Err(Error::new(ErrorKind::InvalidData, "merge op without a body"))
+                }).collect::<Result<Vec<_>, _>>()?;
+                Ok(Command::Merge { ops })
             },
-            Command::Delete { key } => { 
-                buf.put_u8(2);
-                buf.put_u32(key.len() as u32);
-                buf.extend_from_slice(&key);
-            }
+            Some(pb::command::Body::ReadBarrier(pb::ReadBarrier { nonce })) => Ok(Command::ReadBarrier { nonce }),
+            Some(pb::command::Body::Cas(pb::Cas { key, expected, new_val })) => Ok(Command::Cas { key, expected, new_val }),
+            None => Err(Error::new(ErrorKind::InvalidData, "command without a body"))
         }
-        buf.to_vec()
     }
+}
 
-    pub fn decode(mut data: &[u8]) -> Self { 
-        let tag = data.get_u8();
-        let key_len = data.get_u32();
-        let mut key = vec![0u8; key_len as usize];
-        data.copy_to_slice(&mut key);
-        match tag { 
-            1=> { 
-                let val_len = data.get_u32();
-                let mut val = vec![0u8; val_len as usize];
-                data.copy_to_slice(&mut val);
-                return Self::Put{key, val};
-            },
-            2 => { 
-                return Self::Delete { key };
-            },
-            _ => todo!()
+/**
+ * Decodes a command from raft entry data.
+ * * Data written before the protobuf encoding starts with a tag byte of `1`, `2` or `3`,
+ *   which no encoded `pb::Command` does (its first byte is a field key), and is read with
+ *   the legacy decoder so old logs can still be replayed.
+ */
+impl TryFrom<&[u8]> for Command { 
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> { 
+        match data.first() { 
+            Some(1..=3) => decode_legacy(data),
+            _ => <pb::Command as prost::Message>::decode(data)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+                .try_into()
+        }
+    }
+}
+
+/**
+ * # Legacy Binary Format:
+ * * Put: [1][KeyLen (4B)][Key][ValLen (4B)][Val]
+ * * Delete: [2][KeyLen (4B)][Key]
+ * * Merge: [3][OpCount (4B)] followed by `[OpLen (4B)][Op]` per op, where every op is
+ *   encoded like the matching Put or Delete command.
+ */
+fn decode_legacy(mut data: &[u8]) -> std::io::Result<Command> { 
+    let truncated = || Error::new(ErrorKind::InvalidData, "truncated legacy command");
+    let take = |data: &mut &[u8]| -> std::io::Result<Vec<u8>> { 
+        if data.remaining() < 4 { 
+            return Err(truncated());
         }
+        let len = data.get_u32() as usize;
+        if data.remaining() < len { 
+            return Err(truncated());
+        }
+        let (bytes, rest) = data.split_at(len);
+        *data = rest;
+        Ok(bytes.to_vec())
+    };
+    if !data.has_remaining() { 
+        return Err(truncated());
     }
-}
\ No newline at end of file
+    let tag = data.get_u8();
+    match tag { 
+        1 => { 
+            let key = take(&mut data)?;
+            let val = take(&mut data)?;
+            Ok(Command::Put { key, val })
+        },
+        2 => Ok(Command::Delete { key: take(&mut data)? }),
+        3 => { 
+            if data.remaining() < 4 { 
+                return Err(truncated());
+            }
+            let count = data.get_u32();
+            // every op takes at least its length, so a count the data can not hold does
+            // not get to reserve memory
+            let mut ops = Vec::with_capacity((count as usize).min(data.remaining() / 4));
+            for _ in 0..count { 
+                let op = take(&mut data)?;
+                match decode_legacy(&op)? { 
+                    Command::Put { key, val } => ops.push(SingleOp::Put { key, val }),
+                    Command::Delete { key } => ops.push(SingleOp::Delete { key }),
+                    Command::Merge { .. } | Command::ReadBarrier { .. } | Command::Cas { .. } => return Err(Error::new(ErrorKind::InvalidData, "nested merge"))
+                }
+            }
+            Ok(Command::Merge { ops })
+        },
+        _ => Err(Error::new(ErrorKind::InvalidData, format!("unknown command tag {}", tag)))
+    }
+}
@@ -0,0 +1,59 @@
+use prost::Message;
+
+use crate::command::{Command, SingleOp, pb};
+
+fn round_trip(cmd: Command) { 
+    let encoded = pb::Command::from(cmd.clone()).encode_to_vec();
+    assert_eq!(Command::try_from(&encoded[..]).unwrap(), cmd);
+}
+
+#[test]
+pub fn command_test_round_trips_every_variant() { 
+    round_trip(Command::Put { key: b"k".to_vec(), val: b"v".to_vec() });
+    round_trip(Command::Put { key: b"k".to_vec(), val: Vec::new() });
+    round_trip(Command::Delete { key: b"k".to_vec() });
+    round_trip(Command::Merge { ops: vec![
+        SingleOp::Put { key: b"k1".to_vec(), val: b"v1".to_vec() },
+        SingleOp::Delete { key: b"k2".to_vec() },
+        SingleOp::Put { key: b"k3".to_vec(), val: Vec::new() }
+    ]});
+    round_trip(Command::Merge { ops: Vec::new() });
+    round_trip(Command::ReadBarrier { nonce: 42 });
+    round_trip(Command::Cas { key: b"k".to_vec(), expected: b"old".to_vec(), new_val: b"new".to_vec() });
+    round_trip(Command::Cas { key: b"k".to_vec(), expected: Vec::new(), new_val: Vec::new() });
+}
+
+#[test]
+pub fn command_test_decodes_legacy_encoding() { 
+    let mut put = vec![1u8];
+    put.extend(2u32.to_be_bytes());
+    put.extend(b"k1");
+    put.extend(2u32.to_be_bytes());
+    put.extend(b"v1");
+    assert_eq!(Command::try_from(&put[..]).unwrap(), Command::Put { key: b"k1".to_vec(), val: b"v1".to_vec() });
+
+    let mut delete = vec![2u8];
+    delete.extend(2u32.to_be_bytes());
+    delete.extend(b"k2");
+    assert_eq!(Command::try_from(&delete[..]).unwrap(), Command::Delete { key: b"k2".to_vec() });
+
+    let mut merge = vec![3u8];
+    merge.extend(2u32.to_be_bytes());
+    for op in [&put, &delete] { 
+        merge.extend((op.len() as u32).to_be_bytes());
+        merge.extend(op.iter());
+    }
+    assert_eq!(Command::try_from(&merge[..]).unwrap(), Command::Merge { ops: vec![
+        SingleOp::Put { key: b"k1".to_vec(), val: b"v1".to_vec() },
+        SingleOp::Delete { key: b"k2".to_vec() }
+    ]});
+    assert!(Command::try_from(&put[..put.len() - 1]).is_err());
+}
+
+#[test]
+pub fn command_test_rejects_malformed_legacy_merges() { 
+    // an empty nested op and an op count far beyond the data
+    assert!(Command::try_from(&[3u8, 0, 0, 0, 1, 0, 0, 0, 0][..]).is_err());
+    assert!(Command::try_from(&[3u8, 0xff, 0xff, 0xff, 0xff][..]).is_err());
+    assert!(Command::try_from(&[3u8, 0, 0, 0][..]).is_err());
+}
@@ -0,0 +1,19 @@
+pub mod command;
+pub mod log_store;
+pub mod region;
+pub mod router;
+pub mod server;
+pub mod store;
+#[cfg(test)]
+pub mod command_test;
+#[cfg(test)]
+pub mod log_store_test;
+#[cfg(test)]
+pub mod region_test;
+#[cfg(test)]
+pub mod router_test;
+#[cfg(test)]
+pub mod server_test;
+
+#[cfg(test)]
+pub mod store_test;
\ No newline at end of file
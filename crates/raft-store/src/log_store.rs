@@ -1,6 +1,9 @@
-use std::sync::{Arc, Mutex};
+use std::{collections::BTreeMap, io::ErrorKind, path::PathBuf, sync::{Arc, Mutex}};
 
-use raft::{Result as RaftResult, eraftpb::{ConfState, Entry, HardState, Snapshot}, storage::{RaftState, Storage}};
+use protobuf::Message;
+
+use raft::{Result as RaftResult, StorageError, eraftpb::{ConfState, Entry, HardState, Snapshot, SnapshotMetadata}, storage::{RaftState, Storage}};
+use sledlite_core::engine::{Config, Engine};
 
 
 #[derive(Clone)]
@@ -10,32 +13,273 @@ pub struct RaftLogStore {
 
 pub struct Inner { 
     hard_state: HardState,
-    entries: Vec<Entry>
+    conf_state: ConfState,
+    entries: BTreeMap<u64, Entry>, // keyed by entry index
+    region_id: u64,
+    engine: Option<Engine>, // durable copy of the log, `None` keeps it in memory only
+    snapshot_metadata: SnapshotMetadata, // last index and term folded into a snapshot
+    state_machine: Option<(Arc<Mutex<Engine>>, PathBuf)>, // engine to checkpoint and where to put checkpoints
+    applied_index: u64
+}
+
+fn log_key(region_id: u64, index: u64) -> Vec<u8> { 
+    format!("raft-log-{}-{:020}", region_id, index).into_bytes()
+}
+
+fn hard_state_key(region_id: u64) -> Vec<u8> { 
+    format!("raft-hard-state-{}", region_id).into_bytes()
+}
+
+fn conf_state_key(region_id: u64) -> Vec<u8> { 
+    format!("raft-conf-state-{}", region_id).into_bytes()
+}
+
+fn snapshot_metadata_key(region_id: u64) -> Vec<u8> { 
+    format!("raft-snapshot-metadata-{}", region_id).into_bytes()
+}
+
+/**
+ * Dummy entry left behind by the last `compact_log`, holding the index and term of the
+ * last compacted entry.
+ */
+fn compacted_key(region_id: u64) -> Vec<u8> { 
+    format!("raft-compacted-{}", region_id).into_bytes()
+}
+
+fn invalid_data(err: protobuf::ProtobufError) -> std::io::Error { 
+    std::io::Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+impl Default for RaftLogStore { 
+    fn default() -> Self { 
+        Self::new()
+    }
 }
 
 impl RaftLogStore { 
     pub fn new() -> Self { 
-        let mut entries = Vec::new();
+        Self::with_engine(0, None, HardState::default(), ConfState::default(), SnapshotMetadata::default(), BTreeMap::new())
+    }
+
+    fn with_engine(region_id: u64, engine: Option<Engine>, hard_state: HardState, conf_state: ConfState, snapshot_metadata: SnapshotMetadata, mut entries: BTreeMap<u64, Entry>) -> Self { 
+        // the dummy entry keeps the term of the last index folded into a snapshot
         let mut dummy = Entry::default();
-        dummy.set_index(0);
-        dummy.set_term(0);
-        entries.push(dummy);
+        dummy.set_index(snapshot_metadata.index);
+        dummy.set_term(snapshot_metadata.term);
+        entries.insert(snapshot_metadata.index, dummy);
+        let applied_index = snapshot_metadata.index;
         Self { 
             inner: Arc::new(Mutex::new(Inner { 
-                hard_state: HardState::default(),
-                entries
+                hard_state,
+                conf_state,
+                entries,
+                region_id,
+                engine,
+                snapshot_metadata,
+                state_machine: None,
+                applied_index
             }))
         } 
     }
 
-    pub fn append(&self, entries: &[Entry]) { 
+    /**
+     * Opens the log of `region_id` persisted in `engine`, replaying every entry and the
+     * hard state written by earlier runs.
+     * * Entries live under `raft-log-{region_id}-{index:020}`, the hard state under
+     *   `raft-hard-state-{region_id}` and the conf state under `raft-conf-state-{region_id}`,
+     *   all as serialized protobuf.
+     */
+    pub fn open(mut engine: Engine, region_id: u64) -> std::io::Result<Self> { 
+        let snapshot_metadata = match engine.get(&snapshot_metadata_key(region_id))? { 
+            Some(buf) => SnapshotMetadata::parse_from_bytes(&buf).map_err(invalid_data)?,
+            None => SnapshotMetadata::default()
+        };
+        let mut entries = BTreeMap::new();
+        let start = log_key(region_id, snapshot_metadata.index + 1);
+        let end = log_key(region_id, u64::MAX);
+        for (_, buf) in engine.scan(&start, &end)? { 
+            let entry = Entry::parse_from_bytes(&buf).map_err(invalid_data)?;
+            entries.insert(entry.index, entry);
+        }
+        let hard_state = match engine.get(&hard_state_key(region_id))? { 
+            Some(buf) => HardState::parse_from_bytes(&buf).map_err(invalid_data)?,
+            None => HardState::default()
+        };
+        let conf_state = match engine.get(&conf_state_key(region_id))? { 
+            Some(buf) => ConfState::parse_from_bytes(&buf).map_err(invalid_data)?,
+            None => ConfState::default()
+        };
+        let compacted = match engine.get(&compacted_key(region_id))? { 
+            Some(buf) => Some(Entry::parse_from_bytes(&buf).map_err(invalid_data)?),
+            None => None
+        };
+        let store = Self::with_engine(region_id, Some(engine), hard_state, conf_state, snapshot_metadata, entries);
+        if let Some(dummy) = compacted { 
+            let mut inner = store.inner.lock().unwrap();
+            // `compact_log` deleted the entries up to its dummy, which replaces the snapshot's
+            if dummy.index > *inner.entries.keys().next().unwrap() { 
+                inner.entries.pop_first();
+                inner.entries.insert(dummy.index, dummy);
+            }
+        }
+        Ok(store)
+    }
+
+    /**
+     * Appends entries to the log, keyed by their index.
+     * * An entry at an index that is already present replaces it, and every entry after
+     *   it is dropped as well: a leader overwriting part of the log invalidates its tail.
+     * * With an engine attached the entries are persisted before they become visible.
+     */
+    pub fn append(&self, entries: &[Entry]) -> std::io::Result<()> { 
+        let mut inner = self.inner.lock().unwrap();
+        let Some(first) = entries.first() else { 
+            return Ok(());
+        };
+        let truncated = inner.entries.split_off(&first.index);
+        let region_id = inner.region_id;
+        if let Some(engine) = inner.engine.as_mut() { 
+            for entry in entries { 
+                engine.put(&log_key(region_id, entry.index), &entry.write_to_bytes().map_err(invalid_data)?)?;
+            }
+            let last_index = entries.last().unwrap().index;
+            for &index in truncated.keys().filter(|&&index| index > last_index) { 
+                engine.delete(&log_key(region_id, index))?;
+            }
+        }
+        for entry in entries { 
+            inner.entries.insert(entry.index, entry.clone());
+        }
+        Ok(())
+    }
+
+    pub fn set_hard_state(&self, hard_state : HardState) -> std::io::Result<()> { 
+        let mut inner = self.inner.lock().unwrap();
+        let region_id = inner.region_id;
+        if let Some(engine) = inner.engine.as_mut() { 
+            engine.put(&hard_state_key(region_id), &hard_state.write_to_bytes().map_err(invalid_data)?)?;
+        }
+        inner.hard_state = hard_state;
+        Ok(())
+    }
+
+    /**
+     * Drops every entry up to and including `up_to_index`, which has to be covered by a
+     * snapshot already, e.g. after `Storage::snapshot` checkpointed the state machine.
+     * * A dummy entry at `up_to_index` keeps its term, `first_index` moves to
+     *   `up_to_index + 1` and reads below it fail with `StorageError::Compacted`.
+     * * Compacting to an index that is already compacted does nothing, an index past
+     *   `last_index` fails with `StorageError::Unavailable`.
+     */
+    pub fn compact_log(&self, up_to_index: u64) -> RaftResult<()> { 
+        let mut inner = self.inner.lock().unwrap();
+        let offset = *inner.entries.keys().next().unwrap();
+        if up_to_index <= offset { 
+            return Ok(());
+        }
+        let Some(term) = inner.entries.get(&up_to_index).map(|e| e.term) else { 
+            return Err(raft::Error::Store(StorageError::Unavailable));
+        };
+        let mut dummy = Entry::default();
+        dummy.set_index(up_to_index);
+        dummy.set_term(term);
+        let kept = inner.entries.split_off(&(up_to_index + 1));
+        let compacted = std::mem::replace(&mut inner.entries, kept);
+        let region_id = inner.region_id;
+        if let Some(engine) = inner.engine.as_mut() { 
+            engine.put(&compacted_key(region_id), &dummy.write_to_bytes().map_err(invalid_data)?)?;
+            for &index in compacted.keys().filter(|&&index| index > offset) { 
+                engine.delete(&log_key(region_id, index))?;
+            }
+        }
+        inner.entries.insert(up_to_index, dummy);
+        Ok(())
+    }
+
+    /**
+     * Updates the commit index of the stored hard state.
+     */
+    pub fn set_commit(&self, commit: u64) -> std::io::Result<()> { 
+        let mut hard_state = self.inner.lock().unwrap().hard_state.clone();
+        hard_state.set_commit(commit);
+        self.set_hard_state(hard_state)
+    }
+
+    pub fn set_conf_state(&self, conf_state: ConfState) -> std::io::Result<()> { 
         let mut inner = self.inner.lock().unwrap();
-        inner.entries.extend_from_slice(entries);
+        let region_id = inner.region_id;
+        if let Some(engine) = inner.engine.as_mut() { 
+            engine.put(&conf_state_key(region_id), &conf_state.write_to_bytes().map_err(invalid_data)?)?;
+        }
+        inner.conf_state = conf_state;
+        Ok(())
+    }
+}
+
+impl RaftLogStore { 
+    /**
+     * Attaches the state machine engine that `snapshot` checkpoints into `snapshot_dir`
+     * and `apply_snapshot` replaces.
+     */
+    pub fn attach_state_machine(&self, engine: Arc<Mutex<Engine>>, snapshot_dir: PathBuf) { 
+        self.inner.lock().unwrap().state_machine = Some((engine, snapshot_dir));
+    }
+
+    /**
+     * Records how far the state machine has applied the log, the point `snapshot` captures.
+     */
+    pub fn set_applied_index(&self, applied_index: u64) { 
+        self.inner.lock().unwrap().applied_index = applied_index;
     }
 
-    pub fn set_hard_state(&self, hard_state : HardState) { 
+    /**
+     * Installs a snapshot produced by `snapshot`, on this or another peer.
+     * * The checkpoint named by the snapshot data is opened as the new state machine engine,
+     *   every log entry up to the last included index is dropped and the hard state commit
+     *   moves up to it.
+     */
+    pub fn apply_snapshot(&self, snapshot: Snapshot) -> std::io::Result<()> { 
         let mut inner = self.inner.lock().unwrap();
+        let metadata = snapshot.get_metadata().clone();
+        if let Some((state_machine, _)) = inner.state_machine.as_ref() { 
+            let dir = PathBuf::from(String::from_utf8_lossy(&snapshot.data).into_owned());
+            let mut state_machine = state_machine.lock().unwrap();
+            // the checkpoint holds every table, the data directories of the old engine stay out
+            let config = Config { 
+                dir,
+                data_dirs: Vec::new(),
+                ..state_machine.config().clone()
+            };
+            *state_machine = Engine::open(config)?;
+        }
+
+        // keep the tail only if it continues the snapshot, otherwise the whole log is stale
+        let keeps_tail = inner.entries.get(&metadata.index).is_some_and(|e| e.term == metadata.term);
+        let tail = if keeps_tail { inner.entries.split_off(&(metadata.index + 1)) } else { BTreeMap::new() };
+        let compacted: Vec<u64> = inner.entries.keys().copied().filter(|&index| index > inner.snapshot_metadata.index).collect();
+        let mut dummy = Entry::default();
+        dummy.set_index(metadata.index);
+        dummy.set_term(metadata.term);
+        inner.entries = tail;
+        inner.entries.insert(metadata.index, dummy);
+
+        let mut hard_state = inner.hard_state.clone();
+        hard_state.set_commit(hard_state.commit.max(metadata.index));
+        hard_state.set_term(hard_state.term.max(metadata.term));
+        let region_id = inner.region_id;
+        if let Some(engine) = inner.engine.as_mut() { 
+            for index in compacted { 
+                engine.delete(&log_key(region_id, index))?;
+            }
+            engine.put(&snapshot_metadata_key(region_id), &metadata.write_to_bytes().map_err(invalid_data)?)?;
+            engine.put(&hard_state_key(region_id), &hard_state.write_to_bytes().map_err(invalid_data)?)?;
+            engine.put(&conf_state_key(region_id), &metadata.get_conf_state().write_to_bytes().map_err(invalid_data)?)?;
+        }
         inner.hard_state = hard_state;
+        inner.conf_state = metadata.get_conf_state().clone();
+        inner.applied_index = metadata.index;
+        inner.snapshot_metadata = metadata;
+        Ok(())
     }
 }
 
@@ -44,7 +288,7 @@ impl Storage for RaftLogStore {
         let inner =  self.inner.lock().unwrap();
         Ok(RaftState { 
             hard_state: inner.hard_state.clone(),
-            conf_state: ConfState::default()
+            conf_state: inner.conf_state.clone()
          })
     }
 
@@ -56,32 +300,65 @@ impl Storage for RaftLogStore {
         _context: raft::GetEntriesContext,
     ) -> RaftResult<Vec<Entry>> {
         let inner = self.inner.lock().unwrap();
+        // the first entry is the dummy left by the last snapshot or compaction
+        if low <= *inner.entries.keys().next().unwrap() { 
+            return Err(raft::Error::Store(StorageError::Compacted));
+        }
         Ok(inner
             .entries
-            .iter()
-            .filter(|e| e.index >= low && e.index < high)
-            .cloned()
+            .range(low..high)
+            .map(|(_, e)| e.clone())
             .collect()
         )
     }
 
     fn term(&self, idx: u64) -> RaftResult<u64> {
         let inner = self.inner.lock().unwrap();
-        inner.entries.iter().find(|e| e.index == idx).map(|e| e.term)
+        if idx < *inner.entries.keys().next().unwrap() { 
+            return Err(raft::Error::Store(StorageError::Compacted));
+        }
+        inner.entries.get(&idx).map(|e| e.term)
             .ok_or(raft::Error::Store(raft::StorageError::Unavailable))
     }
 
     fn first_index(&self) -> RaftResult<u64> {
         let inner = self.inner.lock().unwrap();
-        Ok(inner.entries.first().unwrap().index + 1)
+        Ok(inner.entries.keys().next().unwrap() + 1)
     }
 
     fn last_index(&self) -> RaftResult<u64> {
         let inner = self.inner.lock().unwrap();
-        Ok(inner.entries.last().map(|e| e.index).unwrap_or(0))
+        Ok(inner.entries.keys().next_back().copied().unwrap_or(0))
     }
 
-    fn snapshot(&self, _request_index: u64, _to: u64) -> RaftResult<raft::prelude::Snapshot> {
-        Ok(Snapshot::default())
+    /**
+     * Checkpoints the state machine as of the applied index.
+     * * The snapshot data holds the checkpoint directory, which peers in this process
+     *   open directly in `apply_snapshot`.
+     */
+    fn snapshot(&self, request_index: u64, _to: u64) -> RaftResult<raft::prelude::Snapshot> {
+        let inner = self.inner.lock().unwrap();
+        let Some((state_machine, snapshot_dir)) = inner.state_machine.as_ref() else { 
+            return Err(raft::Error::Store(StorageError::SnapshotTemporarilyUnavailable));
+        };
+        let index = inner.applied_index;
+        if index < request_index { 
+            return Err(raft::Error::Store(StorageError::SnapshotTemporarilyUnavailable));
+        }
+        let term = inner.entries.get(&index).map(|e| e.term)
+            .ok_or(raft::Error::Store(StorageError::SnapshotTemporarilyUnavailable))?;
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let dest = snapshot_dir.join(format!("snap-{}-{}-{}", inner.region_id, index, nanos));
+        state_machine.lock().unwrap().checkpoint(&dest)?;
+
+        let mut snapshot = Snapshot { 
+            data: dest.to_string_lossy().into_owned().into_bytes().into(),
+            ..Default::default()
+        };
+        let metadata = snapshot.mut_metadata();
+        metadata.set_index(index);
+        metadata.set_term(term);
+        metadata.set_conf_state(inner.conf_state.clone());
+        Ok(snapshot)
     }
 }
\ No newline at end of file
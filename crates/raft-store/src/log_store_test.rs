@@ -0,0 +1,113 @@
+use raft::{GetEntriesContext, StorageError, eraftpb::{Entry, HardState}, storage::Storage};
+
+use std::{fs::remove_dir_all, path::PathBuf};
+
+use sledlite_core::engine::{Config, Engine, EngineMode};
+
+use crate::log_store::RaftLogStore;
+
+fn entry(index: u64, term: u64) -> Entry { 
+    let mut entry = Entry::default();
+    entry.set_index(index);
+    entry.set_term(term);
+    entry.data = format!("entry-{}-{}", index, term).into_bytes().into();
+    entry
+}
+
+#[test]
+pub fn log_store_test_storage_methods() { 
+    let store = RaftLogStore::new();
+    assert_eq!(store.first_index().unwrap(), 1);
+    assert_eq!(store.last_index().unwrap(), 0);
+    assert_eq!(store.term(0).unwrap(), 0);
+
+    store.append(&(1..=10).map(|i| entry(i, 1)).collect::<Vec<_>>()).unwrap();
+    assert_eq!(store.first_index().unwrap(), 1);
+    assert_eq!(store.last_index().unwrap(), 10);
+    assert_eq!(store.term(7).unwrap(), 1);
+    assert!(store.term(11).is_err());
+    let entries = store.entries(3, 6, None, GetEntriesContext::empty(false)).unwrap();
+    assert_eq!(entries, (3..6).map(|i| entry(i, 1)).collect::<Vec<_>>());
+
+    // a new leader overwrites the tail from index 8 on
+    store.append(&[entry(8, 2), entry(9, 2)]).unwrap();
+    assert_eq!(store.last_index().unwrap(), 9);
+    assert_eq!(store.term(7).unwrap(), 1);
+    assert_eq!(store.term(8).unwrap(), 2);
+    assert!(store.term(10).is_err());
+    let entries = store.entries(7, 20, None, GetEntriesContext::empty(false)).unwrap();
+    assert_eq!(entries, vec![entry(7, 1), entry(8, 2), entry(9, 2)]);
+
+    let mut hard_state = HardState::default();
+    hard_state.set_term(2);
+    hard_state.set_commit(9);
+    store.set_hard_state(hard_state.clone()).unwrap();
+    assert_eq!(store.initial_state().unwrap().hard_state, hard_state);
+}
+
+#[test]
+pub fn log_store_test_survives_reopen() { 
+    let dir = PathBuf::from("./temp-raft-log");
+    let _ = remove_dir_all(&dir);
+    let config = Config { 
+        dir: dir.clone(),
+        memtable_max_bytes: 4 * 1024,
+        max_wal_bytes: None,
+        mode: EngineMode::Persistent,
+        block_cache_capacity: 0,
+        data_dirs: Vec::new()
+    };
+    let store = RaftLogStore::open(Engine::open(config.clone()).unwrap(), 7).unwrap();
+    store.append(&(1..=100).map(|i| entry(i, 1)).collect::<Vec<_>>()).unwrap();
+    // truncate the tail, entries 91..=100 must not come back after a reopen
+    store.append(&[entry(90, 2)]).unwrap();
+    let mut hard_state = HardState::default();
+    hard_state.set_term(2);
+    hard_state.set_commit(90);
+    store.set_hard_state(hard_state.clone()).unwrap();
+    drop(store);
+
+    let store = RaftLogStore::open(Engine::open(config).unwrap(), 7).unwrap();
+    assert_eq!(store.first_index().unwrap(), 1);
+    assert_eq!(store.last_index().unwrap(), 90);
+    let mut expected: Vec<Entry> = (1..90).map(|i| entry(i, 1)).collect();
+    expected.push(entry(90, 2));
+    assert_eq!(store.entries(1, 91, None, GetEntriesContext::empty(false)).unwrap(), expected);
+    assert_eq!(store.term(90).unwrap(), 2);
+    assert_eq!(store.initial_state().unwrap().hard_state, hard_state);
+    drop(store);
+    let _ = remove_dir_all(&dir);
+}
+
+#[test]
+pub fn log_store_test_compact_log_drops_snapshotted_entries() { 
+    let dir = PathBuf::from("./temp-raft-log-compact");
+    let _ = remove_dir_all(&dir);
+    let config = Config { 
+        dir: dir.clone(),
+        memtable_max_bytes: 4 * 1024,
+        max_wal_bytes: None,
+        mode: EngineMode::Persistent,
+        block_cache_capacity: 0,
+        data_dirs: Vec::new()
+    };
+    let compacted = |result: raft::Result<_>| matches!(result, Err(raft::Error::Store(StorageError::Compacted)));
+    let store = RaftLogStore::open(Engine::open(config.clone()).unwrap(), 3).unwrap();
+    store.append(&(1..=5).map(|i| entry(i, 1)).chain((6..=20).map(|i| entry(i, 2))).collect::<Vec<_>>()).unwrap();
+    store.compact_log(8).unwrap();
+    // compacting to an older index is a no-op, past the end is an error
+    store.compact_log(4).unwrap();
+    assert!(matches!(store.compact_log(21), Err(raft::Error::Store(StorageError::Unavailable))));
+    drop(store);
+
+    let store = RaftLogStore::open(Engine::open(config).unwrap(), 3).unwrap();
+    assert_eq!(store.first_index().unwrap(), 9);
+    assert_eq!(store.last_index().unwrap(), 20);
+    assert_eq!(store.term(8).unwrap(), 2);
+    assert!(compacted(store.term(7).map(|_| ())));
+    assert!(compacted(store.entries(5, 12, None, GetEntriesContext::empty(false)).map(|_| ())));
+    assert!(compacted(store.entries(8, 12, None, GetEntriesContext::empty(false)).map(|_| ())));
+    assert_eq!(store.entries(9, 21, None, GetEntriesContext::empty(false)).unwrap(), (9..=20).map(|i| entry(i, 2)).collect::<Vec<_>>());
+    drop(store);
+    let _ = remove_dir_all(&dir);
+}
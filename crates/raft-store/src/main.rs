@@ -1,15 +1,10 @@
-mod region;
-mod store;
-mod command;
-
-use store::RaftStore;
-use command::Command;
+use raft_store::{command::Command, store::RaftStore};
 
 fn main() {
     let mut store = RaftStore::new();
 
-    store.create_region(1);
-    store.create_region(2);
+    store.create_region(1, vec![1], None);
+    store.create_region(2, vec![2], None);
 
     // tick to elect leaders
     for _ in 0..50 {
@@ -20,13 +15,13 @@ fn main() {
     store.propose(1, Command::Put {
         key: b"k1".to_vec(),
         val: b"v1".to_vec(),
-    });
+    }).expect("region 1 has no leader");
 
     // propose to region 2
     store.propose(2, Command::Put {
         key: b"k2".to_vec(),
         val: b"v2".to_vec(),
-    });
+    }).expect("region 2 has no leader");
 
     for _ in 0..50 {
         store.tick_all();
@@ -1,70 +1,460 @@
-use raft::storage::MemStorage;
-use raft::{Config, RawNode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use crate::{command::Command};
+use crossbeam_channel::Sender;
+use raft::eraftpb::{ConfChange, ConfChangeV2, ConfState, Entry, EntryType, Message};
+use raft::{Config, RawNode, StateRole, storage::Storage};
+use sledlite_core::engine::Engine;
+
+use crate::{command::{Command, SingleOp, pb}, log_store::RaftLogStore};
+use prost::Message as _;
+use protobuf::Message as _;
 use slog::{Drain, Logger};
 use slog_async;
 use slog_term;
 
-fn create_logger() -> Logger {
+/**
+ * Logger raft nodes use unless one is injected, writes to stdout.
+ */
+pub(crate) fn create_logger() -> Logger {
     let decorator = slog_term::PlainDecorator::new(std::io::stdout());
     let drain = slog_term::FullFormat::new(decorator).build().fuse();
     let drain = slog_async::Async::new(drain).build().fuse();
     Logger::root(drain, slog::o!())
 }
+/**
+ * Raft timing of a region, in ticks of `Region::tick`.
+ * * `election_tick` - ticks a follower waits for the leader before starting an election,
+ *   has to be larger than `heartbeat_tick`.
+ * * `heartbeat_tick` - ticks between the heartbeats of a leader.
+ * * `max_size_per_msg` - byte limit of the entries in one append message, 0 sends one
+ *   entry per message.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionConfig { 
+    pub election_tick: u64,
+    pub heartbeat_tick: u64,
+    pub max_size_per_msg: u64
+}
+
+impl Default for RegionConfig { 
+    fn default() -> Self { 
+        Self { election_tick: 10, heartbeat_tick: 3, max_size_per_msg: 0 }
+    }
+}
+
 pub struct Region { 
     pub id: u64,
-    pub raft: RawNode<MemStorage>
+    pub raft: RawNode<RaftLogStore>,
+    pub start_key: Vec<u8>, // first key the region owns
+    pub end_key: Vec<u8>, // first key past the region, empty for no upper bound
+    engine: Option<Arc<Mutex<Engine>>>, // state machine, committed commands are only printed without one
+    applied_index: u64,
+    outbox: Option<Sender<(u64, Vec<Message>)>>, // outbound raft messages tagged with this region id
+    pending: HashMap<u64, Sender<bool>> // proposal context -> waiter, resolved once the entry is applied
 }
 
 impl Region { 
     pub fn new(id: u64) -> Self { 
-        let storage = MemStorage::new_with_conf_state(
-            (vec![id], vec![])
-        );
+        Self::new_with_config(id, RegionConfig::default())
+    }
+
+    /**
+     * Creates a single peer region with the raft timing of `cfg`.
+     */
+    pub fn new_with_config(id: u64, cfg: RegionConfig) -> Self { 
+        Self::with_peers_and_config(id, vec![id], cfg)
+    }
+
+    /**
+     * Creates a region whose raft group starts out with `peer_ids` as voters.
+     * * `id` doubles as the raft peer id of this region.
+     */
+    pub fn with_peers(id: u64, peer_ids: Vec<u64>) -> Self { 
+        Self::with_peers_and_config(id, peer_ids, RegionConfig::default())
+    }
+
+    /**
+     * Creates a single peer region whose raft node logs to `logger`.
+     */
+    pub fn with_logger(id: u64, logger: Logger) -> Self { 
+        Self::with_peers_config_and_logger(id, vec![id], RegionConfig::default(), logger)
+    }
+
+    /**
+     * Creates a region like `with_peers` with the raft timing of `region_cfg`.
+     */
+    pub fn with_peers_and_config(id: u64, peer_ids: Vec<u64>, region_cfg: RegionConfig) -> Self { 
+        Self::with_peers_config_and_logger(id, peer_ids, region_cfg, create_logger())
+    }
+
+    /**
+     * Creates a region like `with_peers_and_config` whose raft node logs to `logger`.
+     */
+    pub fn with_peers_config_and_logger(id: u64, peer_ids: Vec<u64>, region_cfg: RegionConfig, logger: Logger) -> Self { 
+        Self::with_log_store(id, peer_ids, region_cfg, logger, RaftLogStore::new())
+    }
+
+    /**
+     * Creates a region like `with_peers_config_and_logger` on top of `storage`, e.g. a log
+     * reopened with `RaftLogStore::open`, so the raft log of the region survives restarts.
+     * * `peer_ids` only seed the voters of a new log, the voters persisted in `storage`
+     *   win otherwise.
+     * * The applied index is not persisted, every committed entry still in the log is
+     *   applied to the state machine again after a restart.
+     */
+    pub fn with_log_store(id: u64, peer_ids: Vec<u64>, region_cfg: RegionConfig, logger: Logger, storage: RaftLogStore) -> Self { 
+        if storage.initial_state().unwrap().conf_state.voters.is_empty() { 
+            let mut conf_state = ConfState::default();
+            conf_state.set_voters(peer_ids);
+            storage.set_conf_state(conf_state).unwrap();
+        }
         let cfg = Config { 
             id,
-            election_tick: 10,
-            heartbeat_tick: 3,
+            election_tick: region_cfg.election_tick as usize,
+            heartbeat_tick: region_cfg.heartbeat_tick as usize,
+            max_size_per_msg: region_cfg.max_size_per_msg,
             ..Default::default()
         };
-        let logger = create_logger();
         let raft = RawNode::new(&cfg, storage, &logger).unwrap();
+        // entries folded into a snapshot of the log count as applied
+        let applied_index = raft.raft.raft_log.applied;
         
         Self { 
             id,
-            raft
+            raft,
+            start_key: Vec::new(),
+            end_key: Vec::new(),
+            engine: None,
+            applied_index,
+            outbox: None,
+            pending: HashMap::new()
+        }
+    }
+
+    /**
+     * Creates a region whose committed commands are applied to `engine`.
+     * * Raft snapshots of the region are checkpoints of `engine`, written under its
+     *   `checkpoints` directory.
+     */
+    pub fn new_with_engine(id: u64, engine: Engine) -> Self { 
+        Self::new(id).with_engine(engine)
+    }
+
+    /**
+     * Attaches `engine` as the state machine of the region, see `new_with_engine`.
+     */
+    pub fn with_engine(mut self, engine: Engine) -> Self { 
+        let snapshot_dir = engine.config().dir.join("checkpoints");
+        let engine = Arc::new(Mutex::new(engine));
+        self.raft.store().attach_state_machine(engine.clone(), snapshot_dir);
+        self.engine = Some(engine);
+        self
+    }
+
+    /**
+     * Restricts the region to the keys in `[start_key, end_key)`, an empty `end_key` has
+     * no upper bound. A new region owns every key.
+     */
+    pub fn with_key_range(mut self, start_key: Vec<u8>, end_key: Vec<u8>) -> Self { 
+        self.start_key = start_key;
+        self.end_key = end_key;
+        self
+    }
+
+    /**
+     * Part of `[start, end)` the region owns, `None` if they do not overlap.
+     */
+    pub fn overlap(&self, start: &[u8], end: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> { 
+        let start = start.max(&self.start_key);
+        let end = match self.end_key.is_empty() { 
+            true => end,
+            false => end.min(&self.end_key)
+        };
+        (start < end).then(|| (start.to_vec(), end.to_vec()))
+    }
+
+    /**
+     * Routes the raft messages this region produces into `outbox`.
+     * * Without an outbox messages are dropped, which is only fine for single peer groups.
+     */
+    pub fn connect(&mut self, outbox: Sender<(u64, Vec<Message>)>) { 
+        self.outbox = Some(outbox);
+    }
+
+    /**
+     * Feeds a raft message from another peer into this region.
+     */
+    pub fn step(&mut self, msg: Message) { 
+        if let Err(err) = self.raft.step(msg) { 
+            tracing::warn!(region = self.id, error = ?err, "dropped raft message");
         }
     }
 
+    fn send(&self, messages: Vec<Message>) { 
+        if messages.is_empty() { 
+            return;
+        }
+        if let Some(outbox) = self.outbox.as_ref() { 
+            let _ = outbox.send((self.id, messages));
+        }
+    }
+
+    pub fn engine(&self) -> Option<MutexGuard<'_, Engine>> { 
+        self.engine.as_ref().map(|engine| engine.lock().unwrap())
+    }
+
+    pub fn applied_index(&self) -> u64 { 
+        self.applied_index
+    }
+
+    pub fn is_leader(&self) -> bool { 
+        self.raft.raft.state == StateRole::Leader
+    }
+
+    /**
+     * Peer id of the leader of the raft group as this region knows it, `None` while no
+     * leader is known, e.g. during an election.
+     */
+    pub fn leader(&self) -> Option<u64> { 
+        Some(self.raft.raft.leader_id).filter(|id| *id != raft::INVALID_ID)
+    }
+
+    /**
+     * Voters of the raft group as of the last conf change applied by this region.
+     */
+    pub fn voters(&self) -> Vec<u64> { 
+        self.raft.store().initial_state().map(|state| state.conf_state.voters).unwrap_or_default()
+    }
+
+    /**
+     * Asks the raft group to hand leadership to the peer `target_id`.
+     * * Only has an effect on the leader. The handoff happens over the next rounds of
+     *   messages, the caller has to keep ticking until `leader` reports `target_id`.
+     */
+    pub fn transfer_leadership(&mut self, target_id: u64) { 
+        self.raft.transfer_leader(target_id);
+    }
+
+    /**
+     * Proposes adding or removing a peer of the raft group.
+     * * Only the leader accepts it. `on_ready` applies the change once it is committed and
+     *   persists the new conf state, see `voters`.
+     */
+    pub fn propose_config_change(&mut self, cc: ConfChange) -> raft::Result<()> { 
+        self.raft.propose_conf_change(vec![], cc)
+    }
+
+    /**
+     * Tears the region down and hands back its engine, or `None` if it has no engine.
+     */
+    pub fn into_engine(self) -> Option<Engine> { 
+        let Region { raft, engine, .. } = self;
+        // the log store holds the other reference to the engine
+        drop(raft);
+        engine.and_then(|engine| Arc::try_unwrap(engine).ok())
+            .map(|engine| engine.into_inner().unwrap())
+    }
+
     pub fn tick(&mut self) { 
         self.raft.tick();
     }
-    pub fn propose(&mut self, cmd: Command) { 
-        let encoded = cmd.encode();
-        self.raft.propose(vec![], encoded).unwrap();
+
+    /**
+     * Proposes `cmd` without waiting for it, see `propose_with_context`.
+     * * Fails when raft drops the proposal, e.g. because the group has no leader yet.
+     */
+    pub fn propose(&mut self, cmd: Command) -> raft::Result<()> { 
+        let encoded = pb::Command::from(cmd).encode_to_vec();
+        self.raft.propose(vec![], encoded)
+    }
+
+    /**
+     * Proposes `cmd` tagged with `context` and signals `done` once the entry is applied here.
+     * * `done` receives whether the command took effect, which is only `false` for a
+     *   `Command::Cas` whose expected value did not match, and for a `Command::Merge` that
+     *   was rolled back because one of its ops failed.
+     * * Fails without registering `done` when raft drops the proposal, e.g. because this
+     *   peer is not the leader.
+     */
+    pub fn propose_with_context(&mut self, cmd: Command, context: u64, done: Sender<bool>) -> raft::Result<()> { 
+        self.raft.propose(context.to_be_bytes().to_vec(), pb::Command::from(cmd).encode_to_vec())?;
+        self.pending.insert(context, done);
+        Ok(())
+    }
+
+    /**
+     * Drops the waiter `propose_with_context` registered for `context`, e.g. once the
+     * caller stopped waiting. The command itself may still be applied.
+     */
+    pub fn forget_proposal(&mut self, context: u64) { 
+        self.pending.remove(&context);
+    }
+
+    /**
+     * Number of proposals whose waiter has not been signalled yet.
+     */
+    pub fn pending_proposals(&self) -> usize { 
+        self.pending.len()
     }
 
+    /**
+     * Drives one round of the raft ready loop.
+     * * New entries and the hard state are persisted to the log store before the ready is
+     *   advanced, then committed entries are applied to the state machine in log order.
+     * * When this peer stops being the leader the waiters of its proposals are dropped,
+     *   their entries may never be committed.
+     */
     pub fn on_ready(&mut self) {
         if !self.raft.has_ready() {
             return;
         }
 
-        let ready = self.raft.ready();
+        let mut ready = self.raft.ready();
+        let stepped_down = ready.ss().is_some_and(|ss| ss.raft_state != StateRole::Leader);
 
-        if !ready.messages().is_empty() {
-            // ignore networking for now
+        self.send(ready.take_messages());
+
+        if !ready.snapshot().is_empty() { 
+            self.raft.store().apply_snapshot(ready.snapshot().clone()).expect("applying raft snapshot failed");
+            self.applied_index = ready.snapshot().get_metadata().index;
+        }
+        self.apply_committed(ready.take_committed_entries());
+        if !ready.entries().is_empty() { 
+            self.raft.store().append(ready.entries()).expect("persisting raft entries failed");
+        }
+        if let Some(hard_state) = ready.hs() { 
+            self.raft.store().set_hard_state(hard_state.clone()).expect("persisting raft hard state failed");
         }
+        self.send(ready.take_persisted_messages());
 
-        for entry in ready.committed_entries() {
-            if entry.data.is_empty() {
-                continue;
+        let mut light_ready = self.raft.advance(ready);
+        if let Some(commit) = light_ready.commit_index() { 
+            self.raft.store().set_commit(commit).expect("persisting raft hard state failed");
+        }
+        self.send(light_ready.take_messages());
+        self.apply_committed(light_ready.take_committed_entries());
+        self.raft.store().set_applied_index(self.applied_index);
+        self.raft.advance_apply_to(self.applied_index);
+        if stepped_down { 
+            self.pending.clear();
+        }
+    }
+
+    fn apply_committed(&mut self, entries: Vec<Entry>) { 
+        for entry in entries {
+            self.applied_index = entry.index;
+            let applied = match entry.get_entry_type() { 
+                EntryType::EntryNormal => self.apply_entry(&entry),
+                _ => { 
+                    self.apply_conf_change(&entry);
+                    true
+                }
+            };
+            let waiter = <[u8; 8]>::try_from(&entry.context[..]).ok()
+                .and_then(|context| self.pending.remove(&u64::from_be_bytes(context)));
+            if let Some(waiter) = waiter { 
+                let _ = waiter.send(applied);
             }
+        }
+    }
 
-            let cmd = Command::decode(&entry.data);
-            println!("Region {} applied: {:?}", self.id, cmd);
+    /**
+     * Applies a committed conf change to the raft group and persists the new conf state.
+     */
+    fn apply_conf_change(&mut self, entry: &Entry) { 
+        if entry.data.is_empty() { 
+            return;
         }
+        let applied = match entry.get_entry_type() { 
+            EntryType::EntryConfChange => ConfChange::parse_from_bytes(&entry.data)
+                .map_err(raft::Error::from)
+                .and_then(|cc| self.raft.apply_conf_change(&cc)),
+            _ => ConfChangeV2::parse_from_bytes(&entry.data)
+                .map_err(raft::Error::from)
+                .and_then(|cc| self.raft.apply_conf_change(&cc))
+        };
+        match applied { 
+            Ok(conf_state) => self.raft.store().set_conf_state(conf_state).expect("persisting raft conf state failed"),
+            Err(err) => tracing::warn!(region = self.id, index = entry.index, error = ?err, "skipped conf change")
+        }
+    }
 
-        self.raft.advance(ready);
+    /**
+     * Applies a committed command to the engine.
+     * * # Returns
+     * * `false` if the command was a `Command::Cas` that did not write, `true` otherwise.
+     */
+    fn apply_entry(&self, entry: &Entry) -> bool { 
+        if entry.data.is_empty() {
+            return true;
+        }
+
+        let cmd = match Command::try_from(&entry.data[..]) { 
+            Ok(cmd) => cmd,
+            Err(err) => { 
+                tracing::warn!(region = self.id, index = entry.index, error = ?err, "skipped undecodable entry");
+                return true;
+            }
+        };
+        match self.engine.as_ref() { 
+            Some(engine) => { 
+                let mut engine = engine.lock().unwrap();
+                match cmd { 
+                    Command::Put { key, val } => engine.put(&key, &val).map(|_| true),
+                    Command::Delete { key } => engine.delete(&key).map(|_| true),
+                    Command::Merge { ops } => match apply_merge(&mut engine, ops) { 
+                        Ok(()) => Ok(true),
+                        Err(err) => { 
+                            tracing::warn!(region = self.id, index = entry.index, error = ?err, "rolled back merge");
+                            Ok(false)
+                        }
+                    },
+                    Command::ReadBarrier { .. } => Ok(true),
+                    Command::Cas { key, expected, new_val } => engine.get(&key).and_then(|current| match current == Some(expected) { 
+                        true => engine.put(&key, &new_val).map(|_| true),
+                        false => Ok(false)
+                    })
+                }.expect("applying a committed command failed")
+            },
+            None => { 
+                tracing::debug!(region = self.id, index = entry.index, ?cmd, "applied without an engine");
+                true
+            }
+        }
+    }
+}
+
+/**
+ * Applies every op of a merge or none of them.
+ * * Ops are applied in order while recording how to undo each one. When an op fails the
+ *   applied ones are undone in reverse order and the error is returned.
+ * * The undo only covers failed ops. A crash in the middle of a merge can leave part of
+ *   it applied, the region does not replay the entry on restart.
+ */
+fn apply_merge(engine: &mut Engine, ops: Vec<SingleOp>) -> std::io::Result<()> { 
+    let mut undo: Vec<SingleOp> = Vec::with_capacity(ops.len());
+    for op in ops { 
+        let applied = match op { 
+            SingleOp::Put { key, val } => engine.put(&key, &val).map(|old| match old { 
+                Some(old) => Some(SingleOp::Put { key, val: old }),
+                None => Some(SingleOp::Delete { key })
+            }),
+            SingleOp::Delete { key } => engine.delete(&key).map(|old| old.map(|old| SingleOp::Put { key, val: old }))
+        };
+        match applied { 
+            Ok(Some(inverse)) => undo.push(inverse),
+            Ok(None) => {},
+            Err(err) => { 
+                for inverse in undo.into_iter().rev() { 
+                    match inverse { 
+                        SingleOp::Put { key, val } => engine.put(&key, &val).map(|_| ()),
+                        SingleOp::Delete { key } => engine.delete(&key).map(|_| ())
+                    }?;
+                }
+                return Err(err);
+            }
+        }
     }
-}
\ No newline at end of file
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::{fs::remove_dir_all, path::PathBuf};
+
+use crossbeam_channel::bounded;
+use raft::{GetEntriesContext, storage::Storage};
+use sledlite_core::engine::{Config, Engine, EngineMode};
+use slog::Logger;
+
+use crate::{command::{Command, SingleOp}, log_store::RaftLogStore, region::{Region, RegionConfig}};
+
+/**
+ * Short timeouts so tests elect a leader in a handful of ticks.
+ */
+pub fn fast_config() -> RegionConfig { 
+    RegionConfig { election_tick: 3, heartbeat_tick: 1, ..RegionConfig::default() }
+}
+
+/**
+ * Logger dropping every record, keeps tests free of raft log noise.
+ */
+pub fn silent_logger() -> Logger { 
+    Logger::root(slog::Discard, slog::o!())
+}
+
+fn quiet_region(id: u64) -> Region { 
+    Region::with_peers_config_and_logger(id, vec![id], fast_config(), silent_logger())
+}
+
+fn run(region: &mut Region, ticks: usize) { 
+    for _ in 0..ticks { 
+        region.tick();
+        region.on_ready();
+    }
+}
+
+#[test]
+pub fn region_test_applies_committed_put_to_engine() { 
+    let dir = PathBuf::from("./temp-region-apply");
+    let _ = remove_dir_all(&dir);
+    let engine = Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).unwrap();
+    let mut region = quiet_region(1).with_engine(engine);
+    run(&mut region, 10);
+    region.propose(Command::Put { key: b"k1".to_vec(), val: b"v1".to_vec() }).unwrap();
+    region.propose(Command::Put { key: b"k2".to_vec(), val: b"v2".to_vec() }).unwrap();
+    region.propose(Command::Delete { key: b"k2".to_vec() }).unwrap();
+    run(&mut region, 10);
+    assert_eq!(region.applied_index(), region.raft.raft.raft_log.committed);
+    let mut engine = region.engine().unwrap();
+    assert_eq!(engine.get(b"k1").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(engine.get(b"k2").unwrap(), None);
+    drop(engine);
+    drop(region);
+    let _ = remove_dir_all(&dir);
+}
+
+#[test]
+pub fn region_test_snapshot_restores_state_machine() { 
+    let dir = PathBuf::from("./temp-region-snapshot");
+    let restored_dir = PathBuf::from("./temp-region-snapshot-restored");
+    let _ = remove_dir_all(&dir);
+    let _ = remove_dir_all(&restored_dir);
+    let mut region = quiet_region(1).with_engine(Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).unwrap());
+    run(&mut region, 10);
+    for i in 0..20 { 
+        region.propose(Command::Put { key: format!("key-{}", i).into_bytes(), val: format!("val-{}", i).into_bytes() }).unwrap();
+    }
+    run(&mut region, 10);
+    let applied = region.applied_index();
+    let snapshot = region.raft.store().snapshot(applied, 0).expect("snapshot failed");
+    assert_eq!(snapshot.get_metadata().index, applied);
+
+    // a region with an empty log and an empty engine catches up from the snapshot alone
+    let restored = quiet_region(1).with_engine(Engine::open(Config { dir: restored_dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).unwrap());
+    restored.raft.store().apply_snapshot(snapshot).expect("apply snapshot failed");
+    let store = restored.raft.store();
+    assert_eq!(store.first_index().unwrap(), applied + 1);
+    assert_eq!(store.last_index().unwrap(), applied);
+    assert!(store.entries(1, applied, None, GetEntriesContext::empty(false)).is_err());
+    assert_eq!(store.initial_state().unwrap().hard_state.commit, applied);
+    let mut engine = restored.engine().unwrap();
+    for i in 0..20 { 
+        assert_eq!(engine.get(format!("key-{}", i).as_bytes()).unwrap(), Some(format!("val-{}", i).into_bytes()));
+    }
+    drop(engine);
+    drop(restored);
+    drop(region);
+    let _ = remove_dir_all(&dir);
+    let _ = remove_dir_all(&restored_dir);
+}
+
+#[test]
+pub fn region_test_merge_applies_all_or_nothing() { 
+    let dir = PathBuf::from("./temp-region-merge");
+    let _ = remove_dir_all(&dir);
+    let engine = Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).unwrap();
+    let mut region = quiet_region(1).with_engine(engine);
+    run(&mut region, 10);
+    region.propose(Command::Put { key: b"k0".to_vec(), val: b"old".to_vec() }).unwrap();
+    let (applied_done, applied) = bounded(1);
+    let (rolled_back_done, rolled_back) = bounded(1);
+    region.propose_with_context(Command::Merge { ops: vec![
+        SingleOp::Put { key: b"k1".to_vec(), val: b"v1".to_vec() },
+        SingleOp::Put { key: b"k2".to_vec(), val: b"v2".to_vec() },
+        SingleOp::Put { key: b"k3".to_vec(), val: b"v3".to_vec() },
+        SingleOp::Delete { key: b"k0".to_vec() },
+        SingleOp::Put { key: b"k4".to_vec(), val: b"v4".to_vec() }
+    ]}, 1, applied_done).unwrap();
+    // the empty key fails the last op, so the merge has to be rolled back as a whole
+    region.propose_with_context(Command::Merge { ops: vec![
+        SingleOp::Put { key: b"k1".to_vec(), val: b"changed".to_vec() },
+        SingleOp::Delete { key: b"k2".to_vec() },
+        SingleOp::Put { key: b"k5".to_vec(), val: b"v5".to_vec() },
+        SingleOp::Put { key: b"k0".to_vec(), val: b"back".to_vec() },
+        SingleOp::Put { key: Vec::new(), val: b"bad".to_vec() }
+    ]}, 2, rolled_back_done).unwrap();
+    run(&mut region, 10);
+    assert_eq!(applied.try_recv(), Ok(true));
+    assert_eq!(rolled_back.try_recv(), Ok(false));
+    let mut engine = region.engine().unwrap();
+    assert_eq!(engine.get(b"k0").unwrap(), None);
+    for i in 1..5 { 
+        assert_eq!(engine.get(format!("k{}", i).as_bytes()).unwrap(), Some(format!("v{}", i).into_bytes()));
+    }
+    assert_eq!(engine.get(b"k5").unwrap(), None);
+    drop(engine);
+    drop(region);
+    let _ = remove_dir_all(&dir);
+}
+
+#[test]
+pub fn region_test_only_one_of_two_racing_cas_wins() { 
+    let dir = PathBuf::from("./temp-region-cas");
+    let _ = remove_dir_all(&dir);
+    let engine = Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).unwrap();
+    let mut region = quiet_region(1).with_engine(engine);
+    run(&mut region, 10);
+    region.propose(Command::Put { key: b"counter".to_vec(), val: b"0".to_vec() }).unwrap();
+    run(&mut region, 10);
+
+    // both clients read "0" and race to replace it, the one ordered second by raft loses
+    let (first_done, first) = bounded(1);
+    let (second_done, second) = bounded(1);
+    region.propose_with_context(Command::Cas { key: b"counter".to_vec(), expected: b"0".to_vec(), new_val: b"1-first".to_vec() }, 1, first_done).unwrap();
+    region.propose_with_context(Command::Cas { key: b"counter".to_vec(), expected: b"0".to_vec(), new_val: b"1-second".to_vec() }, 2, second_done).unwrap();
+    run(&mut region, 10);
+    assert_eq!(first.try_recv(), Ok(true));
+    assert_eq!(second.try_recv(), Ok(false));
+
+    // a missing key never matches, not even an empty expected value
+    let (missing_done, missing) = bounded(1);
+    region.propose_with_context(Command::Cas { key: b"missing".to_vec(), expected: Vec::new(), new_val: b"v".to_vec() }, 3, missing_done).unwrap();
+    run(&mut region, 10);
+    assert_eq!(missing.try_recv(), Ok(false));
+
+    let mut engine = region.engine().unwrap();
+    assert_eq!(engine.get(b"counter").unwrap(), Some(b"1-first".to_vec()));
+    assert_eq!(engine.get(b"missing").unwrap(), None);
+    drop(engine);
+    drop(region);
+    let _ = remove_dir_all(&dir);
+}
+
+#[test]
+pub fn region_test_log_store_survives_restart() { 
+    let log_dir = PathBuf::from("./temp-region-restart-log");
+    let dir = PathBuf::from("./temp-region-restart");
+    let _ = remove_dir_all(&log_dir);
+    let _ = remove_dir_all(&dir);
+    let config = |dir: &PathBuf| Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let open = || { 
+        let storage = RaftLogStore::open(Engine::open(config(&log_dir)).unwrap(), 1).unwrap();
+        Region::with_log_store(1, vec![1], fast_config(), silent_logger(), storage)
+            .with_engine(Engine::open(config(&dir)).unwrap())
+    };
+    let mut region = open();
+    run(&mut region, 10);
+    for i in 0..5 { 
+        region.propose(Command::Put { key: format!("key-{}", i).into_bytes(), val: format!("val-{}", i).into_bytes() }).unwrap();
+    }
+    run(&mut region, 10);
+    let last_index = region.raft.raft.raft_log.last_index();
+    let term = region.raft.raft.term;
+    drop(region);
+
+    // the log, the commit index and the term come back from the log store
+    let mut region = open();
+    assert_eq!(region.raft.raft.raft_log.last_index(), last_index);
+    assert_eq!(region.raft.raft.raft_log.committed, last_index);
+    assert_eq!(region.raft.raft.term, term);
+    assert_eq!(region.voters(), vec![1]);
+    run(&mut region, 10);
+    assert!(region.raft.raft.term > term);
+    region.propose(Command::Put { key: b"key-5".to_vec(), val: b"val-5".to_vec() }).unwrap();
+    run(&mut region, 10);
+    assert_eq!(region.applied_index(), region.raft.raft.raft_log.committed);
+    let mut engine = region.engine().unwrap();
+    for i in 0..6 { 
+        assert_eq!(engine.get(format!("key-{}", i).as_bytes()).unwrap(), Some(format!("val-{}", i).into_bytes()));
+    }
+    drop(engine);
+    drop(region);
+    let _ = remove_dir_all(&log_dir);
+    let _ = remove_dir_all(&dir);
+}
@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+/**
+ * Maps keys to the region owning them.
+ * * Regions split the key space into consecutive ranges, so a range is known by its first
+ *   key alone: a key belongs to the region registered under the greatest start key not
+ *   above it. Peers of one raft group share a range, the router names one of them.
+ */
+#[derive(Debug, Default, Clone)]
+pub struct RegionKeyRouter { 
+    ranges: BTreeMap<Vec<u8>, u64> // range start key -> region id
+}
+
+impl RegionKeyRouter { 
+    pub fn new() -> Self { 
+        Self::default()
+    }
+
+    /**
+     * Routes the keys from `start_key` up to the next registered start key to `region_id`,
+     * replacing the region registered there before.
+     */
+    pub fn insert(&mut self, start_key: Vec<u8>, region_id: u64) { 
+        self.ranges.insert(start_key, region_id);
+    }
+
+    /**
+     * Whether a range starts exactly at `start_key`.
+     */
+    pub fn contains(&self, start_key: &[u8]) -> bool { 
+        self.ranges.contains_key(start_key)
+    }
+
+    /**
+     * Points every range routed to `from` at `to`, or drops them with `None`. Keys of a
+     * dropped range fall to the range before it.
+     */
+    pub fn reassign(&mut self, from: u64, to: Option<u64>) { 
+        match to { 
+            Some(to) => self.ranges.values_mut().filter(|id| **id == from).for_each(|id| *id = to),
+            None => self.ranges.retain(|_, id| *id != from)
+        }
+    }
+
+    /**
+     * Region owning `key`, `None` if `key` sorts before every registered range.
+     */
+    pub fn route(&self, key: &[u8]) -> Option<u64> { 
+        self.ranges.range::<[u8], _>((Bound::Unbounded, Bound::Included(key))).next_back().map(|(_, id)| *id)
+    }
+}
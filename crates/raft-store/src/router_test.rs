@@ -0,0 +1,23 @@
+use crate::router::RegionKeyRouter;
+
+#[test]
+pub fn router_test_routes_keys_to_the_range_they_fall_in() { 
+    let mut router = RegionKeyRouter::new();
+    assert_eq!(router.route(b"anything"), None);
+    router.insert(b"b".to_vec(), 1);
+    router.insert(b"m".to_vec(), 2);
+    assert_eq!(router.route(b"a"), None);
+    assert_eq!(router.route(b"b"), Some(1));
+    assert_eq!(router.route(b"lzzz"), Some(1));
+    assert_eq!(router.route(b"m"), Some(2));
+    assert_eq!(router.route(b"zzz"), Some(2));
+
+    router.insert(Vec::new(), 3);
+    assert_eq!(router.route(b""), Some(3));
+    assert_eq!(router.route(b"a"), Some(3));
+    router.reassign(2, Some(4));
+    assert_eq!(router.route(b"x"), Some(4));
+    router.reassign(4, None);
+    assert!(!router.contains(b"m"));
+    assert_eq!(router.route(b"x"), Some(1));
+}
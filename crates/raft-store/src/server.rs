@@ -0,0 +1,88 @@
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use crate::command::Command;
+use crate::store::RaftStore;
+
+/**
+ * Key value front end over the regions of a `RaftStore`, with linearizable reads and
+ * writes.
+ * * Every request is routed to the region that owns the key and leads its raft group.
+ *   Writes return once the leader applied them. Reads propose a `Command::ReadBarrier` and
+ *   read the leader's engine once the barrier is applied, so they see every write committed
+ *   before the read started, even when the leader has just changed.
+ * * Requests drive the store until they complete. `tick` has to be called periodically in
+ *   between to keep elections and heartbeats going.
+ */
+pub struct KvServer { 
+    store: RaftStore,
+    timeout: Duration, // per request, see `RaftStore::propose_and_wait`
+    next_nonce: u64
+}
+
+impl KvServer { 
+    pub fn new(store: RaftStore, timeout: Duration) -> Self { 
+        Self { store, timeout, next_nonce: 1 }
+    }
+
+    pub fn store(&mut self) -> &mut RaftStore { 
+        &mut self.store
+    }
+
+    pub fn tick(&mut self) { 
+        self.store.tick_all();
+    }
+
+    /**
+     * Writes `val` under `key` and waits until the write is applied.
+     * * # Returns
+     * * `Err(NotFound)` if no region in the store leads the group owning `key`.
+     * * `Err(TimedOut)` if the write was not applied within the timeout, it may still be
+     *   applied later.
+     */
+    pub fn put(&mut self, key: &[u8], val: &[u8]) -> std::io::Result<()> { 
+        self.propose(key, Command::Put { key: key.to_vec(), val: val.to_vec() }).map(|_| ())
+    }
+
+    /**
+     * Deletes `key` and waits until the delete is applied, see `put`.
+     */
+    pub fn delete(&mut self, key: &[u8]) -> std::io::Result<()> { 
+        self.propose(key, Command::Delete { key: key.to_vec() }).map(|_| ())
+    }
+
+    /**
+     * Value of `key` as of a point after every write committed before the call, see `put`
+     * for the errors.
+     */
+    pub fn get(&mut self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> { 
+        let nonce = self.next_nonce;
+        self.next_nonce += 1;
+        let leader = self.propose(key, Command::ReadBarrier { nonce })?;
+        let mut engine = self.store.regions[&leader].engine()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("region {} has no engine to read", leader)))?;
+        engine.get(key)
+    }
+
+    /**
+     * Proposes `cmd` to the leader owning `key` and waits until it is applied.
+     * * # Returns
+     * * `Ok(u64)` with the id of the leader that applied `cmd`.
+     */
+    fn propose(&mut self, key: &[u8], cmd: Command) -> std::io::Result<u64> { 
+        let leader = self.leader_for(key)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no leader for key {}", String::from_utf8_lossy(key))))?;
+        self.store.propose_and_wait(leader, cmd, self.timeout)?;
+        Ok(leader)
+    }
+
+    /**
+     * Region owning `key` that leads its raft group.
+     */
+    fn leader_for(&self, key: &[u8]) -> Option<u64> { 
+        let next = [key, &[0]].concat();
+        self.store.regions.values()
+            .find(|region| region.is_leader() && region.overlap(key, &next).is_some())
+            .map(|region| region.id)
+    }
+}
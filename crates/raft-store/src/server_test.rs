@@ -0,0 +1,37 @@
+use std::{fs::remove_dir_all, path::PathBuf, time::Duration};
+
+use sledlite_core::engine::{Config, Engine, EngineMode};
+
+use crate::{command::Command, region::Region, region_test::{fast_config, silent_logger}, server::KvServer, store::RaftStore};
+
+#[test]
+pub fn server_test_get_sees_put_despite_concurrent_proposals() { 
+    let dirs: Vec<PathBuf> = (1..=3).map(|id| PathBuf::from(format!("./temp-server-{}", id))).collect();
+    let mut store = RaftStore::new();
+    store.set_logger(silent_logger());
+    for (id, dir) in (1..=3).zip(&dirs) { 
+        let _ = remove_dir_all(dir);
+        let engine = Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).unwrap();
+        store.insert_region(Region::with_peers_config_and_logger(id, vec![1, 2, 3], fast_config(), silent_logger()).with_engine(engine));
+    }
+    let mut server = KvServer::new(store, Duration::from_secs(1));
+    for _ in 0..30 { 
+        server.tick();
+    }
+    for i in 0..20 { 
+        let key = format!("key-{}", i).into_bytes();
+        // other clients keep proposing to every peer while the writes and reads go through
+        for id in 1..=3 { 
+            server.store().propose(id, Command::Put { key: format!("other-{}", id).into_bytes(), val: vec![i] }).unwrap();
+        }
+        server.put(&key, format!("val-{}", i).as_bytes()).unwrap();
+        assert_eq!(server.get(&key).unwrap(), Some(format!("val-{}", i).into_bytes()));
+    }
+    server.delete(b"key-3").unwrap();
+    assert_eq!(server.get(b"key-3").unwrap(), None);
+    assert_eq!(server.get(b"other-1").unwrap(), Some(vec![19]));
+    drop(server);
+    for dir in dirs { 
+        let _ = remove_dir_all(dir);
+    }
+}
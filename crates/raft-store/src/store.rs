@@ -1,34 +1,346 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::remove_dir_all;
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, Instant};
 
-use crate::region::Region;
+use crossbeam_channel::{Receiver, Sender, TryRecvError, bounded, unbounded};
+use raft::eraftpb::{ConfChange, ConfChangeType, Message};
+use sledlite_core::engine::{Config, Engine};
+
+use slog::Logger;
+
+use crate::region::{Region, RegionConfig, create_logger};
+use crate::router::RegionKeyRouter;
 use crate::command::Command;
 
+/**
+ * Upper bound on the `tick_all` rounds spent waiting for a raft group to reach a state.
+ */
+const MAX_WAIT_TICKS: usize = 500;
+
+#[derive(Debug, PartialEq)]
+pub enum ProposeError { 
+    Timeout,
+    RegionNotFound,
+    NotLeader,
+    CompareFailed,
+    MergeRolledBack
+}
+
+impl From<ProposeError> for Error { 
+    fn from(err: ProposeError) -> Self { 
+        match err { 
+            ProposeError::Timeout => Error::new(ErrorKind::TimedOut, "proposal was not applied in time"),
+            ProposeError::RegionNotFound => Error::new(ErrorKind::NotFound, "region does not exist"),
+            ProposeError::NotLeader => Error::other("region is not the leader of its group"),
+            ProposeError::CompareFailed => Error::other("compare-and-swap found a different value"),
+            ProposeError::MergeRolledBack => Error::other("merge was rolled back, none of its ops took effect")
+        }
+    }
+}
+
 pub struct RaftStore {
-    pub regions: HashMap<u64, Region>
+    pub regions: HashMap<u64, Region>,
+    pub router: RegionKeyRouter, // key -> region, see `propose_keyed`
+    next_id: u64, // smallest id above every region created so far
+    next_context: u64, // tags proposals made through propose_and_wait
+    logger: Option<Logger>, // given to the regions the store creates, stdout if unset
+    sender: Sender<(u64, Vec<Message>)>,
+    receiver: Receiver<(u64, Vec<Message>)>
+}
+
+impl Default for RaftStore { 
+    fn default() -> Self { 
+        Self::new()
+    }
 }
 
 impl RaftStore {
     pub fn new() -> Self {
+        let (sender, receiver) = unbounded();
         Self {
             regions: HashMap::new(),
+            router: RegionKeyRouter::new(),
+            next_id: 1,
+            next_context: 1,
+            logger: None,
+            sender,
+            receiver
         }
     }
 
-    pub fn create_region(&mut self, region_id: u64) { 
-        let region = Region::new(region_id);
-        self.regions.insert(region_id, region);
+    /**
+     * Sets the logger of the raft nodes of regions created from now on by `create_region`
+     * and `split_region`, instead of a new stdout logger per region.
+     */
+    pub fn set_logger(&mut self, logger: Logger) { 
+        self.logger = Some(logger);
+    }
+
+    fn region_logger(&self) -> Logger { 
+        self.logger.clone().unwrap_or_else(create_logger)
+    }
+
+    /**
+     * Creates a region whose raft group starts out with `peer_ids` as voters.
+     * * Peers of the same group living in this store are separate regions keyed by their
+     *   peer id, and talk to each other through the store.
+     * * `config` defaults to `RegionConfig::default()`.
+     */
+    pub fn create_region(&mut self, region_id: u64, peer_ids: Vec<u64>, config: Option<RegionConfig>) { 
+        let region = Region::with_peers_config_and_logger(region_id, peer_ids, config.unwrap_or_default(), self.region_logger());
+        self.insert_region(region);
+    }
+
+    /**
+     * Adds an already built region and connects it to the store's message channel.
+     * * The router sends the keys of the region's range to it, unless another region, e.g.
+     *   a peer of the same group, already owns a range starting at the same key.
+     */
+    pub fn insert_region(&mut self, mut region: Region) { 
+        region.connect(self.sender.clone());
+        if !self.router.contains(&region.start_key) { 
+            self.router.insert(region.start_key.clone(), region.id);
+        }
+        self.next_id = self.next_id.max(region.id + 1);
+        self.regions.insert(region.id, region);
+    }
+
+    /**
+     * Splits a region into two new single peer regions at `split_key`.
+     * * Keys below `split_key` move to the first returned region, the rest to the second.
+     * * The new engines live next to the old one in `region-{id}` directories and use its
+     *   config otherwise, keeping all their tables in that directory. The old region is
+     *   removed, its directory is left on disk.
+     * * The new regions split the key range of the old one at `split_key`, and the router
+     *   sends the keys of each half to its region.
+     */
+    pub fn split_region(&mut self, region_id: u64, split_key: Vec<u8>) -> std::io::Result<(u64, u64)> { 
+        let region = self.regions.get(&region_id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("region {} does not exist", region_id)))?;
+        let (start_key, end_key) = (region.start_key.clone(), region.end_key.clone());
+        let (config, left, right) = { 
+            let mut engine = region.engine()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("region {} has no engine to split", region_id)))?;
+            let left = engine.scan(&[], &split_key)?;
+            let right = engine.scan_from(&split_key)?;
+            (engine.config().clone(), left, right)
+        };
+        let ids = (self.next_id, self.next_id + 1);
+        let ranges = [(start_key.clone(), split_key.clone()), (split_key.clone(), end_key)];
+        for ((id, entries), (start, end)) in [(ids.0, left), (ids.1, right)].into_iter().zip(ranges) { 
+            let dir = config.dir.with_file_name(format!("region-{}", id));
+            let mut engine = Engine::open(Config { dir, data_dirs: Vec::new(), ..config.clone() })?;
+            for (key, val) in entries { 
+                engine.put(&key, &val)?;
+            }
+            let region = Region::with_logger(id, self.region_logger());
+            self.insert_region(region.with_engine(engine).with_key_range(start, end));
+        }
+        self.regions.remove(&region_id);
+        self.router.reassign(region_id, None);
+        self.router.insert(start_key, ids.0);
+        self.router.insert(split_key, ids.1);
+        Ok(ids)
+    }
+
+    /**
+     * Returns every pair whose key falls in `[start, end)` across all regions, in key order.
+     * * Each region whose key range overlaps `[start, end)` scans its own part of it. Peers
+     *   of one raft group hold the same keys, a key is returned once with the value of the
+     *   lowest region id holding it. Regions without an engine are skipped.
+     * * Reads go straight to the engines, a follower may not have applied the latest
+     *   writes yet.
+     */
+    pub fn multi_region_scan(&mut self, start: &[u8], end: &[u8]) -> std::io::Result<Vec<(Vec<u8>, Vec<u8>)>> { 
+        let mut ids: Vec<u64> = self.regions.keys().copied().collect();
+        ids.sort_unstable();
+        let mut merged = BTreeMap::new();
+        for id in ids { 
+            let region = &self.regions[&id];
+            let (Some((lo, hi)), Some(mut engine)) = (region.overlap(start, end), region.engine()) else { 
+                continue;
+            };
+            for (key, val) in engine.scan(&lo, &hi)? { 
+                merged.entry(key).or_insert(val);
+            }
+        }
+        Ok(merged.into_iter().collect())
     }
 
+    /**
+     * Ticks every region, then delivers the messages they sent to their target regions.
+     * * Delivered messages are handled by the receiver's next `on_ready`.
+     */
     pub fn tick_all(&mut self) {
         for region in self.regions.values_mut() { 
             region.tick();
             region.on_ready();
         }
+        while let Ok((_, messages)) = self.receiver.try_recv() { 
+            for msg in messages { 
+                if let Some(region) = self.regions.get_mut(&msg.to) { 
+                    region.step(msg);
+                }
+            }
+        }
+    }
+
+    /**
+     * Leader of the raft group `region_id` belongs to, as that region knows it. `None` if
+     * the region does not exist or knows no leader.
+     */
+    pub fn region_leader(&self, region_id: u64) -> Option<u64> { 
+        self.regions.get(&region_id).and_then(|region| region.leader())
+    }
+
+    /**
+     * Asks region `region_id`, which has to be the leader of its group, to hand leadership
+     * to the peer `target_id`. Drive `tick_all` until `region_leader` returns `target_id`.
+     */
+    pub fn transfer_region_leadership(&mut self, region_id: u64, target_id: u64) -> std::io::Result<()> { 
+        let region = self.regions.get_mut(&region_id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("region {} does not exist", region_id)))?;
+        region.transfer_leadership(target_id);
+        Ok(())
+    }
+
+    /**
+     * Proposes `cmd` to region `region_id` without waiting for it to be applied.
+     * * # Returns
+     * * `Err(RegionNotFound)` if the region does not exist.
+     * * `Err(NotLeader)` if raft dropped the proposal, e.g. because the group of the
+     *   region has no leader yet. A follower forwards it to a known leader instead.
+     */
+    pub fn propose(&mut self, region_id: u64, cmd: Command) -> Result<(), ProposeError> { 
+        let region = self.regions.get_mut(&region_id).ok_or(ProposeError::RegionNotFound)?;
+        region.propose(cmd).map_err(|_| ProposeError::NotLeader)
     }
 
-    pub fn propose(&mut self, region_id: u64, cmd: Command) { 
+    /**
+     * Proposes `cmd` to the region the router sends `key` to, see `propose`.
+     * * # Returns
+     * * `Err(RegionNotFound)` if no range covers `key` or its region is gone.
+     * * `Err(NotLeader)` if raft dropped the proposal, the region the router names does
+     *   not have to be the leader of its group.
+     */
+    pub fn propose_keyed(&mut self, key: &[u8], cmd: Command) -> Result<(), ProposeError> { 
+        let region_id = self.router.route(key).ok_or(ProposeError::RegionNotFound)?;
+        self.propose(region_id, cmd)
+    }
+
+    /**
+     * Proposes `cmd` and drives `tick_all` until the region has applied it.
+     * * Only the leader of the region accepts proposals, `NotLeader` is returned otherwise.
+     * * `Timeout` does not mean the command was dropped, it may still be applied later.
+     *   The region forgets the proposal then, nothing is signalled when it is.
+     * * `NotLeader` is also returned when the region stops being the leader before the
+     *   command was applied, which may still happen.
+     * * `CompareFailed` means a `Command::Cas` was applied without writing.
+     * * `MergeRolledBack` means a `Command::Merge` was applied, but one of its ops failed
+     *   and the ones before it were undone.
+     */
+    pub fn propose_and_wait(&mut self, region_id: u64, cmd: Command, timeout: Duration) -> Result<(), ProposeError> { 
+        let deadline = Instant::now() + timeout;
+        let region = self.regions.get_mut(&region_id).ok_or(ProposeError::RegionNotFound)?;
+        if !region.is_leader() { 
+            return Err(ProposeError::NotLeader);
+        }
+        let context = self.next_context;
+        self.next_context += 1;
+        let merge = matches!(cmd, Command::Merge { .. });
+        let (done, applied) = bounded(1);
+        region.propose_with_context(cmd, context, done).map_err(|_| ProposeError::NotLeader)?;
+        while Instant::now() < deadline { 
+            self.tick_all();
+            match applied.try_recv() { 
+                Ok(true) => return Ok(()),
+                Ok(false) if merge => return Err(ProposeError::MergeRolledBack),
+                Ok(false) => return Err(ProposeError::CompareFailed),
+                Err(TryRecvError::Disconnected) => return Err(ProposeError::NotLeader),
+                Err(TryRecvError::Empty) => {}
+            }
+        }
         if let Some(region) = self.regions.get_mut(&region_id) { 
-            region.propose(cmd);
+            region.forget_proposal(context);
         }
+        Err(ProposeError::Timeout)
+    }
+
+    /**
+     * Adds `peer_id` as a voter to the raft group of region `region_id`, which has to be
+     * the leader of its group, and ticks until the region applied the change.
+     * * A peer living in this store has to be created on its own with the current voters
+     *   of the group, e.g. `create_region(peer_id, store.regions[&region_id].voters(), None)`.
+     *   It catches up from the leader's log and becomes a voter once it applied the change.
+     */
+    pub fn add_peer(&mut self, region_id: u64, peer_id: u64) -> std::io::Result<()> { 
+        self.change_peer(region_id, peer_id, ConfChangeType::AddNode)
     }
-}
\ No newline at end of file
+
+    /**
+     * Removes `peer_id` from the raft group of region `region_id`, see `add_peer`.
+     * * The region of the removed peer stays in the store, see `remove_region`.
+     */
+    pub fn remove_peer(&mut self, region_id: u64, peer_id: u64) -> std::io::Result<()> { 
+        self.change_peer(region_id, peer_id, ConfChangeType::RemoveNode)
+    }
+
+    fn change_peer(&mut self, region_id: u64, peer_id: u64, change_type: ConfChangeType) -> std::io::Result<()> { 
+        let region = self.regions.get_mut(&region_id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("region {} does not exist", region_id)))?;
+        let mut cc = ConfChange::default();
+        cc.set_change_type(change_type);
+        cc.node_id = peer_id;
+        region.propose_config_change(cc).map_err(|e| Error::other(e.to_string()))?;
+        let added = change_type == ConfChangeType::AddNode;
+        self.tick_until(|store| (store.regions[&region_id].voters().contains(&peer_id) == added).then_some(()))
+    }
+
+    /**
+     * Removes a region from its raft group, then deletes it together with its data.
+     * * When the group has other voters, leadership is handed to one of them first if
+     *   needed, and the removal is proposed through the group leader. The other voters have
+     *   to live in this store, their regions are ticked until they applied the removal.
+     * * The engine is closed and its directory removed before the region is forgotten.
+     *   Keys the router sent to it go to one of the other voters, if any.
+     */
+    pub fn remove_region(&mut self, region_id: u64) -> std::io::Result<()> { 
+        let region = self.regions.get_mut(&region_id)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("region {} does not exist", region_id)))?;
+        let peers: Vec<u64> = region.voters().into_iter().filter(|id| *id != region_id).collect();
+        if !peers.is_empty() { 
+            if region.is_leader() { 
+                region.transfer_leadership(peers[0]);
+            }
+            let leader = self.tick_until(|store| peers.iter().copied()
+                .find(|id| store.regions.get(id).is_some_and(|region| region.is_leader())))?;
+            self.remove_peer(leader, region_id)?;
+            self.tick_until(|store| peers.iter()
+                .filter_map(|id| store.regions.get(id))
+                .all(|region| !region.voters().contains(&region_id))
+                .then_some(()))?;
+        }
+        let region = self.regions.remove(&region_id).unwrap();
+        self.router.reassign(region_id, peers.first().copied());
+        if let Some(engine) = region.into_engine() { 
+            let dir = engine.config().dir.clone();
+            engine.close()?;
+            remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Ticks every region until `done` returns a value, giving up after `MAX_WAIT_TICKS`.
+     */
+    fn tick_until<T>(&mut self, done: impl Fn(&Self) -> Option<T>) -> std::io::Result<T> { 
+        for _ in 0..MAX_WAIT_TICKS { 
+            self.tick_all();
+            if let Some(value) = done(self) { 
+                return Ok(value);
+            }
+        }
+        Err(Error::new(ErrorKind::TimedOut, "raft group did not make progress"))
+    }
+}
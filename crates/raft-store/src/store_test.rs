@@ -0,0 +1,316 @@
+use std::{fs::remove_dir_all, path::PathBuf, time::Duration};
+
+use crossbeam_channel::{TryRecvError, bounded};
+use raft::{INVALID_ID, StateRole};
+use sledlite_core::engine::{Config, Engine, EngineMode};
+
+use crate::{command::{Command, SingleOp}, region::Region, region_test::{fast_config, silent_logger}, store::{ProposeError, RaftStore}};
+
+fn leader(store: &RaftStore) -> Option<u64> { 
+    store.regions.values().find(|region| region.raft.raft.state == StateRole::Leader).map(|region| region.id)
+}
+
+/**
+ * Three peer group 1..=3 with an engine per peer under `./temp-store-{name}-{id}`, ticked
+ * until it has a leader.
+ */
+fn cluster(name: &str) -> (RaftStore, Vec<PathBuf>) { 
+    let dirs: Vec<PathBuf> = (1..=3).map(|id| PathBuf::from(format!("./temp-store-{}-{}", name, id))).collect();
+    let mut store = RaftStore::new();
+    store.set_logger(silent_logger());
+    for (id, dir) in (1..=3).zip(&dirs) { 
+        let _ = remove_dir_all(dir);
+        let engine = Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).unwrap();
+        store.insert_region(Region::with_peers_config_and_logger(id, vec![1, 2, 3], fast_config(), silent_logger()).with_engine(engine));
+    }
+    for _ in 0..100 { 
+        store.tick_all();
+        if leader(&store).is_some() { 
+            break;
+        }
+    }
+    (store, dirs)
+}
+
+#[test]
+pub fn store_test_three_node_cluster_replicates() { 
+    let (mut store, dirs) = cluster("cluster");
+    let leader_id = leader(&store).expect("no leader elected");
+    store.propose(leader_id, Command::Put { key: b"k1".to_vec(), val: b"v1".to_vec() }).unwrap();
+    for _ in 0..20 { 
+        store.tick_all();
+    }
+    for region in store.regions.values() { 
+        let mut engine = region.engine().unwrap();
+        assert_eq!(engine.get(b"k1").unwrap(), Some(b"v1".to_vec()), "region {} did not apply", region.id);
+    }
+    drop(store);
+    for dir in dirs { 
+        let _ = remove_dir_all(dir);
+    }
+}
+
+#[test]
+pub fn store_test_propose_and_wait_returns_once_applied() { 
+    let (mut store, dirs) = cluster("wait");
+    let leader_id = leader(&store).expect("no leader elected");
+    let follower_id = (1..=3).find(|id| *id != leader_id).unwrap();
+    let put = || Command::Put { key: b"k1".to_vec(), val: b"v1".to_vec() };
+    assert_eq!(store.propose_and_wait(99, put(), Duration::from_millis(100)), Err(ProposeError::RegionNotFound));
+    assert_eq!(store.propose_and_wait(follower_id, put(), Duration::from_millis(100)), Err(ProposeError::NotLeader));
+    assert_eq!(store.propose_and_wait(leader_id, put(), Duration::from_millis(100)), Ok(()));
+    assert_eq!(store.regions[&leader_id].engine().unwrap().get(b"k1").unwrap(), Some(b"v1".to_vec()));
+    // the empty key fails the merge after its first op, which is undone
+    let merge = Command::Merge { ops: vec![
+        SingleOp::Put { key: b"k1".to_vec(), val: b"changed".to_vec() },
+        SingleOp::Put { key: Vec::new(), val: b"bad".to_vec() }
+    ]};
+    assert_eq!(store.propose_and_wait(leader_id, merge, Duration::from_millis(100)), Err(ProposeError::MergeRolledBack));
+    assert_eq!(store.regions[&leader_id].engine().unwrap().get(b"k1").unwrap(), Some(b"v1".to_vec()));
+    drop(store);
+    for dir in dirs { 
+        let _ = remove_dir_all(dir);
+    }
+}
+
+#[test]
+pub fn store_test_unanswered_proposals_are_forgotten() { 
+    let (mut store, dirs) = cluster("forget");
+    let leader_id = leader(&store).expect("no leader elected");
+    let put = || Command::Put { key: b"k1".to_vec(), val: b"v1".to_vec() };
+    assert_eq!(store.propose_and_wait(leader_id, put(), Duration::ZERO), Err(ProposeError::Timeout));
+    assert_eq!(store.regions[&leader_id].pending_proposals(), 0);
+
+    // a leader stepping down drops the waiters of its proposals
+    let region = store.regions.get_mut(&leader_id).unwrap();
+    let (done, applied) = bounded(1);
+    region.propose_with_context(put(), 1000, done).unwrap();
+    assert_eq!(region.pending_proposals(), 1);
+    let term = region.raft.raft.term;
+    region.raft.raft.become_follower(term + 1, INVALID_ID);
+    region.on_ready();
+    assert_eq!(region.pending_proposals(), 0);
+    assert_eq!(applied.try_recv(), Err(TryRecvError::Disconnected));
+    drop(store);
+    for dir in dirs { 
+        let _ = remove_dir_all(dir);
+    }
+}
+
+#[test]
+pub fn store_test_split_region_divides_keys() { 
+    let root = PathBuf::from("./temp-store-split");
+    let _ = remove_dir_all(&root);
+    let mut store = RaftStore::new();
+    store.set_logger(silent_logger());
+    let engine = Engine::open(Config { dir: root.join("region-1"), memtable_max_bytes: 256, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).unwrap();
+    store.insert_region(Region::with_logger(1, silent_logger()).with_engine(engine));
+    for _ in 0..20 { 
+        store.tick_all();
+    }
+    for i in 0..40 { 
+        store.propose(1, Command::Put { key: format!("key-{:02}", i).into_bytes(), val: b"v".to_vec() }).unwrap();
+    }
+    for _ in 0..5 { 
+        store.tick_all();
+    }
+    let (left, right) = store.split_region(1, b"key-20".to_vec()).unwrap();
+    assert_eq!((left, right), (2, 3));
+    assert!(!store.regions.contains_key(&1));
+    let mut left_engine = store.regions[&left].engine().unwrap();
+    let mut right_engine = store.regions[&right].engine().unwrap();
+    for i in 0..40 { 
+        let key = format!("key-{:02}", i).into_bytes();
+        let (owner, other) = if i < 20 { (&mut left_engine, &mut right_engine) } else { (&mut right_engine, &mut left_engine) };
+        assert_eq!(owner.get(&key).unwrap(), Some(b"v".to_vec()));
+        assert_eq!(other.get(&key).unwrap(), None);
+    }
+    drop(left_engine);
+    drop(right_engine);
+    drop(store);
+    let _ = remove_dir_all(&root);
+}
+
+#[test]
+pub fn store_test_multi_region_scan_merges_split_regions() { 
+    let root = PathBuf::from("./temp-store-multi-scan");
+    let _ = remove_dir_all(&root);
+    let mut store = RaftStore::new();
+    store.set_logger(silent_logger());
+    let engine = Engine::open(Config { dir: root.join("region-1"), memtable_max_bytes: 256, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).unwrap();
+    store.insert_region(Region::with_peers_config_and_logger(1, vec![1], fast_config(), silent_logger()).with_engine(engine));
+    for _ in 0..10 { 
+        store.tick_all();
+    }
+    for i in 0..40 { 
+        store.propose(1, Command::Put { key: format!("key-{:02}", i).into_bytes(), val: format!("v{}", i).into_bytes() }).unwrap();
+    }
+    for _ in 0..5 { 
+        store.tick_all();
+    }
+    let (left, right) = store.split_region(1, b"key-20".to_vec()).unwrap();
+    assert_eq!(store.regions[&left].overlap(b"key-30", b"key-40"), None);
+    assert_eq!(store.regions[&right].overlap(b"key-10", b"key-25"), Some((b"key-20".to_vec(), b"key-25".to_vec())));
+    let expected = |range: std::ops::Range<usize>| range
+        .map(|i| (format!("key-{:02}", i).into_bytes(), format!("v{}", i).into_bytes()))
+        .collect::<Vec<_>>();
+    assert_eq!(store.multi_region_scan(b"key-15", b"key-25").unwrap(), expected(15..25));
+    assert_eq!(store.multi_region_scan(b"", b"key-99").unwrap(), expected(0..40));
+    assert_eq!(store.multi_region_scan(b"key-22", b"key-28").unwrap(), expected(22..28));
+    drop(store);
+    let _ = remove_dir_all(&root);
+}
+
+#[test]
+pub fn store_test_propose_keyed_follows_splits() { 
+    let root = PathBuf::from("./temp-store-keyed");
+    let _ = remove_dir_all(&root);
+    let mut store = RaftStore::new();
+    store.set_logger(silent_logger());
+    let engine = Engine::open(Config { dir: root.join("region-1"), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).unwrap();
+    store.insert_region(Region::with_peers_config_and_logger(1, vec![1], fast_config(), silent_logger()).with_engine(engine));
+    for _ in 0..10 { 
+        store.tick_all();
+    }
+    for key in [&b""[..], b"key-05", b"key-30"] { 
+        assert_eq!(store.router.route(key), Some(1));
+    }
+    assert_eq!(store.propose_keyed(b"key-05", Command::Put { key: b"key-05".to_vec(), val: b"before".to_vec() }), Ok(()));
+    for _ in 0..5 { 
+        store.tick_all();
+    }
+
+    let (left, right) = store.split_region(1, b"key-20".to_vec()).unwrap();
+    assert_eq!(store.router.route(b"key-05"), Some(left));
+    assert_eq!(store.router.route(b"key-1z"), Some(left));
+    assert_eq!(store.router.route(b"key-20"), Some(right));
+    assert_eq!(store.router.route(b"key-30"), Some(right));
+    for _ in 0..20 { 
+        store.tick_all();
+    }
+    for key in [&b"key-10"[..], b"key-30"] { 
+        assert_eq!(store.propose_keyed(key, Command::Put { key: key.to_vec(), val: b"after".to_vec() }), Ok(()));
+    }
+    for _ in 0..5 { 
+        store.tick_all();
+    }
+    let mut left_engine = store.regions[&left].engine().unwrap();
+    assert_eq!(left_engine.get(b"key-05").unwrap(), Some(b"before".to_vec()));
+    assert_eq!(left_engine.get(b"key-10").unwrap(), Some(b"after".to_vec()));
+    assert_eq!(left_engine.get(b"key-30").unwrap(), None);
+    drop(left_engine);
+    assert_eq!(store.regions[&right].engine().unwrap().get(b"key-30").unwrap(), Some(b"after".to_vec()));
+    drop(store);
+    let _ = remove_dir_all(&root);
+}
+
+#[test]
+pub fn store_test_propose_fails_without_a_leader() { 
+    let mut store = RaftStore::new();
+    store.set_logger(silent_logger());
+    for id in 1..=3 { 
+        store.create_region(id, vec![1, 2, 3], Some(fast_config()));
+    }
+    let put = || Command::Put { key: b"k1".to_vec(), val: b"v1".to_vec() };
+    assert_eq!(store.propose(99, put()), Err(ProposeError::RegionNotFound));
+    // no election has run yet, so every peer drops the proposal
+    assert_eq!(store.propose(2, put()), Err(ProposeError::NotLeader));
+    assert_eq!(store.propose_keyed(b"k1", put()), Err(ProposeError::NotLeader));
+    // once a follower knows the leader it forwards the proposal instead
+    while store.regions.values().any(|region| region.raft.raft.leader_id == INVALID_ID) { 
+        store.tick_all();
+    }
+    assert_eq!(store.propose_keyed(b"k1", put()), Ok(()));
+}
+
+#[test]
+pub fn store_test_remove_region_deletes_data() { 
+    let (mut store, dirs) = cluster("remove");
+    let leader_id = leader(&store).expect("no leader elected");
+    let put = || Command::Put { key: b"k1".to_vec(), val: b"v1".to_vec() };
+    assert_eq!(store.propose_and_wait(leader_id, put(), Duration::from_millis(100)), Ok(()));
+    store.remove_region(leader_id).unwrap();
+    assert!(!dirs[leader_id as usize - 1].exists());
+    assert_eq!(store.propose_and_wait(leader_id, put(), Duration::from_millis(100)), Err(ProposeError::RegionNotFound));
+    for region in store.regions.values() { 
+        assert!(!region.voters().contains(&leader_id));
+    }
+    drop(store);
+    for dir in dirs { 
+        let _ = remove_dir_all(dir);
+    }
+}
+
+#[test]
+pub fn store_test_region_leader_is_known_to_every_peer() { 
+    let (mut store, dirs) = cluster("leader");
+    let leader_id = leader(&store).expect("no leader elected");
+    // followers learn about the leader from its first heartbeat
+    for _ in 0..10 { 
+        store.tick_all();
+    }
+    for id in 1..=3 { 
+        assert_eq!(store.region_leader(id), Some(leader_id), "region {id}");
+    }
+    assert_eq!(store.region_leader(99), None);
+    drop(store);
+    for dir in dirs { 
+        let _ = remove_dir_all(dir);
+    }
+
+    let mut store = RaftStore::new();
+    store.set_logger(silent_logger());
+    store.create_region(7, vec![7], Some(fast_config()));
+    assert_eq!(store.region_leader(7), None);
+    for _ in 0..10 { 
+        store.tick_all();
+    }
+    assert_eq!(store.region_leader(7), Some(7));
+}
+
+#[test]
+pub fn store_test_transfer_region_leadership_hands_off_within_50_ticks() { 
+    let (mut store, dirs) = cluster("transfer");
+    let leader_id = leader(&store).expect("no leader elected");
+    let target_id = (1..=3).find(|id| *id != leader_id).unwrap();
+    for _ in 0..10 { 
+        store.tick_all();
+    }
+    store.transfer_region_leadership(leader_id, target_id).expect("transfer failed");
+    let ticks = (1..=50).find(|_| { 
+        store.tick_all();
+        (1..=3).all(|id| store.region_leader(id) == Some(target_id))
+    });
+    assert!(ticks.is_some(), "leadership stayed with {:?}", store.region_leader(leader_id));
+    assert!(store.regions[&target_id].is_leader());
+    assert_eq!(store.propose_and_wait(target_id, Command::Put { key: b"k1".to_vec(), val: b"v1".to_vec() }, Duration::from_millis(500)), Ok(()));
+    assert_eq!(store.transfer_region_leadership(99, target_id).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    drop(store);
+    for dir in dirs { 
+        let _ = remove_dir_all(dir);
+    }
+}
+
+#[test]
+pub fn store_test_add_and_remove_peer_change_the_voters() { 
+    let mut store = RaftStore::new();
+    store.set_logger(silent_logger());
+    store.create_region(1, vec![1], Some(fast_config()));
+    for _ in 0..10 { 
+        store.tick_all();
+    }
+    assert_eq!(store.region_leader(1), Some(1));
+    // the new peer starts out knowing the current voters and learns the rest from the leader's log
+    store.create_region(2, store.regions[&1].voters(), Some(fast_config()));
+    store.add_peer(1, 2).unwrap();
+    assert_eq!(store.regions[&1].voters(), vec![1, 2]);
+    for _ in 0..10 { 
+        store.tick_all();
+    }
+    assert_eq!(store.regions[&2].voters(), vec![1, 2]);
+    assert_eq!(store.region_leader(2), Some(1));
+
+    store.remove_peer(1, 2).unwrap();
+    assert_eq!(store.regions[&1].voters(), vec![1]);
+    assert!(store.add_peer(9, 2).is_err());
+}
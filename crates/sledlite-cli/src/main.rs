@@ -0,0 +1,135 @@
+use std::{io::{self, BufWriter, ErrorKind, Write}, path::PathBuf, process::ExitCode};
+
+use clap::{Parser, Subcommand};
+use sledlite_core::engine::{Config, Engine, EngineMode};
+
+/// Reads and writes a sledlite engine directory from the command line.
+///
+/// Keys and values are taken as UTF-8, `\xNN` stands for an arbitrary byte and `\\` for
+/// a backslash. Output uses the same escapes for bytes that are not printable UTF-8.
+#[derive(Parser)]
+#[command(name = "sledlite-cli", version)]
+struct Cli { 
+    /// Engine directory, created if it does not exist
+    #[arg(long)]
+    dir: PathBuf,
+    /// Memtable size at which writes are flushed into an SSTable
+    #[arg(long, default_value_t = 4 * 1024 * 1024)]
+    memtable_max_bytes: usize,
+    #[command(subcommand)]
+    command: Command
+}
+
+#[derive(Subcommand)]
+enum Command { 
+    /// Prints the value of a key, exits with 1 if it is not set
+    Get { key: String },
+    /// Sets the value of a key
+    Put { key: String, value: String },
+    /// Removes a key
+    Delete { key: String },
+    /// Prints every `key<TAB>value` pair in [start, end), one per line
+    Scan { start: String, end: String },
+    /// Prints the sizes of the SSTables, memtable and WAL
+    Stats
+}
+
+fn main() -> ExitCode { 
+    let cli = Cli::parse();
+    match run(cli) { 
+        Ok(code) => code,
+        Err(err) => { 
+            eprintln!("sledlite-cli: {err}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run(cli: Cli) -> io::Result<ExitCode> { 
+    let mut engine = Engine::open(Config { 
+        dir: cli.dir,
+        memtable_max_bytes: cli.memtable_max_bytes,
+        max_wal_bytes: None,
+        mode: EngineMode::Persistent,
+        block_cache_capacity: 0,
+        data_dirs: Vec::new()
+    })?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    match cli.command { 
+        Command::Get { key } => match engine.get(&unescape(&key)?)? { 
+            Some(value) => writeln!(out, "{}", escape(&value))?,
+            None => { 
+                eprintln!("sledlite-cli: key not found");
+                return Ok(ExitCode::from(1));
+            }
+        },
+        Command::Put { key, value } => { 
+            engine.put(&unescape(&key)?, &unescape(&value)?)?;
+        },
+        Command::Delete { key } => { 
+            engine.delete(&unescape(&key)?)?;
+        },
+        Command::Scan { start, end } => { 
+            for (key, value) in engine.scan(&unescape(&start)?, &unescape(&end)?)? { 
+                writeln!(out, "{}\t{}", escape(&key), escape(&value))?;
+            }
+        },
+        Command::Stats => { 
+            let stats = engine.stats();
+            writeln!(out, "sst_tables: {}", stats.sst_tables)?;
+            writeln!(out, "sst_bytes: {}", stats.sst_bytes)?;
+            writeln!(out, "memtable_bytes: {}", stats.memtable_bytes)?;
+            writeln!(out, "wal_bytes: {}", stats.wal_bytes)?;
+            writeln!(out, "pending_merges: {}", stats.pending_merges)?;
+        }
+    }
+    out.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/**
+ * Turns a command line argument into bytes, decoding `\xNN` and `\\`.
+ */
+fn unescape(arg: &str) -> io::Result<Vec<u8>> { 
+    let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("invalid escape in {arg:?}, expected \\xNN or \\\\"));
+    let mut bytes = Vec::with_capacity(arg.len());
+    let mut rest = arg.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() { 
+        rest = tail;
+        if byte != b'\\' { 
+            bytes.push(byte);
+            continue;
+        }
+        match rest { 
+            [b'\\', tail @ ..] => { 
+                bytes.push(b'\\');
+                rest = tail;
+            },
+            [b'x', hi, lo, tail @ ..] => { 
+                let hex = std::str::from_utf8(&[*hi, *lo]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                bytes.push(hex.ok_or_else(invalid)?);
+                rest = tail;
+            },
+            _ => return Err(invalid())
+        }
+    }
+    Ok(bytes)
+}
+
+/**
+ * Prints bytes as UTF-8 where that is readable, see `unescape` for the way back.
+ */
+fn escape(bytes: &[u8]) -> String { 
+    let mut escaped = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() { 
+        for c in chunk.valid().chars() { 
+            match c { 
+                '\\' => escaped.push_str("\\\\"),
+                c if c.is_control() => c.to_string().bytes().for_each(|b| escaped.push_str(&format!("\\x{b:02x}"))),
+                c => escaped.push(c)
+            }
+        }
+        chunk.invalid().iter().for_each(|b| escaped.push_str(&format!("\\x{b:02x}")));
+    }
+    escaped
+}
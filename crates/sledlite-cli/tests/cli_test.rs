@@ -0,0 +1,45 @@
+use std::{path::{Path, PathBuf}, process::{Command, Output}};
+
+fn cli(dir: &Path, args: &[&str]) -> Output { 
+    Command::new(env!("CARGO_BIN_EXE_sledlite-cli"))
+        .arg("--dir")
+        .arg(dir)
+        // small enough that the puts are flushed and the delete has to hide a flushed key
+        .args(["--memtable-max-bytes", "64"])
+        .args(args)
+        .output()
+        .expect("can not run sledlite-cli")
+}
+
+fn stdout(output: Output) -> String { 
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+pub fn cli_test_put_get_delete_and_scan() { 
+    let dir = PathBuf::from("./temp-cli");
+    let _ = std::fs::remove_dir_all(&dir);
+    for i in 0..10 { 
+        stdout(cli(&dir, &["put", &format!("key-{i}"), &format!("val-{i}")]));
+    }
+    stdout(cli(&dir, &["put", "bin\\x00", "\\xff\\x01 back\\\\slash"]));
+    assert_eq!(stdout(cli(&dir, &["get", "key-3"])), "val-3\n");
+    assert_eq!(stdout(cli(&dir, &["get", "bin\\x00"])), "\\xff\\x01 back\\\\slash\n");
+
+    stdout(cli(&dir, &["delete", "key-3"]));
+    let missing = cli(&dir, &["get", "key-3"]);
+    assert_eq!(missing.status.code(), Some(1));
+    assert!(missing.stdout.is_empty());
+
+    let scanned = stdout(cli(&dir, &["scan", "key-2", "key-5"]));
+    assert_eq!(scanned, "key-2\tval-2\nkey-4\tval-4\n");
+
+    let stats = stdout(cli(&dir, &["stats"]));
+    assert!(stats.lines().any(|line| line.starts_with("sst_tables: ")), "{stats}");
+    assert!(stats.contains("wal_bytes: "), "{stats}");
+
+    let invalid = cli(&dir, &["get", "bad\\q"]);
+    assert_eq!(invalid.status.code(), Some(2));
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,48 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/**
+ * Fixed size bloom filter over byte keys.
+ * * `may_contain` never returns `false` for an inserted key, it may return `true` for a key
+ *   that was never inserted. Keys can not be removed, only the whole filter is cleared.
+ * * Every key sets `hashes` bits derived from one 64 bit hash by double hashing.
+ */
+#[derive(Debug, Clone)]
+pub struct BloomFilter { 
+    bits: Vec<u64>,
+    hashes: u32
+}
+
+impl BloomFilter { 
+    /**
+     * Creates an empty filter of at least `bits` bits setting `hashes` bits per key.
+     */
+    pub fn new(bits: usize, hashes: u32) -> Self { 
+        Self { 
+            bits: vec![0; bits.div_ceil(64).max(1)],
+            hashes: hashes.max(1)
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) { 
+        for bit in self.bit_positions(key) { 
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn may_contain(&self, key: &[u8]) -> bool { 
+        self.bit_positions(key).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn clear(&mut self) { 
+        self.bits.fill(0);
+    }
+
+    fn bit_positions(&self, key: &[u8]) -> impl Iterator<Item = usize> + use<> { 
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let len = (self.bits.len() * 64) as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
@@ -0,0 +1,17 @@
+use crate::bloom::BloomFilter;
+
+#[test]
+pub fn bloom_test_inserted_keys_are_always_found() { 
+    let mut bloom = BloomFilter::new(8 * 1024, 4);
+    let keys: Vec<Vec<u8>> = (0..500).map(|i| format!("key-{}", i).into_bytes()).collect();
+    for key in &keys { 
+        bloom.insert(key);
+    }
+    assert!(keys.iter().all(|key| bloom.may_contain(key)));
+    // 16 bits per key keep false positives rare
+    let false_positives = (0..1000).filter(|i| bloom.may_contain(format!("other-{}", i).as_bytes())).count();
+    assert!(false_positives < 50, "{} false positives", false_positives);
+
+    bloom.clear();
+    assert!(keys.iter().all(|key| !bloom.may_contain(key)));
+}
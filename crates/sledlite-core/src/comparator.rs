@@ -0,0 +1,40 @@
+use std::{cmp::Ordering, fmt};
+
+/**
+ * Order of keys in SSTables and engine scans.
+ * * Every SSTable of an engine must be written and read with the same comparator; the
+ *   order is not recorded in the file.
+ */
+pub trait Comparator: Send + Sync { 
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+
+    /**
+     * Whether `compare` is plain byte order, which lets readers use byte-prefix
+     * shortcuts such as the memtable's prefix iterator for range scans.
+     */
+    fn is_lexicographic(&self) -> bool { 
+        false
+    }
+}
+
+impl fmt::Debug for dyn Comparator { 
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { 
+        f.write_str(if self.is_lexicographic() { "LexicographicComparator" } else { "dyn Comparator" })
+    }
+}
+
+/**
+ * Byte-wise lexicographic order, the default of `SSTConfig` and `Engine`.
+ */
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LexicographicComparator;
+
+impl Comparator for LexicographicComparator { 
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering { 
+        a.cmp(b)
+    }
+
+    fn is_lexicographic(&self) -> bool { 
+        true
+    }
+}
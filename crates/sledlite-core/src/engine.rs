@@ -1,26 +1,143 @@
-use std::{error::Error, fs::{create_dir_all, read_dir}, io::ErrorKind, path::PathBuf, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc}};
+use std::{cmp::Ordering as CmpOrdering, collections::{BTreeMap, BTreeSet, HashMap}, fs::{copy, create_dir_all, hard_link, read_dir, remove_file, File}, io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write}, num::NonZeroUsize, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc, Mutex}, time::{Duration, Instant, SystemTime}};
 
-use chrono::Timelike;
+use bytes::Bytes;
+
+use crate::{bloom::BloomFilter, comparator::{Comparator, LexicographicComparator}, mvcc::{decode_version, encode_version, split_versioned_key, versioned_key, EngineSnapshot, Timestamp}, radix::{RadixError, RadixTree}, sst::{BlockCache, KeyState, SSTConfig, SSTEntry, SSTMerger, SSTReader, SSTWriter}, txn::Transaction, wal::{WalOp, WalReader, WalWriter}};
+/**
+ * Upper bound on the bytes a single `Engine::compact` run reads or writes.
+ */
+pub const MAX_COMPACTION_BYTES: u64 = 256 * 1024 * 1024;
+
+// how long `Engine::approximate_disk_usage` may hand out a cached total
+const DISK_USAGE_TTL: Duration = Duration::from_secs(1);
+
+// records of one SSTable read by a scan, tombstones included, from `range_iter` or a filtered `iter`
+type RecordIter<'a> = Box<dyn Iterator<Item = SSTEntry> + 'a>;
+
+/**
+ * Where an engine keeps its data.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EngineMode { 
+    /**
+     * Writes go to the WAL and are flushed into SSTables under `Config::dir`.
+     */
+    #[default]
+    Persistent,
+    /**
+     * Everything lives in the memtable, nothing is read from or written to `Config::dir`.
+     * Flushes are no-ops and the data is gone once the engine is dropped.
+     */
+    InMemory
+}
 
-use crate::{radix::{RadixError, RadixTree}, sst::{SSTReader, SSTWriter}, wal::{WalOp, WalReader, WalWriter}};
 #[derive(Clone)]
 pub struct Config { 
     pub dir: PathBuf,
-    pub memtable_max_bytes : usize
+    pub memtable_max_bytes : usize,
+    /**
+     * Size the WAL may reach before `put` flushes the memtable, which truncates it.
+     * `None` leaves the WAL bounded by `memtable_max_bytes` alone.
+     */
+    pub max_wal_bytes: Option<usize>,
+    /**
+     * `EngineMode::InMemory` keeps the whole store in the memtable, see `EngineMode`.
+     */
+    pub mode: EngineMode,
+    /**
+     * Number of SSTable blocks kept in the `BlockCache` shared by all tables, 0 turns
+     * the cache off.
+     */
+    pub block_cache_capacity: usize,
+    /**
+     * More directories for SSTables, e.g. on other drives. New tables go to `dir` and
+     * these in turn, the WAL always stays in `dir`. Empty keeps everything in `dir`.
+     */
+    pub data_dirs: Vec<PathBuf>
+}
+
+
+/**
+ * Point-in-time sizes of an engine, see `Engine::stats`.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineStats { 
+    pub sst_tables: usize,
+    pub sst_bytes: u64,
+    pub memtable_bytes: usize,
+    pub wal_bytes: u64, // 0 in `EngineMode::InMemory`
+    pub pending_merges: usize // keys with merge operands not folded yet
+}
+
+/**
+ * Result of `Engine::verify_integrity`, everything empty means no corruption was found.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport { 
+    pub wal_valid_records: usize,
+    pub wal_corrupted: Vec<u64>, // LSNs of the records failing their checksum
+    pub sst_valid_files: usize,
+    pub sst_corrupted: Vec<PathBuf> // tables holding at least one unreadable record
+}
+
+/**
+ * What `Engine::online_backup` put into the backup directory.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackupStats { 
+    pub sst_files: usize,
+    pub sst_bytes: u64,
+    pub wal_bytes: u64,
+    pub lsn: u64 // last LSN the backup covers
+}
+
+/**
+ * Keys returned by `Engine::scan_keys_only`, in the engine's key order. Yields the error
+ * that stopped the scan instead of any key.
+ */
+pub struct KeyOnlyIter { 
+    keys: std::vec::IntoIter<Vec<u8>>,
+    error: Option<std::io::Error>
 }
 
+impl Iterator for KeyOnlyIter { 
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> { 
+        if let Some(err) = self.error.take() { 
+            return Some(Err(err));
+        }
+        self.keys.next().map(Ok)
+    }
+}
+
+/**
+ * Folds a merge operand into the current value of a key, see `Engine::set_merge_operator`.
+ */
+pub type MergeOperator = Box<dyn Fn(&[u8], &[u8]) -> Vec<u8> + Send + Sync>;
 
 // todo: handle concurrent flushing of memtable snapshot into disc with the safest way possible
 
 pub struct Engine {
     wal_path: PathBuf, 
-    wal : WalWriter,
+    wal : Option<WalWriter>, // `None` in `EngineMode::InMemory`
     dir: PathBuf,
     memtable : Arc<RadixTree>,
+    memtable_bloom: BloomFilter, // every key put into the memtable or tombstoned since the last flush
     memtable_bytes : AtomicUsize,
+    memtable_tombstones: BTreeSet<Vec<u8>>, // keys deleted since the last flush that an SSTable may still hold
     sst_readers: Vec<(PathBuf, SSTReader)>,
     cfg : Config,
-    next_lsn : AtomicU64
+    next_lsn : AtomicU64,
+    comparator: Arc<dyn Comparator>,
+    merge_operator: Option<MergeOperator>,
+    pending_merges: HashMap<Vec<u8>, Vec<Vec<u8>>>, // operands not folded into the memtable yet, oldest first
+    txn_starts: BTreeMap<u64, usize>, // start version of every open transaction to how many started there
+    key_versions: HashMap<Vec<u8>, u64>, // version of the last write to each key, kept while transactions are open
+    disk_usage: Mutex<Option<(Instant, u64)>>, // last `approximate_disk_usage` total and when it was taken
+    block_cache: Option<Arc<BlockCache>>, // handed to every SSTable reader, `None` when the capacity is 0
+    next_sst_dir: usize, // round-robin position in `dir` and `cfg.data_dirs` for the next SSTable
+    next_sst_seq: u64 // sequence number in the name of the next SSTable, one past the newest on disk
 }
 
 
@@ -30,81 +147,176 @@ impl Engine {
     /**
      * Initializes the storage engine.
      * * # Steps:
-     * 1. Creates the data directory if it doesn't exist.
+     * 1. Creates the data directory, and every `data_dirs` entry, if they don't exist.
      * 2. Opens the WAL for appending new operations.
-     * 3. Scans the directory for existing `sst-*.dat` files and loads them into readers.
+     * 3. Scans the directories for existing `sst-*.dat` files and loads them into readers.
      * 4. Triggers `replay_records()` to recover any data from the WAL into the memtable.
      */
     pub fn open(cfg: Config) -> std::io::Result<Self> { 
-        println!("openging the engien");
-        create_dir_all(cfg.dir.clone())?;
+        Self::open_with_comparator(cfg, Arc::new(LexicographicComparator))
+    }
+
+    /**
+     * Opens the engine like `open`, keeping its SSTables and `scan` results in the order
+     * of `comparator`. An existing directory must be reopened with the same comparator.
+     */
+    pub fn open_with_comparator(cfg: Config, comparator: Arc<dyn Comparator>) -> std::io::Result<Self> { 
+        Self::open_inner(cfg, comparator, false)
+    }
+
+    /**
+     * Disaster recovery for a directory whose SSTables are gone or damaged but whose
+     * WAL survived.
+     * * SSTables that fail to open are skipped with a warning instead of failing the open,
+     *   as are SSTables named by `WalOp::SstImport` records that no longer exist. Every WAL
+     *   record is replayed into the memtable, which is then written to a new SSTable so the
+     *   recovered state no longer depends on the WAL.
+     * * Data that only lived in the lost SSTables can not be recovered.
+     */
+    pub fn recover_from_wal_only(cfg: Config) -> std::io::Result<Self> { 
+        let mut engine = Self::open_inner(cfg, Arc::new(LexicographicComparator), true)?;
+        engine.replay_records()?;
+        if engine.has_unflushed() { 
+            engine.flush_memtable()?;
+        }
+        Ok(engine)
+    }
+
+    /**
+     * Loads the SSTables and opens the WAL. Unless `recovering`, the WAL is replayed here
+     * and an SSTable that fails to open fails the whole open.
+     */
+    fn open_inner(cfg: Config, comparator: Arc<dyn Comparator>, recovering: bool) -> std::io::Result<Self> { 
+        if cfg.mode == EngineMode::InMemory { 
+            return Ok(Self::in_memory(cfg, comparator));
+        }
+        for dir in sst_dirs(&cfg) { 
+            create_dir_all(dir)?;
+        }
         let wal_path = cfg.dir.clone().join("wal.log");
-        println!("trying to open wal writer");
-        let mut wal = WalWriter::open(wal_path.clone(), false)?;
-        println!("wal writer opened");
+        let wal = WalWriter::open(wal_path.clone(), false)?;
         let mut sst_readers = Vec::new();
-        let mut sst_paths: Vec<PathBuf> = read_dir(cfg.dir.clone())?
-            .filter_map(|rd| rd.ok().map(|r| r.path()))
-            .filter(|path| path.is_file() 
-                && path.file_name().and_then(|os_str| os_str.to_str())
-                .map(|s| s.starts_with("sst-") && s.ends_with(".dat"))
-                .unwrap_or(false)
-            ).collect();
-        sst_paths.sort();
-        println!("sst paths : {:?}", sst_paths);
+        let mut sst_paths: Vec<PathBuf> = Vec::new();
+        for dir in sst_dirs(&cfg) { 
+            sst_paths.extend(read_dir(dir)?
+                .filter_map(|rd| rd.ok().map(|r| r.path()))
+                .filter(|path| path.is_file() 
+                    && path.file_name().and_then(|os_str| os_str.to_str())
+                    .map(|s| s.starts_with("sst-") && s.ends_with(".dat"))
+                    .unwrap_or(false)
+                ));
+        }
+        sst_paths.sort_by_key(|path| sst_seq(path));
+        let next_sst_seq = sst_paths.iter().filter_map(|path| sst_seq(path).first().copied()).max().map_or(1, |seq| seq + 1);
+        tracing::debug!(dir = ?cfg.dir, tables = sst_paths.len(), "opening engine");
+        let block_cache = NonZeroUsize::new(cfg.block_cache_capacity).map(|capacity| Arc::new(BlockCache::new(capacity)));
         for path in sst_paths { 
-            let sst_reader = SSTReader::open(path.clone())?;
-            sst_readers.push((path, sst_reader));
+            match open_sst(path.clone(), &comparator, &block_cache) { 
+                Ok(sst_reader) => sst_readers.push((path, sst_reader)),
+                Err(err) if recovering => tracing::warn!(path = ?path, error = %err, "skipping unreadable sstable"),
+                Err(err) => return Err(err)
+            }
         }
         let memtable = Arc::new(RadixTree::new());
-        let lsn = wal.lsn.load(Ordering::SeqCst);
-        println!("val of lsn {lsn}");
         let next_lsn = wal.appendable_lsn.load(Ordering::SeqCst) as u64;
-        println!("next lsn {next_lsn}");
         let mut engine = Self {
             wal_path, 
-            wal,
+            wal: Some(wal),
             dir: cfg.dir.clone(),
             memtable,
+            memtable_bloom: memtable_bloom(&cfg),
             memtable_bytes: AtomicUsize::new(0),
+            memtable_tombstones: BTreeSet::new(),
             sst_readers,
             cfg,
-            next_lsn: AtomicU64::new(next_lsn + 1)
+            next_lsn: AtomicU64::new(next_lsn + 1),
+            comparator,
+            merge_operator: None,
+            pending_merges: HashMap::new(),
+            txn_starts: BTreeMap::new(),
+            key_versions: HashMap::new(),
+            disk_usage: Mutex::new(None),
+            block_cache,
+            next_sst_dir: 0,
+            next_sst_seq
         };
+        if recovering { 
+            return Ok(engine);
+        }
         if let Err(err) = engine.replay_records(){ 
-            println!("error while replaying wal records : {:?}", err);
+            tracing::warn!(error = %err, "replaying the wal failed");
         }
         Ok(engine)
     }
 
+    fn in_memory(cfg: Config, comparator: Arc<dyn Comparator>) -> Self { 
+        tracing::debug!("opening in-memory engine");
+        Self {
+            wal_path: cfg.dir.join("wal.log"),
+            wal: None,
+            dir: cfg.dir.clone(),
+            memtable: Arc::new(RadixTree::new()),
+            memtable_bloom: memtable_bloom(&cfg),
+            memtable_bytes: AtomicUsize::new(0),
+            memtable_tombstones: BTreeSet::new(),
+            sst_readers: Vec::new(),
+            cfg,
+            next_lsn: AtomicU64::new(1),
+            comparator,
+            merge_operator: None,
+            pending_merges: HashMap::new(),
+            txn_starts: BTreeMap::new(),
+            key_versions: HashMap::new(),
+            disk_usage: Mutex::new(None),
+            block_cache: None,
+            next_sst_dir: 0,
+            next_sst_seq: 1
+        }
+    }
+
 
     /**
      * Recovers the engine state after a crash or restart.
      * * It reads all records from the `wal.log`, sorts them by LSN to ensure 
-     * correct operation order, and applies them to the in-memory RadixTree.
+     *   correct operation order, and applies them to the in-memory RadixTree.
      */
     pub fn replay_records(&mut self) -> std::io::Result<()>{ 
-        println!("opening wal reader");
+        if self.wal.is_none() { 
+            return Ok(());
+        }
         let mut wal_reader = WalReader::open(self.wal_path.clone())?;
-        println!("reading wal reader");
         if self.wal_path.metadata()?.len() < 9 {
             return Err(std::io::Error::new(ErrorKind::InvalidData, "invalid wal data"));
         }
         let mut wal_records = wal_reader.read_all().expect("reading wal records failed");
         wal_records.sort_by_key(|w| w.lsn);
-        println!("wal records {wal_records:#?}");
+        // records covered by a compaction marker are already in the SSTables
+        let compacted_up_to = wal_records.iter().filter_map(|record| record.compacted_up_to()).max();
+        tracing::debug!(records = wal_records.len(), compacted_up_to, "replaying wal records");
         for record in wal_records { 
+            if compacted_up_to.is_some_and(|lsn| record.lsn <= lsn) { 
+                continue;
+            }
             match record.op { 
                 WalOp::Put => { 
-                    self.memtable.put(&record.key, record.value.unwrap().to_vec()).map_err(|err| {
-                        println!("error {err:?}");
-                        std::io::Error::new(std::io::ErrorKind::Other, "memtable insertion failed ")
-                    })?; 
+                    self.pending_merges.remove(&record.key);
+                    self.memtable_put(&record.key, record.value.unwrap_or_default().into())?; 
                 },
-                WalOp::Delete => { self.memtable.remove(&record.key).map_err(|_| {
-                        std::io::Error::new(std::io::ErrorKind::Other, "memtable deletion failed ")
-                    })?; 
+                WalOp::Delete => { 
+                    self.pending_merges.remove(&record.key);
+                    self.memtable_delete(&record.key)?; 
                 } 
+                WalOp::PutTs => { 
+                    self.memtable_put(&record.key, encode_version(Some(&record.value.unwrap_or_default())).into())?; 
+                }
+                WalOp::DeleteTs => { 
+                    self.memtable_put(&record.key, encode_version(None).into())?; 
+                }
+                WalOp::Merge => { 
+                    self.pending_merges.entry(record.key).or_default().push(record.value.unwrap_or_default());
+                }
+                WalOp::SstImport => self.reload_imported_sst(&record.key)?,
+                WalOp::CompactionMarker => {}
             }
         }
         Ok(())  
@@ -112,8 +324,109 @@ impl Engine {
 
 
     
-    fn memtable_dump(&mut self) -> Vec<(Vec<u8>, Vec<u8>)> { 
-        self.memtable.iter_all()
+    /**
+     * Every pair in the memtable, and every tombstone with a `None` value, in the
+     * comparator's order.
+     */
+    fn memtable_dump(&mut self) -> Vec<(Vec<u8>, Option<Vec<u8>>)> { 
+        let mut entries: Vec<(Vec<u8>, Option<Vec<u8>>)> = self.memtable.iter_all().into_iter().map(|(key, value)| (key, Some(value.into()))).collect();
+        entries.extend(self.memtable_tombstones.iter().map(|key| (key.clone(), None)));
+        // the writer rejects unsorted input, so the order is not left to the tree walk;
+        // already sorted entries cost a single pass
+        entries.sort_by(|a, b| self.comparator.compare(&a.0, &b.0));
+        entries
+    }
+
+    /**
+     * Whether a flush would write anything: memtable pairs, tombstones or merge operands.
+     */
+    fn has_unflushed(&self) -> bool { 
+        !self.memtable.is_empty() || !self.memtable_tombstones.is_empty() || !self.pending_merges.is_empty()
+    }
+
+    /**
+     * Puts byte-ordered entries into the order of the engine's comparator.
+     */
+    fn sort_entries(&self, entries: &mut [(Vec<u8>, Vec<u8>)]) { 
+        if !self.comparator.is_lexicographic() { 
+            entries.sort_by(|a, b| self.comparator.compare(&a.0, &b.0));
+        }
+    }
+
+    /**
+     * Appends a record to the WAL under the next lsn.
+     * * # Returns
+     * * `Ok(Some(u64))` with the end offset of the WAL after the record.
+     * * `Ok(None)` in `EngineMode::InMemory`, where nothing is logged.
+     */
+    fn log_record<F>(&mut self, append: F) -> std::io::Result<Option<u64>> 
+    where 
+        F: FnOnce(&mut WalWriter, u64) -> std::io::Result<u64>
+    { 
+        // lsns double as transaction versions, so they advance even without a WAL
+        let next_lsn = self.next_lsn.fetch_add(1, Ordering::SeqCst);
+        let Some(wal) = self.wal.as_mut() else { 
+            return Ok(None);
+        };
+        append(wal, next_lsn).map(Some)
+    }
+
+    /**
+     * Remembers that `key` was written at the current version, for open transactions
+     * to validate against. Nothing is kept while no transaction is open.
+     */
+    fn note_write(&mut self, key: &[u8]) { 
+        if !self.txn_starts.is_empty() { 
+            self.key_versions.insert(key.to_vec(), self.next_lsn.load(Ordering::SeqCst));
+        }
+    }
+
+    /**
+     * Opens a transaction at the current version, see `Transaction`.
+     */
+    pub(crate) fn register_transaction(&mut self) -> u64 { 
+        let start = self.next_lsn.load(Ordering::SeqCst);
+        *self.txn_starts.entry(start).or_default() += 1;
+        start
+    }
+
+    /**
+     * Closes a transaction opened at `start`, forgetting the key versions no open
+     * transaction can conflict with anymore.
+     */
+    pub(crate) fn finish_transaction(&mut self, start: u64) { 
+        if let Some(open) = self.txn_starts.get_mut(&start) { 
+            *open -= 1;
+            if *open == 0 { 
+                self.txn_starts.remove(&start);
+            }
+        }
+        match self.txn_starts.keys().next() { 
+            Some(&oldest) => self.key_versions.retain(|_, version| *version > oldest),
+            None => self.key_versions.clear()
+        }
+    }
+
+    /**
+     * Whether `key` was written after a transaction opened at `start`.
+     */
+    pub(crate) fn written_since(&self, key: &[u8], start: u64) -> bool { 
+        self.key_versions.get(key).is_some_and(|&version| version > start)
+    }
+
+    /**
+     * Starts a transaction on an engine shared behind a mutex, see `Transaction`.
+     */
+    pub fn begin_transaction(engine: &Mutex<Self>) -> Transaction<'_> { 
+        Transaction::begin(engine)
+    }
+
+    fn sst_config(&self) -> SSTConfig { 
+        SSTConfig { comparator: Arc::clone(&self.comparator), ..SSTConfig::default() }
+    }
+
+    fn open_sst(&self, path: PathBuf) -> std::io::Result<SSTReader> { 
+        open_sst(path, &self.comparator, &self.block_cache)
     }
 
 
@@ -121,51 +434,635 @@ impl Engine {
      * Moves data from memory to permanent storage.
      * * # Workflow:
      * 1. Dumps all current key-value pairs from the RadixTree.
-     * 2. Writes them to a new SSTable file named with a unique timestamp.
+     * 2. Writes them to a new SSTable file named with the next sequence number.
      * 3. Atomically resets the memtable and clears the `memtable_bytes` counter.
      * 4. Truncates the WAL, as the logged data is now safely persisted in an SSTable.
      * 5. Adds the new SSTable to the list of active readers.
+     * * A no-op in `EngineMode::InMemory`.
      */
     fn flush_memtable(&mut self) -> std::io::Result<()>{ 
-        println!("flushing");
+        if self.wal.is_none() { 
+            return Ok(());
+        }
+        // the WAL holding the operands is truncated below, so they have to be folded first
+        self.resolve_merges()?;
         let k_v_iters = self.memtable_dump();
-        let sst_id = chrono::Utc::now().nanosecond();
-        let sst_path = self.dir.join(format!("sst-{}.dat", sst_id));
-        let mut sst_writer = SSTWriter::open(sst_path.clone())?;
-        sst_writer.write_all(k_v_iters)?;
+        let entries = k_v_iters.len();
+        let sst_path = next_sst_path(&self.cfg, &mut self.next_sst_dir, &mut self.next_sst_seq);
+        let mut sst_writer = SSTWriter::open_with_config(sst_path.clone(), self.sst_config())?;
+        sst_writer.write_entries(k_v_iters.into_iter())?;
+        sst_writer.close()?;
 
-        // clear the memtable
-        self.memtable = Arc::new(RadixTree::new());
+        // clear the memtable, freeing the old nodes when nobody else holds on to them
+        match Arc::get_mut(&mut self.memtable) { 
+            Some(memtable) => memtable.clear(),
+            None => self.memtable = Arc::new(RadixTree::new())
+        }
+        self.memtable_bloom.clear();
+        self.memtable_tombstones.clear();
         self.memtable_bytes.store(0, Ordering::SeqCst);
 
         // rotate the wal
         let wal_path = self.dir.join("wal.log");
-        self.wal = WalWriter::open(wal_path, true)?;
-        let sst_reader = SSTReader::open(sst_path.clone())?;
+        self.wal = Some(WalWriter::open(wal_path, true)?);
+        let sst_reader = self.open_sst(sst_path.clone())?;
+        tracing::info!(entries, path = ?sst_path, "memtable flushed");
         self.sst_readers.push((sst_path, sst_reader));
+        self.forget_disk_usage();
+        Ok(())
+    }
+
+
+    pub fn config(&self) -> &Config { 
+        &self.cfg
+    }
+
+    /**
+     * Shuts the engine down, flushing the memtable so the directory holds only SSTables
+     * and an empty WAL.
+     */
+    pub fn close(mut self) -> std::io::Result<()> { 
+        if self.has_unflushed() { 
+            self.flush_memtable()?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Writes a consistent copy of the engine into `dest` that can be opened as an engine.
+     * * The memtable is flushed first so that everything lives in SSTables, which are then
+     *   hard-linked (copied when linking fails) into `dest`. SSTables are never modified once
+     *   written, so the checkpoint stays valid while this engine keeps running.
+     */
+    pub fn checkpoint(&mut self, dest: &Path) -> std::io::Result<()> { 
+        if dest == self.dir { 
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, "checkpoint destination is the engine directory"));
+        }
+        if self.cfg.mode == EngineMode::InMemory { 
+            return Err(std::io::Error::new(ErrorKind::Unsupported, "an in-memory engine has no files to checkpoint"));
+        }
+        if self.has_unflushed() { 
+            self.flush_memtable()?;
+        }
+        create_dir_all(dest)?;
+        for (path, _) in &self.sst_readers { 
+            let target = dest.join(path.file_name().unwrap());
+            if hard_link(path, &target).is_err() { 
+                copy(path, &target)?;
+            }
+        }
         Ok(())
     }
 
+    /**
+     * Backs the engine up into `dest`, which can then be opened as an engine, without
+     * closing it.
+     * * Runs `checkpoint`, so the memtable is flushed and the SSTables are hard-linked or
+     *   copied under their names. Then the WAL is copied up to its end after the flush and a
+     *   `BACKUP_MANIFEST` naming every file and the LSN the backup covers is written.
+     * * The engine stays open and writable afterwards. The backup holds every write made
+     *   before the call; writes made after it returns, e.g. by threads that were waiting on
+     *   the engine's mutex meanwhile, go to the live WAL only and are not in the backup.
+     * * # Returns
+     * * `Err(InvalidInput)` and `Err(Unsupported)` like `checkpoint`.
+     */
+    pub fn online_backup(&mut self, dest: &Path) -> std::io::Result<BackupStats> { 
+        self.checkpoint(dest)?;
+        let mut stats = BackupStats { lsn: self.next_lsn.load(Ordering::SeqCst) - 1, ..BackupStats::default() };
+        let mut manifest = format!("lsn {}\n", stats.lsn);
+        for (path, _) in &self.sst_readers { 
+            let name = path.file_name().unwrap().to_string_lossy();
+            let len = std::fs::metadata(dest.join(name.as_ref()))?.len();
+            manifest.push_str(&format!("sst {} {}\n", name, len));
+            stats.sst_files += 1;
+            stats.sst_bytes += len;
+        }
+        if let Some(wal) = self.wal.as_mut() { 
+            stats.wal_bytes = wal.end()?;
+            let mut wal_copy = File::create(dest.join("wal.log"))?;
+            std::io::copy(&mut File::open(&self.wal_path)?.take(stats.wal_bytes), &mut wal_copy)?;
+            wal_copy.sync_all()?;
+            manifest.push_str(&format!("wal wal.log {}\n", stats.wal_bytes));
+        }
+        std::fs::write(dest.join("BACKUP_MANIFEST"), manifest)?;
+        tracing::info!(dest = ?dest, tables = stats.sst_files, lsn = stats.lsn, "online backup written");
+        Ok(stats)
+    }
+
+
+    /**
+     * Merges every SSTable into a single new one, the newest value of each key winning.
+     * * The memtable is merged in as well, after which a `WalOp::CompactionMarker` is
+     *   logged and the WAL is cut down to it with `WalWriter::truncate_up_to_marker`. The
+     *   memtable itself is left alone.
+     * * The I/O cost is checked before doing any work: the run is skipped when the
+     *   existing tables, or the merged output estimated by `SSTWriter::estimated_output_size`,
+     *   exceed `MAX_COMPACTION_BYTES`.
+     * * # Returns
+     * * `Ok(true)` if the tables were merged.
+     * * `Ok(false)` if there was nothing to merge or the run would be too large.
+     */
+    pub fn compact(&mut self) -> std::io::Result<bool> { 
+        if self.sst_readers.len() < 2 { 
+            return Ok(false);
+        }
+        let input_bytes: u64 = self.sst_readers.iter().map(|(_, reader)| reader.approx_size_bytes()).sum();
+        if input_bytes > MAX_COMPACTION_BYTES { 
+            return Ok(false);
+        }
+        // the memtable goes into the output too, so the WAL up to here can be dropped
+        self.resolve_merges()?;
+        let lsn_up_to = self.next_lsn.load(Ordering::SeqCst) - 1;
+        // every table is merged, so no tombstone is left with anything to hide
+        let mut merged = self.merge_sst_entries()?;
+        merged.extend(self.memtable.iter_all().into_iter().map(|(key, value)| (key, value.into())));
+        for key in &self.memtable_tombstones { 
+            merged.remove(key);
+        }
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = merged.into_iter().collect();
+        self.sort_entries(&mut entries);
+        if SSTWriter::estimated_output_size(&entries) > MAX_COMPACTION_BYTES { 
+            return Ok(false);
+        }
+        let sst_path = next_sst_path(&self.cfg, &mut self.next_sst_dir, &mut self.next_sst_seq);
+        let mut sst_writer = SSTWriter::open_with_config(sst_path.clone(), self.sst_config())?;
+        sst_writer.write_all(entries)?;
+        sst_writer.close()?;
+        let sst_reader = self.open_sst(sst_path.clone())?;
+        tracing::info!(tables = self.sst_readers.len(), input_bytes, path = ?sst_path, "sstables compacted");
+        for (path, _) in std::mem::replace(&mut self.sst_readers, vec![(sst_path, sst_reader)]) { 
+            if let Some(cache) = &self.block_cache { 
+                cache.evict_file(&path);
+            }
+            remove_file(path)?;
+        }
+        self.log_record(|wal, lsn| wal.append_compaction_marker(lsn, lsn_up_to))?;
+        if let Some(wal) = self.wal.as_mut() { 
+            wal.truncate_up_to_marker()?;
+        }
+        self.forget_disk_usage();
+        Ok(true)
+    }
+
+    /**
+     * Merges only the SSTables holding keys in `[start, end)` instead of every table like
+     * `compact`.
+     * * A table overlaps when its `[min_key, max_key]` meets the range. The tables between
+     *   the oldest and the newest overlapping one are merged too, whatever their keys, so the
+     *   output can take their place in the oldest-to-newest order without a table in between
+     *   coming out newer or older than it was. The inputs are merged with `SSTMerger` and
+     *   split into up to three tables: the keys before `start`, the keys in the range and
+     *   the keys from `end` on, so keys outside the range that shared a table with keys
+     *   inside it are rewritten, unchanged, into tables of their own.
+     * * The outputs are named after the newest input, `sst-<seq>-1.dat` to `sst-<seq>-3.dat`,
+     *   rather than with a fresh sequence number, so on the next open they still sort after
+     *   the tables older than the inputs and before the newer ones.
+     * * Tombstones are carried into the outputs, they may still hide keys in older tables.
+     *   When the oldest table is among the inputs there is nothing older left, and they are
+     *   dropped.
+     * * The memtable and the WAL are left alone, and no `MAX_COMPACTION_BYTES` check is made,
+     *   the caller picks the range.
+     * * # Returns
+     * * `Ok(())` also when no table overlaps the range, nothing is written then.
+     * * `Err(Unsupported)` with a comparator other than `LexicographicComparator`, which
+     *   `SSTMerger` cannot merge in.
+     */
+    pub fn compact_sst_range(&mut self, start: &[u8], end: &[u8]) -> std::io::Result<()> { 
+        if !self.comparator.is_lexicographic() { 
+            return Err(std::io::Error::new(ErrorKind::Unsupported, "range compaction needs the lexicographic comparator"));
+        }
+        let overlapping: Vec<usize> = self.sst_readers.iter().enumerate()
+            .filter(|(_, (_, reader))| reader.entry_count() > 0 && reader.min_key() < end && reader.max_key() >= start)
+            .map(|(idx, _)| idx)
+            .collect();
+        let (Some(&first), Some(&last)) = (overlapping.first(), overlapping.last()) else { 
+            return Ok(());
+        };
+        let sst_config = self.sst_config();
+        let newest = self.sst_readers[last].0.file_stem().unwrap().to_string_lossy().into_owned();
+        // one output per section: before `start`, inside the range, from `end` on
+        let mut outputs: [Option<(PathBuf, SSTWriter)>; 3] = [None, None, None];
+        let mut merger = SSTMerger::new(self.sst_readers[first..=last].iter_mut().map(|(_, reader)| reader).collect());
+        let merged = merger.merge_each(|key, value| { 
+            if value.is_none() && first == 0 { 
+                return Ok(());
+            }
+            let section = match (key.as_slice() < start, key.as_slice() < end) { 
+                (true, _) => 0,
+                (false, true) => 1,
+                (false, false) => 2
+            };
+            if outputs[section].is_none() { 
+                let sst_path = next_sst_dir(&self.cfg, &mut self.next_sst_dir).join(format!("{newest}-{}.dat", section + 1));
+                let sst_writer = SSTWriter::open_with_config(sst_path.clone(), sst_config.clone())?;
+                outputs[section] = Some((sst_path, sst_writer));
+            }
+            let (_, sst_writer) = outputs[section].as_mut().unwrap();
+            sst_writer.write_entries(std::iter::once((key, value)))
+        })?;
+        let mut tables = Vec::new();
+        for (sst_path, sst_writer) in outputs.into_iter().flatten() { 
+            sst_writer.close()?;
+            let sst_reader = self.open_sst(sst_path.clone())?;
+            tables.push((sst_path, sst_reader));
+        }
+        tracing::info!(tables = last - first + 1, outputs = tables.len(), entries = merged, "sstable range compacted");
+        for (path, _) in self.sst_readers.splice(first..=last, tables).collect::<Vec<_>>() { 
+            if let Some(cache) = &self.block_cache { 
+                cache.evict_file(&path);
+            }
+            remove_file(path)?;
+        }
+        self.forget_disk_usage();
+        Ok(())
+    }
+
+    /**
+     * Bulk-loads a two-column `key,value` CSV file straight into a new SSTable.
+     * * Fields are UTF-8 and may be wrapped in double quotes, with `""` standing for a quote,
+     *   to hold commas. A row repeating an earlier key replaces it.
+     * * The memtable is flushed first so the imported table is newer than every earlier
+     *   write, then the rows are written sorted to the table, skipping the memtable and the
+     *   per-key WAL records. A single `WalOp::SstImport` record names the table so that
+     *   `replay_records` keeps it ordered after the tables flushed before the import.
+     * * # Returns
+     * * `Ok(usize)` with the number of imported records.
+     * * `Err(InvalidData)` naming the line of a row that does not have exactly two
+     *   fields, or has an empty key.
+     */
+    pub fn import_csv(&mut self, path: &Path) -> std::io::Result<usize> { 
+        let mut rows = BTreeMap::new();
+        for (line_no, line) in BufReader::new(File::open(path)?).lines().enumerate() { 
+            let line = line?;
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+            if line.is_empty() { 
+                continue;
+            }
+            match parse_csv_row(line).as_deref() { 
+                Some([key, value]) if !key.is_empty() => { 
+                    rows.insert(key.as_bytes().to_vec(), value.as_bytes().to_vec());
+                },
+                _ => return Err(std::io::Error::new(ErrorKind::InvalidData, format!("invalid csv row on line {}", line_no + 1)))
+            }
+        }
+        if rows.is_empty() { 
+            return Ok(0);
+        }
+        if self.cfg.mode == EngineMode::InMemory { 
+            let imported = rows.len();
+            for (key, value) in rows { 
+                self.put(&key, &value)?;
+            }
+            return Ok(imported);
+        }
+        if self.has_unflushed() { 
+            self.flush_memtable()?;
+        }
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = rows.into_iter().collect();
+        self.sort_entries(&mut entries);
+        let imported = entries.len();
+        let sst_path = next_sst_path(&self.cfg, &mut self.next_sst_dir, &mut self.next_sst_seq);
+        // open transactions have to see every imported key as written
+        let written: Vec<Vec<u8>> = match self.txn_starts.is_empty() { 
+            true => Vec::new(),
+            false => entries.iter().map(|(key, _)| key.clone()).collect()
+        };
+        let mut sst_writer = SSTWriter::open_with_config(sst_path.clone(), self.sst_config())?;
+        sst_writer.write_all(entries)?;
+        sst_writer.close()?;
+        let file_name = sst_path.file_name().unwrap().as_encoded_bytes().to_vec();
+        self.log_record(|wal, lsn| wal.append_sst_import(lsn, &file_name))?;
+        for key in written { 
+            self.note_write(&key);
+        }
+        let sst_reader = self.open_sst(sst_path.clone())?;
+        tracing::info!(entries = imported, path = ?sst_path, "csv imported");
+        self.sst_readers.push((sst_path, sst_reader));
+        self.forget_disk_usage();
+        Ok(imported)
+    }
+
+    /**
+     * Writes every live pair to `dest` as JSON lines, `{"key":"<hex>","value":"<hex>"}`,
+     * in key order. Keys and values are hex-encoded so arbitrary bytes survive.
+     * * SSTables are read oldest to newest and the memtable is laid on top, so each key is
+     *   written once with its newest value, see `import_json` for the way back.
+     * * # Returns
+     * * `Ok(usize)` with the number of records written.
+     */
+    pub fn export_json(&mut self, dest: &Path) -> std::io::Result<usize> { 
+        let entries = self.all_entries()?;
+        let mut out = BufWriter::new(File::create(dest)?);
+        for (key, value) in &entries { 
+            writeln!(out, "{{\"key\":\"{}\",\"value\":\"{}\"}}", to_hex(key), to_hex(value))?;
+        }
+        out.flush()?;
+        out.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+        Ok(entries.len())
+    }
+
+    /**
+     * Reads a file written by `export_json` and `put`s every record in it.
+     * * # Returns
+     * * `Ok(usize)` with the number of records imported.
+     * * `Err(InvalidData)` naming the first line that is not a record with hex `key`
+     *   and `value` fields; the records before it are imported.
+     */
+    pub fn import_json(&mut self, src: &Path) -> std::io::Result<usize> { 
+        let mut imported = 0;
+        for (line_no, line) in BufReader::new(File::open(src)?).lines().enumerate() { 
+            let line = line?;
+            if line.trim().is_empty() { 
+                continue;
+            }
+            let field = |name| json_string_field(&line, name).and_then(from_hex);
+            let (Some(key), Some(value)) = (field("key"), field("value")) else { 
+                return Err(std::io::Error::new(ErrorKind::InvalidData, format!("invalid json record on line {}", line_no + 1)));
+            };
+            self.put(&key, &value)?;
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    /**
+     * Every live pair in the engine, newest value per key, in the comparator's order.
+     */
+    fn all_entries(&mut self) -> std::io::Result<Vec<(Vec<u8>, Vec<u8>)>> { 
+        let mut merged = self.merge_sst_entries()?;
+        merged.extend(self.memtable.iter_all().into_iter().map(|(key, value)| (key, value.into())));
+        for key in &self.memtable_tombstones { 
+            merged.remove(key);
+        }
+        let merging: Vec<Vec<u8>> = self.pending_merges.keys().cloned().collect();
+        for key in merging { 
+            let stored = merged.remove(&key);
+            if let Some(value) = self.apply_merges(&key, stored)? { 
+                merged.insert(key, value);
+            }
+        }
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = merged.into_iter().collect();
+        self.sort_entries(&mut entries);
+        Ok(entries)
+    }
+
+    /**
+     * The newest live value of every key in the SSTables, read oldest to newest so that a
+     * later value or tombstone replaces an earlier one. The memtable is not included.
+     */
+    fn merge_sst_entries(&mut self) -> std::io::Result<BTreeMap<Vec<u8>, Vec<u8>>> { 
+        let mut merged = BTreeMap::new();
+        for (_, sst_reader) in self.sst_readers.iter_mut() { 
+            for record in sst_reader.iter().with_tombstones() { 
+                match record? { 
+                    (key, Some(value)) => merged.insert(key, value),
+                    (key, None) => merged.remove(&key)
+                };
+            }
+        }
+        Ok(merged)
+    }
+
+    /**
+     * Makes the SSTable named by a `WalOp::SstImport` record the newest one, opening it
+     * if it was not picked up from the directory. A table that is missing or unreadable
+     * is skipped with a warning, its rows are lost.
+     */
+    fn reload_imported_sst(&mut self, file_name: &[u8]) -> std::io::Result<()> { 
+        let name = String::from_utf8_lossy(file_name).into_owned();
+        let position = self.sst_readers.iter().position(|(path, _)| path.file_name().is_some_and(|n| *n == *name));
+        // the table can be in any of the directories
+        let sst_path = sst_dirs(&self.cfg).map(|dir| dir.join(&name)).find(|path| path.exists())
+            .unwrap_or_else(|| self.dir.join(&name));
+        let table = match position { 
+            Some(position) => self.sst_readers.remove(position),
+            None => match self.open_sst(sst_path.clone()) { 
+                Ok(sst_reader) => (sst_path, sst_reader),
+                Err(err) => { 
+                    tracing::warn!(path = ?sst_path, error = %err, "imported sstable named in the wal is unreadable");
+                    return Ok(());
+                }
+            }
+        };
+        self.sst_readers.push(table);
+        Ok(())
+    }
+
+    /**
+     * Returns every live pair whose key falls in `[start, end)`, in key order.
+     * * SSTables are read oldest to newest through `range_iter`, then the memtable is
+     *   laid on top, so the newest version of each key wins just like in `get`.
+     */
+    pub fn scan(&mut self, start: &[u8], end: &[u8]) -> std::io::Result<Vec<(Vec<u8>, Vec<u8>)>> { 
+        self.scan_bounded(start, Some(end))
+    }
+
+    /**
+     * Returns every live pair whose key is `>= start`, in key order, see `scan`.
+     */
+    pub fn scan_from(&mut self, start: &[u8]) -> std::io::Result<Vec<(Vec<u8>, Vec<u8>)>> { 
+        self.scan_bounded(start, None)
+    }
+
+    /**
+     * The keys `scan(start, end)` would return, without reading a single value.
+     * * SSTable keys come from their index blocks through `SSTReader::keys_in_range`, and
+     *   the memtable is walked under the longest prefix `start` and `end` share, like in
+     *   `scan`. Keys with pending merge operands are included without folding them.
+     */
+    pub fn scan_keys_only(&mut self, start: &[u8], end: &[u8]) -> KeyOnlyIter { 
+        match self.keys_in_range(start, end) { 
+            Ok(keys) => KeyOnlyIter { keys: keys.into_iter(), error: None },
+            Err(err) => KeyOnlyIter { keys: Vec::new().into_iter(), error: Some(err) }
+        }
+    }
+
+    fn keys_in_range(&mut self, start: &[u8], end: &[u8]) -> std::io::Result<Vec<Vec<u8>>> { 
+        let comparator = Arc::clone(&self.comparator);
+        let in_range = |key: &[u8]| comparator.compare(key, start) != CmpOrdering::Less
+            && comparator.compare(key, end) == CmpOrdering::Less;
+        let mut merged = BTreeSet::new();
+        for (_, sst_reader) in self.sst_readers.iter_mut() { 
+            if comparator.compare(end, sst_reader.min_key()) != CmpOrdering::Greater
+                || comparator.compare(start, sst_reader.max_key()) == CmpOrdering::Greater { 
+                continue;
+            }
+            for (key, deleted) in sst_reader.key_states_in_range(start, end)? { 
+                match deleted { 
+                    true => merged.remove(&key),
+                    false => merged.insert(key)
+                };
+            }
+        }
+        let shared = match comparator.is_lexicographic() { 
+            true => start.iter().zip(end).take_while(|(a, b)| a == b).count(),
+            false => 0
+        };
+        merged.extend(self.memtable.prefix_iter(&start[..shared]).map(|(key, _)| key).filter(|key| in_range(key)));
+        for key in self.memtable_tombstones.iter().filter(|key| in_range(key)) { 
+            merged.remove(key);
+        }
+        merged.extend(self.pending_merges.keys().filter(|key| in_range(key)).cloned());
+        let mut keys: Vec<Vec<u8>> = merged.into_iter().collect();
+        if !comparator.is_lexicographic() { 
+            keys.sort_by(|a, b| comparator.compare(a, b));
+        }
+        Ok(keys)
+    }
+
+    fn scan_bounded(&mut self, start: &[u8], end: Option<&[u8]>) -> std::io::Result<Vec<(Vec<u8>, Vec<u8>)>> { 
+        let comparator = Arc::clone(&self.comparator);
+        let in_range = |key: &[u8]| comparator.compare(key, start) != CmpOrdering::Less
+            && end.is_none_or(|end| comparator.compare(key, end) == CmpOrdering::Less);
+        let mut merged = BTreeMap::new();
+        for (_, sst_reader) in self.sst_readers.iter_mut() { 
+            if end.is_some_and(|end| comparator.compare(end, sst_reader.min_key()) != CmpOrdering::Greater)
+                || comparator.compare(start, sst_reader.max_key()) == CmpOrdering::Greater { 
+                continue;
+            }
+            let iter: RecordIter = match end { 
+                Some(end) => Box::new(sst_reader.range_iter(start, end).with_tombstones()),
+                None => Box::new(sst_reader.iter().with_tombstones().filter(|record| !matches!(record, Ok((key, _)) if !in_range(key))))
+            };
+            for record in iter { 
+                match record? { 
+                    (key, Some(value)) => merged.insert(key, value),
+                    (key, None) => merged.remove(&key)
+                };
+            }
+        }
+        // byte prefixes only bound the range when the comparator is byte order
+        let shared = match end { 
+            Some(end) if comparator.is_lexicographic() => start.iter().zip(end).take_while(|(a, b)| a == b).count(),
+            _ => 0
+        };
+        for (key, value) in self.memtable.prefix_iter(&start[..shared]) { 
+            if in_range(&key) { 
+                merged.insert(key, value.into());
+            }
+        }
+        for key in self.memtable_tombstones.iter().filter(|key| in_range(key)) { 
+            merged.remove(key);
+        }
+        let merging: Vec<Vec<u8>> = self.pending_merges.keys()
+            .filter(|key| in_range(key))
+            .cloned()
+            .collect();
+        for key in merging { 
+            let stored = merged.remove(&key);
+            if let Some(value) = self.apply_merges(&key, stored)? { 
+                merged.insert(key, value);
+            }
+        }
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = merged.into_iter().collect();
+        self.sort_entries(&mut entries);
+        Ok(entries)
+    }
+
 
     /**
      * Searches for a key across all storage layers.
      * * # Search Order:
      * 1. **Memtable:** Checks the latest in-memory writes.
      * 2. **SSTables:** If not found, searches SSTables from newest to oldest 
-     * (reverse order) to ensure the most recent version of a key is returned.
+     *    (reverse order) to ensure the most recent version of a key is returned.
+     *    Tables whose `[min_key, max_key]` range does not contain the key are skipped.
      */
     pub fn get(&mut self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> { 
-        // lets do full scan of the memtable first
-        let result = self.memtable.get(key);
-        if let Err(err) = result { 
-            return Err(std::io::Error::new::<String>(ErrorKind::InvalidInput, err.into()));
+        let stored = self.get_stored(key)?;
+        self.apply_merges(key, stored)
+    }
+
+    /**
+     * `put` for string keys and values, returning the previous value as a `String`.
+     * * The pair is stored as its UTF-8 bytes, so it reads back through `get` as well.
+     * * # Returns
+     * * `Err(InvalidData)` if the previous value is not valid UTF-8. The new value has
+     *   been written by then.
+     */
+    pub fn put_str(&mut self, key: &str, val: &str) -> std::io::Result<Option<String>> { 
+        let old_value = self.put(key.as_bytes(), val.as_bytes())?;
+        utf8_value(old_value)
+    }
+
+    /**
+     * `get` for string keys, returning the value as a `String`.
+     * * # Returns
+     * * `Err(InvalidData)` if the stored value is not valid UTF-8, e.g. written through
+     *   `put` with arbitrary bytes.
+     */
+    pub fn get_str(&mut self, key: &str) -> std::io::Result<Option<String>> { 
+        let value = self.get(key.as_bytes())?;
+        utf8_value(value)
+    }
+
+    /**
+     * Returns the value of `key`, first inserting the one `f` makes if the key has none.
+     * * The lookup is a `get`, memtable first, then the SSTables newest to oldest, and `f`
+     *   is only called when it finds nothing. Holding `&mut self` keeps anything else from
+     *   writing the key between the lookup and the insert; an engine shared between threads
+     *   needs a `Transaction` for the same guarantee, since the lock would be released in
+     *   between.
+     * * # Returns
+     * * `Ok(Vec<u8>)` with the existing value, or the inserted one.
+     * * `Err` if the lookup or the insert fails, `f` is not called when the lookup fails.
+     */
+    pub fn get_or_insert_with<F: FnOnce() -> Vec<u8>>(&mut self, key: &[u8], f: F) -> std::io::Result<Vec<u8>> { 
+        if let Some(value) = self.get(key)? { 
+            return Ok(value);
+        }
+        let value = f();
+        self.put(key, &value)?;
+        Ok(value)
+    }
+
+    /**
+     * Whether `get(key)` would return a value, without reading the value from an SSTable.
+     * * The memtable is checked first, then the SSTables newest to oldest through
+     *   `SSTReader::key_state`, stopping at the first tombstone. A key with pending merge
+     *   operands always exists.
+     */
+    pub fn contains_key(&mut self, key: &[u8]) -> std::io::Result<bool> { 
+        if self.pending_merges.contains_key(key) { 
+            return Ok(true);
+        }
+        if self.memtable_bloom.may_contain(key) { 
+            if self.memtable.get(key)?.is_some() { 
+                return Ok(true);
+            }
+            if self.memtable_tombstones.contains(key) { 
+                return Ok(false);
+            }
         }
-        if let Ok(Some(val)) = result { 
-            return Ok(Some(val));
+        for (_, sst_reader) in self.sst_readers.iter_mut().rev() { 
+            match sst_reader.key_state(key)? { 
+                KeyState::Live => return Ok(true),
+                KeyState::Deleted => return Ok(false),
+                KeyState::Absent => {}
+            }
+        }
+        Ok(false)
+    }
+
+    fn get_stored(&mut self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> { 
+        // the memtable is only walked for keys that may have been put into it
+        if self.memtable_bloom.may_contain(key) { 
+            if let Some(val) = self.memtable.get(key)? { 
+                return Ok(Some(val.into()));
+            }
+            if self.memtable_tombstones.contains(key) { 
+                return Ok(None);
+            }
         }
         for &mut (_, ref mut sst_reader) in self.sst_readers.iter_mut().rev() { 
-            if let Some(val) = sst_reader.get(key)? { 
-                return Ok(Some(val));
+            // skip tables whose key range can not hold the key
+            if self.comparator.compare(key, sst_reader.min_key()) == CmpOrdering::Less
+                || self.comparator.compare(key, sst_reader.max_key()) == CmpOrdering::Greater { 
+                continue;
+            }
+            // a tombstone hides the key in every older table
+            if let Some(val) = sst_reader.get_entry(key)? { 
+                return Ok(val);
             }
         }
         
@@ -180,56 +1077,502 @@ impl Engine {
      * 2. Writes the operation to the WAL first (Write-Ahead) for durability.
      * 3. Updates the in-memory RadixTree.
      * 4. Increments the global LSN.
+     * 5. Flushes the memtable if the WAL reached `max_wal_bytes`, so the WAL stays bounded
+     *    even while overwrites keep the memtable small.
+     * * # Returns
+     * * `Ok(Some(Vec<u8>))` with the previous value if the key was already in the memtable.
+     * * `Ok(None)` if the key is new to the memtable.
+     * * `memtable_bytes` is adjusted by the net delta, so overwriting a key with a
+     *   differently-sized value does not drift the counter.
      */
     pub fn put(&mut self, key: &[u8], val: &[u8]) -> std::io::Result<Option<Vec<u8>>> {     
-        // check wheather the memtable is full
+        self.put_value(key, Bytes::copy_from_slice(val))
+    }
+
+    /**
+     * Same as `put` for a caller already holding its key and value as `Bytes`.
+     * * The memtable keeps a handle on `val` itself instead of a copy, and the WAL record
+     *   is written straight from both buffers.
+     */
+    pub fn put_bytes(&mut self, key: Bytes, val: Bytes) -> std::io::Result<Option<Vec<u8>>> { 
+        self.put_value(&key, val)
+    }
+
+    fn put_value(&mut self, key: &[u8], val: Bytes) -> std::io::Result<Option<Vec<u8>>> { 
+        // check wheather the memtable is full, an overwrite only adds the size difference;
+        // the pair it replaces is only looked up when the put could fill the memtable
         let curr_memtable_bytes = self.memtable_bytes.load(Ordering::SeqCst);
-        println!("current memtable bytes : {curr_memtable_bytes}");
         if curr_memtable_bytes + key.len() + val.len() >= self.cfg.memtable_max_bytes { 
+            let replaced = self.memtable.get(key)?.map_or(0, |old| key.len() + old.len());
+            if (curr_memtable_bytes + key.len() + val.len()).saturating_sub(replaced) >= self.cfg.memtable_max_bytes { 
+                self.flush_memtable()?;
+            }
+        }
+
+        self.pending_merges.remove(key);
+        let old_value = self.memtable_put(key, val.clone())?;
+        let wal_end = self.log_record(|wal, lsn| wal.append_put(lsn, key, &val))?;
+        self.note_write(key);
+        if let (Some(wal_end), Some(max)) = (wal_end, self.cfg.max_wal_bytes) && wal_end >= max as u64 { 
             self.flush_memtable()?;
         }
+        Ok(old_value.map(Vec::from))
+    }
 
-        match self.memtable.put(key, val.to_vec()) { 
-            Ok(Some(value)) => { 
-                println!("ncrementing the memtable bytes");
-                self.memtable_bytes.fetch_add(key.len() + val.len(), Ordering::SeqCst);
-                let next_lsn = self.next_lsn.fetch_add(1 as u64, Ordering::SeqCst);               
-                self.wal.append_put(next_lsn, key, &val)?;
-                return Ok(Some(value));
-            },
-            Ok(None) =>  { 
-                self.memtable_bytes.fetch_add(key.len() + val.len(), Ordering::SeqCst);
-                let next_lsn = self.next_lsn.fetch_add(1 as u64, Ordering::SeqCst);
-                self.wal.append_put(next_lsn , key, &val)?;
-                Ok(None)
-            },
-            Err(e) => Err(std::io::Error::new::<String>(ErrorKind::Other, e.into())) 
+    /**
+     * Puts `value` into the memtable and moves `memtable_bytes` by the net change, the
+     * pair it replaces, if any, coming off the new one.
+     * * # Returns
+     * * `Ok(Some(Bytes))` with the replaced value.
+     */
+    fn memtable_put(&mut self, key: &[u8], value: Bytes) -> std::io::Result<Option<Bytes>> { 
+        let added = key.len() + value.len();
+        self.memtable_bloom.insert(key);
+        let old_value = self.memtable.replace(key, value)?;
+        let mut removed = old_value.as_ref().map_or(0, |old| key.len() + old.len());
+        if self.memtable_tombstones.remove(key) { 
+            removed += key.len();
         }
+        self.adjust_memtable_bytes(added, removed);
+        Ok(old_value)
     }
 
+    /**
+     * Removes `key` from the memtable like `memtable_remove` and, when the key range of an
+     * SSTable holds the key, records a tombstone for the next flush so that the older
+     * value stays hidden. A tombstone counts its key towards `memtable_bytes`.
+     */
+    fn memtable_delete(&mut self, key: &[u8]) -> std::io::Result<Option<Bytes>> { 
+        let removed = self.memtable_remove(key)?;
+        let comparator = &self.comparator;
+        let shadowed = self.sst_readers.iter().any(|(_, sst_reader)| { 
+            comparator.compare(key, sst_reader.min_key()) != CmpOrdering::Less
+                && comparator.compare(key, sst_reader.max_key()) != CmpOrdering::Greater
+        });
+        if shadowed && self.memtable_tombstones.insert(key.to_vec()) { 
+            self.memtable_bloom.insert(key);
+            self.adjust_memtable_bytes(key.len(), 0);
+        }
+        Ok(removed)
+    }
+
+    /**
+     * Removes `key` from the memtable, taking its pair off `memtable_bytes`.
+     */
+    fn memtable_remove(&mut self, key: &[u8]) -> std::io::Result<Option<Bytes>> { 
+        let removed = self.memtable.remove(key)?;
+        if let Some(old) = &removed { 
+            self.adjust_memtable_bytes(0, key.len() + old.len());
+        }
+        Ok(removed)
+    }
+
+    /**
+     * Applies a change of `added - removed` bytes, which may be negative, to `memtable_bytes`.
+     */
+    fn adjust_memtable_bytes(&self, added: usize, removed: usize) { 
+        match added >= removed { 
+            true => self.memtable_bytes.fetch_add(added - removed, Ordering::SeqCst),
+            false => self.memtable_bytes.fetch_sub(removed - added, Ordering::SeqCst)
+        };
+    }
+
+    /**
+     * Returns the number of key and value bytes currently held by the memtable, the keys
+     * of its tombstones included.
+     */
+    pub fn memtable_bytes(&self) -> usize { 
+        self.memtable_bytes.load(Ordering::SeqCst)
+    }
+
+    /**
+     * Approximate number of key and value bytes held by the memtable, like `memtable_bytes`
+     * but read without ordering against concurrent writes.
+     */
+    pub fn approximate_memtable_usage(&self) -> usize { 
+        self.memtable_bytes.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Bytes the engine takes on disk: the size of the WAL plus the size of every SSTable.
+     * * The total is cached for up to a second so that polling it does not `stat` every
+     *   file on each call, which means WAL appends can show up late. Flushes, compactions
+     *   and imports drop the cached total since they change the set of tables. An
+     *   in-memory engine has nothing on disk and reports 0.
+     */
+    pub fn approximate_disk_usage(&self) -> std::io::Result<u64> { 
+        let mut cached = self.disk_usage.lock().unwrap();
+        if let Some((taken, total)) = *cached && taken.elapsed() < DISK_USAGE_TTL { 
+            return Ok(total);
+        }
+        let mut total = 0;
+        if self.wal.is_some() { 
+            total += std::fs::metadata(&self.wal_path)?.len();
+        }
+        for (path, _) in &self.sst_readers { 
+            total += std::fs::metadata(path)?.len();
+        }
+        *cached = Some((Instant::now(), total));
+        Ok(total)
+    }
+
+    /**
+     * How long ago the oldest live SSTable was written, for telling whether compaction
+     * keeps up with flushes.
+     * * The creation time comes from the file metadata. Where the platform does not record
+     *   one, the modification time is used instead; SSTables are never written to after
+     *   `close`, so the two agree. File names carry a sequence number, not a timestamp, so
+     *   they are not consulted.
+     * * # Returns
+     * * `None` if there are no SSTables, or none of them could be `stat`ed.
+     */
+    pub fn oldest_sst_age(&self) -> Option<Duration> { 
+        let oldest = self.sst_readers.iter()
+            .filter_map(|(path, _)| { 
+                let metadata = std::fs::metadata(path).ok()?;
+                metadata.created().or_else(|_| metadata.modified()).ok()
+            })
+            .min()?;
+        Some(SystemTime::now().duration_since(oldest).unwrap_or_default())
+    }
+
+    fn forget_disk_usage(&mut self) { 
+        *self.disk_usage.get_mut().unwrap() = None;
+    }
+
+    /**
+     * Estimates how many keys start with `prefix` without reading any value.
+     * * The memtable part is exact, it is counted by walking the radix tree under the prefix.
+     *   Each SSTable is counted from its index blocks, which is exact for that table, but the
+     *   counts are added up without merging: a key living in the memtable and in SSTables, or
+     *   in several SSTables, is counted once per place, and keys deleted after they were
+     *   flushed are still counted. The result is therefore an upper bound on the live keys,
+     *   use `scan` when the exact number matters.
+     */
+    pub fn prefix_count(&mut self, prefix: &[u8]) -> std::io::Result<usize> { 
+        let mut count = self.memtable.prefix_count(prefix);
+        for (_, sst_reader) in self.sst_readers.iter_mut() { 
+            count += sst_reader.prefix_count(prefix)?;
+        }
+        Ok(count)
+    }
+
+    /**
+     * Approximate number of keys in the engine, without reading any record.
+     * * Adds the key count of the memtable to the record counts of the SSTables. The
+     *   result is an upper bound: a key living in the memtable and in SSTables, or in several
+     *   tables, is counted once per place, and deleted keys stay counted in the tables.
+     * * Never more than the number of writes that went into the engine, since every
+     *   counted entry was written at least once.
+     */
+    pub fn key_count_estimate(&self) -> usize { 
+        let sst_entries: u64 = self.sst_readers.iter().map(|(_, sst_reader)| sst_reader.entry_count()).sum();
+        self.memtable.count() + sst_entries as usize
+    }
+
+    /**
+     * Returns the number and size of the SSTables, the memtable and the WAL.
+     */
+    pub fn stats(&self) -> EngineStats { 
+        let wal_bytes = match self.wal { 
+            Some(_) => self.wal_path.metadata().map(|meta| meta.len()).unwrap_or(0),
+            None => 0
+        };
+        EngineStats { 
+            sst_tables: self.sst_readers.len(),
+            sst_bytes: self.sst_readers.iter().map(|(_, reader)| reader.approx_size_bytes()).sum(),
+            memtable_bytes: self.memtable_bytes(),
+            wal_bytes,
+            pending_merges: self.pending_merges.len()
+        }
+    }
+
+
+    /**
+     * Reads back every WAL record and every SSTable record and checks their checksums.
+     * * The WAL is checked by `WalReader::validate` and every table by
+     *   `SSTReader::validate_all`, which reads each record through the offset stored in the
+     *   index. A table whose index itself can not be read counts as corrupted.
+     * * Nothing is repaired and the engine keeps serving reads, so this can run as a
+     *   periodic health check or after a crash to see what recovery will lose.
+     */
+    pub fn verify_integrity(&mut self) -> std::io::Result<IntegrityReport> { 
+        let mut report = IntegrityReport::default();
+        if self.wal.is_some() { 
+            (report.wal_valid_records, report.wal_corrupted) = WalReader::open(&self.wal_path)?.validate()?;
+        }
+        for (path, sst_reader) in self.sst_readers.iter_mut() { 
+            match sst_reader.validate_all() { 
+                Ok(corrupted) if corrupted.is_empty() => report.sst_valid_files += 1,
+                Ok(_) => report.sst_corrupted.push(path.clone()),
+                Err(err) if matches!(err.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof) => report.sst_corrupted.push(path.clone()),
+                Err(err) => return Err(err)
+            }
+        }
+        if !report.wal_corrupted.is_empty() || !report.sst_corrupted.is_empty() { 
+            tracing::warn!(wal_records = ?report.wal_corrupted, tables = ?report.sst_corrupted, "integrity check found corruption");
+        }
+        Ok(report)
+    }
 
     /**
      * Removes a key from the engine.
      * * Similar to `put`, it logs a `Delete` operation to the WAL and 
-     * removes the key from the memtable. 
-     * * Note: For full LSM-tree correctness, deletes in SSTables are usually 
-     * handled via "tombstones" during compaction.: Not yet implemented : to be done
+     *   removes the key from the memtable. 
+     * * A key that may still live in an SSTable gets a tombstone, which the next flush
+     *   writes out so that `get` and `scan` stop at it instead of finding the older value.
+     *   Compaction drops tombstones once it merges down to the oldest table.
+     * * # Returns
+     * * `Ok(Some(Vec<u8>))` with the value the memtable held, values only in SSTables are
+     *   not read.
      */
     pub fn delete(&mut self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> {     
-        
-        match self.memtable.remove(key) { 
-            Ok(Some(value)) => { 
-                println!("ncrementing the memtable bytes");
-                let next_lsn = self.next_lsn.fetch_add(1 as u64, Ordering::SeqCst);               
-                self.wal.append_delete(next_lsn, key)?;
-                return Ok(Some(value));
-            },
-            Ok(None) =>  { 
-                let next_lsn = self.next_lsn.fetch_add(1 as u64, Ordering::SeqCst);
-                self.wal.append_delete(next_lsn , key)?;
-                Ok(None)
-            },
-            Err(e) => Err(std::io::Error::new::<String>(ErrorKind::Other, e.into())) 
-        }
-    }
-} 
+        let removed = self.memtable_delete(key)?;
+        self.pending_merges.remove(key);
+        self.log_record(|wal, lsn| wal.append_delete(lsn, key))?;
+        self.note_write(key);
+        Ok(removed.map(Vec::from))
+    }
+
+    /**
+     * Sets the function `merge` folds operands with. It is called with the current value
+     * of the key, empty if there is none, and the operand, and returns the new value.
+     * * Operands read back from the WAL on `open` are kept until an operator is set.
+     */
+    pub fn set_merge_operator<F>(&mut self, f: F) 
+    where 
+        F: Fn(&[u8], &[u8]) -> Vec<u8> + Send + Sync + 'static
+    { 
+        self.merge_operator = Some(Box::new(f));
+    }
+
+    /**
+     * Records `operand` to be folded into the value of `key` by the merge operator.
+     * * The operand is logged as a `WalOp::Merge` record and kept pending; `get` and `scan`
+     *   apply pending operands left to right, a flush folds them into the stored value.
+     *   A later `put` or `delete` of the key discards them.
+     * * Fails with `InvalidInput` if no merge operator is set.
+     */
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> std::io::Result<()> { 
+        if self.merge_operator.is_none() { 
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, "no merge operator set"));
+        }
+        if key.is_empty() { 
+            return Err(RadixError::InvalidKey.into());
+        }
+        self.log_record(|wal, lsn| wal.append_merge(lsn, key, operand))?;
+        self.note_write(key);
+        self.pending_merges.entry(key.to_vec()).or_default().push(operand.to_vec());
+        Ok(())
+    }
+
+    fn apply_merges(&self, key: &[u8], stored: Option<Vec<u8>>) -> std::io::Result<Option<Vec<u8>>> { 
+        let Some(operands) = self.pending_merges.get(key) else { 
+            return Ok(stored);
+        };
+        let Some(operator) = self.merge_operator.as_ref() else { 
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, "pending merge operands but no merge operator set"));
+        };
+        let merged = operands.iter().fold(stored.unwrap_or_default(), |value, operand| operator(&value, operand));
+        Ok(Some(merged))
+    }
+
+    /**
+     * Folds every pending merge operand into the memtable.
+     */
+    fn resolve_merges(&mut self) -> std::io::Result<()> { 
+        let keys: Vec<Vec<u8>> = self.pending_merges.keys().cloned().collect();
+        for key in keys { 
+            let Some(value) = self.get(&key)? else { continue };
+            self.memtable_put(&key, value.into())?;
+            self.pending_merges.remove(&key);
+        }
+        Ok(())
+    }
+
+    /**
+     * Writes `val` as the version of `key` at `ts`, leaving older versions readable
+     * through `get_ts`.
+     * * Versioned keys share the key space with plain ones, a key should be written either
+     *   through `put` or through `put_ts`, not both.
+     */
+    pub fn put_ts(&mut self, key: &[u8], val: &[u8], ts: Timestamp) -> std::io::Result<()> { 
+        self.write_version(key, Some(val), ts)
+    }
+
+    /**
+     * Records that `key` is deleted as of `ts`. Reads at earlier timestamps still see
+     * the older versions.
+     */
+    pub fn delete_ts(&mut self, key: &[u8], ts: Timestamp) -> std::io::Result<()> { 
+        self.write_version(key, None, ts)
+    }
+
+    fn write_version(&mut self, key: &[u8], val: Option<&[u8]>, ts: Timestamp) -> std::io::Result<()> { 
+        let versioned = versioned_key(key, ts);
+        let stored = encode_version(val);
+        if self.memtable_bytes.load(Ordering::SeqCst) + versioned.len() + stored.len() >= self.cfg.memtable_max_bytes { 
+            self.flush_memtable()?;
+        }
+        self.memtable_put(&versioned, stored.into())?;
+        self.log_record(|wal, lsn| match val { 
+            Some(val) => wal.append_put_ts(lsn, &versioned, val),
+            None => wal.append_delete_ts(lsn, &versioned)
+        })?;
+        self.note_write(&versioned);
+        Ok(())
+    }
+
+    /**
+     * Returns the newest version of `key` written at or before `ts`.
+     * * All versions up to `ts` are gathered with `scan` over `[key ++ 0, key ++ ts]`,
+     *   the last one wins. `Ok(None)` if there is none or it is a delete.
+     */
+    pub fn get_ts(&mut self, key: &[u8], ts: Timestamp) -> std::io::Result<Option<Vec<u8>>> { 
+        let start = versioned_key(key, Timestamp(0));
+        // the extra byte makes the exclusive end cover `key ++ ts` itself
+        let mut end = versioned_key(key, ts);
+        end.push(0);
+        let newest = self.scan(&start, &end)?
+            .into_iter()
+            .rev()
+            .find(|(versioned, _)| split_versioned_key(versioned).is_some_and(|(k, _)| k == key));
+        Ok(newest.and_then(|(_, stored)| decode_version(&stored)))
+    }
+
+    /**
+     * Returns a read handle whose reads only see versions written at or before `ts`.
+     */
+    pub fn new_snapshot(&mut self, ts: Timestamp) -> EngineSnapshot<'_> { 
+        EngineSnapshot::new(self, ts)
+    }
+}
+
+/**
+ * Opens the SSTable at `path` with the engine's comparator, reading through
+ * `block_cache` if there is one.
+ */
+fn open_sst(path: PathBuf, comparator: &Arc<dyn Comparator>, block_cache: &Option<Arc<BlockCache>>) -> std::io::Result<SSTReader> { 
+    let sst_reader = SSTReader::open_with_comparator(path, Arc::clone(comparator))?;
+    Ok(match block_cache { 
+        Some(cache) => sst_reader.with_block_cache(Arc::clone(cache)),
+        None => sst_reader
+    })
+}
+
+/**
+ * Directories SSTables live in: `dir` first, then `data_dirs`.
+ */
+fn sst_dirs(cfg: &Config) -> impl Iterator<Item = &PathBuf> { 
+    std::iter::once(&cfg.dir).chain(&cfg.data_dirs)
+}
+
+/**
+ * Directory for a new SSTable, the one after the directory the previous table went to.
+ */
+fn next_sst_dir<'a>(cfg: &'a Config, next_dir: &mut usize) -> &'a PathBuf { 
+    let dir = sst_dirs(cfg).nth(*next_dir % (cfg.data_dirs.len() + 1)).unwrap();
+    *next_dir = next_dir.wrapping_add(1);
+    dir
+}
+
+/**
+ * Path for a new SSTable, in the directory picked by `next_sst_dir`.
+ * * Tables are named `sst-<seq>.dat` with a zero-padded sequence number that only grows,
+ *   so a newer table always sorts after an older one, see `sst_seq`.
+ */
+fn next_sst_path(cfg: &Config, next_dir: &mut usize, next_seq: &mut u64) -> PathBuf { 
+    let seq = *next_seq;
+    *next_seq += 1;
+    next_sst_dir(cfg, next_dir).join(format!("sst-{:020}.dat", seq))
+}
+
+/**
+ * The numbers in an SSTable's `sst-<n>[-<n>...].dat` name, which order the tables oldest
+ * to newest whichever directory they live in.
+ * * Compared as numbers rather than as text, so names written before the sequence was
+ *   zero-padded still sort among the padded ones.
+ * * # Returns
+ * * An empty `Vec` for a name that is not made of numbers, it sorts before every table.
+ */
+fn sst_seq(path: &Path) -> Vec<u64> { 
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix("sst-"))
+        .and_then(|name| name.strip_suffix(".dat"))
+        .and_then(|stem| stem.split('-').map(|n| n.parse().ok()).collect::<Option<Vec<u64>>>())
+        .unwrap_or_default()
+}
+
+/**
+ * Bloom filter for the keys of a memtable, one bit per byte the memtable may hold before
+ * it is flushed. That is 16 bits for a 16 byte entry, entries are rarely smaller.
+ */
+fn memtable_bloom(cfg: &Config) -> BloomFilter { 
+    BloomFilter::new(cfg.memtable_max_bytes.max(1024), 4)
+}
+
+fn utf8_value(value: Option<Vec<u8>>) -> std::io::Result<Option<String>> { 
+    value.map(|value| String::from_utf8(value).map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))).transpose()
+}
+
+/**
+ * Splits one CSV line into its fields, unquoting fields wrapped in double quotes.
+ * * Returns `None` for an unterminated quote or text after a closing quote.
+ */
+fn parse_csv_row(line: &str) -> Option<Vec<String>> { 
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop { 
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') { 
+            chars.next();
+            loop { 
+                match chars.next()? { 
+                    '"' if chars.peek() == Some(&'"') => { 
+                        chars.next();
+                        field.push('"');
+                    },
+                    '"' => break,
+                    c => field.push(c)
+                }
+            }
+            if !matches!(chars.peek(), None | Some(',')) { 
+                return None;
+            }
+        } else { 
+            while let Some(&c) = chars.peek() { 
+                if c == ',' { 
+                    break;
+                }
+                field.push(c);
+                chars.next();
+            }
+        }
+        fields.push(field);
+        if chars.next().is_none() { 
+            return Some(fields);
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String { 
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> { 
+    if !hex.len().is_multiple_of(2) { 
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/**
+ * Value of the string field `name` of a flat JSON object on one line. Escapes are not
+ * unescaped, which is enough for the hex strings `export_json` writes.
+ */
+fn json_string_field<'a>(line: &'a str, name: &str) -> Option<&'a str> { 
+    let after_name = &line[line.find(&format!("\"{}\"", name))? + name.len() + 2..];
+    let value = after_name.trim_start().strip_prefix(':')?.trim_start().strip_prefix('"')?;
+    value.get(..value.find('"')?)
+}
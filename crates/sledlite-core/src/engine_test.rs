@@ -1,13 +1,19 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
-use crate::engine::{Config, Engine};
+use bytes::Bytes;
+
+use crate::{comparator::{Comparator, LexicographicComparator}, engine::{Config, Engine, EngineMode, IntegrityReport}, mvcc::Timestamp, sst::SSTReader, sst_test::ReverseComparator, wal::WalReader};
 
 #[test]
 pub fn engine_test_put_and_get() { 
     let dir = PathBuf::from("./temp");
     let config = Config { 
         dir,
-        memtable_max_bytes: 100
+        memtable_max_bytes: 100,
+        max_wal_bytes: None,
+        mode: EngineMode::Persistent,
+        block_cache_capacity: 0,
+        data_dirs: Vec::new()
     };
     let mut engine = Engine::open(config).expect("can not open engine");
     // for i in 0..38 { 
@@ -19,4 +25,1284 @@ pub fn engine_test_put_and_get() {
     let val = engine.get(b"key-25").expect("could not get a value"); //.expect("some value atleast");
     println!("{val:?}");
     //println!("value {}", String::from_utf8_lossy(&val).to_string())
-}   
\ No newline at end of file
+}   
+
+#[test]
+pub fn engine_test_get_skips_tables_outside_key_range() { 
+    let dir = PathBuf::from("./temp-key-range");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { 
+        dir: dir.clone(),
+        memtable_max_bytes: 16,
+        max_wal_bytes: None,
+        mode: EngineMode::Persistent,
+        block_cache_capacity: 0,
+        data_dirs: Vec::new()
+    };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    for c in b'a'..=b'm' { 
+        engine.put(&[c], &[c; 3]).expect("put the value");
+    }
+    assert_eq!(engine.get(b"a").expect("get failed"), Some(b"aaa".to_vec()));
+    assert_eq!(engine.get(b"m").expect("get failed"), Some(b"mmm".to_vec()));
+    assert_eq!(engine.get(b"z").expect("get failed"), None);
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_compact_merges_tables() { 
+    let dir = PathBuf::from("./temp-compact");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { 
+        dir: dir.clone(),
+        memtable_max_bytes: 64,
+        max_wal_bytes: None,
+        mode: EngineMode::Persistent,
+        block_cache_capacity: 0,
+        data_dirs: Vec::new()
+    };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    for i in 0..40 { 
+        engine.put(format!("key-{}", i % 10).as_bytes(), format!("val-{}", i).as_bytes()).expect("put the value");
+    }
+    let tables = || std::fs::read_dir(&dir).unwrap().filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().starts_with("sst-")).count();
+    assert!(tables() > 1);
+    let before: Vec<_> = (0..10).map(|i| engine.get(format!("key-{}", i).as_bytes()).unwrap()).collect();
+    assert!(engine.compact().expect("compaction failed"));
+    assert_eq!(tables(), 1);
+    let after: Vec<_> = (0..10).map(|i| engine.get(format!("key-{}", i).as_bytes()).unwrap()).collect();
+    assert_eq!(before, after);
+    assert!(!engine.compact().expect("compaction failed"));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_scan_merges_memtable_and_tables() { 
+    let dir = PathBuf::from("./temp-scan");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { 
+        dir: dir.clone(),
+        memtable_max_bytes: 64,
+        max_wal_bytes: None,
+        mode: EngineMode::Persistent,
+        block_cache_capacity: 0,
+        data_dirs: Vec::new()
+    };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    for i in 0..30 { 
+        engine.put(format!("key-{:02}", i).as_bytes(), b"old").expect("put the value");
+    }
+    engine.put(b"key-05", b"new").expect("put the value");
+    engine.put(b"other", b"x").expect("put the value");
+    let scanned = engine.scan(b"key-03", b"key-07").expect("scan failed");
+    let expected: Vec<(Vec<u8>, Vec<u8>)> = (3..7)
+        .map(|i| (format!("key-{:02}", i).into_bytes(), if i == 5 { b"new".to_vec() } else { b"old".to_vec() }))
+        .collect();
+    assert_eq!(scanned, expected);
+    assert_eq!(engine.scan(b"key-", b"key.").expect("scan failed").len(), 30);
+    let tail: Vec<Vec<u8>> = engine.scan_from(b"key-28").expect("scan failed").into_iter().map(|(key, _)| key).collect();
+    assert_eq!(tail, vec![b"key-28".to_vec(), b"key-29".to_vec(), b"other".to_vec()]);
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_checkpoint_opens_as_engine() { 
+    let dir = PathBuf::from("./temp-checkpoint");
+    let dest = PathBuf::from("./temp-checkpoint-copy");
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_dir_all(&dest);
+    let mut engine = Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 64, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).expect("can not open engine");
+    for i in 0..20 { 
+        engine.put(format!("key-{:02}", i).as_bytes(), format!("val-{}", i).as_bytes()).expect("put the value");
+    }
+    engine.checkpoint(&dest).expect("checkpoint failed");
+    engine.put(b"key-00", b"after").expect("put the value");
+    assert!(engine.checkpoint(&dir).is_err());
+
+    let mut copy = Engine::open(Config { dir: dest.clone(), memtable_max_bytes: 64, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).expect("can not open checkpoint");
+    for i in 0..20 { 
+        assert_eq!(copy.get(format!("key-{:02}", i).as_bytes()).unwrap(), Some(format!("val-{}", i).into_bytes()));
+    }
+    drop(engine);
+    drop(copy);
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_dir_all(&dest);
+}
+
+#[test]
+pub fn engine_test_close_flushes_memtable() { 
+    let dir = PathBuf::from("./temp-close");
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut engine = Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).expect("can not open engine");
+    engine.put(b"k1", b"v1").expect("put the value");
+    engine.close().expect("close failed");
+    let wal_len = std::fs::metadata(dir.join("wal.log")).expect("wal missing").len();
+    assert!(wal_len <= 16, "wal still holds records after close");
+    let mut engine = Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).expect("can not open engine");
+    assert_eq!(engine.get(b"k1").expect("get failed"), Some(b"v1".to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_empty_key_is_invalid_input() { 
+    let dir = PathBuf::from("./temp-empty-key");
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut engine = Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).expect("can not open engine");
+    let err = engine.put(b"", b"v1").expect_err("empty key accepted");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(err.to_string(), "radix tree key must be non-empty");
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_wal_limit_triggers_flush() { 
+    let dir = PathBuf::from("./temp-wal-limit");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: Some(1024), mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    // overwrites keep the memtable tiny, only the WAL limit can force a flush
+    for i in 0..200u32 { 
+        engine.put(b"hot-key", &i.to_be_bytes()).expect("put the value");
+        let wal_len = std::fs::metadata(dir.join("wal.log")).expect("wal missing").len();
+        assert!(wal_len < 1024, "wal grew to {wal_len} bytes");
+    }
+    let tables = std::fs::read_dir(&dir).unwrap()
+        .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().starts_with("sst-"))
+        .count();
+    assert!(tables > 0, "no flush happened");
+    assert_eq!(engine.get(b"hot-key").expect("get failed"), Some(199u32.to_be_bytes().to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_reads_at_earlier_timestamp_see_old_versions() { 
+    let dir = PathBuf::from("./temp-mvcc");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    engine.put_ts(b"k", b"v1", Timestamp(10)).expect("put failed");
+    engine.put_ts(b"k", b"v2", Timestamp(20)).expect("put failed");
+    engine.put_ts(b"k2", b"other", Timestamp(15)).expect("put failed");
+    engine.delete_ts(b"k", Timestamp(30)).expect("delete failed");
+
+    let check = |engine: &mut Engine| { 
+        assert_eq!(engine.get_ts(b"k", Timestamp(5)).unwrap(), None);
+        assert_eq!(engine.get_ts(b"k", Timestamp(10)).unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(engine.get_ts(b"k", Timestamp(25)).unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(engine.get_ts(b"k", Timestamp(u64::MAX)).unwrap(), None);
+        assert_eq!(engine.get_ts(b"k2", Timestamp(40)).unwrap(), Some(b"other".to_vec()));
+        let mut snapshot = engine.new_snapshot(Timestamp(20));
+        assert_eq!(snapshot.get(b"k").unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(snapshot.get(b"k2").unwrap(), Some(b"other".to_vec()));
+    };
+    check(&mut engine);
+    drop(engine);
+
+    // versions come back from the WAL, and again once they live in an SSTable
+    let mut engine = Engine::open(config.clone()).expect("can not reopen engine");
+    check(&mut engine);
+    engine.close().expect("close failed");
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    check(&mut engine);
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_merge_operator_sums_concurrent_operands() { 
+    let dir = PathBuf::from("./temp-merge");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let add = |value: &[u8], operand: &[u8]| { 
+        let current = value.try_into().map(u64::from_be_bytes).unwrap_or(0);
+        (current + u64::from_be_bytes(operand.try_into().unwrap())).to_be_bytes().to_vec()
+    };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    assert!(engine.merge(b"counter", &1u64.to_be_bytes()).is_err(), "merge without an operator");
+    engine.set_merge_operator(add);
+    engine.put(b"counter", &10u64.to_be_bytes()).expect("put the value");
+
+    let engine = std::sync::Arc::new(std::sync::Mutex::new(engine));
+    let handles: Vec<_> = (1..=3u64).map(|n| { 
+        let engine = std::sync::Arc::clone(&engine);
+        std::thread::spawn(move || engine.lock().unwrap().merge(b"counter", &n.to_be_bytes()).expect("merge failed"))
+    }).collect();
+    handles.into_iter().for_each(|h| h.join().unwrap());
+    let mut engine = std::sync::Arc::into_inner(engine).unwrap().into_inner().unwrap();
+    assert_eq!(engine.get(b"counter").unwrap(), Some(16u64.to_be_bytes().to_vec()));
+    assert_eq!(engine.scan(b"a", b"z").unwrap(), vec![(b"counter".to_vec(), 16u64.to_be_bytes().to_vec())]);
+    drop(engine);
+
+    // pending operands are replayed from the WAL, then folded in by the flush on close
+    let mut engine = Engine::open(config.clone()).expect("can not reopen engine");
+    engine.set_merge_operator(add);
+    engine.merge(b"counter", &4u64.to_be_bytes()).expect("merge failed");
+    assert_eq!(engine.get(b"counter").unwrap(), Some(20u64.to_be_bytes().to_vec()));
+    engine.close().expect("close failed");
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    assert_eq!(engine.get(b"counter").unwrap(), Some(20u64.to_be_bytes().to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_empty_value_survives_reopen() { 
+    let dir = PathBuf::from("./temp-empty-value");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    engine.put(b"k", b"").expect("put the value");
+    assert_eq!(engine.get(b"k").unwrap(), Some(Vec::new()));
+    drop(engine);
+
+    // the WAL logs an empty value without its bytes, replay reads it back as empty
+    let mut engine = Engine::open(config.clone()).expect("can not reopen engine");
+    assert_eq!(engine.get(b"k").unwrap(), Some(Vec::new()));
+    engine.close().expect("close failed");
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    assert_eq!(engine.get(b"k").unwrap(), Some(Vec::new()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_reverse_comparator_scans_in_reverse() { 
+    let dir = PathBuf::from("./temp-reverse-engine");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 64, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open_with_comparator(config.clone(), Arc::new(ReverseComparator)).expect("can not open engine");
+    // the small memtable spreads the keys over several SSTables
+    for i in 0..20u8 { 
+        engine.put(&[b'a' + i], &[i]).expect("put the value");
+    }
+    let keys = |entries: Vec<(Vec<u8>, Vec<u8>)>| entries.into_iter().map(|(k, _)| k[0]).collect::<Vec<_>>();
+    assert_eq!(keys(engine.scan(b"p", b"f").unwrap()), b"ponmlkjihg".to_vec());
+    assert_eq!(keys(engine.scan_from(b"c").unwrap()), b"cba".to_vec());
+    let key_only: Vec<u8> = engine.scan_keys_only(b"p", b"f").map(|key| key.unwrap()[0]).collect();
+    assert_eq!(key_only, b"ponmlkjihg".to_vec());
+    assert_eq!(engine.get(b"k").unwrap(), Some(vec![10]));
+    engine.compact().expect("compact failed");
+    drop(engine);
+
+    let mut engine = Engine::open_with_comparator(config, Arc::new(ReverseComparator)).expect("can not reopen engine");
+    assert_eq!(keys(engine.scan(b"t", b"p").unwrap()), b"tsrq".to_vec());
+    assert_eq!(engine.get(b"a").unwrap(), Some(vec![0]));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/**
+ * `io::Write` handle appending into a shared buffer, for capturing formatted log events.
+ */
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for CapturedLogs { 
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { 
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> { 
+        Ok(())
+    }
+}
+
+#[test]
+pub fn engine_test_emits_flush_and_wal_events() { 
+    let dir = PathBuf::from("./temp-tracing");
+    let _ = std::fs::remove_dir_all(&dir);
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    tracing::subscriber::with_default(subscriber, || { 
+        let mut engine = Engine::open(Config { dir: dir.clone(), memtable_max_bytes: 1024, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).expect("can not open engine");
+        engine.put(b"k1", b"v1").expect("put the value");
+        engine.close().expect("close failed");
+    });
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains("opening engine"), "{logs}");
+    assert!(logs.contains("wal record written lsn=1"), "{logs}");
+    assert!(logs.contains("memtable flushed entries=1"), "{logs}");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_import_csv_round_trips() { 
+    let dir = PathBuf::from("./temp-import-csv");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let csv_path = dir.with_extension("csv");
+    let mut csv = String::from("\"quoted,key\",\"say \"\"hi\"\"\"\r\n");
+    for i in 0..10_000 { 
+        csv.push_str(&format!("key-{:05},value-{}\n", (i * 7919) % 10_000, i));
+    }
+    std::fs::write(&csv_path, csv).unwrap();
+
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    engine.put(b"key-00001", b"before import").expect("put the value");
+    assert_eq!(engine.import_csv(&csv_path).expect("import failed"), 10_001);
+    let check = |engine: &mut Engine| { 
+        for i in [0usize, 1, 4242, 9999] { 
+            let expected = format!("value-{}", (0..10_000).find(|j| (j * 7919) % 10_000 == i).unwrap());
+            assert_eq!(engine.get(format!("key-{:05}", i).as_bytes()).unwrap(), Some(expected.into_bytes()));
+        }
+        assert_eq!(engine.get(b"quoted,key").unwrap(), Some(b"say \"hi\"".to_vec()));
+        assert_eq!(engine.scan(b"key-", b"key.").unwrap().len(), 10_000);
+    };
+    check(&mut engine);
+    engine.put(b"after", b"import").expect("put the value");
+    drop(engine);
+
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    check(&mut engine);
+    assert_eq!(engine.get(b"after").unwrap(), Some(b"import".to_vec()));
+    std::fs::write(&csv_path, "one,two,three\n").unwrap();
+    let err = engine.import_csv(&csv_path).expect_err("three columns accepted");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    drop(engine);
+    let _ = std::fs::remove_file(&csv_path);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_export_json_imports_into_fresh_engine() { 
+    let src_dir = PathBuf::from("./temp-export-src");
+    let dest_dir = PathBuf::from("./temp-export-dest");
+    let json_path = src_dir.with_extension("jsonl");
+    let _ = std::fs::remove_dir_all(&src_dir);
+    let _ = std::fs::remove_dir_all(&dest_dir);
+    let mut source = Engine::open(Config { dir: src_dir.clone(), memtable_max_bytes: 256, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).expect("can not open engine");
+    // arbitrary bytes, overwrites and a delete spread over several SSTables and the memtable
+    for i in 0..100u8 { 
+        source.put(&[i, 0xff, b'"', b'\n'], &[i; 7]).expect("put the value");
+    }
+    source.put(&[5, 0xff, b'"', b'\n'], b"overwritten").expect("put the value");
+    source.delete(&[99, 0xff, b'"', b'\n']).expect("delete failed");
+    let exported = source.export_json(&json_path).expect("export failed");
+    let expected = source.scan_from(&[]).expect("scan failed");
+    assert_eq!(exported, expected.len());
+    let first_line = std::fs::read_to_string(&json_path).unwrap().lines().next().unwrap().to_string();
+    assert_eq!(first_line, r#"{"key":"00ff220a","value":"00000000000000"}"#);
+
+    let mut dest = Engine::open(Config { dir: dest_dir.clone(), memtable_max_bytes: 256, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() }).expect("can not open engine");
+    assert_eq!(dest.import_json(&json_path).expect("import failed"), exported);
+    assert_eq!(dest.scan_from(&[]).expect("scan failed"), expected);
+    assert_eq!(dest.get(&[5, 0xff, b'"', b'\n']).unwrap(), Some(b"overwritten".to_vec()));
+
+    std::fs::write(&json_path, "{\"key\":\"zz\",\"value\":\"00\"}\n").unwrap();
+    assert_eq!(dest.import_json(&json_path).expect_err("bad hex accepted").kind(), std::io::ErrorKind::InvalidData);
+    drop((source, dest));
+    let _ = std::fs::remove_file(&json_path);
+    let _ = std::fs::remove_dir_all(&src_dir);
+    let _ = std::fs::remove_dir_all(&dest_dir);
+}
+
+#[test]
+pub fn engine_test_recover_from_wal_only_without_sstables() { 
+    let dir = PathBuf::from("./temp-recover-wal");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let csv_path = dir.with_extension("csv");
+    std::fs::write(&csv_path, "imported,row\n").unwrap();
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    engine.put(b"flushed", b"gone").expect("put the value");
+    engine.import_csv(&csv_path).expect("import failed");
+    for i in 0..50u32 { 
+        engine.put(format!("wal-{i}").as_bytes(), &i.to_be_bytes()).expect("put the value");
+    }
+    drop(engine);
+    let sst_files: Vec<PathBuf> = std::fs::read_dir(&dir).unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.file_name().unwrap().to_string_lossy().starts_with("sst-"))
+        .collect();
+    assert_eq!(sst_files.len(), 2);
+    // one table disappears, the other is cut short
+    std::fs::remove_file(&sst_files[0]).unwrap();
+    std::fs::write(&sst_files[1], b"junk").unwrap();
+    assert!(Engine::open(config.clone()).is_err());
+
+    let mut engine = Engine::recover_from_wal_only(config.clone()).expect("recovery failed");
+    for i in 0..50u32 { 
+        assert_eq!(engine.get(format!("wal-{i}").as_bytes()).unwrap(), Some(i.to_be_bytes().to_vec()));
+    }
+    drop(engine);
+    // the recovered state lives in a fresh SSTable, the WAL is empty again
+    let wal_len = std::fs::metadata(dir.join("wal.log")).unwrap().len();
+    assert!(wal_len <= 16, "wal still holds {wal_len} bytes");
+    std::fs::remove_file(&sst_files[1]).unwrap();
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    assert_eq!(engine.get(b"wal-49").unwrap(), Some(49u32.to_be_bytes().to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_file(&csv_path);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_open_survives_a_torn_wal_record() { 
+    let dir = PathBuf::from("./temp-torn-wal");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    engine.put(b"k1", b"v1").expect("put the value");
+    engine.put(b"k2", b"v2").expect("put the value");
+    drop(engine);
+    // a crash in the middle of the second append
+    let wal_path = dir.join("wal.log");
+    let wal_len = std::fs::metadata(&wal_path).unwrap().len();
+    std::fs::OpenOptions::new().write(true).open(&wal_path).unwrap().set_len(wal_len - 3).unwrap();
+    assert_eq!(WalReader::open(&wal_path).unwrap().read_all().unwrap().len(), 1);
+
+    let mut engine = Engine::open(config.clone()).expect("a torn wal record fails the open");
+    assert_eq!(engine.get(b"k1").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(engine.get(b"k2").unwrap(), None);
+    // the torn bytes are gone, so writes after the reopen are replayed as well
+    engine.put(b"k3", b"v3").expect("put the value");
+    drop(engine);
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    assert_eq!(engine.get(b"k1").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(engine.get(b"k3").unwrap(), Some(b"v3".to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/**
+ * Drives an engine through writes, deletes, merges and versioned writes, returning what
+ * it reads back along the way.
+ */
+fn engine_transcript(engine: &mut Engine) -> Vec<Option<Vec<u8>>> { 
+    engine.set_merge_operator(|value, operand| [value, operand].concat());
+    for i in 0..60u32 { 
+        engine.put(format!("key-{}", i % 25).as_bytes(), &i.to_be_bytes()).expect("put the value");
+    }
+    for i in (0..25).step_by(3) { 
+        engine.delete(format!("key-{i}").as_bytes()).expect("delete failed");
+    }
+    engine.merge(b"key-1", b"+a").expect("merge failed");
+    engine.merge(b"key-1", b"+b").expect("merge failed");
+    engine.put_ts(b"versioned", b"old", Timestamp(5)).expect("put_ts failed");
+    engine.put_ts(b"versioned", b"new", Timestamp(9)).expect("put_ts failed");
+    let mut reads: Vec<_> = (0..25).map(|i| engine.get(format!("key-{i}").as_bytes()).unwrap()).collect();
+    reads.push(engine.get_ts(b"versioned", Timestamp(6)).unwrap());
+    reads.extend(engine.scan(b"key-1", b"key-2").unwrap().into_iter().map(|(k, v)| Some([k, v].concat())));
+    reads
+}
+
+#[test]
+pub fn engine_test_in_memory_matches_persistent_without_files() { 
+    let dir = PathBuf::from("./temp-in-memory");
+    let _ = std::fs::remove_dir_all(&dir);
+    // small enough that the persistent engine flushes along the way
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 128, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut persistent = Engine::open(config).expect("can not open engine");
+    let expected = engine_transcript(&mut persistent);
+    drop(persistent);
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // limits that would flush a persistent engine many times over
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 64, max_wal_bytes: Some(128), mode: EngineMode::InMemory, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut in_memory = Engine::open(config).expect("can not open engine");
+    assert_eq!(engine_transcript(&mut in_memory), expected);
+    assert_eq!(in_memory.checkpoint(&dir.with_extension("checkpoint")).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+    assert!(!in_memory.compact().expect("compaction failed"));
+    in_memory.close().expect("close failed");
+    assert!(!dir.exists());
+}
+
+#[test]
+pub fn engine_test_compaction_shrinks_the_wal() { 
+    let dir = PathBuf::from("./temp-compaction-marker");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 256, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    for i in 0..60u32 { 
+        engine.put(format!("key-{:02}", i % 40).as_bytes(), format!("val-{i}").as_bytes()).expect("put the value");
+    }
+    let wal_len = || std::fs::metadata(dir.join("wal.log")).unwrap().len();
+    let before = wal_len();
+    assert!(engine.compact().expect("compaction failed"));
+    assert!(wal_len() < before, "{} vs {before}", wal_len());
+    engine.put(b"key-00", b"after").expect("put the value");
+    drop(engine);
+
+    // the records before the marker are in the compacted table, only the marker and the new put are left
+    let records = WalReader::open(dir.join("wal.log")).unwrap().read_all().unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(records[0].compacted_up_to().is_some());
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    assert_eq!(engine.get(b"key-00").unwrap(), Some(b"after".to_vec()));
+    for i in 1..40u32 { 
+        let newest = if i < 20 { i + 40 } else { i };
+        assert_eq!(engine.get(format!("key-{i:02}").as_bytes()).unwrap(), Some(format!("val-{newest}").into_bytes()));
+    }
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_stats_track_tables_memtable_and_wal() { 
+    let dir = PathBuf::from("./temp-stats");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 64, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    assert_eq!(engine.stats().sst_tables, 0);
+    for i in 0..20 { 
+        engine.put(format!("key-{i}").as_bytes(), b"value").expect("put the value");
+    }
+    let stats = engine.stats();
+    assert!(stats.sst_tables > 0);
+    assert_eq!(stats.sst_bytes, std::fs::read_dir(&dir).unwrap()
+        .map(|e| e.unwrap())
+        .filter(|e| e.file_name().to_string_lossy().starts_with("sst-"))
+        .map(|e| e.metadata().unwrap().len())
+        .sum::<u64>());
+    assert_eq!(stats.memtable_bytes, engine.memtable_bytes());
+    assert_eq!(stats.wal_bytes, std::fs::metadata(dir.join("wal.log")).unwrap().len());
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_prefix_count_is_an_upper_bound() { 
+    let dir = PathBuf::from("./temp-prefix-count");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    for i in 0..30 { 
+        engine.put(format!("user-{i:02}").as_bytes(), b"v").expect("put the value");
+    }
+    engine.put(b"order-1", b"v").expect("put the value");
+    assert_eq!(engine.prefix_count(b"user-").unwrap(), 30);
+    assert_eq!(engine.prefix_count(b"user-1").unwrap(), 10);
+    engine.close().expect("close failed");
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    // overwritten keys are counted in the memtable and in the table
+    for i in 0..5 { 
+        engine.put(format!("user-{i:02}").as_bytes(), b"w").expect("put the value");
+    }
+    assert_eq!(engine.prefix_count(b"user-0").unwrap(), 15);
+    assert_eq!(engine.scan_from(b"user-0").unwrap().iter().filter(|(k, _)| k.starts_with(b"user-0")).count(), 10);
+    assert_eq!(engine.prefix_count(b"order").unwrap(), 1);
+    assert_eq!(engine.prefix_count(b"none").unwrap(), 0);
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+mod memtable_bytes_props { 
+    use std::{collections::HashMap, fs::remove_dir_all, path::PathBuf};
+
+    use proptest::prelude::*;
+
+    use crate::engine::{Config, Engine, EngineMode};
+
+    proptest! { 
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn memtable_bytes_tracks_live_key_value_sizes(
+            ops in proptest::collection::vec(
+                (proptest::collection::vec(0u8..4, 1..4), proptest::collection::vec(any::<u8>(), 1..16)),
+                1..32
+            )
+        ) { 
+            let dir = PathBuf::from("./temp-memtable-bytes");
+            let _ = remove_dir_all(&dir);
+            let config = Config { 
+                dir: dir.clone(),
+                memtable_max_bytes: 1 << 30,
+                max_wal_bytes: None,
+                mode: EngineMode::Persistent,
+                block_cache_capacity: 0,
+                data_dirs: Vec::new()
+            };
+            let mut engine = Engine::open(config).expect("can not open engine");
+            let mut model: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+            for (key, val) in ops { 
+                let old = engine.put(&key, &val).expect("put the value");
+                prop_assert_eq!(old, model.insert(key, val));
+                let expected: usize = model.iter().map(|(k, v)| k.len() + v.len()).sum();
+                prop_assert_eq!(engine.memtable_bytes(), expected);
+            }
+            drop(engine);
+            let _ = remove_dir_all(&dir);
+        }
+    }
+}
+
+mod consistency_props { 
+    use std::{collections::HashMap, fs::remove_dir_all, path::PathBuf};
+
+    use proptest::prelude::*;
+
+    use crate::engine::{Config, Engine, EngineMode};
+
+    #[derive(Debug, Clone)]
+    enum Op { 
+        Put(Vec<u8>, Vec<u8>),
+        Delete(Vec<u8>),
+        Get(Vec<u8>)
+    }
+
+    fn key() -> impl Strategy<Value = Vec<u8>> { 
+        proptest::collection::vec(b'a'..=b'd', 1..3)
+    }
+
+    fn ops(with_deletes: bool) -> impl Strategy<Value = Vec<Op>> { 
+        let put = (key(), proptest::collection::vec(any::<u8>(), 0..40)).prop_map(|(key, val)| Op::Put(key, val));
+        let delete = key().prop_map(Op::Delete);
+        let get = key().prop_map(Op::Get);
+        let op = match with_deletes { 
+            true => prop_oneof![4 => put, 1 => delete, 2 => get].boxed(),
+            false => prop_oneof![4 => put, 2 => get].boxed()
+        };
+        proptest::collection::vec(op, 1..40)
+    }
+
+    /**
+     * Every key `key()` can generate has to read back as the map holds it.
+     */
+    fn check_all_keys(engine: &mut Engine, model: &HashMap<Vec<u8>, Vec<u8>>) -> Result<(), TestCaseError> { 
+        let keys = (b'a'..=b'd').map(|a| vec![a]).chain((b'a'..=b'd').flat_map(|a| (b'a'..=b'd').map(move |b| vec![a, b])));
+        for key in keys { 
+            prop_assert_eq!(engine.get(&key).expect("get the value"), model.get(&key).cloned());
+        }
+        Ok(())
+    }
+
+    /**
+     * Applies `ops` to an engine small enough to flush every few writes and to a
+     * `HashMap`, and checks after every op that `get` agrees with the map.
+     * * The engine is reopened halfway through and at the end, replaying its WAL, and
+     *   has to agree with the map on every key after each reopen.
+     */
+    fn check_against_map(name: &str, ops: Vec<Op>) -> Result<(), TestCaseError> { 
+        let dir = PathBuf::from(format!("./temp-consistency-{name}"));
+        let _ = remove_dir_all(&dir);
+        let config = Config { 
+            dir: dir.clone(),
+            memtable_max_bytes: 50,
+            max_wal_bytes: None,
+            mode: EngineMode::Persistent,
+            block_cache_capacity: 0,
+            data_dirs: Vec::new()
+        };
+        let mut engine = Engine::open(config.clone()).expect("can not open engine");
+        let mut model: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let halfway = ops.len() / 2;
+        for (i, op) in ops.into_iter().enumerate() { 
+            if i == halfway { 
+                drop(engine);
+                engine = Engine::open(config.clone()).expect("can not reopen engine");
+                check_all_keys(&mut engine, &model)?;
+            }
+            let key = match op { 
+                Op::Put(key, val) => { 
+                    engine.put(&key, &val).expect("put the value");
+                    model.insert(key.clone(), val);
+                    key
+                },
+                Op::Delete(key) => { 
+                    engine.delete(&key).expect("delete the key");
+                    model.remove(&key);
+                    key
+                },
+                Op::Get(key) => key
+            };
+            prop_assert_eq!(engine.get(&key).expect("get the value"), model.get(&key).cloned());
+        }
+        check_all_keys(&mut engine, &model)?;
+        drop(engine);
+        let mut engine = Engine::open(config).expect("can not reopen engine");
+        check_all_keys(&mut engine, &model)?;
+        drop(engine);
+        let _ = remove_dir_all(&dir);
+        Ok(())
+    }
+
+    proptest! { 
+        #![proptest_config(ProptestConfig::with_cases(1000))]
+
+        #[test]
+        fn puts_and_gets_match_a_hash_map(ops in ops(false)) { 
+            check_against_map("puts", ops)?;
+        }
+
+        #[test]
+        fn puts_deletes_and_gets_match_a_hash_map(ops in ops(true)) { 
+            check_against_map("deletes", ops)?;
+        }
+    }
+}
+
+#[test]
+pub fn engine_test_verify_integrity_finds_corrupted_tables_and_wal_records() { 
+    let dir = PathBuf::from("./temp-verify-integrity");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    for i in 0..10 { 
+        engine.put(format!("key-{i}").as_bytes(), b"value").expect("put the value");
+    }
+    engine.close().expect("close failed");
+    let mut engine = Engine::open(config.clone()).expect("can not reopen engine");
+    for i in 0..10 { 
+        engine.put(format!("new-{i}").as_bytes(), b"value").expect("put the value");
+    }
+    assert_eq!(engine.verify_integrity().unwrap(), IntegrityReport { 
+        wal_valid_records: 10,
+        wal_corrupted: Vec::new(),
+        sst_valid_files: 1,
+        sst_corrupted: Vec::new()
+    });
+    drop(engine);
+
+    let sst_path = std::fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.file_name().unwrap().to_string_lossy().starts_with("sst-"))
+        .unwrap();
+    let corrupt = |path: &PathBuf, at: usize| { 
+        let mut bytes = std::fs::read(path).unwrap();
+        bytes[at] ^= 0xff;
+        std::fs::write(path, bytes).unwrap();
+    };
+    // the value of the first record in the table, and of the last record in the wal
+    corrupt(&sst_path, 8 + 1 + 4 + 5 + 4);
+    let wal_len = std::fs::metadata(dir.join("wal.log")).unwrap().len() as usize;
+    corrupt(&dir.join("wal.log"), wal_len - 5);
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    let report = engine.verify_integrity().unwrap();
+    assert_eq!(report.wal_valid_records, 9);
+    assert_eq!(report.wal_corrupted.len(), 1);
+    assert_eq!(report.sst_valid_files, 0);
+    assert_eq!(report.sst_corrupted, vec![sst_path]);
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_key_count_estimate_bounds_the_live_keys() { 
+    let dir = PathBuf::from("./temp-key-count-estimate");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 512, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    assert_eq!(engine.key_count_estimate(), 0);
+    let mut puts = 0;
+    for round in 0..3 { 
+        for i in 0..40 { 
+            engine.put(format!("key-{i:02}").as_bytes(), format!("value-{round}").as_bytes()).expect("put the value");
+            puts += 1;
+        }
+    }
+    for i in 0..10 { 
+        engine.delete(format!("key-{i:02}").as_bytes()).expect("delete the value");
+    }
+    let live = engine.scan(b"", b"key-99").unwrap().len();
+    let estimate = engine.key_count_estimate();
+    assert!(live <= estimate && estimate <= puts, "{live} <= {estimate} <= {puts}");
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_contains_key_agrees_with_get() { 
+    let dir = PathBuf::from("./temp-contains-key");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 256, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    engine.set_merge_operator(|value, operand| [value, operand].concat());
+    for i in (0..60).step_by(2) { 
+        engine.put(format!("key-{i:02}").as_bytes(), b"value").expect("put the value");
+    }
+    engine.delete(b"key-58").expect("delete the value");
+    engine.merge(b"key-61", b"operand").expect("merge the operand");
+    for i in 0..64 { 
+        let key = format!("key-{i:02}").into_bytes();
+        assert_eq!(engine.contains_key(&key).unwrap(), engine.get(&key).unwrap().is_some(), "key {i}");
+    }
+    assert!(engine.contains_key(b"key-00").unwrap());
+    assert!(engine.contains_key(b"key-61").unwrap());
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_flush_writes_sorted_sstables() { 
+    for (name, comparator) in [("bytes", None), ("reverse", Some(Arc::new(ReverseComparator) as Arc<dyn Comparator>))] { 
+        let dir = PathBuf::from(format!("./temp-flush-sorted-{name}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+        let mut engine = match &comparator { 
+            Some(comparator) => Engine::open_with_comparator(config, Arc::clone(comparator)),
+            None => Engine::open(config)
+        }.expect("can not open engine");
+        // scattered insertion order, keys of different lengths sharing prefixes
+        for i in (0..500u32).map(|i| i * 7919 % 500) { 
+            engine.put(format!("k{}", i).as_bytes(), b"value").expect("put the value");
+        }
+        engine.close().expect("close failed");
+
+        let sst_path = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.file_name().unwrap().to_string_lossy().starts_with("sst-"))
+            .unwrap();
+        let comparator = comparator.unwrap_or_else(|| Arc::new(LexicographicComparator));
+        let mut reader = SSTReader::open_with_comparator(&sst_path, Arc::clone(&comparator)).expect("can not open table");
+        let keys: Vec<Vec<u8>> = reader.iter().map(|record| record.unwrap().0).collect();
+        assert_eq!(keys.len(), 500);
+        assert!(keys.windows(2).all(|pair| comparator.compare(&pair[0], &pair[1]) == std::cmp::Ordering::Less));
+        assert!(reader.validate_all().unwrap().is_empty());
+        assert_eq!(reader.get(b"k42").unwrap(), Some(b"value".to_vec()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
+
+#[test]
+pub fn engine_test_disk_and_memtable_usage_follow_writes_and_compaction() { 
+    let dir = PathBuf::from("./temp-disk-usage");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 512, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    let empty = engine.approximate_disk_usage().unwrap();
+    assert_eq!(engine.approximate_memtable_usage(), 0);
+    engine.put(b"first", b"value").expect("put the value");
+    assert_eq!(engine.approximate_memtable_usage(), b"firstvalue".len());
+    // every round overwrites the same keys, flushing a few tables on the way
+    for round in 0..4 { 
+        for i in 0..40 { 
+            engine.put(format!("key-{i:02}").as_bytes(), format!("value-{round}").as_bytes()).expect("put the value");
+        }
+    }
+    let written = engine.approximate_disk_usage().unwrap();
+    assert!(written > empty, "{written} > {empty}");
+    assert_eq!(engine.approximate_disk_usage().unwrap(), written);
+    assert!(engine.compact().unwrap());
+    let compacted = engine.approximate_disk_usage().unwrap();
+    assert!(compacted < written, "{compacted} < {written}");
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_put_bytes_matches_put() { 
+    let dir = PathBuf::from("./temp-put-bytes");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 4096, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    for i in 0..30 { 
+        let key = Bytes::from(format!("key-{i:02}"));
+        assert_eq!(engine.put_bytes(key.clone(), Bytes::from(format!("value-{i}"))).unwrap(), None);
+        assert_eq!(engine.put_bytes(key, Bytes::from_static(b"latest")).unwrap(), Some(format!("value-{i}").into_bytes()));
+    }
+    assert_eq!(engine.memtable_bytes(), 30 * b"key-00latest".len());
+    drop(engine);
+
+    // the wal records written from the buffers replay like any other put
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    for i in 0..30 { 
+        assert_eq!(engine.get(format!("key-{i:02}").as_bytes()).unwrap(), Some(b"latest".to_vec()));
+    }
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_str_api_shares_keys_with_the_byte_api() { 
+    let dir = PathBuf::from("./temp-str-api");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 4096, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    assert_eq!(engine.put_str("city", "Zürich").unwrap(), None);
+    assert_eq!(engine.get(b"city").unwrap(), Some("Zürich".as_bytes().to_vec()));
+    engine.put(b"country", b"Schweiz").unwrap();
+    assert_eq!(engine.get_str("country").unwrap(), Some("Schweiz".to_string()));
+    assert_eq!(engine.put_str("city", "Genf").unwrap(), Some("Zürich".to_string()));
+    assert_eq!(engine.get_str("missing").unwrap(), None);
+
+    engine.put(b"raw", &[0xff, 0xfe]).unwrap();
+    assert_eq!(engine.get_str("raw").unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(engine.put_str("raw", "text").unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(engine.get_str("raw").unwrap(), Some("text".to_string()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_scan_keys_only_never_reads_values() { 
+    let dir = PathBuf::from("./temp-scan-keys-only");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 256, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    for i in 0..80 { 
+        engine.put(format!("key-{:02}", i % 60).as_bytes(), format!("value-{i}").as_bytes()).expect("put the value");
+    }
+    let ranges: [(&[u8], &[u8]); 4] = [(b"", b"key-99"), (b"key-10", b"key-35"), (b"key-59", b"key-60"), (b"key-7", b"key-8")];
+    let key_only = |engine: &mut Engine, start: &[u8], end: &[u8]| engine.scan_keys_only(start, end).collect::<std::io::Result<Vec<_>>>().unwrap();
+    for (start, end) in ranges { 
+        let scanned: Vec<Vec<u8>> = engine.scan(start, end).unwrap().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(key_only(&mut engine, start, end), scanned);
+    }
+
+    // wipe the data block of every table, values can no longer be read but keys can
+    for entry in std::fs::read_dir(&dir).unwrap() { 
+        let path = entry.unwrap().path();
+        if !path.file_name().unwrap().to_string_lossy().starts_with("sst-") { 
+            continue;
+        }
+        let mut bytes = std::fs::read(&path).unwrap();
+        let properties_offset = u64::from_be_bytes(bytes[bytes.len() - 24..bytes.len() - 16].try_into().unwrap()) as usize;
+        bytes[8..properties_offset].fill(0);
+        std::fs::write(&path, bytes).unwrap();
+    }
+    assert!(engine.scan(b"", b"key-99").is_err());
+    assert_eq!(key_only(&mut engine, b"", b"key-99"), (0..60).map(|i| format!("key-{:02}", i).into_bytes()).collect::<Vec<_>>());
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_block_cache_keeps_reads_consistent_across_compaction() { 
+    let dir = PathBuf::from("./temp-block-cache");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 256, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 64, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    for round in 0..3 { 
+        for i in 0..40 { 
+            engine.put(format!("key-{i:02}").as_bytes(), format!("value-{round}-{i}").as_bytes()).expect("put the value");
+        }
+        // every read goes through the cache twice, the answers must not go stale
+        for _ in 0..2 { 
+            for i in 0..40 { 
+                assert_eq!(engine.get(format!("key-{i:02}").as_bytes()).unwrap(), Some(format!("value-{round}-{i}").into_bytes()));
+            }
+        }
+        engine.compact().expect("compact failed");
+    }
+    drop(engine);
+
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    assert_eq!(engine.get(b"key-07").unwrap(), Some(b"value-2-7".to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_memtable_bytes_tracks_overwrites_and_deletes() { 
+    let dir = PathBuf::from("./temp-memtable-bytes-overwrites");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 512, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    engine.put(b"other", b"fixed").unwrap();
+    // each overwrite shrinks the value, the counter must only ever hold the latest pair
+    for i in 0..100 { 
+        let val = vec![b'x'; 400 - i * 4];
+        engine.put(b"key", &val).unwrap();
+        assert_eq!(engine.memtable_bytes(), b"otherfixed".len() + b"key".len() + val.len());
+    }
+    // an overwrite only grows the memtable by the difference, so none of them flushed
+    assert!(std::fs::read_dir(&dir).unwrap().all(|e| !e.unwrap().file_name().to_string_lossy().starts_with("sst-")));
+    drop(engine);
+
+    // replaying the wal rebuilds the same count
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    assert_eq!(engine.memtable_bytes(), b"otherfixed".len() + b"key".len() + 4);
+    engine.delete(b"key").unwrap();
+    assert_eq!(engine.memtable_bytes(), b"otherfixed".len());
+    engine.delete(b"other").unwrap();
+    assert_eq!(engine.memtable_bytes(), 0);
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_oldest_sst_age_appears_after_a_flush() { 
+    let dir = PathBuf::from("./temp-oldest-sst-age");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 128, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    assert_eq!(engine.oldest_sst_age(), None);
+    for i in 0..20 { 
+        engine.put(format!("key-{i:02}").as_bytes(), b"value").expect("put the value");
+    }
+    let first = engine.oldest_sst_age().expect("the memtable was flushed");
+    std::thread::sleep(Duration::from_millis(20));
+    let later = engine.oldest_sst_age().expect("the tables are still there");
+    assert!(later >= first + Duration::from_millis(20), "{later:?} >= {first:?} + 20ms");
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_deletes_stay_deleted_across_flushes_and_compactions() { 
+    let dir = PathBuf::from("./temp-tombstones");
+    let _ = std::fs::remove_dir_all(&dir);
+    let csv_path = dir.with_extension("csv");
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    std::fs::write(&csv_path, "a-00,1\nb-00,2\nc-00,3\n").unwrap();
+    engine.import_csv(&csv_path).expect("import failed");
+    engine.delete(b"b-00").expect("delete the key");
+    let check = |engine: &mut Engine, live: &[(&[u8], &[u8])]| { 
+        let scanned = engine.scan(b"a", b"zz").unwrap();
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = live.iter().map(|(k, v)| (k.to_vec(), v.to_vec())).collect();
+        assert_eq!(scanned, expected);
+        let keys: Vec<Vec<u8>> = engine.scan_keys_only(b"a", b"zz").collect::<std::io::Result<_>>().unwrap();
+        assert_eq!(keys, expected.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>());
+        for key in [&b"a-00"[..], b"b-00", b"c-00", b"z-00"] { 
+            let value = live.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_vec());
+            assert_eq!(engine.get(key).unwrap(), value);
+            assert_eq!(engine.contains_key(key).unwrap(), value.is_some());
+        }
+    };
+    check(&mut engine, &[(b"a-00", b"1"), (b"c-00", b"3")]);
+    // the flush on close writes the tombstone, the imported table still holds `b-00`
+    engine.close().expect("close failed");
+
+    let mut engine = Engine::open(config.clone()).expect("can not reopen engine");
+    check(&mut engine, &[(b"a-00", b"1"), (b"c-00", b"3")]);
+    engine.delete(b"a-00").expect("delete the key");
+    std::fs::write(&csv_path, "z-00,9\n").unwrap();
+    engine.import_csv(&csv_path).expect("import failed");
+    check(&mut engine, &[(b"c-00", b"3"), (b"z-00", b"9")]);
+
+    // the oldest table is an input, so the tombstones are dropped with the keys they hide
+    engine.compact_sst_range(b"a", b"b").expect("compaction failed");
+    check(&mut engine, &[(b"c-00", b"3"), (b"z-00", b"9")]);
+    assert_eq!(engine.key_count_estimate(), 2);
+
+    // an older table is left out, so the tombstone is kept
+    engine.delete(b"z-00").expect("delete the key");
+    engine.close().expect("close failed");
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    engine.compact_sst_range(b"z", b"zz").expect("compaction failed");
+    check(&mut engine, &[(b"c-00", b"3")]);
+    assert_eq!(engine.key_count_estimate(), 2);
+    assert!(engine.compact().unwrap());
+    check(&mut engine, &[(b"c-00", b"3")]);
+    assert_eq!(engine.key_count_estimate(), 1);
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_file(&csv_path);
+}
+
+#[test]
+pub fn engine_test_compact_sst_range_keeps_table_order_after_reopen() { 
+    let dir = PathBuf::from("./temp-compact-range-order");
+    let _ = std::fs::remove_dir_all(&dir);
+    let csv_path = dir.with_extension("csv");
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    std::fs::write(&csv_path, "a-00,old-a\nz-00,old-z\n").unwrap();
+    engine.import_csv(&csv_path).expect("import failed");
+    std::fs::write(&csv_path, "z-00,new-z\n").unwrap();
+    engine.import_csv(&csv_path).expect("import failed");
+    // only the older table overlaps, its `z-00` is rewritten into a table of its own that
+    // has to stay older than the table holding the newer `z-00`
+    engine.compact_sst_range(b"a", b"b").expect("compaction failed");
+    assert_eq!(engine.get(b"z-00").unwrap(), Some(b"new-z".to_vec()));
+    // the flush on close rotates the wal, so the reopen can only go by the table names
+    engine.put(b"m-00", b"flushed").expect("put the value");
+    engine.close().expect("close failed");
+
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    assert_eq!(engine.get(b"z-00").unwrap(), Some(b"new-z".to_vec()));
+    assert_eq!(engine.get(b"a-00").unwrap(), Some(b"old-a".to_vec()));
+    assert_eq!(engine.get(b"m-00").unwrap(), Some(b"flushed".to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_file(&csv_path);
+}
+
+#[test]
+pub fn engine_test_compact_sst_range_only_touches_overlapping_tables() { 
+    let dir = PathBuf::from("./temp-compact-range");
+    let _ = std::fs::remove_dir_all(&dir);
+    let csv_path = dir.with_extension("csv");
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    let sst_files = || -> Vec<PathBuf> { 
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&dir).unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.file_name().unwrap().to_string_lossy().starts_with("sst-"))
+            .collect();
+        files.sort();
+        files
+    };
+    // three tables with disjoint key ranges
+    for prefix in ["a", "m", "z"] { 
+        let csv: String = (0..10).map(|i| format!("{prefix}-{i:02},{prefix}-value-{i}\n")).collect();
+        std::fs::write(&csv_path, csv).unwrap();
+        engine.import_csv(&csv_path).expect("import failed");
+    }
+    let before = sst_files();
+    assert_eq!(before.len(), 3);
+    let check = |engine: &mut Engine| { 
+        for prefix in ["a", "m", "z"] { 
+            for i in 0..10 { 
+                let expected = match (prefix, i) { 
+                    ("m", 3) => "newer".to_string(),
+                    _ => format!("{prefix}-value-{i}")
+                };
+                assert_eq!(engine.get(format!("{prefix}-{i:02}").as_bytes()).unwrap(), Some(expected.into_bytes()));
+            }
+        }
+    };
+
+    engine.compact_sst_range(b"m", b"n").expect("compaction failed");
+    let after = sst_files();
+    assert_eq!(after.len(), 3);
+    let kept: Vec<&PathBuf> = before.iter().filter(|path| after.contains(path)).collect();
+    assert_eq!(kept.len(), 2, "only the middle table is rewritten");
+    engine.compact_sst_range(b"n", b"o").expect("compaction failed");
+    assert_eq!(sst_files(), after, "no table overlaps the range");
+
+    // a newer table holding keys on both sides of the range is split around it, the
+    // `z` table sits between the two in age and is merged along
+    std::fs::write(&csv_path, "b-00,bee\nm-03,newer\n").unwrap();
+    engine.import_csv(&csv_path).expect("import failed");
+    engine.compact_sst_range(b"m", b"n").expect("compaction failed");
+    assert_eq!(sst_files().len(), 4);
+    check(&mut engine);
+    assert_eq!(engine.get(b"b-00").unwrap(), Some(b"bee".to_vec()));
+    drop(engine);
+
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    check(&mut engine);
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_file(&csv_path);
+}
+
+#[test]
+pub fn engine_test_get_or_insert_with_only_builds_missing_values() { 
+    let dir = PathBuf::from("./temp-get-or-insert");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 128, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config).expect("can not open engine");
+    // enough writes for `key-00` to be flushed out of the memtable into an SSTable
+    for i in 0..20 { 
+        engine.put(format!("key-{i:02}").as_bytes(), b"stored").expect("put the value");
+    }
+    let mut calls = 0;
+    for key in [&b"key-00"[..], b"key-19"] { 
+        let value = engine.get_or_insert_with(key, || { calls += 1; b"made".to_vec() }).unwrap();
+        assert_eq!(value, b"stored".to_vec());
+    }
+    assert_eq!(calls, 0);
+    let value = engine.get_or_insert_with(b"missing", || { calls += 1; b"made".to_vec() }).unwrap();
+    assert_eq!((value, calls), (b"made".to_vec(), 1));
+    assert_eq!(engine.get(b"missing").unwrap(), Some(b"made".to_vec()));
+    assert_eq!(engine.get_or_insert_with(b"missing", || unreachable!()).unwrap(), b"made".to_vec());
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn engine_test_data_dirs_spread_sstables() { 
+    let dir = PathBuf::from("./temp-jbod-a");
+    let other = PathBuf::from("./temp-jbod-b");
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_dir_all(&other);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 128, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: vec![other.clone()] };
+    let sst_count = |dir: &PathBuf| std::fs::read_dir(dir).unwrap()
+        .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().starts_with("sst-"))
+        .count();
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    for i in 0..60 { 
+        engine.put(format!("key-{i:02}").as_bytes(), format!("value-{i}").as_bytes()).expect("put the value");
+    }
+    let (in_dir, in_other) = (sst_count(&dir), sst_count(&other));
+    assert!(in_dir >= 2 && in_other >= 2, "{in_dir} and {in_other} tables");
+    assert!(in_dir.abs_diff(in_other) <= 1);
+    assert!(dir.join("wal.log").exists());
+    assert!(!other.join("wal.log").exists());
+    drop(engine);
+
+    // both directories are read back on open
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    for i in 0..60 { 
+        assert_eq!(engine.get(format!("key-{i:02}").as_bytes()).unwrap(), Some(format!("value-{i}").into_bytes()));
+    }
+    assert!(engine.compact().unwrap());
+    assert_eq!(sst_count(&dir) + sst_count(&other), 1);
+    assert_eq!(engine.get(b"key-42").unwrap(), Some(b"value-42".to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_dir_all(&other);
+}
+
+#[test]
+pub fn engine_test_newest_table_wins_after_reopen_with_data_dirs() { 
+    let dir = PathBuf::from("./temp-sst-seq-a");
+    let other = PathBuf::from("./temp-sst-seq-b");
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_dir_all(&other);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 128, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: vec![other.clone()] };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    // every table holds its own version of `hot`, spread over both directories
+    for round in 0..12 { 
+        engine.put(b"hot", format!("round-{round}").as_bytes()).expect("put the value");
+        for i in 0..8 { 
+            engine.put(format!("filler-{round}-{i}").as_bytes(), b"0123456789").expect("put the value");
+        }
+    }
+    drop(engine);
+
+    let mut engine = Engine::open(config.clone()).expect("can not reopen engine");
+    assert_eq!(engine.get(b"hot").unwrap(), Some(b"round-11".to_vec()));
+    // tables written after the reopen keep sorting after the ones before it
+    engine.put(b"hot", b"after-reopen").expect("put the value");
+    for i in 0..16 { 
+        engine.put(format!("late-{i}").as_bytes(), b"0123456789").expect("put the value");
+    }
+    drop(engine);
+    let mut engine = Engine::open(config).expect("can not reopen engine");
+    assert_eq!(engine.get(b"hot").unwrap(), Some(b"after-reopen".to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_dir_all(&other);
+}
+
+#[test]
+pub fn engine_test_online_backup_opens_as_engine() { 
+    let dir = PathBuf::from("./temp-online-backup");
+    let dest = PathBuf::from("./temp-online-backup-copy");
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_dir_all(&dest);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 256, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    let mut engine = Engine::open(config.clone()).expect("can not open engine");
+    for i in 0..40 { 
+        engine.put(format!("key-{i:02}").as_bytes(), format!("val-{i}").as_bytes()).expect("put the value");
+    }
+    let stats = engine.online_backup(&dest).expect("backup failed");
+    assert_eq!(stats.lsn, 40);
+    assert!(stats.sst_files >= 2);
+    // the engine keeps taking writes, they stay out of the backup
+    engine.put(b"key-00", b"after").expect("put the value");
+    engine.put(b"key-99", b"after").expect("put the value");
+
+    let manifest = std::fs::read_to_string(dest.join("BACKUP_MANIFEST")).unwrap();
+    assert!(manifest.starts_with("lsn 40\n"), "{manifest}");
+    assert_eq!(manifest.lines().filter(|line| line.starts_with("sst ")).count(), stats.sst_files);
+    assert_eq!(std::fs::metadata(dest.join("wal.log")).unwrap().len(), stats.wal_bytes);
+    let mut copy = Engine::open(Config { dir: dest.clone(), ..config }).expect("can not open backup");
+    for i in 0..40 { 
+        assert_eq!(copy.get(format!("key-{i:02}").as_bytes()).unwrap(), Some(format!("val-{i}").into_bytes()));
+    }
+    assert_eq!(copy.get(b"key-99").unwrap(), None);
+    assert_eq!(engine.get(b"key-00").unwrap(), Some(b"after".to_vec()));
+    drop(engine);
+    drop(copy);
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_dir_all(&dest);
+}
@@ -1,7 +1,21 @@
 pub mod radix;
+pub mod comparator;
 pub mod node;
 pub mod sst;
 pub mod wal;
 pub mod engine;
+pub mod mvcc;
+pub mod txn;
+pub mod bloom;
+#[cfg(test)]
 pub mod radix_test;
-pub mod engine_test;
\ No newline at end of file
+#[cfg(test)]
+pub mod engine_test;
+#[cfg(test)]
+pub mod sst_test;
+#[cfg(test)]
+pub mod wal_test;
+#[cfg(test)]
+pub mod txn_test;
+#[cfg(test)]
+pub mod bloom_test;
\ No newline at end of file
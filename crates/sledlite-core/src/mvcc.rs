@@ -0,0 +1,73 @@
+use crate::engine::Engine;
+
+/**
+ * Version of a key written through `Engine::put_ts` / `Engine::delete_ts`.
+ * * Versions are stored under `key ++ ts.to_be_bytes()`, so the versions of a key sort
+ *   next to each other, oldest first.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(pub u64);
+
+/**
+ * Leading byte of a stored version, telling a written value from a delete.
+ */
+const VERSION_DELETED: u8 = 0;
+const VERSION_VALUE: u8 = 1;
+
+pub(crate) fn versioned_key(key: &[u8], ts: Timestamp) -> Vec<u8> { 
+    let mut versioned = Vec::with_capacity(key.len() + 8);
+    versioned.extend_from_slice(key);
+    versioned.extend_from_slice(&ts.0.to_be_bytes());
+    versioned
+}
+
+/**
+ * Splits a versioned key back into the user key and its timestamp.
+ */
+pub(crate) fn split_versioned_key(versioned: &[u8]) -> Option<(&[u8], Timestamp)> { 
+    let (key, ts) = versioned.split_last_chunk::<8>()?;
+    Some((key, Timestamp(u64::from_be_bytes(*ts))))
+}
+
+pub(crate) fn encode_version(value: Option<&[u8]>) -> Vec<u8> { 
+    match value { 
+        Some(value) => [&[VERSION_VALUE], value].concat(),
+        None => vec![VERSION_DELETED]
+    }
+}
+
+/**
+ * Value a stored version carries, `None` if the version deleted the key.
+ */
+pub(crate) fn decode_version(stored: &[u8]) -> Option<Vec<u8>> { 
+    match stored.split_first() { 
+        Some((&VERSION_VALUE, value)) => Some(value.to_vec()),
+        _ => None
+    }
+}
+
+/**
+ * Read handle returned by `Engine::new_snapshot`.
+ * * Every read sees the engine as of `ts`: versions written later are skipped.
+ */
+pub struct EngineSnapshot<'a> { 
+    engine: &'a mut Engine,
+    ts: Timestamp
+}
+
+impl<'a> EngineSnapshot<'a> { 
+    pub(crate) fn new(engine: &'a mut Engine, ts: Timestamp) -> Self { 
+        Self { engine, ts }
+    }
+
+    pub fn timestamp(&self) -> Timestamp { 
+        self.ts
+    }
+
+    /**
+     * Newest value of `key` written at or before the snapshot timestamp, see `Engine::get_ts`.
+     */
+    pub fn get(&mut self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> { 
+        self.engine.get_ts(key, self.ts)
+    }
+}
@@ -1,29 +1,486 @@
-use crossbeam_epoch::{Atomic};
+use std::sync::atomic::Ordering;
+
+use bytes::Bytes;
+use crossbeam_epoch::{Atomic, Guard, Owned, Shared};
+use crossbeam_queue::SegQueue;
+use smallvec::SmallVec;
 
 
 pub const BRANCH_CAPACITY: usize = 256;
 
+/**
+ * Child storage layouts of an adaptive radix tree node with branching factor `B`.
+ * * `Node4` and `Node16` keep their keys sorted next to the matching child pointers.
+ * * `Node48` maps every digit to a slot in a 48 entry child array (`0` marks an empty digit).
+ * * `Node256` is direct-mapped, one slot per digit, `B` slots in all.
+ *   Anything larger than `Node4` is boxed so that sparse nodes stay small. Layouts that are
+ *   not smaller than `B` are skipped, e.g. a `B = 16` tree goes from `Node4` to `Node256`.
+ */
+#[derive(Debug)]
+pub enum NodeKind<const B: usize> { 
+    Node4 { len: u8, keys: [u8; 4], children: [Atomic<Node<B>>; 4] },
+    Node16 { len: u8, keys: Box<[u8; 16]>, children: Box<[Atomic<Node<B>>; 16]> },
+    Node48 { len: u8, index: Box<[u8; 256]>, children: Box<[Atomic<Node<B>>; 48]> },
+    Node256 { len: u16, children: Box<[Atomic<Node<B>>]> }
+}
+
+impl<const B: usize> NodeKind<B> { 
+    /**
+     * Capacity of the smallest layout holding `len` children. Layouts at least as large as
+     * the branching factor are never used, the direct-mapped one takes their place.
+     */
+    fn capacity_for(len: usize) -> usize { 
+        let capacity = match len { 
+            0..=4 => 4,
+            5..=16 => 16,
+            17..=48 => 48,
+            _ => B
+        };
+        if capacity >= B { B } else { capacity }
+    }
+
+    fn with_capacity(capacity: usize) -> Self { 
+        match Self::capacity_for(capacity) { 
+            c if c == B => Self::Node256 { len: 0, children: vec![Atomic::null(); B].into_boxed_slice() },
+            4 => Self::Node4 { len: 0, keys: [0; 4], children: std::array::from_fn(|_| Atomic::null()) },
+            16 => Self::Node16 { len: 0, keys: Box::new([0; 16]), children: Box::new(std::array::from_fn(|_| Atomic::null())) },
+            _ => Self::Node48 { len: 0, index: Box::new([0; 256]), children: Box::new(std::array::from_fn(|_| Atomic::null())) }
+        }
+    }
+
+    pub fn capacity(&self) -> usize { 
+        match self { 
+            Self::Node4 { .. } => 4,
+            Self::Node16 { .. } => 16,
+            Self::Node48 { .. } => 48,
+            Self::Node256 { .. } => B
+        }
+    }
+
+    pub fn len(&self) -> usize { 
+        match self { 
+            Self::Node4 { len, .. } | Self::Node16 { len, .. } | Self::Node48 { len, .. } => *len as usize,
+            Self::Node256 { len, .. } => *len as usize
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { 
+        self.len() == 0
+    }
+
+    fn find(&self, b: u8) -> Option<&Atomic<Node<B>>> { 
+        match self { 
+            Self::Node4 { len, keys, children } => keys[..*len as usize].iter().position(|&k| k == b).map(|i| &children[i]),
+            Self::Node16 { len, keys, children } => keys[..*len as usize].binary_search(&b).ok().map(|i| &children[i]),
+            Self::Node48 { index, children, .. } => match index[b as usize] { 
+                0 => None,
+                slot => Some(&children[slot as usize - 1])
+            },
+            Self::Node256 { children, .. } => Some(&children[b as usize])
+        }
+    }
+
+    /**
+     * Points byte `b` at `child`, adding the byte if it is not present yet.
+     * * Returns `false` without changing anything if the byte is new and the layout is full.
+     * * Only ever called while building a node that is not published yet, which is why the
+     *   slots can be read through `unprotected`.
+     */
+    fn set(&mut self, b: u8, child: Shared<Node<B>>) -> bool { 
+        if let Some(slot) = self.find(b) 
+            && !slot.load(Ordering::Relaxed, unsafe { crossbeam_epoch::unprotected() }).is_null() { 
+            slot.store(child, Ordering::Relaxed);
+            return true;
+        }
+        if self.len() == self.capacity() { 
+            return false;
+        }
+        match self { 
+            Self::Node4 { len, keys, children } => Self::insert_sorted(len, &mut keys[..], &mut children[..], b, child),
+            Self::Node16 { len, keys, children } => Self::insert_sorted(len, &mut keys[..], &mut children[..], b, child),
+            Self::Node48 { len, index, children } => { 
+                let unprotected = unsafe { crossbeam_epoch::unprotected() };
+                let slot = children.iter().position(|c| c.load(Ordering::Relaxed, unprotected).is_null()).unwrap();
+                children[slot].store(child, Ordering::Relaxed);
+                index[b as usize] = slot as u8 + 1;
+                *len += 1;
+            },
+            Self::Node256 { len, children } => { 
+                children[b as usize].store(child, Ordering::Relaxed);
+                *len += 1;
+            }
+        }
+        true
+    }
+
+    fn insert_sorted(len: &mut u8, keys: &mut [u8], children: &mut [Atomic<Node<B>>], b: u8, child: Shared<Node<B>>) { 
+        let n = *len as usize;
+        let at = keys[..n].iter().position(|&k| k > b).unwrap_or(n);
+        keys.copy_within(at..n, at + 1);
+        children[at..n + 1].rotate_right(1);
+        keys[at] = b;
+        children[at].store(child, Ordering::Relaxed);
+        *len += 1;
+    }
+
+    fn remove(&mut self, b: u8) { 
+        let unprotected = unsafe { crossbeam_epoch::unprotected() };
+        match self { 
+            Self::Node4 { len, keys, children } => Self::remove_sorted(len, &mut keys[..], &mut children[..], b),
+            Self::Node16 { len, keys, children } => Self::remove_sorted(len, &mut keys[..], &mut children[..], b),
+            Self::Node48 { len, index, children } => { 
+                let slot = index[b as usize];
+                if slot != 0 { 
+                    children[slot as usize - 1].store(Shared::null(), Ordering::Relaxed);
+                    index[b as usize] = 0;
+                    *len -= 1;
+                }
+            },
+            Self::Node256 { len, children } => { 
+                if !children[b as usize].swap(Shared::null(), Ordering::Relaxed, unprotected).is_null() { 
+                    *len -= 1;
+                }
+            }
+        }
+    }
+
+    fn remove_sorted(len: &mut u8, keys: &mut [u8], children: &mut [Atomic<Node<B>>], b: u8) { 
+        let n = *len as usize;
+        if let Some(at) = keys[..n].iter().position(|&k| k == b) { 
+            keys.copy_within(at + 1..n, at);
+            children[at].store(Shared::null(), Ordering::Relaxed);
+            children[at..n].rotate_left(1);
+            *len -= 1;
+        }
+    }
+
+    /**
+     * Non-null children in ascending byte order.
+     */
+    fn entries<'g>(&self, guard: &'g Guard) -> Vec<(u8, Shared<'g, Node<B>>)> { 
+        let mut out = Vec::with_capacity(self.len());
+        match self { 
+            Self::Node4 { len, keys, children } => { 
+                for i in 0..*len as usize { 
+                    out.push((keys[i], children[i].load(Ordering::SeqCst, guard)));
+                }
+            },
+            Self::Node16 { len, keys, children } => { 
+                for i in 0..*len as usize { 
+                    out.push((keys[i], children[i].load(Ordering::SeqCst, guard)));
+                }
+            },
+            Self::Node48 { index, children, .. } => { 
+                for b in 0..B { 
+                    if index[b] != 0 { 
+                        out.push((b as u8, children[index[b] as usize - 1].load(Ordering::SeqCst, guard)));
+                    }
+                }
+            },
+            Self::Node256 { children, .. } => { 
+                for (b, child) in children.iter().enumerate() { 
+                    let shared = child.load(Ordering::SeqCst, guard);
+                    if !shared.is_null() { 
+                        out.push((b as u8, shared));
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    fn from_entries(capacity: usize, entries: &[(u8, Shared<Node<B>>)]) -> Self { 
+        let mut kind = Self::with_capacity(capacity);
+        for &(b, child) in entries { 
+            kind.set(b, child);
+        }
+        kind
+    }
+
+    /**
+     * Heap bytes owned by the child storage itself, not counting the children.
+     */
+    fn heap_size(&self) -> usize { 
+        match self { 
+            Self::Node4 { .. } => 0,
+            Self::Node16 { .. } => size_of::<[u8; 16]>() + size_of::<[Atomic<Node<B>>; 16]>(),
+            Self::Node48 { .. } => size_of::<[u8; 256]>() + size_of::<[Atomic<Node<B>>; 48]>(),
+            Self::Node256 { .. } => B * size_of::<Atomic<Node<B>>>()
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct Node { 
-    children: Box<[Atomic<Node>]>,
-    value: Atomic<Vec<u8>>
+pub struct Node<const B: usize = BRANCH_CAPACITY> { 
+    partial_key: SmallVec<[u8; 8]>,
+    kind: NodeKind<B>,
+    value: Atomic<Bytes>
 }
 
 
-impl Node { 
+impl<const B: usize> Default for Node<B> { 
+    fn default() -> Self { 
+        Self::new()
+    }
+}
+
+impl<const B: usize> Node<B> { 
     pub fn new() -> Self { 
-        let children = vec![Atomic::null(); BRANCH_CAPACITY];
+        Self::with_partial_key(&[])
+    }
+
+    /**
+     * Creates an empty `Node4` carrying a compressed path segment.
+     * * The full key of a node is the key of its parent, followed by the byte of
+     *   the slot it hangs from, followed by its `partial_key`.
+     */
+    pub fn with_partial_key(partial_key: &[u8]) -> Self { 
         Self { 
-            children: children.into_boxed_slice(),
+            partial_key: SmallVec::from_slice(partial_key),
+            kind: NodeKind::with_capacity(0),
             value: Atomic::null()
         }
     }
 
-    pub fn get(&self, b: u8) -> &Atomic<Node>{ 
-        &self.children[b as usize]
+    /**
+     * Shallow copy of the node with a different compressed path segment.
+     * * Children and value pointers are shared with `self`, nothing they point to is cloned.
+     *   Dropping both the copy and `self` would free them twice, so one of them has to go
+     *   through `drop_shallow`; the shallow copies are crate-private for that reason.
+     */
+    pub(crate) fn copy_with_partial_key(&self, partial_key: &[u8], guard: &Guard) -> Self { 
+        Self { 
+            partial_key: SmallVec::from_slice(partial_key),
+            kind: NodeKind::from_entries(self.kind.capacity(), &self.kind.entries(guard)),
+            value: Atomic::from(self.value.load(Ordering::SeqCst, guard))
+        }
+    }
+
+    pub(crate) fn copy(&self, guard: &Guard) -> Self { 
+        self.copy_with_partial_key(&self.partial_key, guard)
+    }
+
+    /**
+     * Shallow copy of the node in which byte `b` points at `child`.
+     * * A null `child` drops the byte. The copy is upgraded to a larger layout when the
+     *   new byte does not fit, and downgraded when the remaining children fit a smaller one.
+     */
+    pub(crate) fn with_child(&self, b: u8, child: Shared<Node<B>>, guard: &Guard) -> Self { 
+        let mut copy = self.copy(guard);
+        copy.set_child(b, child, guard);
+        copy
+    }
+
+    /**
+     * Points byte `b` at `child` in place, see `with_child`.
+     * * Only valid on a node that has not been published into a tree yet.
+     */
+    pub(crate) fn set_child(&mut self, b: u8, child: Shared<Node<B>>, guard: &Guard) { 
+        if child.is_null() { 
+            self.kind.remove(b);
+            self.try_shrink(guard);
+            return;
+        }
+        if !self.kind.set(b, child) { 
+            self.kind = self.upgrade(guard);
+            self.kind.set(b, child);
+        }
     }
 
-    pub fn value(&self) -> &Atomic<Vec<u8>> { 
+    /**
+     * Next larger layout holding the same children.
+     */
+    pub fn upgrade(&self, guard: &Guard) -> NodeKind<B> { 
+        NodeKind::from_entries(self.kind.capacity() + 1, &self.kind.entries(guard))
+    }
+
+    /**
+     * Smallest layout holding the same children.
+     */
+    pub fn downgrade(&self, guard: &Guard) -> NodeKind<B> { 
+        NodeKind::from_entries(NodeKind::<B>::capacity_for(self.kind.len()), &self.kind.entries(guard))
+    }
+
+    /**
+     * Number of non-null children.
+     */
+    pub fn occupied_count(&self) -> usize { 
+        self.kind.len()
+    }
+
+    /**
+     * Non-null children with their bytes, in ascending byte order.
+     * * Only the occupied slots are visited for the sparse layouts, so walking a node costs
+     *   O(children) rather than O(BRANCH_CAPACITY), except for `Node256` which has no index.
+     */
+    pub fn iter_children<'a>(&'a self, guard: &'a Guard) -> impl DoubleEndedIterator<Item = (u8, Shared<'a, Node<B>>)> + 'a { 
+        self.kind.entries(guard).into_iter()
+    }
+
+    /**
+     * Moves the children into the smallest layout that holds them.
+     * * Returns `true` if the layout changed. Only valid on a node that has not been
+     *   published into a tree yet, see `set_child`.
+     */
+    pub fn try_shrink(&mut self, guard: &Guard) -> bool { 
+        if NodeKind::<B>::capacity_for(self.occupied_count()) >= self.kind.capacity() { 
+            return false;
+        }
+        self.kind = self.downgrade(guard);
+        true
+    }
+
+    pub fn kind(&self) -> &NodeKind<B> { 
+        &self.kind
+    }
+
+    pub fn partial_key(&self) -> &[u8] { 
+        &self.partial_key
+    }
+
+    pub fn find_child<'g>(&self, b: u8, guard: &'g Guard) -> Option<Shared<'g, Node<B>>> { 
+        self.kind.find(b)
+            .map(|child| child.load(Ordering::SeqCst, guard))
+            .filter(|child| !child.is_null())
+    }
+
+    pub fn value(&self) -> &Atomic<Bytes> { 
         &self.value
     }
-}
\ No newline at end of file
+
+    /**
+     * Frees the node itself but neither its children nor its value.
+     * * Nodes replaced by a copy-on-write update go this way, since the copy that replaced
+     *   them still points at the same children and value. Dropping them normally would free
+     *   a subtree that is still in use.
+     */
+    pub fn drop_shallow(mut self) { 
+        self.reset();
+    }
+
+    /**
+     * Turns the node into an empty one without freeing anything it pointed at.
+     */
+    fn reset(&mut self) { 
+        self.partial_key.clear();
+        self.kind = NodeKind::with_capacity(0);
+        self.value = Atomic::null();
+    }
+
+    /**
+     * Unlinks the value and all children, freeing the value and handing the children back.
+     */
+    fn take_links(&mut self, guard: &Guard) -> Vec<Owned<Node<B>>> { 
+        let value = self.value.swap(Shared::null(), Ordering::Relaxed, guard);
+        if !value.is_null() { 
+            drop(unsafe { value.into_owned()});
+        }
+        let children = self.kind.entries(guard);
+        self.kind = NodeKind::with_capacity(0);
+        children.into_iter().map(|(_, child)| unsafe { child.into_owned()}).collect()
+    }
+
+    /**
+     * Bytes taken by this node alone: the node, its child storage and a spilled `partial_key`.
+     */
+    pub fn footprint(&self) -> usize { 
+        let spilled = if self.partial_key.spilled() { self.partial_key.capacity() } else { 0 };
+        size_of::<Node<B>>() + self.kind.heap_size() + spilled
+    }
+}
+
+/**
+ * Frees the whole subtree below the node, values included.
+ * * Only sound while the node is the sole owner of that subtree and nobody else can reach
+ *   it: a tree being cleared or dropped through `&mut`, or a node that was never published.
+ *   Nodes sharing children with a live copy must be released with `drop_shallow` instead.
+ * * The subtree is walked with an explicit stack, so long keys cannot overflow the call stack.
+ */
+impl<const B: usize> Drop for Node<B> { 
+    fn drop(&mut self) { 
+        let guard = unsafe { crossbeam_epoch::unprotected() };
+        let mut stack = self.take_links(guard);
+        while let Some(mut node) = stack.pop() { 
+            stack.extend(node.take_links(guard));
+        }
+    }
+}
+
+/**
+ * Free list of node boxes, so the nodes built by copy-on-write updates can reuse the
+ * boxes of the nodes they retire instead of going to the allocator every time.
+ * * Pooled nodes are always empty. A released node is reset first, without freeing its
+ *   children or value, just like `Node::drop_shallow`.
+ * * Holds at most `capacity` boxes, anything released past that is freed.
+ */
+#[derive(Debug)]
+pub struct NodePool<const B: usize = BRANCH_CAPACITY> { 
+    free: SegQueue<Box<Node<B>>>,
+    capacity: usize
+}
+
+impl<const B: usize> Default for NodePool<B> { 
+    fn default() -> Self { 
+        Self::new()
+    }
+}
+
+impl<const B: usize> NodePool<B> { 
+    pub const DEFAULT_CAPACITY: usize = 4096;
+    /**
+     * Number of boxes allocated up front by `with_capacity`.
+     */
+    pub const BATCH: usize = 64;
+
+    pub fn new() -> Self { 
+        Self::with_capacity(Self::DEFAULT_CAPACITY)
+    }
+
+    /**
+     * Creates a pool keeping up to `capacity` boxes and pre-allocates the first batch.
+     * * A capacity of `0` disables pooling, every node then comes from the allocator.
+     */
+    pub fn with_capacity(capacity: usize) -> Self { 
+        let free = SegQueue::new();
+        for _ in 0..capacity.min(Self::BATCH) { 
+            free.push(Box::new(Node::new()));
+        }
+        Self { free, capacity }
+    }
+
+    /**
+     * Hands out an empty node, reusing a pooled box if there is one.
+     */
+    pub fn acquire(&self) -> Box<Node<B>> { 
+        self.free.pop().unwrap_or_else(|| Box::new(Node::new()))
+    }
+
+    /**
+     * Takes a node back into the pool. Only the box is kept, see `NodePool`.
+     */
+    pub fn release(&self, mut node: Box<Node<B>>) { 
+        node.reset();
+        if self.free.len() < self.capacity { 
+            self.free.push(node);
+        }
+    }
+
+    pub fn len(&self) -> usize { 
+        self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool { 
+        self.free.is_empty()
+    }
+
+    /**
+     * Frees pooled boxes until at most `keep` are left.
+     */
+    pub fn trim(&self, keep: usize) { 
+        while self.free.len() > keep { 
+            if self.free.pop().is_none() { 
+                break;
+            }
+        }
+    }
+}
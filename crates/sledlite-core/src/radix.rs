@@ -1,54 +1,547 @@
-use std::{sync::atomic::Ordering};
+use std::{borrow::Cow, fmt, io, sync::{Arc, atomic::{AtomicUsize, Ordering}}};
 
-use crate::node::{BRANCH_CAPACITY, Node};
-use crossbeam_epoch::{Atomic, Owned, Shared};
+use crate::node::{BRANCH_CAPACITY, Node, NodePool};
+use bytes::Bytes;
+use crossbeam_epoch::{Atomic, Guard, Owned, Shared};
 
+/**
+ * Lock-free, path-compressed radix tree with branching factor `B`.
+ * * With the default `B = 256` every key byte is one level of the tree. Smaller powers of
+ *   two split each byte into several digits, most significant first, e.g. two nibbles for
+ *   `B = 16`. Keys are translated at the API boundary, so all branching factors hold the
+ *   same keys in the same order.
+ * * Values are `Bytes`. Writers hand in anything that converts into `Bytes`, which moves
+ *   a `Vec<u8>` or a `Bytes` in without copying it, and readers get a reference-counted
+ *   handle to the stored value instead of a copy.
+ */
 #[derive(Debug)]
-pub struct RadixTree { 
-    pub root: Atomic<Node>
+pub struct RadixTree<const B: usize = BRANCH_CAPACITY> { 
+    pub root: Atomic<Node<B>>,
+    key_count: AtomicUsize,
+    pool: Arc<NodePool<B>> // shared with the deferred closures that recycle retired nodes
 }
 
+pub type DefaultRadixTree = RadixTree<BRANCH_CAPACITY>;
+
 #[derive(Debug)]
 pub enum RadixError { 
     InvalidKey,
     Failed{ failed_garbage_value: Vec<u8> },
-    AlreadyWritten{ value : Vec<u8>}    
+    AlreadyWritten{ value : Bytes},
+    Corrupted // `RadixTree::deserialize` input that is truncated or was not written by `serialize`
 }
 
-impl Into<String> for RadixError {
-    fn into(self) -> String {
+impl fmt::Display for RadixError { 
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { 
         match self { 
-            Self::InvalidKey => "invalid key".to_string(),
-            Self::Failed { failed_garbage_value } => format!("failed with garbage value: {:?}", failed_garbage_value),
-            Self::AlreadyWritten { value } => format!("already written : {:?}",value)
+            Self::InvalidKey => write!(f, "radix tree key must be non-empty"),
+            Self::Failed { failed_garbage_value } => write!(f, "radix CAS failed, recovered value of {} bytes", failed_garbage_value.len()),
+            Self::AlreadyWritten { value } => write!(f, "radix tree key already holds a value of {} bytes", value.len()),
+            Self::Corrupted => write!(f, "radix tree encoding is truncated or malformed")
+        }
+    }
+}
+
+impl std::error::Error for RadixError {}
+
+impl From<RadixError> for String { 
+    fn from(err: RadixError) -> Self { 
+        err.to_string()
+    }
+}
+
+/**
+ * Lets engine methods returning `io::Result` propagate tree errors with `?`. An empty key
+ * is the caller's mistake and maps to `InvalidInput`, a bad encoding to `InvalidData`, the
+ * original error is kept as the source.
+ */
+impl From<RadixError> for io::Error { 
+    fn from(err: RadixError) -> Self { 
+        let kind = match err { 
+            RadixError::InvalidKey => io::ErrorKind::InvalidInput,
+            RadixError::Corrupted => io::ErrorKind::InvalidData,
+            _ => io::ErrorKind::Other
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/**
+ * Bookkeeping for one copy-on-write attempt.
+ * * `path` holds every ancestor walked from the root down to the node being replaced,
+ *   paired with the byte of the slot taken out of it. `fresh` holds the nodes allocated
+ *   for this attempt so they can be freed if the attempt loses the race on the root.
+ *   `pruned` holds ancestors unlinked together with the replaced node. `pool` provides the
+ *   boxes of the fresh nodes and takes them back when the attempt fails.
+ */
+struct Rewrite<'g, const B: usize> { 
+    path: Vec<(Shared<'g, Node<B>>, u8)>,
+    fresh: Vec<Shared<'g, Node<B>>>,
+    pruned: Vec<Shared<'g, Node<B>>>,
+    replaced_value: Shared<'g, Bytes>,
+    pool: &'g NodePool<B>
+}
+
+impl<'g, const B: usize> Rewrite<'g, B> { 
+    fn new(pool: &'g NodePool<B>) -> Self { 
+        Self { 
+            path: Vec::new(),
+            fresh: Vec::new(),
+            pruned: Vec::new(),
+            replaced_value: Shared::null(),
+            pool
+        }
+    }
+
+    fn alloc(&mut self, node: Node<B>, guard: &'g Guard) -> Shared<'g, Node<B>> { 
+        let mut boxed = self.pool.acquire();
+        *boxed = node;
+        let shared = Owned::<Node<B>>::from(boxed).into_shared(guard);
+        self.fresh.push(shared);
+        shared
+    }
+}
+
+/**
+ * A private node on the path walked by `bulk_insert`, hanging from slot `byte` of the frame
+ * below it. Its full key spans `start..end` of the key being inserted, `start` being the
+ * position of `byte`.
+ */
+struct BulkFrame<const B: usize> { 
+    node: Node<B>,
+    byte: u8,
+    start: usize,
+    end: usize
+}
+
+/**
+ * Splits the first `n` bytes off `data` for `RadixTree::deserialize`.
+ */
+fn take<'a>(data: &mut &'a [u8], n: usize) -> Result<&'a [u8], RadixError> { 
+    if data.len() < n { 
+        return Err(RadixError::Corrupted);
+    }
+    let (head, rest) = data.split_at(n);
+    *data = rest;
+    Ok(head)
+}
+
+fn take_u32(data: &mut &[u8]) -> Result<usize, RadixError> { 
+    Ok(u32::from_be_bytes(take(data, 4)?.try_into().unwrap()) as usize)
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize { 
+    a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
+}
+
+/**
+ * Bits per digit and digits per key byte of a tree with branching factor `B`.
+ */
+const fn digit_layout<const B: usize>() -> (u32, usize) { 
+    let bits = B.trailing_zeros();
+    (bits, 8usize.div_ceil(bits as usize))
+}
+
+/**
+ * Splits every byte of `key` into its digits, most significant first.
+ * * The first digit of a byte takes whatever bits are left over, so a `B = 64` tree
+ *   stores a byte as a 2 bit digit followed by a 6 bit one.
+ */
+fn to_digits<const B: usize>(key: &[u8]) -> Cow<'_, [u8]> { 
+    if B == BRANCH_CAPACITY { 
+        return Cow::Borrowed(key);
+    }
+    let (bits, per_byte) = digit_layout::<B>();
+    let mut digits = Vec::with_capacity(key.len() * per_byte);
+    for &byte in key { 
+        for i in (0..per_byte).rev() { 
+            digits.push((byte as u32 >> (i as u32 * bits)) as u8 & (B - 1) as u8);
+        }
+    }
+    Cow::Owned(digits)
+}
+
+/**
+ * Inverse of `to_digits`. Only whole keys are passed in, so `digits` always holds a
+ * multiple of the digits per byte.
+ */
+fn from_digits<const B: usize>(digits: Vec<u8>) -> Vec<u8> { 
+    if B == BRANCH_CAPACITY { 
+        return digits;
+    }
+    let (bits, per_byte) = digit_layout::<B>();
+    digits.chunks(per_byte)
+        .map(|chunk| chunk.iter().fold(0u32, |byte, &digit| (byte << bits) | digit as u32) as u8)
+        .collect()
+}
+
+/**
+ * Frees every node and value still reachable from the root.
+ * * Nodes retired by earlier updates are freed by the collector, see `retire`.
+ */
+impl<const B: usize> Drop for RadixTree<B> { 
+    fn drop(&mut self) { 
+        let guard = unsafe { crossbeam_epoch::unprotected() };
+        let root = self.root.swap(Shared::null(), Ordering::SeqCst, guard);
+        if !root.is_null() { 
+            drop(unsafe { root.into_owned()});
         }
     }
 }
 
 impl RadixTree { 
     pub fn new() -> Self { 
+        Self::with_node_pool(NodePool::new())
+    }
+}
+
+impl<const B: usize> Default for RadixTree<B> { 
+    fn default() -> Self { 
+        Self::with_node_pool(NodePool::new())
+    }
+}
+
+impl<const B: usize> RadixTree<B> { 
+    /**
+     * Creates an empty tree that takes the boxes of new nodes from `pool`.
+     * * `B` has to be a power of two between 2 and 256, see `RadixTree`.
+     */
+    pub fn with_node_pool(pool: NodePool<B>) -> Self { 
+        const { assert!(B.is_power_of_two() && B >= 2 && B <= BRANCH_CAPACITY, "branching factor must be a power of two in 2..=256") };
         Self { 
-            root: Atomic::new(Node::new())
+            root: Atomic::new(Node::new()),
+            key_count: AtomicUsize::new(0),
+            pool: Arc::new(pool)
+        }
+    }
+
+    /**
+     * Hands a node replaced by a copy-on-write update to the guard for deferred destruction.
+     * * Its children and value live on in the replacement, so only its box is given back to
+     *   the pool.
+     */
+    unsafe fn retire<'g>(&self, node: Shared<'g, Node<B>>, guard: &'g Guard) { 
+        let pool = self.pool.clone();
+        unsafe { guard.defer_unchecked(move || pool.release(node.into_owned().into_box())) };
+    }
+
+    fn load_root<'g>(&self, guard: &'g Guard) -> Shared<'g, Node<B>> { 
+        let root_shared = self.root.load(Ordering::SeqCst, guard);
+        if !root_shared.is_null() { 
+            return root_shared;
+        }
+        let new_root = Owned::new(Node::new());
+        match self.root.compare_exchange(
+            root_shared, new_root, 
+            Ordering::SeqCst, 
+            Ordering::SeqCst, 
+            guard) { 
+                Ok(shared) => shared,
+                Err(e) => e.current
+            }
+    }
+
+    /**
+     * Walks down from `root` following `key` through the compressed path segments.
+     * * Returns the node whose full key equals `key`, recording every ancestor and the
+     *   slot byte taken out of it into `path`. Returns `None` as soon as the key leaves the tree.
+     */
+    fn walk<'g>(root: Shared<'g, Node<B>>, key: &[u8], path: &mut Vec<(Shared<'g, Node<B>>, u8)>, guard: &'g Guard) -> Option<Shared<'g, Node<B>>> { 
+        let mut curr_shared = root;
+        let mut depth = 0;
+        while depth < key.len() { 
+            let curr_node = unsafe { curr_shared.deref()};
+            let b = key[depth];
+            let next = curr_node.find_child(b, guard)?;
+            let partial = unsafe { next.deref()}.partial_key();
+            let rest = &key[depth + 1..];
+            if !rest.starts_with(partial) { 
+                return None;
+            }
+            path.push((curr_shared, b));
+            curr_shared = next;
+            depth += 1 + partial.len();
+        }
+        Some(curr_shared)
+    }
+
+    /**
+     * Publishes a new version of the tree in which `target` is replaced by `replacement`.
+     * * A null `replacement` unlinks `target` from its parent.
+     * * The ancestors in `rewrite.path` are copied bottom-up so that they point at the
+     *   replacement, and the new root is swapped in with a single CAS against `root`.
+     * * On success the replaced nodes and value are handed to the guard for deferred
+     *   destruction. On failure the nodes built for this attempt are freed right away, since
+     *   they were never reachable by anyone else, and the caller is expected to retry.
+     */
+    fn publish<'g>(&self, root: Shared<'g, Node<B>>, target: Shared<'g, Node<B>>, replacement: Shared<'g, Node<B>>, mut rewrite: Rewrite<'g, B>, guard: &'g Guard) -> bool { 
+        let mut child = replacement;
+        for i in (0..rewrite.path.len()).rev() { 
+            let (ancestor, b) = rewrite.path[i];
+            let copy = unsafe { ancestor.deref()}.with_child(b, child, guard);
+            child = rewrite.alloc(copy, guard);
+        }
+        match self.root.compare_exchange(
+            root,
+            child,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+            guard) { 
+                Ok(_) => { 
+                    unsafe { 
+                        for &(ancestor, _) in rewrite.path.iter() { 
+                            self.retire(ancestor, guard);
+                        }
+                        self.retire(target, guard);
+                        for &pruned in rewrite.pruned.iter() { 
+                            self.retire(pruned, guard);
+                        }
+                        if !rewrite.replaced_value.is_null() { 
+                            guard.defer_destroy(rewrite.replaced_value);
+                        }
+                    }
+                    true
+                },
+                Err(_) => { 
+                    for fresh in rewrite.fresh { 
+                        self.pool.release(unsafe { fresh.into_owned()}.into_box());
+                    }
+                    false
+                }
+            }
+    }
+
+    /**
+     * Copy-on-write insertion shared by `insert` and `put`.
+     * * Handles the three cases of a path-compressed tree:
+     * * full match - the key ends on an existing node, its value is replaced.
+     * * extension - the key runs past a node with no child for the next byte, a new leaf
+     *   carrying the rest of the key is hung from that slot.
+     * * partial match - the key diverges inside a child's segment, the child is split at
+     *   the first mismatching byte.
+     * * Returns the value previously stored under `key`, if any. When `overwrite` is false
+     *   an existing value is left in place and returned, and nothing is written.
+     */
+    fn upsert(&self, key: &[u8], value: Bytes, overwrite: bool) -> Option<Bytes> { 
+        let key = to_digits::<B>(key);
+        let key: &[u8] = &key;
+        let guard = crossbeam_epoch::pin();
+        let value = Owned::new(value).into_shared(&guard);
+        loop { 
+            let root = self.load_root(&guard);
+            let mut rewrite = Rewrite::new(&self.pool);
+            let mut curr_shared = root;
+            let mut depth = 0;
+            let replacement = loop { 
+                let curr_node = unsafe { curr_shared.deref()};
+                if depth == key.len() { 
+                    let curr_value = curr_node.value().load(Ordering::SeqCst, &guard);
+                    if !overwrite && !curr_value.is_null() { 
+                        drop(unsafe { value.into_owned()});
+                        return Some(unsafe { curr_value.deref()}.clone());
+                    }
+                    let copy = curr_node.copy(&guard);
+                    rewrite.replaced_value = copy.value().swap(value, Ordering::Relaxed, &guard);
+                    break rewrite.alloc(copy, &guard);
+                }
+                let b = key[depth];
+                let rest = &key[depth + 1..];
+                let next = match curr_node.find_child(b, &guard) { 
+                    Some(next) => next,
+                    None => { 
+                        let leaf = Node::with_partial_key(rest);
+                        leaf.value().store(value, Ordering::Relaxed);
+                        let leaf = rewrite.alloc(leaf, &guard);
+                        let copy = curr_node.with_child(b, leaf, &guard);
+                        break rewrite.alloc(copy, &guard);
+                    }
+                };
+                let partial = unsafe { next.deref()}.partial_key();
+                let common = common_prefix_len(partial, rest);
+                rewrite.path.push((curr_shared, b));
+                curr_shared = next;
+                if common == partial.len() { 
+                    depth += 1 + common;
+                    continue;
+                }
+                let mut split = Node::with_partial_key(&partial[..common]);
+                let tail = unsafe { next.deref()}.copy_with_partial_key(&partial[common + 1..], &guard);
+                split.set_child(partial[common], rewrite.alloc(tail, &guard), &guard);
+                if common == rest.len() { 
+                    split.value().store(value, Ordering::Relaxed);
+                } else { 
+                    let leaf = Node::with_partial_key(&rest[common + 1..]);
+                    leaf.value().store(value, Ordering::Relaxed);
+                    split.set_child(rest[common], rewrite.alloc(leaf, &guard), &guard);
+                }
+                break rewrite.alloc(split, &guard);
+            };
+            let replaced_value = rewrite.replaced_value;
+            if self.publish(root, curr_shared, replacement, rewrite, &guard) { 
+                if replaced_value.is_null() { 
+                    self.key_count.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                return Some(unsafe { replaced_value.deref()}.clone());
+            }
+        }
+    }
+
+    /**
+     * Inserts many entries in one pass and publishes them with a single CAS on the root.
+     * * Exploits the sorted input: the walk keeps a stack of private copies of the nodes on
+     *   the path to the previous key. For the next key the stack is only unwound down to the
+     *   branch point the two keys share, and the walk continues from there, so every node is
+     *   copied at most once however many keys go through it.
+     * * # Arguments
+     * * `entries` - (Key, Value) pairs, assumed sorted by key. Unsorted input is sorted first,
+     *   and for duplicate keys the last value wins. Values are anything convertible into `Bytes`.
+     * * # Returns
+     * * `Ok(usize)` with the number of keys that were not in the tree before.
+     * * `Err(RadixError)` if any key is empty slice, in which case nothing is written.
+     */
+    pub fn bulk_insert<V: Into<Bytes>>(&self, mut entries: Vec<(Vec<u8>, V)>) -> Result<usize, RadixError> { 
+        if entries.iter().any(|(key, _)| key.is_empty()) { 
+            return Err(RadixError::InvalidKey);
+        }
+        if !entries.is_sorted_by(|a, b| a.0 <= b.0) { 
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        let guard = crossbeam_epoch::pin();
+        let mut keys: Vec<Vec<u8>> = Vec::with_capacity(entries.len());
+        let mut values: Vec<Shared<Bytes>> = Vec::with_capacity(entries.len());
+        for (key, value) in entries { 
+            if keys.last() == Some(&key) { 
+                keys.pop();
+                drop(unsafe { values.pop().unwrap().into_owned()});
+            }
+            keys.push(key);
+            values.push(Owned::new(value.into()).into_shared(&guard));
+        }
+        if B != BRANCH_CAPACITY { 
+            keys = keys.iter().map(|key| to_digits::<B>(key).into_owned()).collect();
+        }
+
+        loop { 
+            let root = self.load_root(&guard);
+            let mut rewrite = Rewrite::new(&self.pool);
+            let mut replaced_values = Vec::new();
+            let mut inserted = 0;
+            let mut stack = vec![BulkFrame { node: unsafe { root.deref()}.copy(&guard), byte: 0, start: 0, end: 0 }];
+            rewrite.pruned.push(root);
+            let mut prev_key: &[u8] = &[];
+            for (key, &value) in keys.iter().zip(values.iter()) { 
+                let lcp = common_prefix_len(prev_key, key);
+                prev_key = key;
+                // unwind to the branch point shared with the previous key
+                while stack.len() > 1 && stack[stack.len() - 1].start >= lcp { 
+                    Self::seal(&mut stack, &mut rewrite, &guard);
+                }
+                // the key diverges inside the segment of the top node, split it in place
+                let top = stack.len() - 1;
+                if stack[top].end > lcp { 
+                    let frame = stack.pop().unwrap();
+                    let common = lcp - frame.start - 1;
+                    let partial = frame.node.partial_key();
+                    let mut split = Node::with_partial_key(&partial[..common]);
+                    let tail = frame.node.copy_with_partial_key(&partial[common + 1..], &guard);
+                    split.set_child(partial[common], rewrite.alloc(tail, &guard), &guard);
+                    frame.node.drop_shallow();
+                    stack.push(BulkFrame { node: split, byte: frame.byte, start: frame.start, end: lcp });
+                }
+                // walk down the existing nodes from the branch point
+                loop { 
+                    let top = stack.len() - 1;
+                    let depth = stack[top].end;
+                    if depth == key.len() { 
+                        let old_value = stack[top].node.value().swap(value, Ordering::Relaxed, &guard);
+                        if old_value.is_null() { 
+                            inserted += 1;
+                        } else { 
+                            replaced_values.push(old_value);
+                        }
+                        break;
+                    }
+                    let b = key[depth];
+                    let rest = &key[depth + 1..];
+                    let next = match stack[top].node.find_child(b, &guard) { 
+                        Some(next) => next,
+                        None => { 
+                            let leaf = Node::with_partial_key(rest);
+                            leaf.value().store(value, Ordering::Relaxed);
+                            stack.push(BulkFrame { node: leaf, byte: b, start: depth, end: key.len() });
+                            inserted += 1;
+                            break;
+                        }
+                    };
+                    let next_node = unsafe { next.deref()};
+                    let partial = next_node.partial_key();
+                    let common = common_prefix_len(partial, rest);
+                    rewrite.pruned.push(next);
+                    if common == partial.len() { 
+                        stack.push(BulkFrame { node: next_node.copy(&guard), byte: b, start: depth, end: depth + 1 + common });
+                        continue;
+                    }
+                    let mut split = Node::with_partial_key(&partial[..common]);
+                    let tail = next_node.copy_with_partial_key(&partial[common + 1..], &guard);
+                    split.set_child(partial[common], rewrite.alloc(tail, &guard), &guard);
+                    stack.push(BulkFrame { node: split, byte: b, start: depth, end: depth + 1 + common });
+                }
+            }
+            while stack.len() > 1 { 
+                Self::seal(&mut stack, &mut rewrite, &guard);
+            }
+            let new_root = rewrite.alloc(stack.pop().unwrap().node, &guard);
+            match self.root.compare_exchange(root, new_root, Ordering::SeqCst, Ordering::SeqCst, &guard) { 
+                Ok(_) => { 
+                    unsafe { 
+                        for &replaced in rewrite.pruned.iter() { 
+                            self.retire(replaced, &guard);
+                        }
+                        for replaced_value in replaced_values { 
+                            guard.defer_destroy(replaced_value);
+                        }
+                    }
+                    self.key_count.fetch_add(inserted, Ordering::Relaxed);
+                    return Ok(inserted);
+                },
+                Err(_) => { 
+                    for fresh in rewrite.fresh { 
+                        self.pool.release(unsafe { fresh.into_owned()}.into_box());
+                    }
+                }
+            }
         }
     }
 
+    /**
+     * Pops the top frame of a `bulk_insert` walk and hangs the finished node from its parent.
+     */
+    fn seal<'g>(stack: &mut Vec<BulkFrame<B>>, rewrite: &mut Rewrite<'g, B>, guard: &'g Guard) { 
+        let frame = stack.pop().unwrap();
+        let shared = rewrite.alloc(frame.node, guard);
+        stack.last_mut().unwrap().node.set_child(frame.byte, shared, guard);
+    }
+
     /**
      * Retrieves the value associated with a given key from the Radix Tree.
      * * This method is lock-free and uses Epoch-Based Reclamation (EBR) via `crossbeam_epoch` 
-     * to ensure memory safety during concurrent reads and writes.
+     *   to ensure memory safety during concurrent reads and writes.
      * * # Arguments
      * * `key` - A byte slice representing the path to the desired node.
      * * # Returns
-     * * `Ok(Some(Vec<u8>))` if the key exists and has an associated value.
+     * * `Ok(Some(Bytes))` if the key exists and has an associated value, sharing the
+     *   stored buffer.
      * * `Ok(None)` if the key path does not exist or the terminal node has no value.
      * * `Err(RadixError)` if the key is empty slice.
      * * # Safety
-     * Traversal relies on `unsafe` dereferencing of `Shared` pointers. This is safe 
-     * because the `guard` prevents any node from being physically deallocated 
-     * while the search is in progress.
+     *   Traversal relies on `unsafe` dereferencing of `Shared` pointers. This is safe 
+     *   because the `guard` prevents any node from being physically deallocated 
+     *   while the search is in progress.
      */
-
-    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, RadixError> {
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>, RadixError> {
+        let key = to_digits::<B>(key);
+        let key: &[u8] = &key;
         if key.is_empty() { 
             return Err(RadixError::InvalidKey);
         } 
@@ -57,236 +550,674 @@ impl RadixTree {
         if curr_shared.is_null() { 
             return Ok(None);
         }
-        for &b in key { 
+        let mut depth = 0;
+        while depth < key.len() { 
             let curr_node = unsafe { curr_shared.deref()};
-            let next = curr_node.get(b).load(Ordering::SeqCst, &guard);
-            if next.is_null() { 
+            let next = match curr_node.find_child(key[depth], &guard) { 
+                Some(next) => next,
+                None => return Ok(None)
+            };
+            let partial = unsafe { next.deref()}.partial_key();
+            if !key[depth + 1..].starts_with(partial) { 
                 return Ok(None);
             }
             curr_shared = next;
+            depth += 1 + partial.len();
         }
         let shared_value = (unsafe { curr_shared.deref()}).value().load(Ordering::SeqCst, &guard);
         if shared_value.is_null() { 
             return Ok(None);
         }
-        let value = unsafe { shared_value.deref()}.clone().to_vec();
+        let value = unsafe { shared_value.deref()}.clone();
         Ok(Some(value))
     }
 
+    /**
+     * Returns the entry with the longest key that is a prefix of `key`, as used for routing
+     * table lookups. With `"192.168"` and `"192.168.1"` stored, `"192.168.1.5"` matches
+     * `"192.168.1"`.
+     * * Walks down the tree as far as `key` allows, remembering the deepest node holding a
+     *   value, and stops when the key is exhausted or leaves the tree.
+     * * # Returns
+     * * `Some((matched_key, value))` for the deepest stored prefix, `None` if no stored key
+     *   is a prefix of `key`.
+     */
+    pub fn longest_prefix_match(&self, key: &[u8]) -> Option<(Vec<u8>, Bytes)> { 
+        let key = to_digits::<B>(key);
+        let key: &[u8] = &key;
+        let guard = crossbeam_epoch::pin();
+        let mut curr_shared = self.root.load(Ordering::SeqCst, &guard);
+        let mut depth = 0;
+        let mut best = None;
+        while !curr_shared.is_null() { 
+            let curr_node = unsafe { curr_shared.deref()};
+            let v_ptr = curr_node.value().load(Ordering::SeqCst, &guard);
+            if !v_ptr.is_null() { 
+                best = Some((depth, v_ptr));
+            }
+            if depth == key.len() { 
+                break;
+            }
+            let next = match curr_node.find_child(key[depth], &guard) { 
+                Some(next) => next,
+                None => break
+            };
+            let partial = unsafe { next.deref()}.partial_key();
+            if !key[depth + 1..].starts_with(partial) { 
+                break;
+            }
+            curr_shared = next;
+            depth += 1 + partial.len();
+        }
+        best.map(|(depth, v_ptr)| (from_digits::<B>(key[..depth].to_vec()), unsafe { v_ptr.deref()}.clone()))
+    }
+
     /**
      * inserts a value associated with a given key in the Radix Tree.
      * * This method is lock-free and uses Epoch-Based Reclamation (EBR) via `crossbeam_epoch` 
-     * to ensure memory safety during concurrent reads and writes.
+     *   to ensure memory safety during concurrent reads and writes.
      * * # Arguments
      * * `key` - A byte slice representing the path to the desired node.
      * * # Returns
-     * * `Ok(Some(Vec<u8>))` if the key exists and has an associated value and it will
-     * always return `Ok(None)`
-     * * `Ok(None)` if the terminal node has no value.
+     * * `Ok(None)` if the terminal node had no value.
      * * `Err(RadixError)` if the key is empty and if the node is already occupied.
      * * # Safety
-     * Traversal relies on `unsafe` dereferencing of `Shared` pointers. This is safe 
-     * because the `guard` prevents any node from being physically deallocated 
-     * while the search is in progress.
+     *   Nodes are never modified once published. The path from the root to the
+     *   written node is copied and swapped in with a CAS on the root, retrying if
+     *   another writer got there first. The replaced nodes are reclaimed through the
+     *   `guard` once no reader can still be traversing them.
      */
-    pub fn insert(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>, RadixError>{
+    pub fn insert(&self, key: &[u8], value: impl Into<Bytes>) -> Result<Option<Bytes>, RadixError>{
         if key.is_empty() {
             return Err(RadixError::InvalidKey);
         }
-        let guard = crossbeam_epoch::pin();
-        let mut curr_shared = self.root.load(Ordering::SeqCst, &guard);
-        if curr_shared.is_null() { 
-            let new_root = Owned::new(Node::new());
-            match self.root.compare_exchange(
-                curr_shared, new_root, 
-                Ordering::SeqCst, 
-                Ordering::SeqCst, 
-                &guard) { 
-                    Ok(shared) => curr_shared = shared,
-                    Err(e) => curr_shared = e.current
-                }
+        match self.upsert(key, value.into(), true) { 
+            None => Ok(None),
+            Some(old_vec) => Err(RadixError::AlreadyWritten { value: old_vec })
         }
+    }
 
-        for &b in key { 
-            let curr_node = unsafe { curr_shared.deref()};
-            let next = curr_node.get(b);
-            let mut next_shared = next.load(Ordering::SeqCst, &guard);
-            if next_shared.is_null() { 
-                let new_next = Owned::new(Node::new());
-                match next.compare_exchange(
-                    next_shared,
-                    new_next, 
-                    Ordering::SeqCst, 
-                    Ordering::SeqCst,
-                    &guard) { 
-                        Ok(shared) => next_shared = shared,
-                        Err(e) => next_shared = e.current
-                    }
-            }
-
-            curr_shared = next_shared;
+    /**
+     * updates the value associated with a given key from the Radix Tree.
+     * walks down the tree and swaps in a copy of the path holding the new value. 
+     * * This method is lock-free and uses Epoch-Based Reclamation (EBR) via `crossbeam_epoch` 
+     *   to ensure memory safety during concurrent reads and writes.
+     * * # Arguments
+     * * `key` - A byte slice representing the path to the desired node.
+     * * # Returns
+     * * `Ok(Some(Bytes))` if the terminal node is updatd with the new value
+     * * `Err(RadixError)` if the key is empty slice.
+     * * # Safety
+     *   Same copy-on-write scheme as `insert`.
+     */
+    pub fn put(&self, key: &[u8], value: impl Into<Bytes>) -> Result<Option<Bytes>, RadixError>{
+        if key.is_empty() { 
+            return Err(RadixError::InvalidKey);
         }
+        let updated_vec: Bytes = value.into();
+        self.upsert(key, updated_vec.clone(), true);
+        Ok(Some(updated_vec))
+    }
 
-        // at tail end swap the value 
-        let curr_node = unsafe { curr_shared.deref()};
-        let old_value_shared = curr_node.value().swap(Owned::new(value), Ordering::SeqCst, &guard);
-        if old_value_shared.is_null() {
-            return Ok(None)
-        } else { 
-            let old_vec = unsafe { old_value_shared.deref()}.clone();
-            return Err(RadixError::AlreadyWritten { value: old_vec });
+    /**
+     * Stores `value` under `key` like `put`, but hands back the value it replaced.
+     * * Lets a caller that has to account for the old value skip a separate `get`, the tree
+     *   is only walked once.
+     * * # Returns
+     * * `Ok(Some(Bytes))` with the value previously stored under `key`.
+     * * `Ok(None)` if the key had no value.
+     * * `Err(RadixError)` if the key is empty slice.
+     * * # Safety
+     *   Same copy-on-write scheme as `insert`.
+     */
+    pub fn replace(&self, key: &[u8], value: impl Into<Bytes>) -> Result<Option<Bytes>, RadixError>{ 
+        if key.is_empty() { 
+            return Err(RadixError::InvalidKey);
         }
+        Ok(self.upsert(key, value.into(), true))
     }
 
     /**
-     * updates the value associated with a given key from the Radix Tree.
-     * walks down the tree and does atomic compare exchange on the given slot. 
+     * Atomically replaces the value stored under `key` with `f(current_value)`.
      * * This method is lock-free and uses Epoch-Based Reclamation (EBR) via `crossbeam_epoch` 
-     * to ensure memory safety during concurrent reads and writes.
+     *   to ensure memory safety during concurrent reads and writes.
      * * # Arguments
      * * `key` - A byte slice representing the path to the desired node.
+     * * `f` - Computes the new value from the current one. It is called again with the
+     *   fresh value every time the CAS loses against a concurrent writer, so it should be
+     *   free of side effects.
      * * # Returns
-     * * `Ok(Some(Vec<u8>))` if the terminal node is updatd with the new value
-     * * `Err(RadixError)` if the key is empty slice or the cas fails at the given slot.
+     * * `Ok(Some(Bytes))` with the value that was written.
+     * * `Ok(None)` if the key has no value, nothing is inserted in that case.
+     * * `Err(RadixError)` if the key is empty slice.
      * * # Safety
-     * Traversal relies on `unsafe` dereferencing of `Shared` pointers. This is safe 
-     * because the `guard` prevents any node from being physically deallocated 
-     * while the search is in progress.
+     *   Same copy-on-write scheme as `insert`.
      */
-
-    pub fn put(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>, RadixError>{
+    pub fn update<F, V>(&self, key: &[u8], f: F) -> Result<Option<Bytes>, RadixError>
+    where 
+        F: Fn(&[u8]) -> V,
+        V: Into<Bytes>
+    { 
+        let key = to_digits::<B>(key);
+        let key: &[u8] = &key;
         if key.is_empty() { 
             return Err(RadixError::InvalidKey);
         }
         let guard = crossbeam_epoch::pin();
-        let mut curr_shared = self.root.load(Ordering::SeqCst, &guard);
-        if curr_shared.is_null() { 
-            let new_root = Owned::new(Node::new());
-            match self.root.compare_exchange(
-                curr_shared, new_root, 
-                Ordering::SeqCst, 
-                Ordering::SeqCst, 
-                &guard) { 
-                    Ok(shared) => curr_shared = shared,
-                    Err(e) => curr_shared = e.current
-                }
-        }
-
-        for &b in key { 
-            let curr_node = unsafe { curr_shared.deref()};
-            let next = curr_node.get(b);
-            let mut next_shared = next.load(Ordering::SeqCst, &guard);
-            if next_shared.is_null() { 
-                let new_next = Owned::new(Node::new());
-                match next.compare_exchange(
-                    next_shared,
-                    new_next, 
-                    Ordering::SeqCst, 
-                    Ordering::SeqCst,
-                    &guard) { 
-                        Ok(shared) => next_shared = shared,
-                        Err(e) => next_shared = e.current
-                    }
+        loop { 
+            let root = self.load_root(&guard);
+            let mut rewrite = Rewrite::new(&self.pool);
+            let target = match Self::walk(root, key, &mut rewrite.path, &guard) { 
+                Some(target) => target,
+                None => return Ok(None)
+            };
+            let target_node = unsafe { target.deref()};
+            let curr_value = target_node.value().load(Ordering::SeqCst, &guard);
+            if curr_value.is_null() { 
+                return Ok(None);
             }
-
-            curr_shared = next_shared;
-        }
-
-        // at tail end swap the value 
-        let curr_node = unsafe { curr_shared.deref()};
-        let curr_shared_value = curr_node.value().load(Ordering::SeqCst, &guard);
-        match curr_node.value().compare_exchange(
-            curr_shared_value, 
-            Owned::new(value), 
-            Ordering::SeqCst, 
-            Ordering::SeqCst, 
-            &guard) {
-                Ok(shared) => { 
-                    let updated_vec = unsafe {shared.deref() }.clone();
-                    return Ok(Some(updated_vec)); 
-                },
-                Err(e) => { 
-                    let current_vec = unsafe { e.current.deref()}.clone();
-                    return Err(RadixError::Failed { failed_garbage_value: current_vec });
-                }
+            let updated_vec: Bytes = f(unsafe { curr_value.deref()}).into();
+            let updated = Owned::new(updated_vec.clone()).into_shared(&guard);
+            let copy = target_node.copy(&guard);
+            copy.value().store(updated, Ordering::Relaxed);
+            let replacement = rewrite.alloc(copy, &guard);
+            rewrite.replaced_value = curr_value;
+            if self.publish(root, target, replacement, rewrite, &guard) { 
+                return Ok(Some(updated_vec));
             }
-        
+            // the new value was only reachable from the discarded copy
+            drop(unsafe { updated.into_owned()});
+        }
     }
 
     /**
-     * walks down the tree along the key path and swap the slot with null.
+     * walks down the tree along the key path and swaps in a copy of the terminal node
+     * with a null value. A terminal node without children is unlinked instead, together
+     * with every ancestor that is left with neither a value nor children, so removed keys
+     * do not leave dead nodes behind.
      * * This method is lock-free and uses Epoch-Based Reclamation (EBR) via `crossbeam_epoch` 
-     * to ensure memory safety during concurrent reads and writes.
+     *   to ensure memory safety during concurrent reads and writes.
      * * # Arguments
      * * `key` - A byte slice representing the path to the desired node.
      * * # Returns
-     * * `Ok(Some(Vec<u8>))` if the key exists and had an associated value.
+     * * `Ok(Some(Bytes))` if the key exists and had an associated value.
      * * `Ok(None)` if the key path does not exist or the terminal node has no value.
      * * `Err(RadixError)` if the key is empty slice.
      * * # Safety
-     * Traversal relies on `unsafe` dereferencing of `Shared` pointers. This is safe 
-     * because the `guard` prevents any node from being physically deallocated 
-     * while the search is in progress.
+     *   Same copy-on-write scheme as `insert`.
      */
-
-    pub fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, RadixError> {
+    pub fn remove(&self, key: &[u8]) -> Result<Option<Bytes>, RadixError> {
+        let key = to_digits::<B>(key);
+        let key: &[u8] = &key;
         if key.is_empty() {
             return Err(RadixError::InvalidKey);
         } 
         let guard = crossbeam_epoch::pin();
-        let mut curr_shared = self.root.load(Ordering::SeqCst, &guard);
-        if curr_shared.is_null() {
-            return Ok(None)
-        }
-        for &b in key { 
-            let curr_node  = unsafe {curr_shared.deref()};
-            let next_shared = curr_node.get(b).load(Ordering::SeqCst, &guard);
-            if next_shared.is_null() { 
+        loop { 
+            let root = self.load_root(&guard);
+            let mut rewrite = Rewrite::new(&self.pool);
+            let target = match Self::walk(root, key, &mut rewrite.path, &guard) { 
+                Some(target) => target,
+                None => return Ok(None)
+            };
+            let target_node = unsafe { target.deref()};
+            let old_val_shared = target_node.value().load(Ordering::SeqCst, &guard);
+            if old_val_shared.is_null() { 
                 return Ok(None)
             }
-            curr_shared = next_shared;
+            let old_vec_clone = unsafe { old_val_shared.deref()}.clone();
+            rewrite.replaced_value = old_val_shared;
+            let replacement = if !target_node.kind().is_empty() { 
+                let copy = target_node.copy(&guard);
+                copy.value().store(Shared::null(), Ordering::Relaxed);
+                rewrite.alloc(copy, &guard)
+            } else { 
+                // a leaf goes away entirely, and so does every ancestor left without a value
+                // or any other child. The root always stays.
+                while rewrite.path.len() > 1 { 
+                    let (parent, _) = rewrite.path[rewrite.path.len() - 1];
+                    let parent_node = unsafe { parent.deref()};
+                    if parent_node.kind().len() > 1 || !parent_node.value().load(Ordering::SeqCst, &guard).is_null() { 
+                        break;
+                    }
+                    rewrite.pruned.push(parent);
+                    rewrite.path.pop();
+                }
+                Shared::null()
+            };
+            if self.publish(root, target, replacement, rewrite, &guard) { 
+                self.key_count.fetch_sub(1, Ordering::Relaxed);
+                return Ok(Some(old_vec_clone))
+            }
+        }
+    }
+
+    /**
+     * Number of keys holding a value, read from a counter maintained by the write paths
+     * instead of walking the tree.
+     * * Under concurrent writes the result may lag behind writes that are still in flight.
+     */
+    pub fn count(&self) -> usize { 
+        self.key_count.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool { 
+        self.count() == 0
+    }
+
+    /**
+     * Replaces the tree with a new empty root and frees every node and value of the old one.
+     * * Taking `&mut self` guarantees no reader or writer can still be traversing the old
+     *   nodes, so they are freed right away instead of being deferred to the collector.
+     */
+    pub fn clear(&mut self) { 
+        let guard = unsafe { crossbeam_epoch::unprotected() };
+        let old_root = self.root.swap(Owned::new(Node::new()), Ordering::SeqCst, guard);
+        self.key_count.store(0, Ordering::SeqCst);
+        if old_root.is_null() { 
+            return;
+        }
+        drop(unsafe { old_root.into_owned()});
+        self.pool.trim(NodePool::<B>::BATCH);
+    }
+
+    /**
+     * Removes every entry for which `f(key, value)` returns false, like `Vec::retain`.
+     * * The predicate runs over a snapshot taken by `iter_all`, then each rejected key goes
+     *   through `remove`, so concurrent readers and writers stay safe. A key written again
+     *   between the snapshot and its removal is removed anyway, one that another thread
+     *   removed first is not counted.
+     * * # Returns
+     * * The number of entries this call removed.
+     */
+    pub fn retain<F: Fn(&[u8], &[u8]) -> bool>(&self, f: F) -> usize { 
+        let mut removed = 0;
+        for (key, value) in self.iter_all() { 
+            if f(&key, &value) { 
+                continue;
+            }
+            if let Ok(Some(_)) = self.remove(&key) { 
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /**
+     * Looks up `key` and returns an `Entry` for in-place manipulation, like `HashMap::entry`.
+     * * The entry holds a snapshot of the value taken during the lookup. Its write methods
+     *   go through the same copy-on-write CAS as `insert`, so they stay correct when other
+     *   threads touch the same key in between, see `VacantEntry::insert`.
+     * * # Returns
+     * * `Err(RadixError)` if the key is empty slice.
+     */
+    pub fn entry<'a>(&'a self, key: &[u8]) -> Result<Entry<'a, B>, RadixError> { 
+        match self.get(key)? { 
+            Some(value) => Ok(Entry::Occupied(OccupiedEntry { tree: self, key: key.to_vec(), value })),
+            None => Ok(Entry::Vacant(VacantEntry { tree: self, key: key.to_vec(), value: None }))
+        }
+    }
+
+    /**
+     * Returns a lazy iterator over every key starting with `prefix`, in lexicographic order.
+     * * The iterator walks the version of the tree that was current when it was created,
+     *   writes made while it is alive are not observed.
+     * * An empty `prefix` yields the same entries as `iter_all`, a prefix that is not
+     *   in the tree yields nothing.
+     */
+    pub fn prefix_iter<'a>(&'a self, prefix: &[u8]) -> PrefixIter<'a, B> { 
+        let prefix = to_digits::<B>(prefix);
+        let prefix: &[u8] = &prefix;
+        let guard = crossbeam_epoch::pin();
+        let mut stack = Vec::new();
+        // the iterator keeps `guard` pinned for as long as it lives, so the nodes loaded here
+        // outlive this call even though they are loaded through `unprotected`
+        let unprotected = unsafe { crossbeam_epoch::unprotected() };
+        let mut curr_shared = self.root.load(Ordering::SeqCst, unprotected);
+        let mut key = Vec::new();
+        while !curr_shared.is_null() { 
+            let depth = key.len();
+            if depth >= prefix.len() { 
+                stack.push((curr_shared, key));
+                break;
+            }
+            let curr_node = unsafe { curr_shared.deref()};
+            let b = prefix[depth];
+            let next = match curr_node.find_child(b, unprotected) { 
+                Some(next) => next,
+                None => break
+            };
+            let partial = unsafe { next.deref()}.partial_key();
+            let rest = &prefix[depth + 1..];
+            if !rest.starts_with(partial) && !partial.starts_with(rest) { 
+                break;
+            }
+            key.push(b);
+            key.extend_from_slice(partial);
+            curr_shared = next;
+        }
+        PrefixIter { 
+            _guard: guard,
+            stack
         }
+    }
 
-        let curr_node = unsafe { curr_shared.deref()};
-        let old_val_shared = curr_node.value().swap(Shared::null(), Ordering::SeqCst, &guard);
-        if old_val_shared.is_null() { 
-            return Ok(None)
-        } else { 
-            let old_vec = unsafe { old_val_shared.deref()};
-            let old_vec_clone = old_vec.clone();
-            unsafe { guard.defer_destroy(old_val_shared); }
-            return Ok(Some(old_vec_clone))
+    /**
+     * Number of keys starting with `prefix`.
+     * * Walks the subtree under the prefix like `prefix_iter`, counting the nodes that hold
+     *   a value without copying any key or value out of the tree.
+     */
+    pub fn prefix_count(&self, prefix: &[u8]) -> usize { 
+        let mut iter = self.prefix_iter(prefix);
+        let unprotected = unsafe { crossbeam_epoch::unprotected() };
+        let mut count = 0;
+        while let Some((shared_node, _)) = iter.stack.pop() { 
+            let node_ref = unsafe { shared_node.deref()};
+            iter.stack.extend(node_ref.iter_children(unprotected).map(|(_, shared_child)| (shared_child, Vec::new())));
+            if !node_ref.value().load(Ordering::SeqCst, unprotected).is_null() { 
+                count += 1;
+            }
         }
+        count
     }
 
-    pub fn iter_all(&self) -> Vec<(Vec<u8>, Vec<u8>)>{ 
+    /**
+     * Every key and value in the tree, in ascending byte order of the keys.
+     * * The order is guaranteed, not incidental: every node layout lists its children in
+     *   ascending digit order, digits keep the byte order of the key, and the walk pops a
+     *   node's value before its children and its children in that order, so a key always
+     *   comes before the keys it is a prefix of and before any greater sibling.
+     */
+    pub fn iter_all(&self) -> Vec<(Vec<u8>, Bytes)>{ 
         let mut out = Vec::new();
         let guard = crossbeam_epoch::pin();
         let root_shared = self.root.load(Ordering::SeqCst, &guard);
         if root_shared.is_null() { 
             return out;
         }
-        let mut stack : Vec<(Shared<Node>, Vec<u8>)> = Vec::new();
+        let mut stack : Vec<(Shared<Node<B>>, Vec<u8>)> = Vec::new();
         stack.push((root_shared, Vec::new()));
         while let Some((shared_node, prefix)) = stack.pop() { 
             let node_ref = unsafe { shared_node.deref()};
             let v_ptr = node_ref.value().load(Ordering::SeqCst, &guard);
             if !v_ptr.is_null() { 
                 let value = unsafe { v_ptr.deref()};
-                out.push((prefix.clone(), value.clone()));
+                out.push((from_digits::<B>(prefix.clone()), value.clone()));
             }
 
-            for idx in (0..BRANCH_CAPACITY).rev() { 
-                let atomic_child = node_ref.get(idx as u8);
-                let shared_child = atomic_child.load(Ordering::SeqCst, &guard);
-                if !shared_child.is_null() { 
-                    let mut new_prefix = prefix.clone();
-                    new_prefix.push(idx as u8);
-                    stack.push((shared_child, new_prefix));
+            // pushed in reverse so the smallest child is popped first
+            for (b, shared_child) in node_ref.iter_children(&guard).rev() { 
+                let mut new_prefix = prefix.clone();
+                new_prefix.push(b);
+                new_prefix.extend_from_slice(unsafe { shared_child.deref()}.partial_key());
+                stack.push((shared_child, new_prefix));
+            }
+        }
+
+        out
+    }
+
+    /**
+     * Changes that turn this tree into `other`, in ascending key order: keys only `other`
+     * holds are `Added`, keys only this tree holds are `Removed`, and keys whose values
+     * differ are `Modified` with this tree's value as `old`.
+     * * Both trees are read with `iter_all` and merge-joined, relying on its sorted order.
+     *   Each side is a snapshot as of its own `iter_all` call.
+     */
+    pub fn diff<const C: usize>(&self, other: &RadixTree<C>) -> Vec<DiffEntry> { 
+        let mut diff = Vec::new();
+        let mut old_iter = self.iter_all().into_iter().peekable();
+        let mut new_iter = other.iter_all().into_iter().peekable();
+        loop { 
+            let order = match (old_iter.peek(), new_iter.peek()) { 
+                (None, None) => break,
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (Some((old_key, _)), Some((new_key, _))) => old_key.cmp(new_key)
+            };
+            match order { 
+                std::cmp::Ordering::Less => diff.push(DiffEntry::Removed(old_iter.next().unwrap().0)),
+                std::cmp::Ordering::Greater => { 
+                    let (key, value) = new_iter.next().unwrap();
+                    diff.push(DiffEntry::Added(key, value));
+                },
+                std::cmp::Ordering::Equal => { 
+                    let ((key, old), (_, new)) = (old_iter.next().unwrap(), new_iter.next().unwrap());
+                    if old != new { 
+                        diff.push(DiffEntry::Modified { key, old, new });
+                    }
                 }
             }
         }
+        diff
+    }
 
+    /**
+     * Encodes the whole tree into bytes that `deserialize` turns back into an equal tree.
+     * * The encoding starts with the bits per digit of `B`, then lists the nodes depth-first,
+     *   each one before its children and the children in ascending digit order:
+     *   [PartialLen (4B)][Partial][ChildCount (2B)][ChildDigit (1B) per child][HasValue (1B)]
+     *   followed by [ValLen (4B)][Value] when `HasValue` is 1. The child count takes two
+     *   bytes since a `B = 256` node can have all 256 children.
+     * * The root is loaded once, so concurrent writers do not tear the encoding: it is the
+     *   tree as of that load.
+     */
+    pub fn serialize(&self) -> Vec<u8> { 
+        let mut out = vec![digit_layout::<B>().0 as u8];
+        let guard = crossbeam_epoch::pin();
+        let root_shared = self.load_root(&guard);
+        let mut stack = vec![root_shared];
+        while let Some(shared_node) = stack.pop() { 
+            let node_ref = unsafe { shared_node.deref()};
+            let partial = node_ref.partial_key();
+            out.extend(&(partial.len() as u32).to_be_bytes());
+            out.extend(partial);
+            let children: Vec<(u8, Shared<Node<B>>)> = node_ref.iter_children(&guard).collect();
+            out.extend(&(children.len() as u16).to_be_bytes());
+            out.extend(children.iter().map(|(b, _)| *b));
+            let v_ptr = node_ref.value().load(Ordering::SeqCst, &guard);
+            match v_ptr.is_null() { 
+                true => out.push(0),
+                false => { 
+                    let value = unsafe { v_ptr.deref()};
+                    out.push(1);
+                    out.extend(&(value.len() as u32).to_be_bytes());
+                    out.extend(value);
+                }
+            }
+            stack.extend(children.into_iter().rev().map(|(_, child)| child));
+        }
         out
     }
-}
\ No newline at end of file
+
+    /**
+     * Rebuilds a tree from the output of `serialize`.
+     * * The keys and values are collected from the encoding and loaded with a single
+     *   `bulk_insert`, so the new tree gets fresh nodes of whatever layout fits them.
+     * * # Returns
+     * * `Err(RadixError::Corrupted)` if `data` is truncated, has bytes left over, or was
+     *   written by a tree with a different branching factor.
+     */
+    pub fn deserialize(data: &[u8]) -> Result<Self, RadixError> { 
+        let mut data = data;
+        if take(&mut data, 1)?[0] as u32 != digit_layout::<B>().0 { 
+            return Err(RadixError::Corrupted);
+        }
+        let per_byte = digit_layout::<B>().1;
+        let mut entries = Vec::new();
+        // full key of each node on the path to the next one, with the digits of the children
+        // still to read, last one first
+        let mut stack: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let mut next_key = Some(Vec::new());
+        while let Some(mut key) = next_key.take() { 
+            let partial_len = take_u32(&mut data)?;
+            key.extend_from_slice(take(&mut data, partial_len)?);
+            let child_count = u16::from_be_bytes(take(&mut data, 2)?.try_into().unwrap()) as usize;
+            let mut children = take(&mut data, child_count)?.to_vec();
+            if children.iter().any(|&b| b as usize >= B) { 
+                return Err(RadixError::Corrupted);
+            }
+            children.reverse();
+            match take(&mut data, 1)?[0] { 
+                0 => {},
+                1 if !key.is_empty() && key.len() % per_byte == 0 => { 
+                    let value_len = take_u32(&mut data)?;
+                    entries.push((from_digits::<B>(key.clone()), take(&mut data, value_len)?.to_vec()));
+                },
+                _ => return Err(RadixError::Corrupted)
+            }
+            stack.push((key, children));
+            while let Some((parent, children)) = stack.last_mut() { 
+                match children.pop() { 
+                    Some(b) => { 
+                        let mut child_key = parent.clone();
+                        child_key.push(b);
+                        next_key = Some(child_key);
+                        break;
+                    },
+                    None => { 
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        if !data.is_empty() { 
+            return Err(RadixError::Corrupted);
+        }
+        let tree = Self::default();
+        tree.bulk_insert(entries)?;
+        Ok(tree)
+    }
+}
+
+
+/**
+ * Depth-first iterator returned by `RadixTree::prefix_iter`.
+ * * Holds its own pinned guard so the nodes on `stack` cannot be reclaimed while iterating.
+ */
+pub struct PrefixIter<'a, const B: usize = BRANCH_CAPACITY> { 
+    _guard: Guard,
+    stack: Vec<(Shared<'a, Node<B>>, Vec<u8>)>
+}
+
+impl<'a, const B: usize> Iterator for PrefixIter<'a, B> { 
+    type Item = (Vec<u8>, Bytes);
+
+    fn next(&mut self) -> Option<Self::Item> { 
+        let unprotected = unsafe { crossbeam_epoch::unprotected() };
+        while let Some((shared_node, prefix)) = self.stack.pop() { 
+            let node_ref = unsafe { shared_node.deref()};
+            for (b, shared_child) in node_ref.iter_children(unprotected).rev() { 
+                let mut new_prefix = prefix.clone();
+                new_prefix.push(b);
+                new_prefix.extend_from_slice(unsafe { shared_child.deref()}.partial_key());
+                self.stack.push((shared_child, new_prefix));
+            }
+            let v_ptr = node_ref.value().load(Ordering::SeqCst, unprotected);
+            if !v_ptr.is_null() { 
+                let value = unsafe { v_ptr.deref()};
+                return Some((from_digits::<B>(prefix), value.clone()));
+            }
+        }
+        None
+    }
+}
+
+/**
+ * A key whose value differs between two trees, see `RadixTree::diff`.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffEntry { 
+    Added(Vec<u8>, Bytes),
+    Removed(Vec<u8>),
+    Modified { key: Vec<u8>, old: Bytes, new: Bytes }
+}
+
+pub enum Entry<'a, const B: usize = BRANCH_CAPACITY> { 
+    Occupied(OccupiedEntry<'a, B>),
+    Vacant(VacantEntry<'a, B>)
+}
+
+impl<'a, const B: usize> Entry<'a, B> { 
+    pub fn key(&self) -> &[u8] { 
+        match self { 
+            Entry::Occupied(entry) => &entry.key,
+            Entry::Vacant(entry) => &entry.key
+        }
+    }
+
+    /**
+     * Returns the value under the key, inserting `f()` first if the key was vacant.
+     * * If another thread fills the key concurrently, its value wins and is returned.
+     */
+    pub fn or_insert_with<V: Into<Bytes>, F: FnOnce() -> V>(self, f: F) -> Bytes { 
+        match self { 
+            Entry::Occupied(entry) => entry.value,
+            Entry::Vacant(mut entry) => entry.insert(f()).clone()
+        }
+    }
+
+    pub fn or_insert(self, value: impl Into<Bytes>) -> Bytes { 
+        self.or_insert_with(|| value)
+    }
+}
+
+pub struct OccupiedEntry<'a, const B: usize = BRANCH_CAPACITY> { 
+    tree: &'a RadixTree<B>,
+    key: Vec<u8>,
+    value: Bytes
+}
+
+impl<'a, const B: usize> OccupiedEntry<'a, B> { 
+    /**
+     * The value seen when the entry was looked up, or last written through it.
+     */
+    pub fn get(&self) -> &[u8] { 
+        &self.value
+    }
+
+    /**
+     * Overwrites the value under the key and returns the value that was replaced.
+     * * If a concurrent `remove` took the key away first, the key is inserted again and
+     *   the value this entry last saw is returned.
+     */
+    pub fn insert(&mut self, value: impl Into<Bytes>) -> Bytes { 
+        let value = value.into();
+        let replaced = self.tree.upsert(&self.key, value.clone(), true);
+        let seen = std::mem::replace(&mut self.value, value);
+        replaced.unwrap_or(seen)
+    }
+
+    /**
+     * Removes the key and returns the value it held.
+     * * If a concurrent `remove` got there first, the value this entry last saw is returned.
+     */
+    pub fn remove(self) -> Bytes { 
+        match self.tree.remove(&self.key) { 
+            Ok(Some(value)) => value,
+            _ => self.value
+        }
+    }
+}
+
+pub struct VacantEntry<'a, const B: usize = BRANCH_CAPACITY> { 
+    tree: &'a RadixTree<B>,
+    key: Vec<u8>,
+    value: Option<Bytes>
+}
+
+impl<'a, const B: usize> VacantEntry<'a, B> { 
+    /**
+     * Inserts `value` if the key is still vacant and returns the value now under the key.
+     * * If another thread filled the key since the lookup, nothing is written and that
+     *   thread's value is returned instead, so every racing caller observes the same value.
+     */
+    pub fn insert(&mut self, value: impl Into<Bytes>) -> &Bytes { 
+        let value = value.into();
+        let stored = match self.tree.upsert(&self.key, value.clone(), false) { 
+            Some(existing) => existing,
+            None => value
+        };
+        self.value.insert(stored)
+    }
+}
@@ -1,4 +1,9 @@
-use crate::radix::RadixTree;
+use std::{sync::{atomic::Ordering, Arc}, thread};
+
+use bytes::Bytes;
+use crossbeam_epoch::{Owned, Shared};
+
+use crate::{node::{Node, NodePool, BRANCH_CAPACITY}, radix::{DiffEntry, Entry, RadixError, RadixTree}};
 
 
 
@@ -6,12 +11,12 @@ use crate::radix::RadixTree;
 pub fn test_radix() { 
 
     let tree = RadixTree::new();
-    let res = tree.insert(&(b"hello".to_vec()), b"there".into());
+    let res = tree.insert(b"hello", b"there".to_vec());
     assert!(res.is_ok());
     if let Ok(val) = res { 
         assert_eq!(val, None);
     }
-    let value = tree.get(&(b"hello".to_vec())).unwrap().unwrap();
+    let value = tree.get(b"hello").unwrap().unwrap();
     assert_eq!(value, b"there".to_vec());
     let key: Vec<u8> = vec![1, 2, 3];
     let value: Vec<u8> = vec![4,5,6];
@@ -42,11 +47,8 @@ pub fn test_radix_re_insertion_will_fail() {
 
     let reinsertion_res  = tree.insert(&key, val.clone());
     assert!(reinsertion_res.is_err());
-    if let Err(err) = reinsertion_res { 
-        match err { 
-            crate::radix::RadixError::AlreadyWritten { value } => assert_eq!(value, val),
-           _ => {}
-        }
+    if let Err(RadixError::AlreadyWritten { value }) = reinsertion_res { 
+        assert_eq!(value, val);
     }  
 }
 
@@ -114,4 +116,656 @@ pub fn test_radix_put() {
 }
 
 
+#[test]
+pub fn test_radix_replace_returns_the_old_value() { 
+    let tree = RadixTree::new();
+    assert_eq!(tree.replace(b"key", b"one".to_vec()).unwrap(), None);
+    assert_eq!(tree.replace(b"key", b"two".to_vec()).unwrap(), Some(Bytes::from_static(b"one")));
+    assert_eq!(tree.get(b"key").unwrap(), Some(Bytes::from_static(b"two")));
+    assert_eq!(tree.count(), 1);
+    assert!(tree.replace(b"", b"x".to_vec()).is_err());
+}
+
+#[test]
+pub fn test_radix_keys_sharing_long_prefixes() { 
+    let tree = RadixTree::new();
+    let prefix = b"a-very-long-shared-prefix-for-every-key/".to_vec();
+    let mut keys = Vec::new();
+    for suffix in [&b""[..], b"x", b"xy", b"xyz", b"y", b"yz"] { 
+        let mut key = prefix.clone();
+        key.extend_from_slice(suffix);
+        keys.push(key);
+    }
+    // insert in an order that forces both splits and extensions
+    for key in keys.iter().rev() { 
+        assert!(tree.insert(key, key.clone()).is_ok());
+    }
+    for key in &keys { 
+        assert_eq!(tree.get(key).unwrap().as_deref(), Some(&key[..]));
+    }
+    // keys that diverge inside a compressed segment are absent
+    assert_eq!(tree.get(&prefix[..10]).unwrap(), None);
+    assert_eq!(tree.get(b"a-very-long-shared-prefix-for-every-key/xq").unwrap(), None);
+    assert_eq!(tree.get(b"a-very-long-shared-prefix-for-every-key/xyzz").unwrap(), None);
+
+    let mut sorted = keys.clone();
+    sorted.sort();
+    let all: Vec<Vec<u8>> = tree.iter_all().into_iter().map(|(k, _)| k).collect();
+    assert_eq!(all, sorted);
+
+    assert_eq!(tree.remove(&keys[2]).unwrap().as_deref(), Some(&keys[2][..]));
+    assert_eq!(tree.get(&keys[2]).unwrap(), None);
+    assert_eq!(tree.get(&keys[3]).unwrap().as_deref(), Some(&keys[3][..]));
+}
+
+
+#[test]
+pub fn test_radix_single_character_keys() { 
+    let tree = RadixTree::new();
+    for b in 0..=255u8 { 
+        assert!(tree.insert(&[b], vec![b]).is_ok());
+    }
+    assert!(tree.insert(b"ab", b"ab".to_vec()).is_ok());
+    for b in 0..=255u8 { 
+        assert_eq!(tree.get(&[b]).unwrap().as_deref(), Some(&[b][..]));
+    }
+    assert_eq!(tree.get(b"ab").unwrap().as_deref(), Some(&b"ab"[..]));
+    assert_eq!(tree.iter_all().len(), 257);
+}
+
+
+#[test]
+pub fn test_node_kind_upgrade_and_downgrade() { 
+    let guard = crossbeam_epoch::pin();
+    let children: Vec<Shared<Node>> = (0..BRANCH_CAPACITY)
+        .map(|_| Owned::new(Node::new()).into_shared(&guard))
+        .collect();
+    let mut node = Node::new();
+    let mut capacities = Vec::new();
+    for (b, child) in children.iter().enumerate().rev() { 
+        node.set_child(b as u8, *child, &guard);
+        capacities.push(node.kind().capacity());
+    }
+    assert_eq!(capacities[3], 4);
+    assert_eq!(capacities[4], 16);
+    assert_eq!(capacities[16], 48);
+    assert_eq!(capacities[48], 256);
+    for (b, child) in children.iter().enumerate() { 
+        assert_eq!(node.find_child(b as u8, &guard), Some(*child));
+    }
+    for b in 0..BRANCH_CAPACITY - 3 { 
+        node.set_child(b as u8, Shared::null(), &guard);
+        assert_eq!(node.find_child(b as u8, &guard), None);
+    }
+    assert_eq!(node.kind().capacity(), 4);
+    assert_eq!(node.kind().len(), 3);
+    assert_eq!(node.find_child(255, &guard), Some(children[255]));
+    // the children are freed one by one below, the node must not free them as well
+    node.drop_shallow();
+    for child in children { 
+        drop(unsafe { child.into_owned() });
+    }
+}
+
+
+#[test]
+pub fn test_node_iter_children_and_try_shrink() { 
+    let guard = crossbeam_epoch::pin();
+    let children: Vec<Shared<Node>> = (0..64)
+        .map(|_| Owned::new(Node::new()).into_shared(&guard))
+        .collect();
+    let mut node = Node::new();
+    for (i, child) in children.iter().enumerate() { 
+        node.set_child((i * 4) as u8, *child, &guard);
+    }
+    assert_eq!(node.kind().capacity(), BRANCH_CAPACITY);
+    assert_eq!(node.occupied_count(), 64);
+    let visited: Vec<(u8, Shared<Node>)> = node.iter_children(&guard).collect();
+    let expected: Vec<(u8, Shared<Node>)> = children.iter().enumerate().map(|(i, child)| ((i * 4) as u8, *child)).collect();
+    assert_eq!(visited, expected);
+    assert!(!node.try_shrink(&guard));
+    for i in 2..children.len() { 
+        node.set_child((i * 4) as u8, Shared::null(), &guard);
+    }
+    assert_eq!(node.occupied_count(), 2);
+    assert_eq!(node.kind().capacity(), 4);
+    assert_eq!(node.iter_children(&guard).map(|(b, _)| b).collect::<Vec<u8>>(), vec![0, 4]);
+    assert!(!node.try_shrink(&guard));
+    node.drop_shallow();
+    for child in children { 
+        drop(unsafe { child.into_owned() });
+    }
+}
+
+
+#[test]
+pub fn test_radix_sparse_tree_uses_a_quarter_of_full_width_nodes() { 
+    let tree = RadixTree::new();
+    let mut seed = 0x2545F4914F6CDD1Du64;
+    for _ in 0..2_000 { 
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        let key = seed.to_be_bytes()[..4].to_vec();
+        let _ = tree.put(&key, vec![1]);
+    }
+    let (nodes, footprint) = tree_footprint(&tree);
+    let full_width = nodes * (size_of::<Node>() + BRANCH_CAPACITY * size_of::<crossbeam_epoch::Atomic<Node>>());
+    assert!(full_width >= 4 * footprint, "{footprint} bytes against {full_width} for {nodes} full-width nodes");
+}
+
+
+#[test]
+pub fn test_radix_prefix_iter() { 
+    let tree = RadixTree::new();
+    for key in [&b"car"[..], b"cart", b"carton", b"cat", b"dog", b"do", b"cargo"] { 
+        assert!(tree.insert(key, key.to_vec()).is_ok());
+    }
+    let keys = |prefix: &[u8]| -> Vec<Vec<u8>> { 
+        tree.prefix_iter(prefix).map(|(k, _)| k).collect()
+    };
+    // overlapping prefixes, including ones ending inside a compressed segment
+    assert_eq!(keys(b"ca"), vec![b"car".to_vec(), b"cargo".to_vec(), b"cart".to_vec(), b"carton".to_vec(), b"cat".to_vec()]);
+    assert_eq!(keys(b"cart"), vec![b"cart".to_vec(), b"carton".to_vec()]);
+    assert_eq!(keys(b"carto"), vec![b"carton".to_vec()]);
+    // prefix equal to a complete key
+    assert_eq!(keys(b"do"), vec![b"do".to_vec(), b"dog".to_vec()]);
+    assert_eq!(keys(b"dog"), vec![b"dog".to_vec()]);
+    // prefixes that are not in the tree
+    assert!(keys(b"cb").is_empty());
+    assert!(keys(b"cartons").is_empty());
+    assert!(keys(b"x").is_empty());
+    // empty prefix matches iter_all
+    let all: Vec<(Vec<u8>, Bytes)> = tree.prefix_iter(b"").collect();
+    assert_eq!(all, tree.iter_all());
+}
+
+
+#[test]
+pub fn test_radix_prefix_count_matches_prefix_iter() { 
+    let tree = RadixTree::new();
+    for key in [&b"car"[..], b"cart", b"carton", b"cat", b"dog", b"do", b"cargo"] { 
+        assert!(tree.insert(key, key.to_vec()).is_ok());
+    }
+    assert!(tree.remove(b"cart").unwrap().is_some());
+    for prefix in [&b""[..], b"c", b"ca", b"car", b"cart", b"carto", b"do", b"dog", b"cb", b"cartons"] { 
+        assert_eq!(tree.prefix_count(prefix), tree.prefix_iter(prefix).count(), "prefix {:?}", String::from_utf8_lossy(prefix));
+    }
+    assert_eq!(tree.prefix_count(b"ca"), 4);
+    assert_eq!(tree.prefix_count(b""), tree.count());
+}
+
+#[test]
+pub fn test_radix_retain_keeps_matching_entries() { 
+    let tree = RadixTree::new();
+    for i in 0..100u32 { 
+        assert!(tree.insert(format!("key-{i}").as_bytes(), i.to_be_bytes().to_vec()).is_ok());
+    }
+    let even = |_: &[u8], val: &[u8]| u32::from_be_bytes(val.try_into().unwrap()) % 2 == 0;
+    assert_eq!(tree.retain(even), 50);
+    assert_eq!(tree.count(), 50);
+    let remaining = tree.iter_all();
+    assert_eq!(remaining.len(), 50);
+    assert!(remaining.iter().all(|(_, val)| even(b"", val)));
+    for i in (0..100u32).step_by(2) { 
+        assert_eq!(tree.get(format!("key-{i}").as_bytes()).unwrap().as_deref(), Some(&i.to_be_bytes()[..]));
+    }
+    // nothing left to reject, and keys can drive the predicate as well
+    assert_eq!(tree.retain(even), 0);
+    assert_eq!(tree.retain(|key, _| !key.starts_with(b"key-1")), 5);
+    assert_eq!(tree.count(), 45);
+}
+
+
+fn tree_footprint(tree: &RadixTree) -> (usize, usize) { 
+    let guard = crossbeam_epoch::pin();
+    let mut nodes = 0;
+    let mut footprint = 0;
+    let mut stack = vec![tree.root.load(Ordering::SeqCst, &guard)];
+    while let Some(shared) = stack.pop() { 
+        let node = unsafe { shared.deref() };
+        nodes += 1;
+        footprint += node.footprint();
+        for b in 0..BRANCH_CAPACITY { 
+            if let Some(child) = node.find_child(b as u8, &guard) { 
+                stack.push(child);
+            }
+        }
+    }
+    (nodes, footprint)
+}
+
+
+#[test]
+pub fn test_radix_remove_reclaims_nodes() { 
+    let tree = RadixTree::new();
+    let baseline = tree_footprint(&tree);
+    let keys: Vec<Vec<u8>> = (0..10_000).map(|i| format!("key-{}", i).into_bytes()).collect();
+    for key in &keys { 
+        assert!(tree.insert(key, key.clone()).is_ok());
+    }
+    let (nodes, footprint) = tree_footprint(&tree);
+    assert!(nodes > 10_000 && footprint > baseline.1);
+    // removing a key that only shares a prefix keeps its siblings reachable
+    assert_eq!(tree.remove(b"key-1").unwrap().as_deref(), Some(&b"key-1"[..]));
+    assert_eq!(tree.get(b"key-10").unwrap().as_deref(), Some(&b"key-10"[..]));
+    for key in &keys { 
+        let _ = tree.remove(key);
+    }
+    assert_eq!(tree_footprint(&tree), baseline);
+    assert!(tree.iter_all().is_empty());
+}
+
+
+#[test]
+pub fn test_radix_concurrent_update_counter() { 
+    let tree = Arc::new(RadixTree::new());
+    assert_eq!(tree.update(b"counter", |_| vec![0]).unwrap(), None);
+    assert!(tree.insert(b"counter", 0u64.to_be_bytes().to_vec()).is_ok());
+    let handles: Vec<_> = (0..8).map(|_| { 
+        let tree = Arc::clone(&tree);
+        thread::spawn(move || { 
+            let mut applied = 0u64;
+            for _ in 0..500 { 
+                let res = tree.update(b"counter", |curr| { 
+                    let n = u64::from_be_bytes(curr.try_into().unwrap());
+                    (n + 1).to_be_bytes().to_vec()
+                });
+                if let Ok(Some(_)) = res { 
+                    applied += 1;
+                }
+            }
+            applied
+        })
+    }).collect();
+    let applied: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    let value = tree.get(b"counter").unwrap().unwrap();
+    assert_eq!(u64::from_be_bytes(value[..].try_into().unwrap()), applied);
+    assert_eq!(applied, 8 * 500);
+}
+
+
+#[test]
+pub fn test_radix_entry() { 
+    let tree = RadixTree::new();
+    assert!(tree.entry(b"").is_err());
+    match tree.entry(b"apple").unwrap() { 
+        Entry::Vacant(mut entry) => assert_eq!(entry.insert(b"red".to_vec()), &b"red"[..]),
+        Entry::Occupied(_) => panic!("apple should be vacant")
+    }
+    match tree.entry(b"apple").unwrap() { 
+        Entry::Occupied(mut entry) => { 
+            assert_eq!(entry.get(), b"red");
+            assert_eq!(entry.insert(b"green".to_vec()), b"red".to_vec());
+            assert_eq!(entry.get(), b"green");
+            assert_eq!(entry.remove(), b"green".to_vec());
+        },
+        Entry::Vacant(_) => panic!("apple should be occupied")
+    }
+    assert_eq!(tree.get(b"apple").unwrap(), None);
+    assert_eq!(tree.entry(b"pear").unwrap().or_insert(b"yellow".to_vec()), b"yellow".to_vec());
+    assert_eq!(tree.entry(b"pear").unwrap().or_insert(b"brown".to_vec()), b"yellow".to_vec());
+}
+
+
+#[test]
+pub fn test_radix_entry_or_insert_with_concurrent() { 
+    let tree = Arc::new(RadixTree::new());
+    let handles: Vec<_> = (0..8u8).map(|t| { 
+        let tree = Arc::clone(&tree);
+        thread::spawn(move || { 
+            (0..200u32).map(|i| { 
+                let key = format!("key-{}", i).into_bytes();
+                tree.entry(&key).unwrap().or_insert_with(|| vec![t])
+            }).collect::<Vec<_>>()
+        })
+    }).collect();
+    let seen: Vec<Vec<Bytes>> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    for i in 0..200usize { 
+        let stored = tree.get(format!("key-{}", i).as_bytes()).unwrap().unwrap();
+        // whichever thread won, every thread got the winning value back
+        for thread_seen in &seen { 
+            assert_eq!(thread_seen[i], stored);
+        }
+    }
+}
+
+
+#[test]
+pub fn test_radix_count() { 
+    let tree = RadixTree::new();
+    assert!(tree.is_empty());
+    for i in 0..100u32 { 
+        assert!(tree.insert(&i.to_be_bytes(), vec![1]).is_ok());
+    }
+    // overwrites and removals of missing keys leave the count alone
+    let _ = tree.insert(&7u32.to_be_bytes(), vec![2]);
+    let _ = tree.put(&8u32.to_be_bytes(), vec![2]);
+    let _ = tree.remove(&1000u32.to_be_bytes());
+    assert_eq!(tree.count(), 100);
+    for i in 0..40u32 { 
+        assert!(tree.remove(&i.to_be_bytes()).unwrap().is_some());
+    }
+    assert_eq!(tree.count(), 60);
+    assert!(!tree.is_empty());
+}
+
+
+#[test]
+pub fn test_radix_count_concurrent() { 
+    let tree = Arc::new(RadixTree::new());
+    let handles: Vec<_> = (0..8u32).map(|t| { 
+        let tree = Arc::clone(&tree);
+        thread::spawn(move || { 
+            for i in 0..500u32 { 
+                let key = (t * 1_000 + i).to_be_bytes();
+                let _ = tree.insert(&key, vec![0]);
+                if i % 4 == 0 { 
+                    let _ = tree.remove(&key);
+                }
+            }
+        })
+    }).collect();
+    for handle in handles { 
+        handle.join().unwrap();
+    }
+    // replaying the same operations sequentially leaves 3 out of every 4 keys
+    assert_eq!(tree.count(), 8 * 375);
+    assert_eq!(tree.count(), tree.iter_all().len());
+}
+
 
+#[test]
+pub fn test_radix_longest_prefix_match() { 
+    let tree = RadixTree::new();
+    assert!(tree.insert(b"192.168", b"lan".to_vec()).is_ok());
+    assert!(tree.insert(b"192.168.1", b"office".to_vec()).is_ok());
+    assert!(tree.insert(b"10", b"vpn".to_vec()).is_ok());
+    // matches at different depths
+    assert_eq!(tree.longest_prefix_match(b"192.168.1.5"), Some((b"192.168.1".to_vec(), Bytes::from_static(b"office"))));
+    assert_eq!(tree.longest_prefix_match(b"192.168.2.5"), Some((b"192.168".to_vec(), Bytes::from_static(b"lan"))));
+    assert_eq!(tree.longest_prefix_match(b"10.0.0.1"), Some((b"10".to_vec(), Bytes::from_static(b"vpn"))));
+    // exact matches
+    assert_eq!(tree.longest_prefix_match(b"192.168"), Some((b"192.168".to_vec(), Bytes::from_static(b"lan"))));
+    assert_eq!(tree.longest_prefix_match(b"192.168.1"), Some((b"192.168.1".to_vec(), Bytes::from_static(b"office"))));
+    // no stored key is a prefix
+    assert_eq!(tree.longest_prefix_match(b"192.16"), None);
+    assert_eq!(tree.longest_prefix_match(b"172.16.0.1"), None);
+    assert_eq!(tree.longest_prefix_match(b""), None);
+}
+
+
+#[test]
+pub fn test_radix_bulk_insert_matches_individual_inserts() { 
+    let mut entries: Vec<(Vec<u8>, Vec<u8>)> = (0..100_000u32)
+        .map(|i| (format!("user/{}/{}", i % 1_000, i).into_bytes(), i.to_be_bytes().to_vec()))
+        .collect();
+    entries.sort();
+
+    let individual = RadixTree::new();
+    for (key, value) in &entries { 
+        assert!(individual.insert(key, value.clone()).is_ok());
+    }
+    let bulk = RadixTree::new();
+    assert_eq!(bulk.bulk_insert(entries.clone()).unwrap(), entries.len());
+    assert_eq!(bulk.iter_all(), individual.iter_all());
+    assert_eq!(bulk.count(), individual.count());
+
+    // on top of existing keys, overwrites are not counted and splits of old nodes still work
+    let more = vec![
+        (b"user/1/1".to_vec(), b"overwritten".to_vec()),
+        (b"user/1/1x".to_vec(), b"new".to_vec()),
+        (b"user/1/1x".to_vec(), b"newer".to_vec()),
+        (b"user/9999".to_vec(), b"new".to_vec()),
+        (b"us".to_vec(), b"new".to_vec()),
+    ];
+    assert_eq!(bulk.bulk_insert(more.clone()).unwrap(), 3);
+    for (key, value) in more { 
+        let _ = individual.put(&key, value);
+    }
+    assert_eq!(bulk.iter_all(), individual.iter_all());
+    assert!(bulk.bulk_insert(vec![(Vec::new(), vec![1])]).is_err());
+}
+
+#[test]
+pub fn test_radix_concurrent_readers_and_writers_without_lock() { 
+    const WRITERS: usize = 8;
+    const READERS: usize = 8;
+    const KEYS: usize = 2_000;
+    let tree = Arc::new(RadixTree::new());
+    let writers: Vec<_> = (0..WRITERS).map(|w| { 
+        let rt_clone = tree.clone();
+        thread::spawn(move || { 
+            for i in 0..KEYS { 
+                let key = format!("key/{}/{}", w, i).into_bytes();
+                let _ = rt_clone.insert(&key, key.repeat(2));
+                if i % 3 == 0 { 
+                    assert!(rt_clone.remove(&key).is_ok());
+                }
+            }
+        })
+    }).collect();
+    let readers: Vec<_> = (0..READERS).map(|r| { 
+        let rt_clone = tree.clone();
+        thread::spawn(move || { 
+            for i in 0..KEYS { 
+                let key = format!("key/{}/{}", r % WRITERS, i).into_bytes();
+                if let Some(value) = rt_clone.get(&key).unwrap() { 
+                    assert_eq!(value, key.repeat(2));
+                }
+                for (key, value) in rt_clone.prefix_iter(format!("key/{}/{}", r % WRITERS, i % 10).as_bytes()).take(16) { 
+                    assert_eq!(value, key.repeat(2));
+                }
+            }
+        })
+    }).collect();
+    for handle in writers.into_iter().chain(readers) { 
+        handle.join().unwrap();
+    }
+    for w in 0..WRITERS { 
+        for i in 0..KEYS { 
+            let key = format!("key/{}/{}", w, i).into_bytes();
+            let expected = if i % 3 == 0 { None } else { Some(key.repeat(2)) };
+            assert_eq!(tree.get(&key).unwrap().as_deref(), expected.as_deref());
+        }
+    }
+    assert_eq!(tree.count(), WRITERS * (KEYS - KEYS.div_ceil(3)));
+}
+
+
+#[test]
+pub fn test_node_pool_reuses_released_boxes() { 
+    let pool = NodePool::with_capacity(1);
+    assert_eq!(pool.len(), 1);
+    let guard = crossbeam_epoch::pin();
+    let child = Owned::new(Node::new()).into_shared(&guard);
+    let mut node = pool.acquire();
+    node.set_child(7, child, &guard);
+    let address = &*node as *const Node;
+    assert!(pool.is_empty());
+    pool.release(node);
+    pool.release(Box::new(Node::new()));
+    assert_eq!(pool.len(), 1);
+    let reused = pool.acquire();
+    assert_eq!(&*reused as *const Node, address);
+    assert_eq!(reused.occupied_count(), 0);
+    // the released node only gave its box back, the child is still ours to free
+    drop(unsafe { child.into_owned() });
+
+    let tree: RadixTree = RadixTree::with_node_pool(NodePool::with_capacity(0));
+    let pooled = RadixTree::new();
+    for i in 0..2_000u32 { 
+        let key = format!("key-{}", i % 700).into_bytes();
+        let _ = tree.put(&key, i.to_be_bytes().to_vec());
+        let _ = pooled.put(&key, i.to_be_bytes().to_vec());
+    }
+    assert_eq!(tree.iter_all(), pooled.iter_all());
+}
+
+/**
+ * Compares insert throughput with and without a node pool.
+ * * Run with `cargo test --release -- --ignored radix_bench_node_pool --nocapture`.
+ */
+#[test]
+#[ignore]
+pub fn radix_bench_node_pool_inserts() { 
+    for (name, capacity) in [("no pool", 0), ("pool", <NodePool>::DEFAULT_CAPACITY)] { 
+        let tree: RadixTree = RadixTree::with_node_pool(NodePool::with_capacity(capacity));
+        let started = std::time::Instant::now();
+        for i in 0..1_000_000u64 { 
+            let key = i.wrapping_mul(0x9E37_79B9_7F4A_7C15).to_be_bytes();
+            let _ = tree.put(&key, vec![1]);
+        }
+        let elapsed = started.elapsed();
+        println!("{name}: {:.0} inserts/s, {:.0} ns per insert",
+            1_000_000.0 / elapsed.as_secs_f64(),
+            elapsed.as_nanos() as f64 / 1_000_000.0);
+    }
+}
+
+/**
+ * Runs the same operations against `tree` and records every observable result.
+ */
+fn radix_transcript<const B: usize>(tree: RadixTree<B>) -> Vec<String> { 
+    let mut out = Vec::new();
+    for i in 0..500u32 { 
+        let key = format!("k{}", i * 7 % 311).into_bytes();
+        out.push(format!("{:?}", tree.put(&key, i.to_be_bytes().to_vec())));
+    }
+    out.push(format!("{:?}", tree.insert(&[0x00, 0xff], vec![1])));
+    out.push(format!("{:?}", tree.insert(&[0xff, 0x00], vec![2])));
+    out.push(format!("{:?}", tree.bulk_insert(vec![(b"k1".to_vec(), vec![3]), (b"bulk".to_vec(), vec![4])])));
+    for i in (0..311u32).step_by(3) { 
+        out.push(format!("{:?}", tree.remove(format!("k{i}").as_bytes())));
+    }
+    out.push(format!("{:?}", tree.update(b"k10", |v| [v, &[9]].concat())));
+    out.push(format!("{:?}", tree.get(b"k10")));
+    out.push(format!("{:?}", tree.get(b"k")));
+    out.push(format!("{:?}", tree.longest_prefix_match(b"k100zzz")));
+    out.push(format!("{:?}", tree.prefix_iter(b"k2").collect::<Vec<_>>()));
+    out.push(format!("{:?}", tree.iter_all()));
+    out.push(format!("{}", tree.count()));
+    out
+}
+
+#[test]
+pub fn test_radix_branching_factor_does_not_change_results() { 
+    let expected = radix_transcript(RadixTree::new());
+    assert_eq!(radix_transcript(RadixTree::<2>::default()), expected);
+    assert_eq!(radix_transcript(RadixTree::<16>::default()), expected);
+    assert_eq!(radix_transcript(RadixTree::<64>::default()), expected);
+}
+
+#[test]
+pub fn test_radix_error_messages() { 
+    assert_eq!(RadixError::InvalidKey.to_string(), "radix tree key must be non-empty");
+    let failed = RadixError::Failed { failed_garbage_value: vec![0; 12] };
+    assert_eq!(failed.to_string(), "radix CAS failed, recovered value of 12 bytes");
+    assert_eq!(String::from(RadixError::Corrupted), "radix tree encoding is truncated or malformed");
+
+    fn boxed() -> Result<(), Box<dyn std::error::Error>> { 
+        RadixTree::new().insert(b"", vec![1])?;
+        Ok(())
+    }
+    assert_eq!(boxed().unwrap_err().to_string(), "radix tree key must be non-empty");
+}
+
+fn assert_iter_all_sorted<const B: usize>(tree: RadixTree<B>) { 
+    // keys that are prefixes of each other, share compressed segments, and push nodes
+    // through every layout, inserted out of order
+    let mut keys: Vec<Vec<u8>> = (0..2000u32).map(|i| { 
+        let n = i * 7919 % 2000;
+        let mut key = format!("{}", n).into_bytes();
+        key.truncate(1 + n as usize % 4);
+        key.push((n % 256) as u8);
+        key
+    }).collect();
+    keys.extend([vec![0], vec![0, 0], vec![255], vec![255, 255, 0]]);
+    for key in &keys { 
+        tree.put(key, key.clone()).unwrap();
+    }
+    for key in keys.iter().step_by(5) { 
+        tree.remove(key).unwrap();
+    }
+    let all: Vec<Vec<u8>> = tree.iter_all().into_iter().map(|(k, _)| k).collect();
+    assert!(all.windows(2).all(|pair| pair[0] < pair[1]), "iter_all is not sorted");
+    assert_eq!(all.len(), tree.count());
+}
+
+#[test]
+pub fn test_radix_iter_all_is_sorted() { 
+    assert_iter_all_sorted(RadixTree::new());
+    assert_iter_all_sorted(RadixTree::<2>::default());
+    assert_iter_all_sorted(RadixTree::<16>::default());
+}
+
+fn assert_serialize_round_trip<const B: usize>(tree: RadixTree<B>) { 
+    for i in 0..1000u32 { 
+        // every tenth key is a prefix of the one after it
+        let key = match i % 10 { 
+            9 => format!("user:{:05}", i - 1).into_bytes()[..9].to_vec(),
+            _ => format!("user:{:05}", i * 7919 % 100_000).into_bytes()
+        };
+        tree.put(&key, i.to_be_bytes().to_vec()).unwrap();
+    }
+    tree.put(&[0, 255, 0], b"binary".to_vec()).unwrap();
+    tree.put(b"empty", Vec::new()).unwrap();
+    let data = tree.serialize();
+    let restored = RadixTree::<B>::deserialize(&data).unwrap();
+    assert_eq!(restored.count(), tree.count());
+    assert_eq!(restored.iter_all(), tree.iter_all());
+    for (key, value) in tree.iter_all() { 
+        assert_eq!(restored.get(&key).unwrap(), Some(value));
+    }
+    assert_eq!(restored.serialize(), data);
+    assert!(matches!(RadixTree::<B>::deserialize(&data[..data.len() - 1]), Err(RadixError::Corrupted)));
+    assert!(matches!(RadixTree::<B>::deserialize(&[data.clone(), vec![0]].concat()), Err(RadixError::Corrupted)));
+}
+
+#[test]
+pub fn test_radix_serialize_round_trip() { 
+    assert_serialize_round_trip(RadixTree::new());
+    assert_serialize_round_trip(RadixTree::<2>::default());
+    assert_serialize_round_trip(RadixTree::<16>::default());
+    assert_serialize_round_trip(RadixTree::<64>::default());
+
+    let empty = RadixTree::new();
+    assert_eq!(RadixTree::<256>::deserialize(&empty.serialize()).unwrap().count(), 0);
+    // the digits of one branching factor mean nothing to another
+    let tree = RadixTree::new();
+    tree.put(b"key", b"value".to_vec()).unwrap();
+    assert!(matches!(RadixTree::<16>::deserialize(&tree.serialize()), Err(RadixError::Corrupted)));
+}
+
+#[test]
+pub fn test_radix_values_share_the_written_buffer() { 
+    let tree = RadixTree::new();
+    let value = Bytes::from(vec![7u8; 64]);
+    tree.put(b"key", value.clone()).unwrap();
+    let stored = tree.get(b"key").unwrap().unwrap();
+    assert_eq!(stored.as_ptr(), value.as_ptr());
+    assert_eq!(tree.remove(b"key").unwrap().unwrap().as_ptr(), value.as_ptr());
+}
+
+#[test]
+pub fn test_radix_diff_classifies_every_changed_key() { 
+    let old = RadixTree::new();
+    let new = RadixTree::<16>::default();
+    for (key, value) in [(&b"apple"[..], &b"1"[..]), (b"app", b"2"), (b"banana", b"3"), (b"cherry", b"4"), (b"date", b"5")] { 
+        old.insert(key, value.to_vec()).unwrap();
+    }
+    // `app` goes away, `banana` and `date` change, `cherry` stays, `a` and `zebra` are new
+    for (key, value) in [(&b"a"[..], &b"0"[..]), (b"apple", b"1"), (b"banana", b"33"), (b"cherry", b"4"), (b"date", b""), (b"zebra", b"6")] { 
+        new.insert(key, value.to_vec()).unwrap();
+    }
+    assert_eq!(old.diff(&new), vec![
+        DiffEntry::Added(b"a".to_vec(), Bytes::from_static(b"0")),
+        DiffEntry::Removed(b"app".to_vec()),
+        DiffEntry::Modified { key: b"banana".to_vec(), old: Bytes::from_static(b"3"), new: Bytes::from_static(b"33") },
+        DiffEntry::Modified { key: b"date".to_vec(), old: Bytes::from_static(b"5"), new: Bytes::new() },
+        DiffEntry::Added(b"zebra".to_vec(), Bytes::from_static(b"6"))
+    ]);
+    assert_eq!(new.diff(&old)[1], DiffEntry::Added(b"app".to_vec(), Bytes::from_static(b"2")));
+    assert!(old.diff(&old).is_empty());
+    assert_eq!(RadixTree::new().diff(&old).len(), 5);
+}
@@ -1,92 +1,477 @@
-use std::{collections::BTreeMap, fs::{File, OpenOptions}, io::{Seek, SeekFrom}, path::{Path, PathBuf}};
+use std::{cmp::{Ordering, Reverse}, collections::{BTreeMap, BinaryHeap}, fs::{File, OpenOptions}, io::{Cursor, ErrorKind, Seek, SeekFrom}, num::NonZeroUsize, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering as AtomicOrdering}, Arc, Mutex}};
 use std::io::{Read, Write};
 
+use bytes::Bytes;
+use crc32fast::Hasher;
+use lru::LruCache;
+use memmap2::Mmap;
+
+use crate::comparator::{Comparator, LexicographicComparator};
+
+/**
+ * Layout knobs for SSTables written by an `SSTWriter`.
+ * * `index_block_size` - number of keys per index block. Readers keep one meta-index
+ *   entry per block in memory and load a single block per lookup.
+ * * `comparator` - order keys must be written in, see `SSTReader::open_with_comparator`.
+ */
+#[derive(Debug, Clone)]
+pub struct SSTConfig { 
+    pub index_block_size: usize,
+    pub comparator: Arc<dyn Comparator>
+}
+
+impl Default for SSTConfig { 
+    fn default() -> Self { 
+        Self { index_block_size: 128, comparator: Arc::new(LexicographicComparator) }
+    }
+}
+
 pub struct SSTWriter { 
     file: File,
-    path: PathBuf,
-    offsets: Vec<(Vec<u8>, u64)> // hold the offsets of the key to the file    
+    config: SSTConfig,
+    offsets: Vec<(Vec<u8>, u64)>, // hold the offsets of the key to the file    
+    properties: BTreeMap<String, Vec<u8>> // user metadata written into the properties block
+}
+
+/**
+ * Entry count written into the header of an SSTable until `SSTWriter::close` finalizes it.
+ */
+const UNFINISHED: u64 = u64::MAX;
+
+/**
+ * Set in the entry count of the header when the records store prefix encoded keys, see
+ * `prefix_encode`. Tables written before the encoding store every key in full.
+ */
+const PREFIX_ENCODED: u64 = 1 << 63;
+
+/**
+ * Set in the entry count of the header when the table has a properties block, whose
+ * offset is then stored in front of the footer. Older tables have neither.
+ */
+const HAS_PROPERTIES: u64 = 1 << 62;
+
+/**
+ * Set in the offset of an index entry whose record is a tombstone, so that lookups and
+ * key listings learn a key is deleted from the index alone. Tables written before
+ * tombstones never set it.
+ */
+const TOMBSTONE: u64 = 1 << 63;
+
+/**
+ * Stored as the value length of a tombstone record, which has no value bytes.
+ */
+const TOMBSTONE_LEN: u32 = u32::MAX;
+
+/**
+ * A record read with tombstones left in, `None` for a deleted key, see `WithTombstones`.
+ */
+pub type SSTEntry = std::io::Result<(Vec<u8>, Option<Vec<u8>>)>;
+
+/**
+ * What an SSTable knows about a key, see `SSTReader::key_state`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState { 
+    Absent,
+    Live,
+    Deleted
+}
+
+/**
+ * Reads the header of an SSTable, its entry count together with the format flags.
+ * Fails with `InvalidData` for a table `SSTWriter::close` never finished.
+ */
+fn read_header(file: &mut File) -> std::io::Result<u64> { 
+    let mut count_buf = [0u8; 8];
+    file.read_exact(&mut count_buf)?;
+    match u64::from_be_bytes(count_buf) { 
+        UNFINISHED => Err(std::io::Error::new(ErrorKind::InvalidData, "sst file was not closed")),
+        header => Ok(header)
+    }
+}
+
+/**
+ * Splits `key` into the length of the prefix it shares with `prev`, at most 255 bytes,
+ * and the suffix after it.
+ * * Records store their key this way relative to the key before them. The first record
+ *   of every index block is encoded against an empty key, so it holds its key in full.
+ */
+pub fn prefix_encode<'a>(prev: &[u8], key: &'a [u8]) -> (u8, &'a [u8]) { 
+    let shared = prev.iter().zip(key).take_while(|(a, b)| a == b).count().min(u8::MAX as usize);
+    (shared as u8, &key[shared..])
 }
 
 impl SSTWriter { 
+    /**
+     * Creates a new SSTWriter at the specified path with the default `SSTConfig`.
+     */
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> { 
+        Self::open_with_config(path, SSTConfig::default())
+    }
+
     /**
      * Creates a new SSTWriter at the specified path.
      * * If a file already exists at the path, it will be truncated (cleared).
-     * The writer maintains an internal `offsets` vector to build the index 
-     * after the data block is written.
+     *   The writer maintains an internal `offsets` vector to build the index 
+     *   after the data block is written.
+     * * The header is marked unfinished, so the file can not be opened by an
+     *   `SSTReader` until `close` is called.
      */
-    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> { 
-        let file = OpenOptions::new()
+    pub fn open_with_config<P: AsRef<Path>>(path: P, config: SSTConfig) -> std::io::Result<Self> { 
+        let mut file = OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
             .open(path.as_ref())?;
+        file.write_all(&UNFINISHED.to_be_bytes())?;
         Ok(Self { 
             file,
-            path: path.as_ref().to_path_buf(),
-            offsets: Vec::new()
+            config,
+            offsets: Vec::new(),
+            properties: BTreeMap::new()
         })
     }
 
     /**
-     * Persists a collection of key-value pairs to disk in a structured format.
-     * * The file structure generated is as follows:
-     * 1. Data Block: [KeyLen][Key][ValLen][Value] repeated N times.
-     * 2. Index Block: [KeyLen][Key][OffsetInFile] repeated N times.
-     * 3. Footer: [IndexOffset (8B)][IndexLength (8B)].
+     * Appends a collection of key-value pairs to the data block.
+     * * The file structure generated once `close` is called is as follows:
+     * 1. Header: [EntryCount (8B)].
+     * 2. Data Block: [Shared (1B)][SuffixLen][Suffix][ValLen][Value][CRC32] repeated N times,
+     *    the key being the first `Shared` bytes of the previous key followed by `Suffix`, see
+     *    `prefix_encode`. The checksum covers everything from `Shared` to the end of `Value`.
+     *    A tombstone, see `write_entries`, stores `u32::MAX` as its ValLen and no Value.
+     * 3. Properties Block: [Count (4B)] then [NameLen][Name][ValLen][Value] per property set
+     *    with `set_property`, in name order.
+     * 4. Index Blocks: [KeyLen][Key][OffsetInFile] repeated N times, cut into blocks of
+     *    `index_block_size` keys. The offset of a tombstone has the `TOMBSTONE` bit set.
+     * 5. Meta-Index: [FirstKeyLen][FirstKey][BlockOffset (8B)][BlockBytes (8B)], one per index block.
+     * 6. Key Range: [MinKeyLen][MinKey][MaxKeyLen][MaxKey], taken from the first and last entries.
+     * 7. Footer: [PropertiesOffset (8B)][MetaIndexOffset (8B)][MetaIndexLength (8B)].
+     * * The header's entry count carries the `PREFIX_ENCODED` and `HAS_PROPERTIES` flags.
+     *   Without the first the records are [KeyLen][Key][ValLen][Value][CRC32], as written
+     *   before keys were encoded; without the second there is no properties block and the
+     *   footer starts at `MetaIndexOffset`.
      * * # Arguments
-     * * `entries` - A vector of (Key, Value) pairs. Should ideally be sorted 
-     * lexicographically for standard SSTable behavior.
+     * * `entries` - A vector of (Key, Value) pairs, sorted by the configured comparator
+     *   without duplicate keys.
+     * * # Returns
+     * * `Err(InvalidInput)` on the first out-of-order or repeated key, see `write_from_iter`.
      */
-
     pub fn write_all(&mut self, entries: Vec<(Vec<u8>, Vec<u8>)>) -> std::io::Result<()> { 
-        let entries_len = entries.len() as u64;
-        self.file.write_all(&entries_len.to_be_bytes())?;
-        for (k,v) in entries { 
-            let offset = self.file.stream_position()?;
-            self.offsets.push((k.clone(), offset));
-            let key_len = k.len() as u32;
-            self.file.write_all(&key_len.to_be_bytes())?;
-            self.file.write_all(&k.clone())?;
-            let val_len = v.len() as u32;
-            self.file.write_all(&val_len.to_be_bytes())?;
-            self.file.write_all(&v.clone())?;
+        self.write_from_iter(entries.into_iter())
+    }
+
+    /**
+     * Streams key-value pairs into the data block as they arrive.
+     * * Only the keys and offsets needed for the index are kept in memory, so large
+     *   merges do not have to materialize every entry first.
+     * * # Arguments
+     * * `iter` - (Key, Value) pairs in strictly increasing order of the configured
+     *   comparator, also relative to anything written before. A repeated key would be found
+     *   by neither the index nor the prefix encoding.
+     * * # Returns
+     * * `Err(InvalidInput)` on the first key not greater than the one before it; the
+     *   entries before it are written.
+     */
+    pub fn write_from_iter<I: Iterator<Item = (Vec<u8>, Vec<u8>)>>(&mut self, iter: I) -> std::io::Result<()> { 
+        self.write_entries(iter.map(|(k, v)| (k, Some(v))))
+    }
+
+    /**
+     * Streams entries into the data block like `write_from_iter`, a `None` value writing
+     * a tombstone: a record saying the key was deleted, which hides the key in older
+     * tables until a compaction that reaches the oldest table drops both.
+     */
+    pub fn write_entries<I: Iterator<Item = (Vec<u8>, Option<Vec<u8>>)>>(&mut self, iter: I) -> std::io::Result<()> { 
+        for (k, v) in iter { 
+            if let Some((last, _)) = self.offsets.last() 
+                && self.config.comparator.compare(&k, last) != Ordering::Greater { 
+                return Err(std::io::Error::new(ErrorKind::InvalidInput, "sst keys must be written in sorted order"));
+            }
+            self.write_entry(k, v)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Predicts the size of the file `write_all` followed by `close` would produce for
+     * `entries` with the default `SSTConfig`, without writing anything.
+     */
+    pub fn estimated_output_size(entries: &[(Vec<u8>, Vec<u8>)]) -> u64 { 
+        // an empty properties block is just its count
+        let header_and_footer = 8 + 4 + 24;
+        let data: usize = entries.chunks(SSTConfig::default().index_block_size)
+            .flat_map(|block| block.iter().scan(&[][..], |prev, (k, v)| { 
+                let (_, suffix) = prefix_encode(prev, k);
+                *prev = k;
+                Some(1 + 4 + suffix.len() + 4 + v.len() + 4)
+            }))
+            .sum();
+        let index: usize = entries.iter().map(|(k, _)| 4 + k.len() + 8).sum();
+        let meta_index: usize = entries.chunks(SSTConfig::default().index_block_size)
+            .map(|block| 4 + block[0].0.len() + 16)
+            .sum();
+        let key_range = 8 + entries.first().map_or(0, |(k, _)| k.len()) + entries.last().map_or(0, |(k, _)| k.len());
+        (header_and_footer + data + index + meta_index + key_range) as u64
+    }
+
+    /**
+     * Stores `val` under `key` in the properties block of the table, replacing an earlier
+     * value. Properties are written by `close` and read back with `SSTReader::property`.
+     */
+    pub fn set_property(&mut self, key: &str, val: Vec<u8>) { 
+        self.properties.insert(key.to_string(), val);
+    }
+
+    fn write_entry(&mut self, k: Vec<u8>, v: Option<Vec<u8>>) -> std::io::Result<()> { 
+        let offset = self.file.stream_position()?;
+        // every index block starts over with a full key
+        let prev = match self.offsets.len() % self.config.index_block_size.max(1) { 
+            0 => &[][..],
+            _ => &self.offsets.last().unwrap().0[..]
+        };
+        let (shared, suffix) = prefix_encode(prev, &k);
+        let value = v.as_deref().unwrap_or_default();
+        let mut record = Vec::with_capacity(13 + suffix.len() + value.len());
+        record.push(shared);
+        record.extend(&(suffix.len() as u32).to_be_bytes());
+        record.extend(suffix);
+        record.extend(&v.as_ref().map_or(TOMBSTONE_LEN, |v| v.len() as u32).to_be_bytes());
+        record.extend(value);
+        record.extend(&crc32fast::hash(&record).to_be_bytes());
+        self.file.write_all(&record)?;
+        let tombstone = match v.is_none() { 
+            true => TOMBSTONE,
+            false => 0
+        };
+        self.offsets.push((k, offset | tombstone));
+        Ok(())
+    }
+
+    /**
+     * Finalizes the SSTable: writes the properties block, index blocks, meta-index, key
+     * range and footer, stamps the entry count into the header and syncs the file to disk.
+     */
+    pub fn close(mut self) -> std::io::Result<()> { 
+        let properties_offset = self.file.stream_position()?;
+        let mut buf = Vec::new();
+        buf.extend(&(self.properties.len() as u32).to_be_bytes());
+        for (name, val) in &self.properties { 
+            buf.extend(&(name.len() as u32).to_be_bytes());
+            buf.extend(name.as_bytes());
+            buf.extend(&(val.len() as u32).to_be_bytes());
+            buf.extend(val);
+        }
+        self.file.write_all(&buf)?;
+        let mut meta_index = Vec::new();
+        for block in self.offsets.chunks(self.config.index_block_size.max(1)) { 
+            let block_offset = self.file.stream_position()?;
+            let mut buf = Vec::new();
+            for (key, offset) in block { 
+                let key_len = key.len() as u32;
+                buf.extend(&key_len.to_be_bytes());
+                buf.extend(key);
+                buf.extend(&offset.to_be_bytes());
+            }
+            self.file.write_all(&buf)?;
+            meta_index.push((&block[0].0, block_offset, buf.len() as u64));
         }
         let index_offset = self.file.stream_position()?;
-        let index_len = self.offsets.len() as u64;
-        for (key, offset) in &self.offsets { 
-            let key_len = key.len() as u32;
-            self.file.write_all(&key_len.to_be_bytes())?;
+        let index_len = meta_index.len() as u64;
+        for (first_key, block_offset, block_len) in meta_index { 
+            self.file.write_all(&(first_key.len() as u32).to_be_bytes())?;
+            self.file.write_all(first_key)?;
+            self.file.write_all(&block_offset.to_be_bytes())?;
+            self.file.write_all(&block_len.to_be_bytes())?;
+        }
+        let empty = Vec::new();
+        for key in [self.offsets.first(), self.offsets.last()] { 
+            let key = key.map(|(key, _)| key).unwrap_or(&empty);
+            self.file.write_all(&(key.len() as u32).to_be_bytes())?;
             self.file.write_all(key)?;
-            self.file.write_all(&offset.to_be_bytes())?;
-        } 
+        }
+        self.file.write_all(&properties_offset.to_be_bytes())?;
         self.file.write_all(&index_offset.to_be_bytes())?;
         self.file.write_all(&index_len.to_be_bytes())?;
-        Ok(())
-        
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&(self.offsets.len() as u64 | PREFIX_ENCODED | HAS_PROPERTIES).to_be_bytes())?;
+        self.file.sync_all()
+    }
+}
+
+/**
+ * Where an `SSTReader` reads its bytes from: a plain file handle, or a read-only
+ * mapping of the whole file where every read is a bounds-checked slice copy.
+ */
+enum SSTSource { 
+    File(File),
+    Mmap(Cursor<Mmap>)
+}
+
+impl Read for SSTSource { 
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { 
+        match self { 
+            Self::File(file) => file.read(buf),
+            Self::Mmap(mmap) => mmap.read(buf)
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> { 
+        match self { 
+            Self::File(file) => file.read_exact(buf),
+            Self::Mmap(mmap) => mmap.read_exact(buf)
+        }
+    }
+}
+
+impl Seek for SSTSource { 
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> { 
+        match self { 
+            Self::File(file) => file.seek(pos),
+            Self::Mmap(mmap) => mmap.seek(pos)
+        }
+    }
+}
+
+impl SSTSource { 
+    fn len(&self) -> std::io::Result<u64> { 
+        match self { 
+            Self::File(file) => Ok(file.metadata()?.len()),
+            Self::Mmap(mmap) => Ok(mmap.get_ref().len() as u64)
+        }
+    }
+}
+
+/**
+ * LRU cache of SSTable blocks, shared by every reader it is handed to with
+ * `SSTReader::with_block_cache`.
+ * * Blocks are keyed by the path of their table and their offset in it. Two kinds are
+ *   cached: index blocks, and the values of records read by `SSTReader::get`, which are
+ *   only stored once their checksum has been verified. Sequential scans read around the
+ *   cache so that they do not push out the blocks point lookups keep coming back to.
+ * * Tables never change once written, but a path can be reused by a new table after the
+ *   old one was deleted, so the owner of the cache calls `evict_file` when deleting one.
+ */
+pub struct BlockCache { 
+    blocks: Mutex<LruCache<(PathBuf, u64), Bytes>>,
+    misses: AtomicU64
+}
+
+impl BlockCache { 
+    /**
+     * Creates a cache holding up to `capacity` blocks.
+     */
+    pub fn new(capacity: NonZeroUsize) -> Self { 
+        Self { blocks: Mutex::new(LruCache::new(capacity)), misses: AtomicU64::new(0) }
+    }
+
+    /**
+     * Number of lookups that had to go to disk since the cache was created.
+     */
+    pub fn misses(&self) -> u64 { 
+        self.misses.load(AtomicOrdering::Relaxed)
+    }
+
+    /**
+     * Drops every block of the table at `path`.
+     */
+    pub fn evict_file(&self, path: &Path) { 
+        let mut blocks = self.blocks.lock().unwrap();
+        let stale: Vec<(PathBuf, u64)> = blocks.iter().filter(|((block_path, _), _)| block_path == path).map(|(key, _)| key.clone()).collect();
+        for key in stale { 
+            blocks.pop(&key);
+        }
+    }
+
+    fn get(&self, path: &Path, offset: u64) -> Option<Bytes> { 
+        let block = self.blocks.lock().unwrap().get(&(path.to_path_buf(), offset)).cloned();
+        if block.is_none() { 
+            self.misses.fetch_add(1, AtomicOrdering::Relaxed);
+        }
+        block
+    }
+
+    fn insert(&self, path: &Path, offset: u64, block: Bytes) { 
+        self.blocks.lock().unwrap().put((path.to_path_buf(), offset), block);
     }
 }
 
 pub struct SSTReader { 
-    file: File,
+    file: SSTSource,
     path: PathBuf,
-    index: BTreeMap<Vec<u8>, u64>
+    meta_index: Vec<(Vec<u8>, (u64, u64))>, // first key of each index block to its offset and length, in file order
+    data_end: u64,
+    entry_count: u64, // from the header, stamped by `SSTWriter::close`
+    prefix_encoded: bool, // records hold prefix encoded keys, see `prefix_encode`
+    min_key: Vec<u8>,
+    max_key: Vec<u8>,
+    properties: BTreeMap<String, Vec<u8>>, // empty for tables written without a properties block
+    comparator: Arc<dyn Comparator>,
+    block_cache: Option<Arc<BlockCache>>
 }
 
 impl SSTReader { 
 
     /**
-     * Opens an existing SSTable file and loads its index into memory.
+     * Opens an existing SSTable file and loads its meta-index into memory.
+     * * Fails with `InvalidData` if the writer never called `SSTWriter::close`.
      * * This method performs a "tail-read":
-     * 1. Seeks to the last 16 bytes of the file to find the Index Offset.
-     * 2. Jumps to that offset to read the BTreeMap of keys to file positions.
-     * * This allows the reader to know where every key is located without 
-     * scanning the entire data block.
+     * 1. Seeks to the footer at the end of the file to find the Index Offset.
+     * 2. Jumps to that offset to read the first key of every index block.
+     * 3. Reads the key range stored right after the meta-index.
+     * 4. Loads the properties block, when the table has one.
+     * * This allows the reader to find the index block of any key without 
+     *   holding every key in memory or scanning the data block.
      */
     pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> { 
-        let mut file = OpenOptions::new().read(true).open(path.as_ref())?;
-        let mut indexes = BTreeMap::new();
-        let size = file.metadata()?.len();
-        file.seek(SeekFrom::Start(size - 16))?;
+        Self::open_with_comparator(path, Arc::new(LexicographicComparator))
+    }
+
+    /**
+     * Opens an existing SSTable like `open` for a file written with `comparator`, which
+     * is used to binary search the meta-index and to bound range scans.
+     */
+    pub fn open_with_comparator<P: AsRef<Path>>(path: P, comparator: Arc<dyn Comparator>) -> std::io::Result<Self> { 
+        let file = OpenOptions::new().read(true).open(path.as_ref())?;
+        Self::open_source(path, SSTSource::File(file), comparator)
+    }
+
+    /**
+     * Opens an existing SSTable file like `open`, but maps it into memory so that
+     * lookups and scans read from the mapping instead of issuing a syscall per read.
+     * * The mapping is read-only. SSTables are never modified once closed, which is
+     *   what makes sharing the pages with the file safe.
+     */
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> std::io::Result<Self> { 
+        let file = OpenOptions::new().read(true).open(path.as_ref())?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        Self::open_source(path, SSTSource::Mmap(Cursor::new(mmap)), Arc::new(LexicographicComparator))
+    }
+
+    fn open_source<P: AsRef<Path>>(path: P, mut file: SSTSource, comparator: Arc<dyn Comparator>) -> std::io::Result<Self> { 
+        let mut indexes = Vec::new();
+        let size = file.len()?;
+        let mut count_buf = [0u8; 8];
+        file.read_exact(&mut count_buf)?;
+        let entry_count = u64::from_be_bytes(count_buf);
+        if size < 24 || entry_count == UNFINISHED { 
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "sst file was not closed"));
+        }
+        let prefix_encoded = entry_count & PREFIX_ENCODED != 0;
+        let has_properties = entry_count & HAS_PROPERTIES != 0;
+        let entry_count = entry_count & !(PREFIX_ENCODED | HAS_PROPERTIES);
+        let properties_offset = match has_properties { 
+            true => { 
+                let mut offset_buf = [0u8; 8];
+                file.seek(SeekFrom::Start(size.checked_sub(24).ok_or(ErrorKind::InvalidData)?))?;
+                file.read_exact(&mut offset_buf)?;
+                Some(u64::from_be_bytes(offset_buf))
+            },
+            false => { 
+                file.seek(SeekFrom::Start(size - 16))?;
+                None
+            }
+        };
         let mut index_offset_buf = [0u8; 8];
         let mut index_len_buf = [0u8; 8];
         file.read_exact(&mut index_offset_buf)?;
@@ -94,7 +479,7 @@ impl SSTReader {
         let index_offset = u64::from_be_bytes(index_offset_buf);
         let index_len = u64::from_be_bytes(index_len_buf);
         file.seek(SeekFrom::Start(index_offset))?;
-        for i in 0..index_len { 
+        for _ in 0..index_len { 
             let mut key_len_buf = [0u8; 4];
             file.read_exact(&mut key_len_buf)?;
             let key_len= u32::from_be_bytes(key_len_buf);
@@ -102,43 +487,695 @@ impl SSTReader {
             file.read_exact(&mut key_buf)?;
             let mut offset_buf = [0u8; 8];
             file.read_exact(&mut offset_buf)?;
-            let offset = u64::from_be_bytes(offset_buf);
-            indexes.insert(key_buf.to_vec(), offset);
+            let mut block_len_buf = [0u8; 8];
+            file.read_exact(&mut block_len_buf)?;
+            indexes.push((key_buf, (u64::from_be_bytes(offset_buf), u64::from_be_bytes(block_len_buf))));
+        }
+        let mut key_range = [Vec::new(), Vec::new()];
+        for key in key_range.iter_mut() { 
+            let mut key_len_buf = [0u8; 4];
+            file.read_exact(&mut key_len_buf)?;
+            *key = vec![0u8; u32::from_be_bytes(key_len_buf) as usize];
+            file.read_exact(key)?;
         }
+        let [min_key, max_key] = key_range;
+        let mut properties = BTreeMap::new();
+        if let Some(offset) = properties_offset { 
+            file.seek(SeekFrom::Start(offset))?;
+            let mut count_buf = [0u8; 4];
+            file.read_exact(&mut count_buf)?;
+            for _ in 0..u32::from_be_bytes(count_buf) { 
+                let mut fields = [Vec::new(), Vec::new()];
+                for field in fields.iter_mut() { 
+                    let mut len_buf = [0u8; 4];
+                    file.read_exact(&mut len_buf)?;
+                    *field = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+                    file.read_exact(field)?;
+                }
+                let [name, val] = fields;
+                let name = String::from_utf8(name).map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "sst property name is not utf-8"))?;
+                properties.insert(name, val);
+            }
+        }
+        let data_end = match properties_offset { 
+            Some(offset) => offset,
+            None => indexes.iter().map(|&(_, (offset, _))| offset).min().unwrap_or(index_offset)
+        };
         Ok(Self { 
             file,
             path: path.as_ref().to_path_buf(),
-            index: indexes
+            meta_index: indexes,
+            data_end,
+            entry_count,
+            prefix_encoded,
+            min_key,
+            max_key,
+            properties,
+            comparator,
+            block_cache: None
         })
     }
 
     /**
-     * Retrieves a value for a specific key by querying the in-memory index.
+     * Serves the index blocks and the values read by `get` from `cache` where it can,
+     * filling it on every miss.
+     */
+    pub fn with_block_cache(mut self, cache: Arc<BlockCache>) -> Self { 
+        self.block_cache = Some(cache);
+        self
+    }
+
+    /**
+     * Number of records in the SSTable at `path`, without opening it.
+     * * Only the 8 byte header is read, which `SSTWriter::close` stamps with the exact
+     *   count, so neither the meta-index nor any index block is loaded.
+     * * # Returns
+     * * `Err(InvalidData)` if the table was never closed.
+     */
+    pub fn approximate_entry_count(path: &Path) -> std::io::Result<usize> { 
+        let header = read_header(&mut File::open(path)?)?;
+        Ok((header & !(PREFIX_ENCODED | HAS_PROPERTIES)) as usize)
+    }
+
+    /**
+     * Bytes taken by the records of the SSTable at `path`, from the end of the header to
+     * the first byte after the last record, without opening it.
+     * * Reads the header and the footer. A table without a properties block has the end of
+     *   its records in the first meta-index entry, which is read as well.
+     * * # Returns
+     * * `Err(InvalidData)` if the table was never closed or is shorter than its footer.
+     */
+    pub fn approximate_data_bytes(path: &Path) -> std::io::Result<u64> { 
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let header = read_header(&mut file)?;
+        let mut u64_buf = [0u8; 8];
+        let data_end = match header & HAS_PROPERTIES != 0 { 
+            true => { 
+                file.seek(SeekFrom::Start(size.checked_sub(24).ok_or(ErrorKind::InvalidData)?))?;
+                file.read_exact(&mut u64_buf)?;
+                u64::from_be_bytes(u64_buf)
+            },
+            false => { 
+                file.seek(SeekFrom::Start(size.checked_sub(16).ok_or(ErrorKind::InvalidData)?))?;
+                file.read_exact(&mut u64_buf)?;
+                let index_offset = u64::from_be_bytes(u64_buf);
+                file.read_exact(&mut u64_buf)?;
+                if u64::from_be_bytes(u64_buf) == 0 { 
+                    index_offset
+                } else { 
+                    // the first index block follows the last record
+                    let mut key_len_buf = [0u8; 4];
+                    file.seek(SeekFrom::Start(index_offset))?;
+                    file.read_exact(&mut key_len_buf)?;
+                    file.seek(SeekFrom::Current(u32::from_be_bytes(key_len_buf) as i64))?;
+                    file.read_exact(&mut u64_buf)?;
+                    u64::from_be_bytes(u64_buf)
+                }
+            }
+        };
+        data_end.checked_sub(8).ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "sst data ends inside the header"))
+    }
+
+    /**
+     * Size of the SSTable file on disk in bytes.
+     */
+    pub fn approx_size_bytes(&self) -> u64 { 
+        self.file.len().unwrap_or(0)
+    }
+
+    /**
+     * Number of records in this SSTable, read from the header when the table was opened.
+     */
+    pub fn entry_count(&self) -> u64 { 
+        self.entry_count
+    }
+
+    /**
+     * Smallest key stored in this SSTable, empty if the table has no entries.
+     */
+    pub fn min_key(&self) -> &[u8] { 
+        &self.min_key
+    }
+
+    /**
+     * Largest key stored in this SSTable, empty if the table has no entries.
+     */
+    pub fn max_key(&self) -> &[u8] { 
+        &self.max_key
+    }
+
+    /**
+     * Value stored under `key` with `SSTWriter::set_property`, `None` if it was never set
+     * or the table predates properties blocks.
+     */
+    pub fn property(&self, key: &str) -> Option<&[u8]> { 
+        self.properties.get(key).map(|val| val.as_slice())
+    }
+
+    /**
+     * Reads one index block back into (Key, OffsetInFile, IsTombstone) entries.
+     */
+    fn read_index_block(&mut self, offset: u64, len: u64) -> std::io::Result<Vec<(Vec<u8>, u64, bool)>> { 
+        let cached = self.block_cache.as_ref().and_then(|cache| cache.get(&self.path, offset));
+        let buf = match cached { 
+            Some(buf) => buf,
+            None => { 
+                let mut buf = vec![0u8; len as usize];
+                self.file.seek(SeekFrom::Start(offset))?;
+                self.file.read_exact(&mut buf)?;
+                let buf = Bytes::from(buf);
+                if let Some(cache) = &self.block_cache { 
+                    cache.insert(&self.path, offset, buf.clone());
+                }
+                buf
+            }
+        };
+        let mut entries = Vec::new();
+        let mut rest = &buf[..];
+        while !rest.is_empty() { 
+            let truncated = || std::io::Error::new(ErrorKind::InvalidData, "sst index block is truncated");
+            let key_len = u32::from_be_bytes(rest.get(..4).ok_or_else(truncated)?.try_into().unwrap()) as usize;
+            let key = rest.get(4..4 + key_len).ok_or_else(truncated)?.to_vec();
+            let offset = u64::from_be_bytes(rest.get(4 + key_len..12 + key_len).ok_or_else(truncated)?.try_into().unwrap());
+            entries.push((key, offset & !TOMBSTONE, offset & TOMBSTONE != 0));
+            rest = &rest[12 + key_len..];
+        }
+        Ok(entries)
+    }
+
+    /**
+     * Offset and length of the last index block whose first key is <= `key`.
+     */
+    fn block_for(&self, key: &[u8]) -> Option<(u64, u64)> { 
+        let after = self.meta_index.partition_point(|(first, _)| self.comparator.compare(first, key) != Ordering::Greater);
+        after.checked_sub(1).map(|i| self.meta_index[i].1)
+    }
+
+    /**
+     * Finds the file offset of `key` through the meta-index and its index block, and
+     * whether its record is a tombstone.
+     */
+    fn locate(&mut self, key: &[u8]) -> std::io::Result<Option<(u64, bool)>> { 
+        let Some((offset, len)) = self.block_for(key) else { 
+            return Ok(None);
+        };
+        let block = self.read_index_block(offset, len)?;
+        Ok(block.into_iter().find(|(k, _, _)| k == key).map(|(_, offset, deleted)| (offset, deleted)))
+    }
+
+    /**
+     * Number of live keys in this SSTable starting with `prefix`, read from the index
+     * blocks alone, no record is read. Tombstones are not counted.
+     * * With a lexicographic comparator the keys sharing a prefix are contiguous, so only
+     *   the index blocks that can hold them are read. Other comparators read every block.
+     */
+    pub fn prefix_count(&mut self, prefix: &[u8]) -> std::io::Result<usize> { 
+        let lexicographic = self.comparator.is_lexicographic();
+        let first = match lexicographic { 
+            true => self.meta_index.partition_point(|(first, _)| first.as_slice() <= prefix).saturating_sub(1),
+            false => 0
+        };
+        let mut count = 0;
+        for i in first..self.meta_index.len() { 
+            let (first_key, (offset, len)) = &self.meta_index[i];
+            if lexicographic && i > first && !first_key.starts_with(prefix) { 
+                break;
+            }
+            let (offset, len) = (*offset, *len);
+            count += self.read_index_block(offset, len)?.iter().filter(|(key, _, deleted)| !deleted && key.starts_with(prefix)).count();
+        }
+        Ok(count)
+    }
+
+    /**
+     * Every live key in `[start, end)`, in the table's order, read from the index blocks
+     * alone. Keys deleted by a tombstone in this table are left out.
+     * * Only the index blocks that can hold keys of the range are loaded, the data block
+     *   is never touched.
+     */
+    pub fn keys_in_range(&mut self, start: &[u8], end: &[u8]) -> std::io::Result<Vec<Vec<u8>>> { 
+        Ok(self.key_states_in_range(start, end)?.into_iter()
+            .filter(|(_, deleted)| !deleted)
+            .map(|(key, _)| key)
+            .collect())
+    }
+
+    /**
+     * Every key in `[start, end)` like `keys_in_range`, tombstones included, each paired
+     * with whether it is deleted. Lets a caller merging tables drop keys an older table
+     * still lists.
+     */
+    pub fn key_states_in_range(&mut self, start: &[u8], end: &[u8]) -> std::io::Result<Vec<(Vec<u8>, bool)>> { 
+        let first = self.meta_index.partition_point(|(first, _)| self.comparator.compare(first, start) != Ordering::Greater).saturating_sub(1);
+        let mut keys = Vec::new();
+        for i in first..self.meta_index.len() { 
+            let (first_key, (offset, len)) = &self.meta_index[i];
+            if self.comparator.compare(first_key, end) != Ordering::Less { 
+                break;
+            }
+            let (offset, len) = (*offset, *len);
+            keys.extend(self.read_index_block(offset, len)?.into_iter()
+                .map(|(key, _, deleted)| (key, deleted))
+                .filter(|(key, _)| self.comparator.compare(key, start) != Ordering::Less && self.comparator.compare(key, end) == Ordering::Less));
+        }
+        Ok(keys)
+    }
+
+    /**
+     * Retrieves a value for a specific key by querying the meta-index.
      * * # Performance
-     * * Index Lookup: O(log n) via BTreeMap.
-     * * Disk Access: O(1) seek and read.
+     * * Index Lookup: O(log n) binary search of the meta-index, then a scan of one index block.
+     * * Disk Access: one read for the index block, one seek and read for the record, each
+     *   skipped when the block is in the `BlockCache`.
      * * # Returns
      * * `Ok(Some(Vec<u8>))` if the key is found in the index and successfully read from disk.
+     * * `Ok(None)` if the key does not exist in this SSTable, or is deleted by a tombstone
+     *   in it, see `get_entry` to tell the two apart.
+     * * `Err(InvalidData)` if the record fails its checksum.
+     */
+    pub fn get(&mut self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> { 
+        Ok(self.get_entry(key)?.flatten())
+    }
+
+    /**
+     * Looks `key` up like `get`, telling a deleted key apart from a missing one. A
+     * tombstone is known from the index, its record is not read.
+     * * # Returns
+     * * `Ok(Some(Some(Vec<u8>)))` with the value of a live key.
+     * * `Ok(Some(None))` if this table holds a tombstone for the key, older tables must
+     *   not be asked for it.
      * * `Ok(None)` if the key does not exist in this SSTable.
+     * * `Err(InvalidData)` if the record fails its checksum.
      */
+    pub fn get_entry(&mut self, key: &[u8]) -> std::io::Result<Option<Option<Vec<u8>>>> { 
+        let Some((offset, deleted)) = self.locate(key)? else { 
+            return Ok(None);
+        };
+        if deleted { 
+            return Ok(Some(None));
+        }
+        if let Some(value) = self.block_cache.as_ref().and_then(|cache| cache.get(&self.path, offset)) { 
+            return Ok(Some(Some(value.to_vec())));
+        }
+        self.file.seek(SeekFrom::Start(offset))?;
+        // the key shares its prefix with the previous one, which is where it came from
+        let (_, value_buf, _) = read_record(&mut self.file, self.prefix_encoded, key)?;
+        let value_buf = value_buf.ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "sst index entry is live but its record is a tombstone"))?;
+        if let Some(cache) = &self.block_cache { 
+            cache.insert(&self.path, offset, Bytes::copy_from_slice(&value_buf));
+        }
+        Ok(Some(Some(value_buf)))
+    }
 
-    pub fn get(&mut self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>> { 
-        if let Some(offset) = self.index.get(key) {
-            self.file.seek(SeekFrom::Start(*offset))?;
-            let mut klen_buf = [0u8; 4];
-            self.file.read_exact(&mut klen_buf)?;
-            let klen = u32::from_be_bytes(klen_buf);
-            let mut key_buf = vec![0u8; klen as usize];
-            self.file.read_exact(&mut key_buf)?;
-            
-            let mut vlen_buf = [0u8; 4];
-            self.file.read_exact(&mut vlen_buf)?;
-            let vlen = u32::from_be_bytes(vlen_buf);
-            let mut value_buf = vec![0u8; vlen as usize];
-            self.file.read_exact(&mut value_buf)?;
-            return Ok(Some(value_buf));
-
-        }
-        Ok(None)
-    }
-}
\ No newline at end of file
+    /**
+     * Whether `key` holds a value in this SSTable, like `get(key).is_some()` without
+     * reading the record, see `key_state`.
+     */
+    pub fn key_exists(&mut self, key: &[u8]) -> std::io::Result<bool> { 
+        Ok(self.key_state(key)? == KeyState::Live)
+    }
+
+    /**
+     * Whether this SSTable holds a value for `key`, a tombstone, or nothing.
+     * * Keys outside the table's key range are answered without I/O, others read one
+     *   index block. The record's checksum is not verified.
+     */
+    pub fn key_state(&mut self, key: &[u8]) -> std::io::Result<KeyState> { 
+        if self.comparator.compare(key, &self.min_key) == Ordering::Less
+            || self.comparator.compare(key, &self.max_key) == Ordering::Greater { 
+            return Ok(KeyState::Absent);
+        }
+        Ok(match self.locate(key)? { 
+            None => KeyState::Absent,
+            Some((_, true)) => KeyState::Deleted,
+            Some((_, false)) => KeyState::Live
+        })
+    }
+
+    /**
+     * Returns a sequential iterator over every key-value pair in the data block.
+     * * Records are read straight from the file in the order they were written, so
+     *   nothing but the current record is held in memory. Tombstones are skipped, see
+     *   `SSTIter::with_tombstones`.
+     * * # Returns
+     * * `SSTIter` yielding `std::io::Result<(Vec<u8>, Vec<u8>)>`, stopping after the first error.
+     */
+    pub fn iter(&mut self) -> SSTIter<'_> { 
+        let (remaining, error) = match self.file.seek(SeekFrom::Start(8)) { 
+            Ok(_) => (self.entry_count, None),
+            Err(err) => (0, Some(err))
+        };
+        SSTIter { 
+            file: &mut self.file,
+            remaining,
+            prefix_encoded: self.prefix_encoded,
+            last_key: Vec::new(),
+            error
+        }
+    }
+
+    /**
+     * Returns an iterator over the pairs whose key falls in `[start, end)`.
+     * * The index is used to seek straight to the largest key <= `start`, and records are
+     *   then read sequentially until the first key >= `end`, so small ranges only touch
+     *   one index block and the records they return. Tombstones are skipped, see
+     *   `SSTRangeIter::with_tombstones`.
+     * * # Returns
+     * * `SSTRangeIter` yielding `std::io::Result<(Vec<u8>, Vec<u8>)>`, stopping after the first error.
+     */
+    pub fn range_iter<'a>(&'a mut self, start: &[u8], end: &[u8]) -> SSTRangeIter<'a> { 
+        let seek_to = match self.block_for(start) { 
+            Some((offset, len)) => self.read_index_block(offset, len).map(|block| { 
+                block.into_iter()
+                    .take_while(|(k, _, _)| self.comparator.compare(k, start) != Ordering::Greater)
+                    .last()
+                    .map_or((8, Vec::new()), |(key, offset, _)| (offset, key))
+            }),
+            None => Ok((8, Vec::new()))
+        };
+        // the index holds the key of the first record read, which later keys build on
+        let (position, last_key, error) = match seek_to.and_then(|(offset, key)| Ok((self.file.seek(SeekFrom::Start(offset))?, key))) { 
+            Ok((position, key)) => (position, key, None),
+            Err(err) => (self.data_end, Vec::new(), Some(err))
+        };
+        SSTRangeIter { 
+            file: &mut self.file,
+            prefix_encoded: self.prefix_encoded,
+            last_key,
+            start: start.to_vec(),
+            end: end.to_vec(),
+            position,
+            data_end: self.data_end,
+            comparator: Arc::clone(&self.comparator),
+            error
+        }
+    }
+
+    /**
+     * Reads back every indexed record and verifies its checksum.
+     * * Records are located through the index rather than by scanning, so a corrupted
+     *   length field only affects its own record.
+     * * # Returns
+     * * `Ok(Vec<Vec<u8>>)` with the keys of all corrupted records, empty if the file is intact.
+     */
+    pub fn validate_all(&mut self) -> std::io::Result<Vec<Vec<u8>>> { 
+        let mut corrupted = Vec::new();
+        let blocks: Vec<(u64, u64)> = self.meta_index.iter().map(|&(_, block)| block).collect();
+        for (block_offset, block_len) in blocks { 
+            for (key, offset, _) in self.read_index_block(block_offset, block_len)? { 
+                self.file.seek(SeekFrom::Start(offset))?;
+                match read_record(&mut self.file, self.prefix_encoded, &key) { 
+                    Ok((record_key, _, _)) if record_key == key => {},
+                    Ok(_) => corrupted.push(key),
+                    Err(err) if matches!(err.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof) => corrupted.push(key),
+                    Err(err) => return Err(err)
+                }
+            }
+        }
+        Ok(corrupted)
+    }
+}
+
+/**
+ * Reads one record at the current position of `file`, see `SSTWriter::write_all` for
+ * both layouts.
+ * * A prefix encoded key is completed from `prev_key`, the key of the record before or
+ *   the record's own key when it is already known from the index.
+ * * # Returns
+ * * `Ok((key, value, len))` with `len` the number of bytes the record takes up, and a
+ *   `None` value for a tombstone.
+ * * `Err(InvalidData)` if the stored checksum does not match the record.
+ */
+fn read_record<R: Read>(file: &mut R, prefix_encoded: bool, prev_key: &[u8]) -> std::io::Result<(Vec<u8>, Option<Vec<u8>>, u64)> { 
+    let mut hasher = Hasher::new();
+    let mut shared_buf = [0u8];
+    if prefix_encoded { 
+        file.read_exact(&mut shared_buf)?;
+        hasher.update(&shared_buf);
+    }
+    let mut klen_buf = [0u8; 4];
+    file.read_exact(&mut klen_buf)?;
+    let klen = u32::from_be_bytes(klen_buf);
+    let mut key_buf = vec![0u8; klen as usize];
+    file.read_exact(&mut key_buf)?;
+
+    let mut vlen_buf = [0u8; 4];
+    file.read_exact(&mut vlen_buf)?;
+    let mut value_buf = match u32::from_be_bytes(vlen_buf) { 
+        TOMBSTONE_LEN => None,
+        vlen => Some(vec![0u8; vlen as usize])
+    };
+    if let Some(value_buf) = value_buf.as_mut() { 
+        file.read_exact(value_buf)?;
+    }
+
+    let mut crc_buf = [0u8; 4];
+    file.read_exact(&mut crc_buf)?;
+    hasher.update(&klen_buf);
+    hasher.update(&key_buf);
+    hasher.update(&vlen_buf);
+    hasher.update(value_buf.as_deref().unwrap_or_default());
+    if hasher.finalize() != u32::from_be_bytes(crc_buf) { 
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "sst record checksum mismatch"));
+    }
+    let len = prefix_encoded as u64 + 12 + key_buf.len() as u64 + value_buf.as_ref().map_or(0, |v| v.len() as u64);
+    if shared_buf[0] > 0 { 
+        let prefix = prev_key.get(..shared_buf[0] as usize)
+            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "sst record shares more than the previous key"))?;
+        key_buf = [prefix, &key_buf].concat();
+    }
+    Ok((key_buf, value_buf, len))
+}
+
+/**
+ * Sequential iterator over the data block of an SSTable, see `SSTReader::iter`.
+ */
+pub struct SSTIter<'a> { 
+    file: &'a mut SSTSource,
+    remaining: u64,
+    prefix_encoded: bool,
+    last_key: Vec<u8>, // prefix encoded keys build on it
+    error: Option<std::io::Error>
+}
+
+impl<'a> SSTIter<'a> { 
+    /**
+     * Turns the iterator into one that yields tombstones too, with a `None` value.
+     */
+    pub fn with_tombstones(self) -> WithTombstones<Self> { 
+        WithTombstones(self)
+    }
+
+    fn next_entry(&mut self) -> Option<SSTEntry> { 
+        if let Some(err) = self.error.take() { 
+            self.remaining = 0;
+            return Some(Err(err));
+        }
+        if self.remaining == 0 { 
+            return None;
+        }
+        let record = read_record(self.file, self.prefix_encoded, &self.last_key);
+        self.remaining = if record.is_ok() { self.remaining - 1 } else { 0 };
+        Some(record.map(|(key, value, _)| { 
+            self.last_key.clone_from(&key);
+            (key, value)
+        }))
+    }
+}
+
+impl<'a> Iterator for SSTIter<'a> { 
+    type Item = std::io::Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> { 
+        next_live(|| self.next_entry())
+    }
+}
+
+/**
+ * Iterator over a key range of an SSTable, see `SSTReader::range_iter`.
+ */
+pub struct SSTRangeIter<'a> { 
+    file: &'a mut SSTSource,
+    prefix_encoded: bool,
+    last_key: Vec<u8>, // prefix encoded keys build on it
+    start: Vec<u8>,
+    end: Vec<u8>,
+    position: u64,
+    data_end: u64,
+    comparator: Arc<dyn Comparator>,
+    error: Option<std::io::Error>
+}
+
+impl<'a> SSTRangeIter<'a> { 
+    /**
+     * Turns the iterator into one that yields tombstones too, with a `None` value.
+     */
+    pub fn with_tombstones(self) -> WithTombstones<Self> { 
+        WithTombstones(self)
+    }
+
+    fn next_entry(&mut self) -> Option<SSTEntry> { 
+        if let Some(err) = self.error.take() { 
+            return Some(Err(err));
+        }
+        while self.position < self.data_end { 
+            let (key, value, len) = match read_record(self.file, self.prefix_encoded, &self.last_key) { 
+                Ok(record) => record,
+                Err(err) => { 
+                    self.position = self.data_end;
+                    return Some(Err(err));
+                }
+            };
+            self.position += len;
+            self.last_key.clone_from(&key);
+            if self.comparator.compare(&key, &self.end) != Ordering::Less { 
+                self.position = self.data_end;
+                return None;
+            }
+            if self.comparator.compare(&key, &self.start) != Ordering::Less { 
+                return Some(Ok((key, value)));
+            }
+        }
+        None
+    }
+}
+
+impl<'a> Iterator for SSTRangeIter<'a> { 
+    type Item = std::io::Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> { 
+        next_live(|| self.next_entry())
+    }
+}
+
+/**
+ * Iterator over the records of an SSTable with the tombstones left in, see
+ * `SSTIter::with_tombstones` and `SSTRangeIter::with_tombstones`.
+ */
+pub struct WithTombstones<I>(I);
+
+impl<'a> Iterator for WithTombstones<SSTIter<'a>> { 
+    type Item = SSTEntry;
+
+    fn next(&mut self) -> Option<Self::Item> { 
+        self.0.next_entry()
+    }
+}
+
+impl<'a> Iterator for WithTombstones<SSTRangeIter<'a>> { 
+    type Item = SSTEntry;
+
+    fn next(&mut self) -> Option<Self::Item> { 
+        self.0.next_entry()
+    }
+}
+
+/**
+ * Pulls entries from `next_entry` until one holds a value, skipping tombstones.
+ */
+fn next_live<F: FnMut() -> Option<SSTEntry>>(mut next_entry: F) -> Option<std::io::Result<(Vec<u8>, Vec<u8>)>> { 
+    while let Some(entry) = next_entry() { 
+        match entry { 
+            Ok((key, Some(value))) => return Some(Ok((key, value))),
+            Ok((_, None)) => {},
+            Err(err) => return Some(Err(err))
+        }
+    }
+    None
+}
+
+/**
+ * Smallest unmerged key of a reader, with its value, `None` for a tombstone, and the
+ * reader's index.
+ */
+type MergeHead = (Vec<u8>, Option<Vec<u8>>, usize);
+
+/**
+ * K-way merge of sorted SSTables into a single `SSTWriter`.
+ * * Keys are merged in byte order, so the tables must use the `LexicographicComparator`.
+ * * Readers are ordered oldest to newest: when several readers hold the same key,
+ *   the value from the reader with the highest index wins.
+ */
+pub struct SSTMerger<'a> { 
+    heap: BinaryHeap<Reverse<MergeHead>>,
+    iters: Vec<WithTombstones<SSTIter<'a>>>,
+    error: Option<std::io::Error>
+}
+
+impl<'a> SSTMerger<'a> { 
+    /**
+     * Primes the heap with the first entry of every reader.
+     * * A read error is held back and returned by `merge_into`.
+     */
+    pub fn new(readers: Vec<&'a mut SSTReader>) -> Self { 
+        let mut merger = Self { 
+            heap: BinaryHeap::new(),
+            iters: readers.into_iter().map(|reader| reader.iter().with_tombstones()).collect(),
+            error: None
+        };
+        for reader_idx in 0..merger.iters.len() { 
+            merger.advance(reader_idx);
+        }
+        merger
+    }
+
+    fn advance(&mut self, reader_idx: usize) { 
+        match self.iters[reader_idx].next() { 
+            Some(Ok((key, value))) => self.heap.push(Reverse((key, value, reader_idx))),
+            Some(Err(err)) => { 
+                self.error.get_or_insert(err);
+            },
+            None => {}
+        }
+    }
+
+    /**
+     * Drains every reader into `writer` in key order, writing each key once. A key whose
+     * newest record is a tombstone is written as a tombstone, it may still hide the key
+     * in tables older than the merged ones.
+     * * # Returns
+     * * `Ok(usize)` with the number of unique keys written.
+     * * `Err` on the first read or write error.
+     */
+    pub fn merge_into(&mut self, writer: &mut SSTWriter) -> std::io::Result<usize> { 
+        self.merge_each(|key, value| writer.write_entries(std::iter::once((key, value))))
+    }
+
+    /**
+     * Drains every reader in key order, handing each key once, with its newest value,
+     * to `emit`. The value is `None` when the newest record is a tombstone, a caller
+     * merging down to the oldest table can drop those. Lets a caller split the output
+     * across several writers.
+     * * # Returns
+     * * `Ok(usize)` with the number of unique keys emitted.
+     * * `Err` on the first read error or error returned by `emit`.
+     */
+    pub fn merge_each<F>(&mut self, mut emit: F) -> std::io::Result<usize> 
+    where 
+        F: FnMut(Vec<u8>, Option<Vec<u8>>) -> std::io::Result<()>
+    { 
+        let mut written = 0;
+        while let Some(Reverse((key, mut value, reader_idx))) = self.heap.pop() { 
+            self.advance(reader_idx);
+            let mut newest = reader_idx;
+            while self.heap.peek().is_some_and(|Reverse((next, _, _))| *next == key) { 
+                let Reverse((_, next_value, next_idx)) = self.heap.pop().unwrap();
+                self.advance(next_idx);
+                if next_idx > newest { 
+                    newest = next_idx;
+                    value = next_value;
+                }
+            }
+            if let Some(err) = self.error.take() { 
+                return Err(err);
+            }
+            emit(key, value)?;
+            written += 1;
+        }
+        match self.error.take() { 
+            Some(err) => Err(err),
+            None => Ok(written)
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use std::{fs::{read, remove_file, write}, io::ErrorKind, num::NonZeroUsize, path::PathBuf, sync::Arc};
+
+use crate::{comparator::Comparator, sst::{BlockCache, KeyState, SSTConfig, SSTMerger, SSTReader, SSTWriter, prefix_encode}};
+
+fn sample_entries(n: usize) -> Vec<(Vec<u8>, Vec<u8>)> { 
+    (0..n).map(|i| (format!("key-{:06}", i).into_bytes(), format!("val-{}", i).into_bytes())).collect()
+}
+
+#[test]
+pub fn sst_test_iter_returns_written_entries_in_order() { 
+    let path = PathBuf::from("./temp-sst-iter.dat");
+    let entries = sample_entries(100);
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+
+    let mut reader = SSTReader::open(&path).expect("can not open reader");
+    assert_eq!(reader.entry_count(), entries.len() as u64);
+    let read: Vec<_> = reader.iter().collect::<std::io::Result<_>>().expect("iter failed");
+    assert_eq!(read, entries);
+    // point lookups still work after the iterator moved the file cursor
+    assert_eq!(reader.get(b"key-000042").unwrap(), Some(b"val-42".to_vec()));
+    assert_eq!(reader.iter().count(), entries.len());
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_flipped_byte_fails_checksum() { 
+    let path = PathBuf::from("./temp-sst-crc.dat");
+    let entries = sample_entries(10);
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+
+    // header (8), the first record with its full key in 1 + 4 + 10 + 4 + 5 + 4 bytes, the
+    // next two sharing 9 key bytes in 1 + 4 + 1 + 4 + 5 + 4, then into the value of key-000003
+    let mut bytes = read(&path).unwrap();
+    let value_at = 8 + 28 + 2 * 19 + 1 + 4 + 1 + 4;
+    bytes[value_at] ^= 0xff;
+    write(&path, bytes).unwrap();
+
+    let mut reader = SSTReader::open(&path).expect("can not open reader");
+    let err = reader.get(b"key-000003").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(reader.get(b"key-000004").unwrap(), Some(b"val-4".to_vec()));
+    assert_eq!(reader.validate_all().unwrap(), vec![b"key-000003".to_vec()]);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_key_range_in_footer() { 
+    let path = PathBuf::from("./temp-sst-range.dat");
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (b'a'..=b'm').map(|c| (vec![c], vec![c; 3])).collect();
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.write_all(entries).expect("write failed");
+    writer.close().expect("close failed");
+
+    let mut reader = SSTReader::open(&path).expect("can not open reader");
+    assert_eq!(reader.min_key(), b"a");
+    assert_eq!(reader.max_key(), b"m");
+    let key: &[u8] = b"z";
+    assert!(key > reader.max_key(), "a lookup of z must skip this table");
+    assert_eq!(reader.get(b"g").unwrap(), Some(b"ggg".to_vec()));
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_unreadable_until_closed() { 
+    let path = PathBuf::from("./temp-sst-close.dat");
+    let entries = sample_entries(50);
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.write_all(entries.clone()).expect("write failed");
+    assert!(SSTReader::open(&path).is_err());
+
+    writer.close().expect("close failed");
+    let mut reader = SSTReader::open(&path).expect("can not open reader");
+    let read: Vec<_> = reader.iter().collect::<std::io::Result<_>>().expect("iter failed");
+    assert_eq!(read, entries);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_write_from_iter() { 
+    let path = PathBuf::from("./temp-sst-from-iter.dat");
+    let entries = sample_entries(1_000);
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.write_from_iter(entries.clone().into_iter()).expect("write failed");
+    let err = writer.write_from_iter(sample_entries(1).into_iter()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    // a repeated key is rejected too
+    let err = writer.write_all(vec![entries[999].clone()]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    writer.close().expect("close failed");
+
+    let mut reader = SSTReader::open(&path).expect("can not open reader");
+    let read: Vec<_> = reader.iter().collect::<std::io::Result<_>>().expect("iter failed");
+    assert_eq!(read, entries);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_two_level_index() { 
+    for n in [1, 100, 100_000] { 
+        let path = PathBuf::from(format!("./temp-sst-two-level-{}.dat", n));
+        let entries = sample_entries(n);
+        let mut writer = SSTWriter::open_with_config(&path, SSTConfig { index_block_size: 16, ..Default::default() }).expect("can not open writer");
+        writer.write_all(entries.clone()).expect("write failed");
+        writer.close().expect("close failed");
+
+        let mut reader = SSTReader::open(&path).expect("can not open reader");
+        let step = (n / 1_000).max(1);
+        for (key, value) in entries.iter().step_by(step).chain(entries.last()) { 
+            assert_eq!(reader.get(key).unwrap().as_ref(), Some(value));
+        }
+        assert_eq!(reader.get(b"key-").unwrap(), None);
+        assert_eq!(reader.get(b"key-000000x").unwrap(), None);
+        assert_eq!(reader.get(b"zzz").unwrap(), None);
+        assert!(reader.validate_all().unwrap().is_empty());
+        let _ = remove_file(&path);
+    }
+}
+
+#[test]
+pub fn sst_test_estimated_output_size() { 
+    let path = PathBuf::from("./temp-sst-estimate.dat");
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..5_000)
+        .map(|i| (format!("key-{}", i * 7).into_bytes(), vec![b'v'; i % 64]))
+        .collect::<std::collections::BTreeMap<_, _>>()
+        .into_iter()
+        .collect();
+    let estimate = SSTWriter::estimated_output_size(&entries);
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.write_all(entries).expect("write failed");
+    writer.close().expect("close failed");
+
+    let reader = SSTReader::open(&path).expect("can not open reader");
+    let actual = reader.approx_size_bytes();
+    assert!(estimate.abs_diff(actual) * 20 <= actual, "estimate {} vs actual {}", estimate, actual);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_mmap_reader_matches_file_reader() { 
+    let path = PathBuf::from("./temp-sst-mmap.dat");
+    let entries = sample_entries(2_000);
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+
+    let mut file_reader = SSTReader::open(&path).expect("can not open reader");
+    let mut mmap_reader = SSTReader::open_mmap(&path).expect("can not map reader");
+    assert_eq!(mmap_reader.min_key(), file_reader.min_key());
+    assert_eq!(mmap_reader.max_key(), file_reader.max_key());
+    assert_eq!(mmap_reader.approx_size_bytes(), file_reader.approx_size_bytes());
+    for (key, _) in entries.iter().step_by(13) { 
+        assert_eq!(mmap_reader.get(key).unwrap(), file_reader.get(key).unwrap());
+    }
+    assert_eq!(mmap_reader.get(b"missing").unwrap(), None);
+    let read: Vec<_> = mmap_reader.iter().collect::<std::io::Result<_>>().expect("iter failed");
+    assert_eq!(read, entries);
+    assert!(mmap_reader.validate_all().unwrap().is_empty());
+    drop(mmap_reader);
+    let _ = remove_file(&path);
+}
+
+/**
+ * Throughput of file vs mmap backed readers, run with
+ * `cargo test --release sst_bench -- --ignored --nocapture`.
+ */
+#[test]
+#[ignore]
+pub fn sst_bench_mmap_vs_file_reads() { 
+    let path = PathBuf::from("./temp-sst-bench.dat");
+    let entries = sample_entries(200_000);
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+
+    // a fixed LCG walk so both readers see the same random access pattern
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let random: Vec<usize> = (0..entries.len()).map(|_| { 
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as usize % entries.len()
+    }).collect();
+    for (name, mut reader) in [("file", SSTReader::open(&path).unwrap()), ("mmap", SSTReader::open_mmap(&path).unwrap())] { 
+        let started = std::time::Instant::now();
+        let scanned = reader.iter().count();
+        let sequential = started.elapsed();
+        let started = std::time::Instant::now();
+        for &i in &random { 
+            assert!(reader.get(&entries[i].0).unwrap().is_some());
+        }
+        let random_elapsed = started.elapsed();
+        println!("{name}: sequential {:.0} records/s, random {:.0} gets/s",
+            scanned as f64 / sequential.as_secs_f64(),
+            random.len() as f64 / random_elapsed.as_secs_f64());
+    }
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_range_iter() { 
+    let path = PathBuf::from("./temp-sst-range-iter.dat");
+    // even numbers only, so odd bounds are absent from the file
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..500).map(|i| (format!("key-{:06}", i * 2).into_bytes(), format!("val-{}", i).into_bytes())).collect();
+    let mut writer = SSTWriter::open_with_config(&path, SSTConfig { index_block_size: 16, ..Default::default() }).expect("can not open writer");
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+
+    let mut reader = SSTReader::open(&path).expect("can not open reader");
+    let bounds: [(&[u8], &[u8]); 6] = [
+        (b"key-000030", b"key-000034"), // straddles the 16th key
+        (b"key-000029", b"key-000097"), // both bounds absent, several blocks
+        (b"key-000032", b"key-000032"), // empty range
+        (b"a", b"key-000004"),          // starts before the first key
+        (b"key-000990", b"z"),          // ends after the last key
+        (b"key-000999", b"z"),          // starts after the last key
+    ];
+    for (start, end) in bounds { 
+        let expected: Vec<_> = entries.iter().filter(|(k, _)| k.as_slice() >= start && k.as_slice() < end).cloned().collect();
+        let read: Vec<_> = reader.range_iter(start, end).collect::<std::io::Result<_>>().expect("range iter failed");
+        assert_eq!(read, expected, "range {:?}..{:?}", String::from_utf8_lossy(start), String::from_utf8_lossy(end));
+    }
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_merger_dedups_newest_wins() { 
+    let paths: Vec<PathBuf> = (0..4).map(|i| PathBuf::from(format!("./temp-sst-merge-{}.dat", i))).collect();
+    // reader i holds every key divisible by i + 1, valued with its own index
+    for (i, path) in paths[..3].iter().enumerate() { 
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..60).filter(|k| k % (i + 1) == 0).map(|k| (format!("key-{:03}", k).into_bytes(), vec![i as u8])).collect();
+        let mut writer = SSTWriter::open(path).expect("can not open writer");
+        writer.write_all(entries).expect("write failed");
+        writer.close().expect("close failed");
+    }
+    let mut readers: Vec<SSTReader> = paths[..3].iter().map(|path| SSTReader::open(path).expect("can not open reader")).collect();
+    let mut writer = SSTWriter::open(&paths[3]).expect("can not open writer");
+    let written = SSTMerger::new(readers.iter_mut().collect()).merge_into(&mut writer).expect("merge failed");
+    writer.close().expect("close failed");
+    assert_eq!(written, 60);
+
+    let mut merged = SSTReader::open(&paths[3]).expect("can not open reader");
+    let read: Vec<_> = merged.iter().collect::<std::io::Result<_>>().expect("iter failed");
+    let expected: Vec<(Vec<u8>, Vec<u8>)> = (0..60).map(|k| { 
+        let newest = if k % 3 == 0 { 2 } else if k % 2 == 0 { 1 } else { 0 };
+        (format!("key-{:03}", k).into_bytes(), vec![newest])
+    }).collect();
+    assert_eq!(read, expected);
+    for path in paths { 
+        let _ = remove_file(path);
+    }
+}
+
+#[test]
+pub fn sst_test_tombstones_hide_keys_and_survive_merges() { 
+    let paths: Vec<PathBuf> = (0..3).map(|i| PathBuf::from(format!("./temp-sst-tombstone-{}.dat", i))).collect();
+    let config = SSTConfig { index_block_size: 4, ..SSTConfig::default() };
+    // every third key is deleted
+    let entries: Vec<(Vec<u8>, Option<Vec<u8>>)> = (0..30)
+        .map(|i| (format!("key-{:03}", i).into_bytes(), (i % 3 != 0).then(|| format!("val-{i}").into_bytes())))
+        .collect();
+    let mut writer = SSTWriter::open_with_config(&paths[0], config.clone()).expect("can not open writer");
+    writer.write_entries(entries.clone().into_iter()).expect("write failed");
+    writer.close().expect("close failed");
+
+    let mut reader = SSTReader::open(&paths[0]).expect("can not open reader");
+    assert_eq!(reader.entry_count(), 30);
+    assert_eq!(reader.get_entry(b"key-003").unwrap(), Some(None));
+    assert_eq!(reader.get_entry(b"key-004").unwrap(), Some(Some(b"val-4".to_vec())));
+    assert_eq!(reader.get_entry(b"key-999").unwrap(), None);
+    assert_eq!(reader.get(b"key-003").unwrap(), None);
+    assert_eq!(reader.key_state(b"key-003").unwrap(), KeyState::Deleted);
+    assert_eq!(reader.key_state(b"key-004").unwrap(), KeyState::Live);
+    assert_eq!(reader.key_state(b"key-999").unwrap(), KeyState::Absent);
+    assert!(!reader.key_exists(b"key-003").unwrap());
+    let live: Vec<(Vec<u8>, Vec<u8>)> = entries.iter().filter_map(|(k, v)| Some((k.clone(), v.clone()?))).collect();
+    let read: Vec<_> = reader.iter().collect::<std::io::Result<_>>().expect("iter failed");
+    assert_eq!(read, live);
+    let read: Vec<_> = reader.iter().with_tombstones().collect::<std::io::Result<_>>().expect("iter failed");
+    assert_eq!(read, entries);
+    assert_eq!(reader.range_iter(b"key-003", b"key-007").count(), 2);
+    assert_eq!(reader.range_iter(b"key-003", b"key-007").with_tombstones().count(), 4);
+    assert_eq!(reader.keys_in_range(b"key-000", b"key-010").unwrap().len(), 6);
+    assert_eq!(reader.key_states_in_range(b"key-000", b"key-010").unwrap().len(), 10);
+    assert_eq!(reader.prefix_count(b"key-0").unwrap(), 20);
+    assert!(reader.validate_all().unwrap().is_empty());
+
+    // a newer table puts some of the deleted keys back, the merge keeps the other tombstones
+    let mut writer = SSTWriter::open(&paths[1]).expect("can not open writer");
+    writer.write_all(vec![(b"key-003".to_vec(), b"back".to_vec())]).expect("write failed");
+    writer.close().expect("close failed");
+    let mut readers = vec![reader, SSTReader::open(&paths[1]).expect("can not open reader")];
+    let mut writer = SSTWriter::open(&paths[2]).expect("can not open writer");
+    let written = SSTMerger::new(readers.iter_mut().collect()).merge_into(&mut writer).expect("merge failed");
+    writer.close().expect("close failed");
+    assert_eq!(written, 30);
+    let mut merged = SSTReader::open(&paths[2]).expect("can not open reader");
+    assert_eq!(merged.get(b"key-003").unwrap(), Some(b"back".to_vec()));
+    assert_eq!(merged.get_entry(b"key-006").unwrap(), Some(None));
+    assert_eq!(merged.iter().count(), 21);
+    drop(readers);
+    for path in paths { 
+        let _ = remove_file(path);
+    }
+}
+
+/**
+ * Orders keys from largest to smallest byte string.
+ */
+pub struct ReverseComparator;
+
+impl Comparator for ReverseComparator { 
+    fn compare(&self, a: &[u8], b: &[u8]) -> std::cmp::Ordering { 
+        b.cmp(a)
+    }
+}
+
+#[test]
+pub fn sst_test_custom_comparator_orders_index_and_ranges() { 
+    let path = PathBuf::from("./temp-sst-reverse.dat");
+    let mut entries = sample_entries(100);
+    entries.reverse();
+    let config = SSTConfig { index_block_size: 16, comparator: Arc::new(ReverseComparator) };
+
+    let mut writer = SSTWriter::open_with_config(&path, SSTConfig::default()).expect("can not open writer");
+    let err = writer.write_all(entries.clone()).expect_err("descending keys accepted in byte order");
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let mut writer = SSTWriter::open_with_config(&path, config.clone()).expect("can not open writer");
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+
+    let mut reader = SSTReader::open_with_comparator(&path, config.comparator).expect("can not open reader");
+    for (key, value) in &entries { 
+        assert_eq!(reader.get(key).expect("get failed").as_ref(), Some(value));
+    }
+    assert_eq!(reader.get(b"key-999999").expect("get failed"), None);
+    let range: Vec<_> = reader.range_iter(b"key-000050", b"key-000040").collect::<std::io::Result<_>>().expect("range failed");
+    let keys: Vec<_> = range.iter().map(|(k, _)| String::from_utf8(k.clone()).unwrap()).collect();
+    assert_eq!(keys, (41..=50).rev().map(|i| format!("key-{:06}", i)).collect::<Vec<_>>());
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_prefix_count_reads_only_the_index() { 
+    let path = PathBuf::from("./temp-sst-prefix-count.dat");
+    let entries = sample_entries(300);
+    let mut writer = SSTWriter::open_with_config(&path, SSTConfig { index_block_size: 7, ..SSTConfig::default() }).expect("can not open writer");
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+    let mut reader = SSTReader::open(&path).expect("can not open reader");
+    for prefix in [&b""[..], b"key-", b"key-0000", b"key-00001", b"key-00012", b"key-000299", b"key-1", b"a", b"z"] { 
+        let expected = entries.iter().filter(|(key, _)| key.starts_with(prefix)).count();
+        assert_eq!(reader.prefix_count(prefix).unwrap(), expected, "prefix {:?}", String::from_utf8_lossy(prefix));
+    }
+    let _ = remove_file(&path);
+
+    let mut reversed = entries;
+    reversed.reverse();
+    let config = SSTConfig { index_block_size: 7, comparator: Arc::new(ReverseComparator) };
+    let mut writer = SSTWriter::open_with_config(&path, config).expect("can not open writer");
+    writer.write_all(reversed).expect("write failed");
+    writer.close().expect("close failed");
+    let mut reader = SSTReader::open_with_comparator(&path, Arc::new(ReverseComparator)).expect("can not open reader");
+    assert_eq!(reader.prefix_count(b"key-00012").unwrap(), 10);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_key_exists_agrees_with_get() { 
+    let path = PathBuf::from("./temp-sst-key-exists.dat");
+    // every other key, so misses fall inside the key range as well as outside of it
+    let entries: Vec<_> = sample_entries(400).into_iter().step_by(2).collect();
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.write_all(entries).expect("write failed");
+    writer.close().expect("close failed");
+
+    let mut reader = SSTReader::open(&path).expect("can not open reader");
+    for i in 0..410 { 
+        let key = format!("key-{:06}", i).into_bytes();
+        assert_eq!(reader.key_exists(&key).unwrap(), reader.get(&key).unwrap().is_some(), "key {i}");
+    }
+    assert!(!reader.key_exists(b"a").unwrap());
+    let _ = remove_file(&path);
+}
+
+/**
+ * Writes `entries` in the layout used before keys were prefix encoded: full keys in the
+ * records, no flag in the header, a single index block.
+ */
+fn write_legacy_sst(path: &PathBuf, entries: &[(Vec<u8>, Vec<u8>)]) { 
+    let mut bytes = (entries.len() as u64).to_be_bytes().to_vec();
+    let mut index = Vec::new();
+    for (key, value) in entries { 
+        index.extend((key.len() as u32).to_be_bytes());
+        index.extend(key);
+        index.extend((bytes.len() as u64).to_be_bytes());
+        let mut record = (key.len() as u32).to_be_bytes().to_vec();
+        record.extend(key);
+        record.extend((value.len() as u32).to_be_bytes());
+        record.extend(value);
+        bytes.extend(&record);
+        bytes.extend(crc32fast::hash(&record).to_be_bytes());
+    }
+    let index_offset = bytes.len() as u64;
+    bytes.extend(&index);
+    let meta_index_offset = bytes.len() as u64;
+    let first = &entries[0].0;
+    bytes.extend((first.len() as u32).to_be_bytes());
+    bytes.extend(first);
+    bytes.extend(index_offset.to_be_bytes());
+    bytes.extend((index.len() as u64).to_be_bytes());
+    for key in [first, &entries[entries.len() - 1].0] { 
+        bytes.extend((key.len() as u32).to_be_bytes());
+        bytes.extend(key);
+    }
+    bytes.extend(meta_index_offset.to_be_bytes());
+    bytes.extend(1u64.to_be_bytes());
+    write(path, bytes).unwrap();
+}
+
+#[test]
+pub fn sst_test_prefix_encoded_keys_round_trip_and_shrink_the_table() { 
+    let path = PathBuf::from("./temp-sst-prefix.dat");
+    let legacy_path = PathBuf::from("./temp-sst-prefix-legacy.dat");
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..1000)
+        .map(|i| (format!("user:{:07}", i).into_bytes(), format!("v{}", i).into_bytes()))
+        .collect();
+    assert_eq!(prefix_encode(b"user:0000001", b"user:0000002"), (11, &b"2"[..]));
+    assert_eq!(prefix_encode(b"", b"user:0000002"), (0, &b"user:0000002"[..]));
+    let mut writer = SSTWriter::open_with_config(&path, SSTConfig { index_block_size: 64, ..SSTConfig::default() }).expect("can not open writer");
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+    write_legacy_sst(&legacy_path, &entries);
+
+    // tables written before the encoding read back the same
+    for path in [&path, &legacy_path] { 
+        let mut reader = SSTReader::open(path).expect("can not open reader");
+        let read: Vec<_> = reader.iter().collect::<std::io::Result<_>>().expect("iter failed");
+        assert_eq!(read, entries);
+        for i in [0, 1, 63, 64, 65, 500, 999] { 
+            assert_eq!(reader.get(&entries[i].0).unwrap(), Some(entries[i].1.clone()));
+        }
+        assert_eq!(reader.get(b"user:0001000").unwrap(), None);
+        let range: Vec<_> = reader.range_iter(b"user:0000060", b"user:0000070").collect::<std::io::Result<_>>().expect("range failed");
+        assert_eq!(range, entries[60..70]);
+        assert!(reader.validate_all().unwrap().is_empty());
+    }
+    let size = read(&path).unwrap().len();
+    let legacy_size = read(&legacy_path).unwrap().len();
+    assert!(size * 10 < legacy_size * 9, "{} vs {} bytes", size, legacy_size);
+    let _ = remove_file(&path);
+    let _ = remove_file(&legacy_path);
+}
+
+#[test]
+pub fn sst_test_properties_round_trip() { 
+    let path = PathBuf::from("./temp-sst-properties.dat");
+    let legacy_path = PathBuf::from("./temp-sst-properties-legacy.dat");
+    let entries = sample_entries(300);
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.set_property("created_at", 1_700_000_000u64.to_be_bytes().to_vec());
+    writer.set_property("region", b"region-7".to_vec());
+    writer.set_property("schema", b"1".to_vec());
+    writer.set_property("schema", b"2".to_vec());
+    writer.set_property("empty", Vec::new());
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+    write_legacy_sst(&legacy_path, &entries);
+
+    for mut reader in [SSTReader::open(&path).unwrap(), SSTReader::open_mmap(&path).unwrap()] { 
+        assert_eq!(reader.property("created_at"), Some(&1_700_000_000u64.to_be_bytes()[..]));
+        assert_eq!(reader.property("region"), Some(&b"region-7"[..]));
+        assert_eq!(reader.property("schema"), Some(&b"2"[..]));
+        assert_eq!(reader.property("empty"), Some(&b""[..]));
+        assert_eq!(reader.property("missing"), None);
+        // the block sits between the data and the index and must not be read as records
+        let read: Vec<_> = reader.iter().collect::<std::io::Result<_>>().expect("iter failed");
+        assert_eq!(read, entries);
+        assert!(reader.validate_all().unwrap().is_empty());
+    }
+    let legacy = SSTReader::open(&legacy_path).unwrap();
+    assert_eq!(legacy.property("region"), None);
+    let _ = remove_file(&path);
+    let _ = remove_file(&legacy_path);
+}
+
+#[test]
+pub fn sst_test_block_cache_serves_repeated_gets() { 
+    let path = PathBuf::from("./temp-sst-block-cache.dat");
+    let entries = sample_entries(300);
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+    let cache = Arc::new(BlockCache::new(NonZeroUsize::new(16).unwrap()));
+    let mut reader = SSTReader::open(&path).unwrap().with_block_cache(Arc::clone(&cache));
+
+    assert_eq!(reader.get(&entries[42].0).unwrap(), Some(entries[42].1.clone()));
+    // one miss for the index block, one for the record
+    assert_eq!(cache.misses(), 2);
+    // the record is gone from disk, the second lookup must not read it
+    let mut bytes = read(&path).unwrap();
+    let value_at = bytes.windows(entries[42].1.len()).position(|window| window == entries[42].1).unwrap();
+    bytes[value_at] ^= 0xff;
+    write(&path, bytes).unwrap();
+    assert_eq!(reader.get(&entries[42].0).unwrap(), Some(entries[42].1.clone()));
+    assert_eq!(cache.misses(), 2);
+    // a neighbour shares the cached index block
+    assert!(reader.get(&entries[43].0).unwrap().is_some());
+    assert_eq!(cache.misses(), 3);
+
+    cache.evict_file(&path);
+    assert_eq!(reader.get(&entries[42].0).unwrap_err().kind(), ErrorKind::InvalidData);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn sst_test_entry_count_and_data_bytes_without_opening() { 
+    let path = PathBuf::from("./temp-sst-approx.dat");
+    let legacy_path = PathBuf::from("./temp-sst-approx-legacy.dat");
+    // keys share no prefix, so every record stores its key in full
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (1..=200u8).map(|i| (vec![i; 3], vec![b'v'; i as usize % 7])).collect();
+    let mut writer = SSTWriter::open(&path).expect("can not open writer");
+    writer.set_property("region", b"region-7".to_vec());
+    writer.write_all(entries.clone()).expect("write failed");
+    writer.close().expect("close failed");
+    write_legacy_sst(&legacy_path, &entries);
+
+    let values: u64 = entries.iter().map(|(_, value)| value.len() as u64).sum();
+    assert_eq!(SSTReader::approximate_entry_count(&path).unwrap(), 200);
+    // [Shared (1B)][KeyLen (4B)][Key][ValLen (4B)][Value][CRC32 (4B)]
+    assert_eq!(SSTReader::approximate_data_bytes(&path).unwrap(), 200 * (1 + 4 + 3 + 4 + 4) + values);
+    assert_eq!(SSTReader::approximate_entry_count(&legacy_path).unwrap(), 200);
+    assert_eq!(SSTReader::approximate_data_bytes(&legacy_path).unwrap(), 200 * (4 + 3 + 4 + 4) + values);
+    assert_eq!(SSTReader::open(&path).unwrap().entry_count(), 200);
+
+    let unfinished = SSTWriter::open(&path).expect("can not open writer");
+    drop(unfinished);
+    assert_eq!(SSTReader::approximate_entry_count(&path).unwrap_err().kind(), ErrorKind::InvalidData);
+    let _ = remove_file(&path);
+    let _ = remove_file(&legacy_path);
+}
@@ -0,0 +1,132 @@
+use std::{cell::RefCell, collections::{BTreeMap, HashSet}, fmt, io, sync::Mutex};
+
+use crate::engine::Engine;
+
+/**
+ * Why `Transaction::commit` did not apply a transaction.
+ */
+#[derive(Debug)]
+pub enum TxError { 
+    /**
+     * `key` was read or written by the transaction and written by someone else after
+     * the transaction began. Nothing was applied, the transaction can be retried.
+     */
+    Conflict { key: Vec<u8> },
+    Io(io::Error)
+}
+
+impl fmt::Display for TxError { 
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { 
+        match self { 
+            Self::Conflict { key } => write!(f, "transaction conflict on key {}", String::from_utf8_lossy(key)),
+            Self::Io(err) => write!(f, "transaction failed: {}", err)
+        }
+    }
+}
+
+impl std::error::Error for TxError { 
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> { 
+        match self { 
+            Self::Io(err) => Some(err),
+            Self::Conflict { .. } => None
+        }
+    }
+}
+
+impl From<io::Error> for TxError { 
+    fn from(err: io::Error) -> Self { 
+        Self::Io(err)
+    }
+}
+
+/**
+ * Optimistic transaction over an engine shared behind a mutex, started with
+ * `Engine::begin_transaction`.
+ * * Writes are buffered in the transaction and reads see them first. Every key the
+ *   transaction reads or writes is validated on `commit`: if any of them was written after
+ *   the transaction began, by another transaction or a plain `put`, the commit fails with
+ *   `TxError::Conflict` and nothing is applied. A committed transaction therefore only ever
+ *   observed the engine as it was when it began.
+ * * The engine lock is only held for a single read and for the commit, so transactions
+ *   on different threads run concurrently. The batch is applied under the lock, no one sees
+ *   half of it, but it is logged record by record: a crash during the commit may leave a
+ *   prefix of the batch in the WAL.
+ * * Dropping a transaction without committing discards its writes.
+ */
+pub struct Transaction<'a> { 
+    engine: &'a Mutex<Engine>,
+    start: u64,
+    reads: RefCell<HashSet<Vec<u8>>>,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>, // `None` deletes the key
+    finished: bool
+}
+
+impl<'a> Transaction<'a> { 
+    pub(crate) fn begin(engine: &'a Mutex<Engine>) -> Self { 
+        let start = engine.lock().unwrap().register_transaction();
+        Self { 
+            engine,
+            start,
+            reads: RefCell::new(HashSet::new()),
+            writes: BTreeMap::new(),
+            finished: false
+        }
+    }
+
+    /**
+     * Value of `key`, the transaction's own writes taking precedence over the engine.
+     */
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> { 
+        if let Some(write) = self.writes.get(key) { 
+            return Ok(write.clone());
+        }
+        self.reads.borrow_mut().insert(key.to_vec());
+        self.engine.lock().unwrap().get(key)
+    }
+
+    pub fn put(&mut self, key: &[u8], val: &[u8]) { 
+        self.writes.insert(key.to_vec(), Some(val.to_vec()));
+    }
+
+    pub fn delete(&mut self, key: &[u8]) { 
+        self.writes.insert(key.to_vec(), None);
+    }
+
+    /**
+     * Validates the keys the transaction touched and applies its writes.
+     * * # Returns
+     * * `Ok(())` if the writes were applied.
+     * * `Err(TxError::Conflict)` naming a key written since the transaction began.
+     * * `Err(TxError::Io)` if a write failed; the writes before it stay applied.
+     */
+    pub fn commit(mut self) -> Result<(), TxError> { 
+        let mut engine = self.engine.lock().unwrap();
+        self.finished = true;
+        let conflict = self.reads.borrow().iter()
+            .chain(self.writes.keys())
+            .find(|key| engine.written_since(key, self.start))
+            .cloned();
+        engine.finish_transaction(self.start);
+        if let Some(key) = conflict { 
+            return Err(TxError::Conflict { key });
+        }
+        for (key, write) in std::mem::take(&mut self.writes) { 
+            match write { 
+                Some(val) => engine.put(&key, &val)?,
+                None => engine.delete(&key)?
+            };
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transaction<'_> { 
+    fn drop(&mut self) { 
+        if !self.finished { 
+            // a poisoned engine has no transactions left to validate against
+            if let Ok(mut engine) = self.engine.lock() { 
+                engine.finish_transaction(self.start);
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::{path::PathBuf, sync::Mutex, thread};
+
+use crate::{engine::{Config, Engine, EngineMode}, txn::TxError};
+
+fn open_engine(name: &str) -> (PathBuf, Mutex<Engine>) { 
+    let dir = PathBuf::from(format!("./temp-txn-{name}"));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config { dir: dir.clone(), memtable_max_bytes: 1 << 20, max_wal_bytes: None, mode: EngineMode::Persistent, block_cache_capacity: 0, data_dirs: Vec::new() };
+    (dir, Mutex::new(Engine::open(config).expect("can not open engine")))
+}
+
+#[test]
+pub fn txn_test_concurrent_writers_of_one_key_commit_at_most_once() { 
+    let (dir, engine) = open_engine("conflict");
+    engine.lock().unwrap().put(b"counter", b"0").expect("put the value");
+    let mut first = Engine::begin_transaction(&engine);
+    let mut second = Engine::begin_transaction(&engine);
+    for txn in [&mut first, &mut second] { 
+        let value = txn.get(b"counter").expect("get failed").unwrap();
+        txn.put(b"counter", &[value, b"+1".to_vec()].concat());
+    }
+    // the second transaction sees its own write, not the first one's
+    assert_eq!(second.get(b"counter").unwrap(), Some(b"0+1".to_vec()));
+    first.commit().expect("first commit failed");
+    match second.commit() { 
+        Err(TxError::Conflict { key }) => assert_eq!(key, b"counter"),
+        other => panic!("second commit was not rejected: {other:?}")
+    }
+    assert_eq!(engine.lock().unwrap().get(b"counter").unwrap(), Some(b"0+1".to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn txn_test_plain_writes_conflict_and_dropped_transactions_apply_nothing() { 
+    let (dir, engine) = open_engine("plain");
+    let mut txn = Engine::begin_transaction(&engine);
+    assert_eq!(txn.get(b"k1").unwrap(), None);
+    txn.put(b"k2", b"from-txn");
+    engine.lock().unwrap().put(b"k1", b"outside").expect("put the value");
+    assert!(matches!(txn.commit(), Err(TxError::Conflict { .. })));
+    assert_eq!(engine.lock().unwrap().get(b"k2").unwrap(), None);
+
+    let mut txn = Engine::begin_transaction(&engine);
+    txn.delete(b"k1");
+    drop(txn);
+    let mut txn = Engine::begin_transaction(&engine);
+    assert_eq!(txn.get(b"k1").unwrap(), Some(b"outside".to_vec()));
+    txn.delete(b"k1");
+    txn.put(b"k2", b"from-txn");
+    txn.commit().expect("commit failed");
+    let mut engine = engine.into_inner().unwrap();
+    assert_eq!(engine.get(b"k1").unwrap(), None);
+    assert_eq!(engine.get(b"k2").unwrap(), Some(b"from-txn".to_vec()));
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+pub fn txn_test_retried_increments_are_serializable() { 
+    let (dir, engine) = open_engine("increments");
+    engine.lock().unwrap().put(b"counter", &0u64.to_be_bytes()).expect("put the value");
+    let (threads, increments) = (4, 50);
+    thread::scope(|scope| { 
+        for _ in 0..threads { 
+            scope.spawn(|| { 
+                for _ in 0..increments { 
+                    loop { 
+                        let mut txn = Engine::begin_transaction(&engine);
+                        let value = txn.get(b"counter").unwrap().unwrap();
+                        let next = u64::from_be_bytes(value.try_into().unwrap()) + 1;
+                        txn.put(b"counter", &next.to_be_bytes());
+                        match txn.commit() { 
+                            Ok(()) => break,
+                            Err(TxError::Conflict { .. }) => continue,
+                            Err(err) => panic!("commit failed: {err}")
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let value = engine.lock().unwrap().get(b"counter").unwrap().unwrap();
+    assert_eq!(u64::from_be_bytes(value.try_into().unwrap()), threads * increments);
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
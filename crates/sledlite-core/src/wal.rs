@@ -1,11 +1,71 @@
-use std::{fs::{File, OpenOptions}, io::{Seek, Read}, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}};
+use std::{fs::{File, OpenOptions}, io::{ErrorKind, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}};
 
 use crc32fast::Hasher;
-use std::os::windows::fs::FileExt;
-#[derive(Debug)]
+
+/**
+ * Size of the header at the start of every WAL file. Its first 8 bytes held the end
+ * offset and the next 8 the last LSN when records were written in place; both are
+ * only kept so older files still open. Byte `COMPRESSION_FLAG_AT` records the
+ * compression the file was created with.
+ */
+const HEADER_LEN: u64 = 16;
+const COMPRESSION_FLAG_AT: usize = 8;
+
+/**
+ * Written in place of the op byte of a record whose payload is zstd compressed.
+ */
+const ZSTD_RECORD: u8 = 0x80;
+
+/**
+ * How `WalWriter` stores record payloads.
+ * * `Zstd(level)` compresses op, key and value of every record with the given zstd level.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WalCompression { 
+    None,
+    Zstd(i32)
+}
+
+/**
+ * How much work `WalWriter::append_record` does to make a record durable.
+ * * `Always` calls `sync_data` after every record.
+ * * `WriteVerify` also reads the record back after syncing, see
+ *   `WalWriter::read_back_last_record`, and fails the append if it does not check out.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsyncPolicy { 
+    Always,
+    WriteVerify
+}
+
+/**
+ * Knobs for WAL files written by a `WalWriter`.
+ */
+#[derive(Debug, Clone)]
+pub struct WalConfig { 
+    pub compression: WalCompression,
+    pub fsync: FsyncPolicy
+}
+
+impl Default for WalConfig { 
+    fn default() -> Self { 
+        Self { compression: WalCompression::None, fsync: FsyncPolicy::Always }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum WalOp { 
     Put = 1,
-    Delete = 2
+    Delete = 2,
+    PutTs = 3,
+    DeleteTs = 4,
+    Merge = 5,
+    SstImport = 6,
+    /**
+     * Written after `Engine::compact` folded everything up to and including the LSN held
+     * in the key, 8 bytes big endian, into an SSTable. Earlier records are redundant.
+     */
+    CompactionMarker = 7
 }
 
 // binary serialized to files 
@@ -17,16 +77,26 @@ impl From<u8> for WalOp {
         match value { 
             1 => Self::Put,
             2 => Self::Delete,
+            3 => Self::PutTs,
+            4 => Self::DeleteTs,
+            5 => Self::Merge,
+            6 => Self::SstImport,
+            7 => Self::CompactionMarker,
             _ => Self::Put
         }
     }
 }
 
-impl Into<u8> for WalOp {
-    fn into(self) -> u8 {
-        match self { 
-            WalOp::Put => 1 as u8,
-            WalOp::Delete => 2 as u8,
+impl From<WalOp> for u8 {
+    fn from(op: WalOp) -> u8 {
+        match op { 
+            WalOp::Put => 1,
+            WalOp::Delete => 2,
+            WalOp::PutTs => 3,
+            WalOp::DeleteTs => 4,
+            WalOp::Merge => 5,
+            WalOp::SstImport => 6,
+            WalOp::CompactionMarker => 7,
         }
     }
 }
@@ -34,60 +104,76 @@ impl Into<u8> for WalOp {
 pub struct WalWriter { 
     file: File,
     path: PathBuf,
-    pub lsn: AtomicUsize,
+    config: WalConfig,
+    // offset of the last record this writer appended
+    last_record_at: Option<u64>,
     pub appendable_lsn: AtomicUsize
 }
 
 impl WalWriter { 
 
     /**
-     * Opens or creates a WAL file at the specified path.
-     * * If `should_truncate` is true, the file is cleared. 
-     * * On opening, it reads the first 16 bytes to initialize the LSN (Log Sequence Number)
-     * and the Appendable LSN from the file header.
+     * Opens or creates a WAL file at the specified path with the default `WalConfig`.
      */
     pub fn open<P: AsRef<Path>>(path: P, should_truncate: bool) -> std::io::Result<Self> { 
+        Self::open_with_config(path, should_truncate, WalConfig::default())
+    }
+
+    /**
+     * Opens or creates a WAL file at the specified path in append mode.
+     * * If `should_truncate` is true, the file is cleared. 
+     * * A new or cleared file gets the 16 byte header readers skip. The header is never
+     *   rewritten afterwards, the end of the log is wherever the file ends.
+     * * Every record says whether it is compressed, so an existing file can be reopened
+     *   with a different `WalCompression`.
+     * * The Appendable LSN is recovered from the last readable record. A last record cut
+     *   short by a crash is cut off the file, so new records follow the readable ones.
+     */
+    pub fn open_with_config<P: AsRef<Path>>(path: P, should_truncate: bool, config: WalConfig) -> std::io::Result<Self> { 
+        if should_truncate { 
+            File::create(path.as_ref())?;
+        }
         let mut file = OpenOptions::new()
-            .write(true)
+            .append(true)
             .create(true)
             .read(true)
-            .truncate(should_truncate)
             .open(path.as_ref())?;
-        let lsn = Self::lsn(&mut file);
-        let appendable_lsn = Self::appendable_lsn(&mut file);
-        println!("wal writer lsn {lsn}");
+        if file.seek(SeekFrom::End(0))? < HEADER_LEN { 
+            file.set_len(0)?;
+            let mut header = [0u8; HEADER_LEN as usize];
+            header[..8].copy_from_slice(&HEADER_LEN.to_be_bytes());
+            header[COMPRESSION_FLAG_AT] = match config.compression { 
+                WalCompression::None => 0,
+                WalCompression::Zstd(_) => ZSTD_RECORD
+            };
+            file.write_all(&header)?;
+            file.sync_data()?;
+        }
+        let mut reader = WalReader::open(path.as_ref())?;
+        let appendable_lsn = reader.read_all()?
+            .iter()
+            .map(|record| record.lsn)
+            .max()
+            .unwrap_or(0);
+        // records appended behind a torn one could never be read back
+        if let Some(torn_at) = reader.torn_at { 
+            file.set_len(torn_at)?;
+        }
         
         Ok(Self { 
             file,
             path: path.as_ref().to_path_buf(),
-            lsn: AtomicUsize::new(lsn as usize),
+            config,
+            last_record_at: None,
             appendable_lsn: AtomicUsize::new(appendable_lsn as usize)
         })
     }
 
-
-    #[inline]
-    pub fn lsn(file: &mut File) -> u64 {
-        let mut lsn_buf = [0u8; 8]; 
-        match file.read_exact(&mut lsn_buf) { 
-            Ok(_) => u64::from_be_bytes(lsn_buf),
-            Err(err) => { 
-                println!("in eror block {err:?}");
-                return 16;
-            }
-        }
-    }
-
-    #[inline]
-    pub fn appendable_lsn(file: &mut File) -> u64 {
-        let mut buf = [0u8; 8]; 
-        match file.seek_read(&mut buf, 8) { 
-            Ok(_) => u64::from_be_bytes(buf),
-            Err(err) => { 
-                println!("in eror block {err:?}");
-                return 0;
-            }
-        }
+    /**
+     * Offset one past the last byte written, i.e. where the next record will start.
+     */
+    pub fn end(&mut self) -> std::io::Result<u64> { 
+        self.file.stream_position()
     }
 
 
@@ -95,7 +181,7 @@ impl WalWriter {
      * Appends a 'Put' operation to the log.
      * * This maps to a key-value insertion or update.
      */
-    pub fn append_put(&mut self, lsn: u64, key: &[u8], value: &[u8]) -> std::io::Result<()> { 
+    pub fn append_put(&mut self, lsn: u64, key: &[u8], value: &[u8]) -> std::io::Result<u64> { 
         self.append_record(lsn, WalOp::Put, key, Some(value))
     }
 
@@ -104,78 +190,153 @@ impl WalWriter {
      * Appends a 'Delete' operation to the log.
      * * This marks a key for removal, storing only the key with no value.
      */
-    pub fn append_delete(&mut self, lsn: u64, key: &[u8]) -> std::io::Result<()> { 
+    pub fn append_delete(&mut self, lsn: u64, key: &[u8]) -> std::io::Result<u64> { 
         self.append_record(lsn, WalOp::Delete, key, None)
     }
 
 
 
+    /**
+     * Appends a 'Merge' operation to the log, `operand` being folded into the value of
+     * `key` by the engine's merge operator.
+     */
+    pub fn append_merge(&mut self, lsn: u64, key: &[u8], operand: &[u8]) -> std::io::Result<u64> { 
+        self.append_record(lsn, WalOp::Merge, key, Some(operand))
+    }
+
+
+    /**
+     * Appends an 'SstImport' operation to the log, recording that the SSTable `file_name`
+     * in the engine directory was added without going through the memtable.
+     */
+    pub fn append_sst_import(&mut self, lsn: u64, file_name: &[u8]) -> std::io::Result<u64> { 
+        self.append_record(lsn, WalOp::SstImport, file_name, None)
+    }
+
+
+    /**
+     * Appends a 'CompactionMarker' to the log, recording that every record up to and
+     * including `lsn_up_to` is held by an SSTable.
+     */
+    pub fn append_compaction_marker(&mut self, lsn: u64, lsn_up_to: u64) -> std::io::Result<u64> { 
+        self.append_record(lsn, WalOp::CompactionMarker, &lsn_up_to.to_be_bytes(), None)
+    }
+
+    /**
+     * Rewrites the log to start at its most recent `WalOp::CompactionMarker`, dropping
+     * the records before it. Does nothing if the log holds no marker.
+     * * The marker itself is kept so the LSNs recovered on the next `open` carry on from it.
+     *   The records are copied into a sibling `.tmp` file which then replaces the log, a
+     *   crash midway leaves the old log in place.
+     */
+    pub fn truncate_up_to_marker(&mut self) -> std::io::Result<()> { 
+        let mut records = WalReader::open(&self.path)?.read_all()?;
+        let Some(marker) = records.iter().rposition(|record| record.compacted_up_to().is_some()) else { 
+            return Ok(());
+        };
+        let tmp_path = self.path.with_extension("tmp");
+        let mut rewritten = Self::open_with_config(&tmp_path, true, self.config.clone())?;
+        for record in records.drain(marker..) { 
+            rewritten.append_record(record.lsn, record.op, &record.key, record.value.as_deref())?;
+        }
+        self.last_record_at = rewritten.last_record_at;
+        drop(rewritten);
+        std::fs::rename(&tmp_path, &self.path)?;
+        self.file = OpenOptions::new().append(true).read(true).open(&self.path)?;
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+
+    /**
+     * Appends a 'PutTs' operation to the log, `versioned_key` being the key with its
+     * timestamp appended.
+     */
+    pub fn append_put_ts(&mut self, lsn: u64, versioned_key: &[u8], value: &[u8]) -> std::io::Result<u64> { 
+        self.append_record(lsn, WalOp::PutTs, versioned_key, Some(value))
+    }
+
+    /**
+     * Appends a 'DeleteTs' operation to the log, see `append_put_ts`.
+     */
+    pub fn append_delete_ts(&mut self, lsn: u64, versioned_key: &[u8]) -> std::io::Result<u64> { 
+        self.append_record(lsn, WalOp::DeleteTs, versioned_key, None)
+    }
+
+
+
     /**
      * Low-level method that serializes a record and writes it to disk.
      * * # Binary Format:
-     * [LSN (8B)][Op (1B)][KeyLen (4B)][Key (NB)][ValLen (4B)][Value (MB)][CRC32 (4B)]
+     *   [LSN (8B)][Op (1B)][KeyLen (4B)][Key (NB)][ValLen (4B)][Value (MB)][CRC32 (4B)]
+     * * With `WalCompression::Zstd` everything between the LSN and the CRC is compressed:
+     *   [LSN (8B)][0x80 (1B)][CompressedLen (4B)][Compressed (CB)][CRC32 (4B)]
      * * # Process:
-     * 1. Calculates a CRC32 checksum for data integrity.
-     * 2. Uses `seek_write` for thread-safe-ish positioning via offsets.
-     * 3. Updates the file header (first 16 bytes) with the new LSNs.
-     * 4. Calls `sync_data()` to ensure the OS flushes the write to physical hardware.
+     * 1. Calculates a CRC32 checksum of the uncompressed payload for data integrity.
+     * 2. Appends the whole record with a single `write_all`, the file is in append mode
+     *    so the OS places it at the current end.
+     * 3. Calls `sync_data()` to ensure the OS flushes the write to physical hardware.
+     * 4. With `FsyncPolicy::WriteVerify`, reads the record back and checks its CRC.
+     * * Returns the end of the log after the record, see `end`.
      */
-    pub fn append_record(&mut self, lsn: u64, wal_op: WalOp, key: &[u8], value: Option<&[u8]>) -> std::io::Result<()> { 
-        let mut buf: Vec<u8> = Vec::new();
-        let mut hasher = Hasher::new();
-        let op_b = [wal_op as u8];
-        let lsn_bytes = lsn.to_be_bytes();
-        buf.extend(&lsn_bytes);
-        buf.extend(&op_b);
-        hasher.update(&op_b);
-        let key_len = key.len() as u32;
-        let key_len_bytes = key_len.to_be_bytes();
-        buf.extend(&key_len_bytes);
-        hasher.update(&key_len_bytes);
-        buf.extend(key);
-        hasher.update(key);
-        match value { 
-            Some(val) => { 
-                let v_len_bytes = (val.len() as u32).to_be_bytes();
-                buf.extend(&v_len_bytes);
-                hasher.update(&v_len_bytes);
-                buf.extend(val);
-                hasher.update(val);
-            },
-            None => { 
-                let v_len_bytes = 0u32.to_be_bytes();
-                buf.extend(&v_len_bytes);
-                hasher.update(&v_len_bytes);
-            }
-        }
+    pub fn append_record(&mut self, lsn: u64, wal_op: WalOp, key: &[u8], value: Option<&[u8]>) -> std::io::Result<u64> { 
+        let payload = encode_payload(wal_op, key, value);
+        let hash_bytes = crc32fast::hash(&payload).to_be_bytes();
 
-        let hash = hasher.finalize();
-        let hash_bytes = hash.to_be_bytes();
-        buf.extend(&hash_bytes);
-        let buf_len = buf.len();
-        let offset = self.lsn.fetch_add(buf_len, Ordering::SeqCst) as u64;
-        let mut written = 0usize;
-        while !buf.is_empty() { 
-            match self.file.seek_write(&buf[written..], offset + written as u64) { 
-                Ok(0) => break,
-                Ok(n) => written = written + n,
-                Err(_) => todo!()
+        let mut buf: Vec<u8> = Vec::with_capacity(payload.len() + 17);
+        buf.extend(&lsn.to_be_bytes());
+        match self.config.compression { 
+            WalCompression::None => buf.extend(&payload),
+            WalCompression::Zstd(level) => { 
+                let compressed = zstd::encode_all(&payload[..], level)?;
+                buf.push(ZSTD_RECORD);
+                buf.extend(&(compressed.len() as u32).to_be_bytes());
+                buf.extend(&compressed);
             }
         }
-        let fetch_lsn = self.lsn.load(Ordering::SeqCst) as u64;
+        buf.extend(&hash_bytes);
+        let start = self.end()?;
+        self.file.write_all(&buf)?;
+        self.last_record_at = Some(start);
         self.appendable_lsn.swap(lsn as usize, Ordering::SeqCst);
-        println!("updating lsn: {lsn}");
-        let _ = self.file.seek_write(&fetch_lsn.to_be_bytes(), 0)?;
-        let _ = self.file.seek_write(&lsn.to_be_bytes(), 8);
         self.file.sync_data()?;
-        Ok(())
+        if self.config.fsync == FsyncPolicy::WriteVerify { 
+            self.read_back_last_record()?;
+        }
+        let end = self.end()?;
+        tracing::trace!(lsn, end, "wal record written");
+        Ok(end)
+    }
+
+    /**
+     * Reads the record this writer appended last back from the file and checks its CRC,
+     * the same way `WalReader::validate` does, for a read-after-write check.
+     * * The file is read through a handle of its own, so the position of the writer does
+     *   not move.
+     * * # Returns
+     * * `Ok(WalRecord)` with the record as read back.
+     * * `Err(InvalidData)` if the record is cut short or its CRC does not match.
+     * * `Err(NotFound)` if this writer has not appended a record since it was opened.
+     */
+    pub fn read_back_last_record(&self) -> std::io::Result<WalRecord> { 
+        let Some(start) = self.last_record_at else { 
+            return Err(std::io::Error::new(ErrorKind::NotFound, "no record appended by this writer"));
+        };
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let record = data.split_first_chunk::<8>()
+            .and_then(|(lsn, body)| WalReader::check_record(u64::from_be_bytes(*lsn), body))
+            .and_then(|(_, record)| record);
+        record.ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "write-verify failed"))
     }
 }
 
 
 pub struct WalReader {
-    file: File, 
-    path: PathBuf
+    file: File,
+    torn_at: Option<u64> // offset of the record `read_all` found cut short by the end of the file
 }
 
 #[derive(Debug)]
@@ -183,7 +344,28 @@ pub struct WalRecord {
     pub lsn: u64,
     pub op: WalOp,
     pub key: Vec<u8>,
-    pub value: Option<Vec<u8>>
+    pub value: Option<Vec<u8>>,
+    pub checksum: u32 // CRC32 of the uncompressed payload, as stored in the log
+}
+
+impl WalRecord { 
+    /**
+     * Whether `checksum` still matches op, key and value, recomputed the way
+     * `WalWriter::append_record` computes it. The LSN is not covered by the CRC.
+     */
+    pub fn validate(&self) -> bool { 
+        crc32fast::hash(&encode_payload(self.op, &self.key, self.value.as_deref())) == self.checksum
+    }
+
+    /**
+     * The LSN a `WalOp::CompactionMarker` record covers, `None` for any other record.
+     */
+    pub fn compacted_up_to(&self) -> Option<u64> { 
+        match self.op { 
+            WalOp::CompactionMarker => Some(u64::from_be_bytes(self.key.as_slice().try_into().ok()?)),
+            _ => None
+        }
+    }
 }
 
 impl WalReader { 
@@ -193,11 +375,8 @@ impl WalReader {
      * * Does not modify the file; opens in read-only mode.
      */
     pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> { 
-        let file = OpenOptions::new().read(true).open(path.as_ref().to_path_buf())?;
-        Ok(Self { 
-            file, 
-            path: path.as_ref().to_path_buf()
-        })
+        let file = OpenOptions::new().read(true).open(path.as_ref())?;
+        Ok(Self { file, torn_at: None })
     }
 
 
@@ -207,71 +386,243 @@ impl WalReader {
      * * Skips the 16-byte header to begin reading records.
      * * For every record, it re-calculates the CRC32 checksum. 
      * * If a checksum mismatch is detected (indicating a partial write or corruption), 
-     * it stops reading and returns the records collected so far.
-     * * Handles `UnexpectedEof` gracefully to determine the end of the log.
+     *   it stops reading and returns the records collected so far.
+     * * A record cut short by the end of the file, what a crash in the middle of an append
+     *   leaves behind, ends the log the same way instead of failing the read.
      */
     pub fn read_all(&mut self) -> std::io::Result<Vec<WalRecord>> { 
 
         let mut records = Vec::new();
-        self.file.seek(std::io::SeekFrom::Start(16))?;
+        self.file.seek(SeekFrom::Start(HEADER_LEN))?;
         loop { 
+            let record_at = self.file.stream_position()?;
             let mut lsn_buf = [0u8; 8];
             if let Err(e)  = self.file.read_exact(&mut lsn_buf) { 
                 if e.kind() == std::io::ErrorKind::UnexpectedEof { 
+                    // a few bytes short of an LSN are a torn record too
+                    if self.file.metadata()?.len() > record_at { 
+                        self.torn_at = Some(record_at);
+                    }
                     break;
                 } else { 
                     return Err(e);
                 }
             }
-            let mut lsn = u64::from_be_bytes(lsn_buf);
-            let mut op_buf = [0u8];
-            if let Err(e) = self.file.read_exact(&mut op_buf) { 
-                if e.kind() == std::io::ErrorKind::UnexpectedEof { 
+            let lsn = u64::from_be_bytes(lsn_buf);
+            match self.read_record_body(lsn, records.len()) { 
+                Ok(Some(record)) => records.push(record),
+                Ok(None) => break,
+                // a record cut short by the end of the file is a torn write, like a bad checksum
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => { 
+                    tracing::warn!(lsn, read = records.len(), "wal record is cut short, ignoring the rest of the log");
+                    self.torn_at = Some(record_at);
                     break;
-                } else { 
-                    return Err(e);
-                }
+                },
+                Err(e) => return Err(e)
             }
-            let op = WalOp::from(op_buf[0]);
-            let mut key_len_buf = [0u8; 4];
-            self.file.read_exact(&mut key_len_buf)?;
-            let mut key_len = u32::from_be_bytes(key_len_buf) as usize;
-            let mut key_buf = vec![0u8; key_len];
-            self.file.read_exact(&mut key_buf)?;
-            let mut val_len_buf = [0u8; 4];
-            self.file.read_exact(&mut val_len_buf)?;
-            let val_len = u32::from_be_bytes(val_len_buf) as usize;
-            let val = if val_len > 0 { 
-                let mut val_buf = vec![0u8; val_len];
-                self.file.read_exact(&mut val_buf)?;
-                Some(val_buf)
-            } else { 
-                None
-            };
-            // validate the crc 
+        }
+        Ok(records)
+    }
+
+    /**
+     * Reads the record following the LSN `read_all` just read.
+     * * # Returns
+     * * `Ok(None)` if the record fails its checksum or does not decompress.
+     * * `Err(UnexpectedEof)` if the file ends inside the record.
+     */
+    fn read_record_body(&mut self, lsn: u64, read: usize) -> std::io::Result<Option<WalRecord>> { 
+        let mut op_buf = [0u8];
+        self.file.read_exact(&mut op_buf)?;
+        if op_buf[0] == ZSTD_RECORD { 
+            let mut len_buf = [0u8; 4];
+            self.file.read_exact(&mut len_buf)?;
+            let mut compressed = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+            self.file.read_exact(&mut compressed)?;
             let mut crc_buf = [0u8; 4];
             self.file.read_exact(&mut crc_buf)?;
-            let crc = u32::from_be_bytes(crc_buf);
-            let mut hasher = Hasher::new(); 
-            hasher.update(&op_buf);
-            hasher.update(&key_len_buf);
-            hasher.update(&key_buf);
-            hasher.update(&val_len_buf);
-            if let Some(ref v) = val { 
-                hasher.update(v);
+            // a payload that does not decompress or check out is treated like a torn write
+            let record = zstd::decode_all(&compressed[..]).ok()
+                .filter(|payload| crc32fast::hash(payload) == u32::from_be_bytes(crc_buf))
+                .and_then(|payload| Self::parse_payload(lsn, &payload, u32::from_be_bytes(crc_buf)));
+            if record.is_none() { 
+                tracing::warn!(lsn, read, "compressed wal record is corrupted, ignoring the rest of the log");
             }
-            let calc = hasher.finalize();
-            if calc != crc { 
-                // corrupted, stop reading to be safe
+            return Ok(record);
+        }
+        let op = WalOp::from(op_buf[0]);
+        let mut key_len_buf = [0u8; 4];
+        self.file.read_exact(&mut key_len_buf)?;
+        let key_len = u32::from_be_bytes(key_len_buf) as usize;
+        let mut key_buf = vec![0u8; key_len];
+        self.file.read_exact(&mut key_buf)?;
+        let mut val_len_buf = [0u8; 4];
+        self.file.read_exact(&mut val_len_buf)?;
+        let val_len = u32::from_be_bytes(val_len_buf) as usize;
+        let val = if val_len > 0 { 
+            let mut val_buf = vec![0u8; val_len];
+            self.file.read_exact(&mut val_buf)?;
+            Some(val_buf)
+        } else { 
+            None
+        };
+        // validate the crc 
+        let mut crc_buf = [0u8; 4];
+        self.file.read_exact(&mut crc_buf)?;
+        let crc = u32::from_be_bytes(crc_buf);
+        let mut hasher = Hasher::new(); 
+        hasher.update(&op_buf);
+        hasher.update(&key_len_buf);
+        hasher.update(&key_buf);
+        hasher.update(&val_len_buf);
+        if let Some(ref v) = val { 
+            hasher.update(v);
+        }
+        let calc = hasher.finalize();
+        if calc != crc { 
+            // corrupted, stop reading to be safe
+            tracing::warn!(lsn, read, "wal record checksum mismatch, ignoring the rest of the log");
+            return Ok(None);
+        }
+        Ok(Some(WalRecord {
+            lsn,
+            op,
+            key: key_buf,
+            value: val,
+            checksum: crc
+        }))
+    }
+
+    /**
+     * Checks the checksum of every record in the file without stopping at the first bad one.
+     * * A record whose checksum does not match is skipped using its own length fields, so
+     *   one flipped byte in a payload only affects its record. A record cut short by the end
+     *   of the file, like a torn write, is reported and ends the check.
+     * * # Returns
+     * * `Ok((usize, Vec<u64>))` with the number of intact records and the LSNs of the
+     *   corrupted ones.
+     */
+    pub fn validate(&mut self) -> std::io::Result<(usize, Vec<u64>)> { 
+        let mut data = Vec::new();
+        self.file.seek(SeekFrom::Start(HEADER_LEN))?;
+        self.file.read_to_end(&mut data)?;
+        let (mut valid, mut corrupted) = (0, Vec::new());
+        let mut rest = &data[..];
+        // a tail shorter than an LSN has nothing to report
+        while let Some((lsn, body)) = rest.split_first_chunk::<8>() { 
+            let lsn = u64::from_be_bytes(*lsn);
+            let Some((len, record)) = Self::check_record(lsn, body) else { 
+                corrupted.push(lsn);
                 break;
+            };
+            match record { 
+                Some(_) => valid += 1,
+                None => corrupted.push(lsn)
             }
-            records.push(WalRecord {
-                lsn,
-                op,
-                key: key_buf,
-                value: val
-            });
+            rest = &body[len..];
         }
-        Ok(records)
+        Ok((valid, corrupted))
+    }
+
+    /**
+     * Rewrites the WAL at `path` to hold only the records before the first corrupted or
+     * torn one, so the garbage after them can not get in the way of later appends.
+     * * The original file is copied to `<path>.bak` first. The records are written to a
+     *   sibling `.tmp` file which then replaces the log, a crash midway leaves the old log
+     *   in place. The compression flag of the header is kept, compressed logs are rewritten
+     *   with zstd's default level.
+     * * # Returns
+     * * `Ok(usize)` with the number of records preserved.
+     */
+    pub fn repair(path: &Path) -> std::io::Result<usize> { 
+        let data = std::fs::read(path)?;
+        let mut records = Vec::new();
+        let mut rest = data.get(HEADER_LEN as usize..).unwrap_or_default();
+        while let Some((lsn, body)) = rest.split_first_chunk::<8>() { 
+            let lsn = u64::from_be_bytes(*lsn);
+            let Some((len, Some(record))) = Self::check_record(lsn, body) else { 
+                break;
+            };
+            records.push(record);
+            rest = &body[len..];
+        }
+        let compression = match data.get(COMPRESSION_FLAG_AT) { 
+            Some(&ZSTD_RECORD) => WalCompression::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL),
+            _ => WalCompression::None
+        };
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        std::fs::copy(path, backup)?;
+        let tmp_path = path.with_extension("tmp");
+        let mut rewritten = WalWriter::open_with_config(&tmp_path, true, WalConfig { compression, ..WalConfig::default() })?;
+        let preserved = records.len();
+        for record in records { 
+            rewritten.append_record(record.lsn, record.op, &record.key, record.value.as_deref())?;
+        }
+        drop(rewritten);
+        std::fs::rename(&tmp_path, path)?;
+        tracing::info!(path = ?path, records = preserved, dropped_bytes = rest.len(), "wal repaired");
+        Ok(preserved)
+    }
+
+    /**
+     * Length of the record starting at `body`, right after its LSN, and the record if its
+     * checksum matches. `None` if the record runs past the end of `body`.
+     */
+    fn check_record(lsn: u64, body: &[u8]) -> Option<(usize, Option<WalRecord>)> { 
+        let be_u32 = |at: usize| body.get(at..at + 4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()));
+        if *body.first()? == ZSTD_RECORD { 
+            let compressed_len = be_u32(1)? as usize;
+            let compressed = body.get(5..5 + compressed_len)?;
+            let crc = be_u32(5 + compressed_len)?;
+            let record = zstd::decode_all(compressed).ok()
+                .filter(|payload| crc32fast::hash(payload) == crc)
+                .and_then(|payload| Self::parse_payload(lsn, &payload, crc));
+            return Some((9 + compressed_len, record));
+        }
+        let key_len = be_u32(1)? as usize;
+        let val_len = be_u32(5 + key_len)? as usize;
+        let payload_len = 9 + key_len + val_len;
+        let crc = be_u32(payload_len)?;
+        let payload = &body[..payload_len];
+        let record = (crc32fast::hash(payload) == crc).then(|| Self::parse_payload(lsn, payload, crc)).flatten();
+        Some((payload_len + 4, record))
     }
+
+    /**
+     * Splits a decompressed payload, laid out like an uncompressed record between its
+     * LSN and CRC, into a `WalRecord` carrying `checksum`. Returns `None` if the lengths
+     * overrun the payload.
+     */
+    fn parse_payload(lsn: u64, payload: &[u8], checksum: u32) -> Option<WalRecord> { 
+        let (&op, rest) = payload.split_first()?;
+        let (key_len, rest) = rest.split_first_chunk::<4>()?;
+        let (key, rest) = rest.split_at_checked(u32::from_be_bytes(*key_len) as usize)?;
+        let (val_len, rest) = rest.split_first_chunk::<4>()?;
+        let val_len = u32::from_be_bytes(*val_len) as usize;
+        if rest.len() != val_len { 
+            return None;
+        }
+        Some(WalRecord { 
+            lsn,
+            op: WalOp::from(op),
+            key: key.to_vec(),
+            value: (val_len > 0).then(|| rest.to_vec()),
+            checksum
+        })
+    }
+}
+
+/**
+ * [Op (1B)][KeyLen (4B)][Key][ValLen (4B)][Value], the part of a record the CRC covers
+ * and zstd compresses.
+ */
+fn encode_payload(wal_op: WalOp, key: &[u8], value: Option<&[u8]>) -> Vec<u8> { 
+    let value = value.unwrap_or_default();
+    let mut payload: Vec<u8> = Vec::with_capacity(9 + key.len() + value.len());
+    payload.push(wal_op as u8);
+    payload.extend(&(key.len() as u32).to_be_bytes());
+    payload.extend(key);
+    payload.extend(&(value.len() as u32).to_be_bytes());
+    payload.extend(value);
+    payload
 }
\ No newline at end of file
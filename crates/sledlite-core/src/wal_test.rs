@@ -0,0 +1,214 @@
+use std::{fs::{metadata, remove_file, OpenOptions}, io::{ErrorKind, Seek, SeekFrom, Write}, path::PathBuf, sync::{Arc, Mutex, atomic::Ordering}, thread};
+
+use crate::wal::{FsyncPolicy, WalCompression, WalConfig, WalOp, WalReader, WalWriter};
+
+#[test]
+pub fn wal_test_concurrent_appends_end_at_file_length() { 
+    let path = PathBuf::from("./temp-wal-concurrent.log");
+    let _ = remove_file(&path);
+    let writer = Arc::new(Mutex::new(WalWriter::open(&path, true).expect("can not open wal")));
+    let start = writer.lock().unwrap().end().expect("end failed");
+    assert_eq!(start, 16);
+
+    // every record has the same size: 8 lsn + 1 op + 4 + 4 key + 4 + 4 value + 4 crc
+    let record_len = 29;
+    let handles: Vec<_> = (0..4u64).map(|t| { 
+        let writer = Arc::clone(&writer);
+        thread::spawn(move || { 
+            (0..50u64).map(|i| { 
+                let lsn = t * 50 + i + 1;
+                writer.lock().unwrap().append_put(lsn, &(lsn as u32).to_be_bytes(), b"vvvv").expect("append failed")
+            }).collect::<Vec<_>>()
+        })
+    }).collect();
+    let mut ends: Vec<u64> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+    ends.sort();
+    let expected: Vec<u64> = (1..=200).map(|n| start + n * record_len).collect();
+    assert_eq!(ends, expected);
+    assert_eq!(metadata(&path).unwrap().len(), *expected.last().unwrap());
+
+    let records = WalReader::open(&path).unwrap().read_all().unwrap();
+    assert_eq!(records.len(), 200);
+    drop(writer);
+
+    // reopening recovers the end and the last lsn from the file itself
+    let mut reopened = WalWriter::open(&path, false).expect("can not reopen wal");
+    assert_eq!(reopened.end().unwrap(), *expected.last().unwrap());
+    assert_eq!(reopened.appendable_lsn.load(Ordering::SeqCst), 200);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn wal_test_compressed_and_plain_logs_replay_the_same() { 
+    let write = |name: &str, compression: WalCompression| { 
+        let path = PathBuf::from(format!("./temp-wal-{name}.log"));
+        let mut writer = WalWriter::open_with_config(&path, true, WalConfig { compression, ..WalConfig::default() }).expect("can not open wal");
+        for lsn in 1..=100u64 { 
+            let key = format!("key-{:04}", lsn % 37).into_bytes();
+            if lsn % 10 == 0 { 
+                writer.append_delete(lsn, &key).expect("append failed");
+            } else { 
+                writer.append_put(lsn, &key, &[b'x'; 256]).expect("append failed");
+            }
+        }
+        drop(writer);
+        let records = WalReader::open(&path).unwrap().read_all().unwrap();
+        let len = metadata(&path).unwrap().len();
+        let _ = remove_file(&path);
+        (records, len)
+    };
+    let (plain, plain_len) = write("plain", WalCompression::None);
+    let (compressed, compressed_len) = write("zstd", WalCompression::Zstd(3));
+    assert_eq!(plain.len(), 100);
+    assert_eq!(format!("{plain:?}"), format!("{compressed:?}"));
+    assert!(compressed_len < plain_len / 4, "{compressed_len} vs {plain_len}");
+}
+
+#[test]
+pub fn wal_test_reopen_with_other_compression_reads_both() { 
+    let path = PathBuf::from("./temp-wal-mixed.log");
+    let mut writer = WalWriter::open(&path, true).expect("can not open wal");
+    writer.append_put(1, b"a", b"plain").unwrap();
+    drop(writer);
+    let mut writer = WalWriter::open_with_config(&path, false, WalConfig { compression: WalCompression::Zstd(1), ..WalConfig::default() }).unwrap();
+    writer.append_put(2, b"b", b"compressed").unwrap();
+    writer.append_delete(3, b"a").unwrap();
+    drop(writer);
+    let records = WalReader::open(&path).unwrap().read_all().unwrap();
+    let summary: Vec<_> = records.iter().map(|r| (r.lsn, r.key.clone(), r.value.clone())).collect();
+    assert_eq!(summary, vec![
+        (1, b"a".to_vec(), Some(b"plain".to_vec())),
+        (2, b"b".to_vec(), Some(b"compressed".to_vec())),
+        (3, b"a".to_vec(), None)
+    ]);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn wal_test_truncate_up_to_marker_keeps_later_records() { 
+    let path = PathBuf::from("./temp-wal-marker.log");
+    let mut writer = WalWriter::open(&path, true).expect("can not open wal");
+    for lsn in 1..=20 { 
+        writer.append_put(lsn, format!("key-{lsn}").as_bytes(), &[7u8; 64]).unwrap();
+    }
+    writer.append_compaction_marker(21, 20).unwrap();
+    writer.append_delete(22, b"key-3").unwrap();
+    let before = writer.end().unwrap();
+    writer.truncate_up_to_marker().expect("truncate failed");
+    let end = writer.append_put(23, b"after", b"truncate").unwrap();
+    assert!(end < before / 4, "{end} vs {before}");
+    assert_eq!(end, metadata(&path).unwrap().len());
+    drop(writer);
+    let records = WalReader::open(&path).unwrap().read_all().unwrap();
+    let lsns: Vec<u64> = records.iter().map(|r| r.lsn).collect();
+    assert_eq!(lsns, vec![21, 22, 23]);
+    assert_eq!(records[0].compacted_up_to(), Some(20));
+    assert_eq!(records[1].compacted_up_to(), None);
+    assert_eq!(WalWriter::open(&path, false).unwrap().appendable_lsn.load(Ordering::SeqCst), 23);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn wal_test_validate_reports_every_corrupted_record() { 
+    let path = PathBuf::from("./temp-wal-validate.log");
+    let mut writer = WalWriter::open(&path, true).expect("can not open wal");
+    for lsn in 1..=10u64 { 
+        writer.append_put(lsn, format!("key-{lsn:02}").as_bytes(), b"value").expect("append failed");
+    }
+    drop(writer);
+    // header (16) + records of 8 + 1 + 4 + 6 + 4 + 5 + 4 bytes, flip a value byte of lsn 3 and 7
+    let mut bytes = std::fs::read(&path).unwrap();
+    for lsn in [3, 7] { 
+        bytes[16 + (lsn - 1) * 32 + 23] ^= 0xff;
+    }
+    // and tear the last record
+    bytes.truncate(bytes.len() - 2);
+    std::fs::write(&path, bytes).unwrap();
+
+    let (valid, corrupted) = WalReader::open(&path).unwrap().validate().unwrap();
+    assert_eq!(valid, 7);
+    assert_eq!(corrupted, vec![3, 7, 10]);
+    // reading stops at the first corrupted record
+    assert_eq!(WalReader::open(&path).unwrap().read_all().unwrap().len(), 2);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn wal_test_repair_drops_the_corrupted_tail() { 
+    let path = PathBuf::from("./temp-wal-repair.log");
+    let backup = PathBuf::from("./temp-wal-repair.log.bak");
+    let mut writer = WalWriter::open(&path, true).expect("can not open wal");
+    for lsn in 1..=20u64 { 
+        writer.append_put(lsn, format!("key-{lsn:02}").as_bytes(), b"value").expect("append failed");
+    }
+    drop(writer);
+    let intact_len = metadata(&path).unwrap().len();
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes.extend([0u8, 0, 0, 0, 0, 0, 0, 21, 1, 0xde, 0xad, 0xbe, 0xef, 0x42]);
+    std::fs::write(&path, &bytes).unwrap();
+
+    assert_eq!(WalReader::repair(&path).unwrap(), 20);
+    assert_eq!(metadata(&path).unwrap().len(), intact_len);
+    assert_eq!(std::fs::read(&backup).unwrap(), bytes);
+    let records = WalReader::open(&path).unwrap().read_all().unwrap();
+    assert_eq!(records.iter().map(|record| record.lsn).collect::<Vec<_>>(), (1..=20).collect::<Vec<_>>());
+    // appends after the repair are read back
+    let mut writer = WalWriter::open(&path, false).expect("can not reopen wal");
+    assert_eq!(writer.appendable_lsn.load(Ordering::SeqCst), 20);
+    writer.append_put(21, b"key-21", b"value").expect("append failed");
+    drop(writer);
+    assert_eq!(WalReader::open(&path).unwrap().read_all().unwrap().len(), 21);
+    let _ = remove_file(&path);
+    let _ = remove_file(&backup);
+}
+
+#[test]
+pub fn wal_test_read_back_last_record_detects_corruption() { 
+    let path = PathBuf::from("./temp-wal-read-back.log");
+    let mut writer = WalWriter::open_with_config(&path, true, WalConfig { fsync: FsyncPolicy::WriteVerify, ..WalConfig::default() }).expect("can not open wal");
+    assert_eq!(writer.read_back_last_record().expect_err("nothing appended yet").kind(), ErrorKind::NotFound);
+    writer.append_put(1, b"first", b"value").expect("append failed");
+    let end = writer.append_put(2, b"second", b"value").expect("append failed");
+    let record = writer.read_back_last_record().expect("read back failed");
+    assert_eq!((record.lsn, record.key, record.value), (2, b"second".to_vec(), Some(b"value".to_vec())));
+
+    // flip the last byte of the value, right before the 4 byte crc
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(end - 5)).unwrap();
+    file.write_all(b"X").unwrap();
+    drop(file);
+    let err = writer.read_back_last_record().expect_err("corruption not detected");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "write-verify failed");
+    // the writer is not moved by reading back, the next record still lands at the end
+    assert_eq!(writer.end().unwrap(), end);
+    drop(writer);
+    let _ = remove_file(&path);
+}
+
+#[test]
+pub fn wal_test_records_carry_lsn_and_checksum_separately() { 
+    for (name, compression) in [("plain", WalCompression::None), ("zstd", WalCompression::Zstd(1))] { 
+        let path = PathBuf::from(format!("./temp-wal-checksum-{name}.log"));
+        let mut writer = WalWriter::open_with_config(&path, true, WalConfig { compression, ..WalConfig::default() }).expect("can not open wal");
+        writer.append_put(41, b"key", b"value").unwrap();
+        writer.append_delete(42, b"key").unwrap();
+        drop(writer);
+        let mut records = WalReader::open(&path).unwrap().read_all().unwrap();
+        assert_eq!(records.iter().map(|r| r.lsn).collect::<Vec<_>>(), vec![41, 42]);
+        // op, key length, key, value length, value
+        let put_crc = crc32fast::hash(b"\x01\x00\x00\x00\x03key\x00\x00\x00\x05value");
+        let delete_crc = crc32fast::hash(b"\x02\x00\x00\x00\x03key\x00\x00\x00\x00");
+        assert_eq!(records.iter().map(|r| r.checksum).collect::<Vec<_>>(), vec![put_crc, delete_crc]);
+        assert!(records.iter().all(|r| r.validate()));
+
+        // the lsn is not part of the checksum, the payload is
+        records[0].lsn = 7;
+        assert!(records[0].validate());
+        records[0].value = Some(b"other".to_vec());
+        assert!(!records[0].validate());
+        records[1].op = WalOp::Put;
+        assert!(!records[1].validate());
+        let _ = remove_file(&path);
+    }
+}
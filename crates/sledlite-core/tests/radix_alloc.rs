@@ -0,0 +1,83 @@
+use std::{alloc::{GlobalAlloc, Layout, System}, sync::{atomic::{AtomicIsize, Ordering}, Mutex}};
+
+use sledlite_core::radix::RadixTree;
+
+/**
+ * Counts the bytes currently allocated by the test binary.
+ * * Kept in its own integration test so no other test allocates in parallel.
+ */
+struct TrackingAllocator;
+
+static LIVE_BYTES: AtomicIsize = AtomicIsize::new(0);
+static SERIAL: Mutex<()> = Mutex::new(());
+
+unsafe impl GlobalAlloc for TrackingAllocator { 
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 { 
+        LIVE_BYTES.fetch_add(layout.size() as isize, Ordering::SeqCst);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) { 
+        LIVE_BYTES.fetch_sub(layout.size() as isize, Ordering::SeqCst);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+/**
+ * crossbeam keeps a couple of its own garbage bags queued even when nothing is pending,
+ * so "back to baseline" allows for that much.
+ */
+const EPOCH_SLACK: isize = 8 * 1024;
+
+/**
+ * Lets the epoch advance until every deferred destruction has run.
+ */
+fn collect_garbage() { 
+    for _ in 0..1_000 { 
+        crossbeam_epoch::pin().flush();
+    }
+}
+
+#[test]
+fn clear_frees_the_old_tree() { 
+    let _serial = SERIAL.lock().unwrap();
+    collect_garbage();
+    let mut tree = RadixTree::new();
+    collect_garbage();
+    let empty = LIVE_BYTES.load(Ordering::SeqCst);
+    for i in 0..10_000u32 { 
+        let _ = tree.insert(format!("key-{}", i).as_bytes(), i.to_be_bytes().to_vec());
+    }
+    collect_garbage();
+    assert!(LIVE_BYTES.load(Ordering::SeqCst) - empty > 100 * EPOCH_SLACK);
+    tree.clear();
+    collect_garbage();
+    assert!((LIVE_BYTES.load(Ordering::SeqCst) - empty).abs() <= EPOCH_SLACK);
+    assert!(tree.is_empty());
+    assert!(tree.iter_all().is_empty());
+    let _ = tree.insert(b"after-clear", vec![1]);
+    assert_eq!(tree.get(b"after-clear").unwrap().as_deref(), Some(&[1][..]));
+}
+
+#[test]
+fn dropping_the_tree_frees_every_node() { 
+    let _serial = SERIAL.lock().unwrap();
+    collect_garbage();
+    let before = LIVE_BYTES.load(Ordering::SeqCst);
+    let tree = RadixTree::new();
+    for i in 0..10_000u32 { 
+        let _ = tree.insert(format!("key-{}", i).as_bytes(), i.to_be_bytes().to_vec());
+    }
+    for i in (0..10_000u32).step_by(3) { 
+        let _ = tree.remove(format!("key-{}", i).as_bytes());
+    }
+    let _ = tree.bulk_insert((0..1_000u32).map(|i| (format!("bulk-{}", i).into_bytes(), vec![0; 16])).collect());
+    collect_garbage();
+    assert!(LIVE_BYTES.load(Ordering::SeqCst) - before > 100 * EPOCH_SLACK);
+    drop(tree);
+    collect_garbage();
+    assert!((LIVE_BYTES.load(Ordering::SeqCst) - before).abs() <= EPOCH_SLACK);
+}